@@ -0,0 +1,64 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kv::{Codec, CommandResponse, FrameCoder, Value};
+use rand::prelude::*;
+
+/// 生成一个带有 size 字节 payload 的响应，一半是随机数据，一半是重复数据
+fn response(size: usize) -> CommandResponse {
+    let mut rng = rand::thread_rng();
+    let mut data = vec![0u8; size];
+    rng.fill_bytes(&mut data[..size / 2]);
+
+    let value: Value = Bytes::from(data).into();
+    value.into()
+}
+
+fn codec(c: &mut Criterion) {
+    for size in [16 * 1024, 1024 * 1024] {
+        let res = response(size);
+
+        let mut group = c.benchmark_group(format!("encode_{}k", size / 1024));
+        group.throughput(Throughput::Bytes(size as u64));
+        for codec in Codec::ALL {
+            group.bench_with_input(
+                BenchmarkId::from_parameter(format!("{:?}", codec)),
+                &codec,
+                |b, codec| {
+                    let mut buf = BytesMut::new();
+                    b.iter(|| {
+                        buf.clear();
+                        res.encode_frame_with(*codec, &mut buf).unwrap();
+                    })
+                },
+            );
+        }
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("decode_{}k", size / 1024));
+        group.throughput(Throughput::Bytes(size as u64));
+        for codec in Codec::ALL {
+            let mut frame = BytesMut::new();
+            res.encode_frame_with(codec, &mut frame).unwrap();
+            eprintln!("{:?}: {} -> {} bytes", codec, size, frame.len());
+
+            group.bench_with_input(
+                BenchmarkId::from_parameter(format!("{:?}", codec)),
+                &frame,
+                |b, frame| {
+                    b.iter(|| {
+                        let mut buf = frame.clone();
+                        CommandResponse::decode_frame(&mut buf).unwrap()
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = codec
+}
+criterion_main!(benches);
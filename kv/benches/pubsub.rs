@@ -2,15 +2,13 @@ use anyhow::Result;
 use criterion::{criterion_group, criterion_main, Criterion};
 use futures::StreamExt;
 use kv::{
-    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest,
-    ProstClientStream, ServerConfig, StorageConfig, YamuxCtrl,
+    start_client_with_config, start_server_with_config, ClientConfig, ClientConnection,
+    CommandRequest, ServerConfig, StorageConfig,
 };
 use rand::prelude::SliceRandom;
 use std::time::Duration;
-use tokio::net::TcpStream;
 use tokio::runtime::Builder;
 use tokio::time;
-use tokio_rustls::client::TlsStream;
 use tracing::{info, span};
 use tracing_subscriber::{layer::SubscriberExt, prelude::*, EnvFilter};
 
@@ -27,21 +25,21 @@ async fn start_server() -> Result<()> {
     Ok(())
 }
 
-async fn connect() -> Result<YamuxCtrl<TlsStream<TcpStream>>> {
+async fn connect() -> Result<ClientConnection> {
     let addr = "127.0.0.1:9999";
     let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
     config.general.addr = addr.into();
 
-    Ok(start_client_with_config(&config).await?)
+    start_client_with_config(&config).await
 }
 
 async fn start_subscribers(topic: &'static str) -> Result<()> {
     let mut ctrl = connect().await?;
-    let stream: ProstClientStream<_> = ctrl.open_stream().await?.into();
+    let stream = ctrl.open_stream().await?;
     info!("C(subscriber): stream opened");
     let cmd = CommandRequest::new_subscribe(topic.to_string());
     tokio::spawn(async move {
-        let mut stream = stream.execute_streaming(&cmd).await.unwrap();
+        let mut stream = stream.execute_streaming(cmd).await.unwrap();
         while let Some(Ok(data)) = stream.next().await {
             drop(data);
         }
@@ -55,11 +53,11 @@ async fn start_publishers(topic: &'static str, values: &'static [&'static str])
     let v = values.choose(&mut rng).unwrap();
 
     let mut ctrl = connect().await.unwrap();
-    let mut stream: ProstClientStream<_> = ctrl.open_stream().await.unwrap().into();
+    let mut stream = ctrl.open_stream().await.unwrap();
     info!("C(publisher): stream opened");
 
     let cmd = CommandRequest::new_publish(topic.to_string(), vec![(*v).into()]);
-    stream.execute_unary(&cmd).await.unwrap();
+    stream.execute_unary(cmd).await.unwrap();
 
     Ok(())
 }
@@ -3,6 +3,8 @@ use std::process::Command;
 fn main() {
     prost_build::Config::new()
         .out_dir("src/pb")
+        // bytes 字段使用 Bytes，decode 时可以直接引用 frame 的内存
+        .bytes(["."])
         .type_attribute(".", "#[derive(PartialOrd, serde::Serialize)]")
         // .type_attribute(".", "#[derive(serde::Deserialize, serde::Serialize)]")
         .compile_protos(&["proto/abi.proto"], &["."])
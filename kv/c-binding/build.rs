@@ -0,0 +1,17 @@
+use std::{env, path::Path};
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let bindings = cbindgen::generate(&crate_dir).expect("Unable to generate C bindings");
+    bindings.write_to_file(Path::new(&out_dir).join("kv.h"));
+
+    // 仓库里的 include/kv.h 只在设置了 KV_UPDATE_HEADER 时更新，普通的构建不修改源码目录
+    if env::var_os("KV_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(Path::new(&crate_dir).join("include/kv.h"));
+    }
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=KV_UPDATE_HEADER");
+}
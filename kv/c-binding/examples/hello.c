@@ -0,0 +1,46 @@
+// 编译：cargo build && cc examples/hello.c -Iinclude -Ltarget/debug -lkv_ffi -o target/hello
+// 运行：LD_LIBRARY_PATH=target/debug ./target/hello [client.conf]
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "kv.h"
+
+static void on_message(void *ctx, const uint8_t *data, size_t len) {
+  (void)ctx;
+  printf("received: %.*s\n", (int)len, (const char *)data);
+}
+
+static int check(KvStatus status) {
+  if (status != KV_STATUS_OK) {
+    fprintf(stderr, "kv error %d: %s\n", status, kv_last_error());
+    return -1;
+  }
+  return 0;
+}
+
+int main(int argc, char **argv) {
+  KvStore *store = NULL;
+  if (argc > 1) {
+    if (check(kv_store_from_config(argv[1], &store))) return 1;
+  } else {
+    store = kv_store_memory();
+  }
+
+  const char *value = "world";
+  if (check(kv_hset(store, "t1", "hello", (const uint8_t *)value, strlen(value), NULL))) return 1;
+
+  KvBuffer buf;
+  if (check(kv_hget(store, "t1", "hello", &buf))) return 1;
+  printf("hello: %.*s\n", (int)buf.len, (const char *)buf.data);
+  kv_buffer_free(&buf);
+
+  KvSubscription *sub = NULL;
+  if (check(kv_subscribe(store, "lobby", on_message, NULL, &sub))) return 1;
+  if (check(kv_publish(store, "lobby", (const uint8_t *)"hi", 2))) return 1;
+  usleep(100 * 1000);
+  if (check(kv_unsubscribe(sub))) return 1;
+
+  kv_store_free(store);
+  return 0;
+}
@@ -0,0 +1,328 @@
+#ifndef KV_H
+#define KV_H
+
+/* 由 cbindgen 生成，不要手动修改 */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * 所有函数的返回值，不是 KV_STATUS_OK 时可以用 kv_last_error 获取错误信息
+ */
+typedef enum KvStatus {
+  KV_STATUS_OK = 0,
+  /**
+   * key 不存在
+   */
+  KV_STATUS_NOT_FOUND = 1,
+  /**
+   * 参数是 NULL 或者不是合法的 UTF-8
+   */
+  KV_STATUS_INVALID_ARGUMENT = 2,
+  /**
+   * 和服务器的连接出错，可以重试
+   */
+  KV_STATUS_CONNECTION = 3,
+  /**
+   * 服务器或者存储返回的其它错误
+   */
+  KV_STATUS_ERROR = 4,
+} KvStatus;
+
+/**
+ * KV 存储的句柄，可以嵌入在进程里，也可以连接远端的 kvs
+ *
+ * 句柄可以在多个线程里同时使用，用完之后调用 kv_store_free 释放。
+ */
+typedef struct KvStore KvStore;
+
+/**
+ * 订阅的句柄，调用 kv_unsubscribe 取消订阅并释放
+ */
+typedef struct KvSubscription KvSubscription;
+
+/**
+ * 由 kv 分配的一段字节，用完之后调用 kv_buffer_free 释放
+ *
+ * data 为 NULL 表示值不存在。
+ */
+typedef struct KvBuffer {
+  uint8_t *data;
+  size_t len;
+} KvBuffer;
+
+/**
+ * 一个 kv pair，key 总是存在
+ */
+typedef struct KvPair {
+  struct KvBuffer key;
+  struct KvBuffer value;
+} KvPair;
+
+/**
+ * 由 kv 分配的一组 KvPair，用完之后调用 kv_pairs_free 释放
+ */
+typedef struct KvPairs {
+  struct KvPair *pairs;
+  size_t len;
+} KvPairs;
+
+/**
+ * 由 kv 分配的一组 KvBuffer，用完之后调用 kv_buffers_free 释放
+ */
+typedef struct KvBuffers {
+  struct KvBuffer *items;
+  size_t len;
+} KvBuffers;
+
+/**
+ * 收到数据时的回调，data 只在回调期间有效，值不存在时 data 为 NULL
+ *
+ * 回调在 kv 的后台线程里执行，应该尽快返回，并且不能在回调里调用 kv 的函数。
+ */
+typedef void (*KvSubscribeCallback)(void *ctx, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * 创建一个嵌入在进程里的内存存储
+ */
+struct KvStore *kv_store_memory(void);
+
+/**
+ * 打开一个嵌入在进程里的持久化存储，数据保存在 path 目录下
+ *
+ * # Safety
+ *
+ * path 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+ */
+enum KvStatus kv_store_open(const char *path, struct KvStore **out);
+
+/**
+ * 不使用 TLS，通过 TCP 连接远端的 kvs
+ *
+ * 连接在第一次使用时建立，断开后自动重连。
+ *
+ * # Safety
+ *
+ * addr 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+ */
+enum KvStatus kv_store_connect(const char *addr, struct KvStore **out);
+
+/**
+ * 使用 kvc 的配置文件连接远端的 kvs
+ *
+ * # Safety
+ *
+ * path 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+ */
+enum KvStatus kv_store_from_config(const char *path, struct KvStore **out);
+
+/**
+ * 释放 KvStore，还在进行的订阅不受影响
+ *
+ * # Safety
+ *
+ * store 必须是 NULL 或者由 kv_store_* 返回的句柄，并且只能释放一次。
+ */
+void kv_store_free(struct KvStore *store);
+
+/**
+ * 获取 table 中 key 的值，key 不存在时返回 KV_STATUS_NOT_FOUND
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，out 必须可写。
+ */
+enum KvStatus kv_hget(const struct KvStore *store,
+                      const char *table,
+                      const char *key,
+                      struct KvBuffer *out);
+
+/**
+ * 获取 table 中所有的 kv pair
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 必须是以 \0 结尾的字符串，out 必须可写。
+ */
+enum KvStatus kv_hgetall(const struct KvStore *store, const char *table, struct KvPairs *out);
+
+/**
+ * 获取 table 中一组 key 的值，不存在的 key 对应的 data 为 NULL
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+ * out 必须可写。
+ */
+enum KvStatus kv_hmget(const struct KvStore *store,
+                       const char *table,
+                       const char *const *keys,
+                       size_t len,
+                       struct KvBuffers *out);
+
+/**
+ * 把 table 中 key 的值设置成 value 指向的 len 个字节，值以 binary 的形式保存
+ *
+ * old 不是 NULL 时返回之前的值。
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，
+ * value 必须指向 len 个可读的字节，old 必须是 NULL 或者可写。
+ */
+enum KvStatus kv_hset(const struct KvStore *store,
+                      const char *table,
+                      const char *key,
+                      const uint8_t *value,
+                      size_t len,
+                      struct KvBuffer *old);
+
+/**
+ * 设置 table 中一组 key 的值，第 i 个 key 的值是 values[i] 指向的 lens[i] 个字节
+ *
+ * old 不是 NULL 时按顺序返回之前的值。
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+ * values 和 lens 必须有 len 个元素，old 必须是 NULL 或者可写。
+ */
+enum KvStatus kv_hmset(const struct KvStore *store,
+                       const char *table,
+                       const char *const *keys,
+                       const uint8_t *const *values,
+                       const size_t *lens,
+                       size_t len,
+                       struct KvBuffers *old);
+
+/**
+ * 删除 table 中的 key，old 不是 NULL 时返回之前的值
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，old 必须是 NULL 或者可写。
+ */
+enum KvStatus kv_hdel(const struct KvStore *store,
+                      const char *table,
+                      const char *key,
+                      struct KvBuffer *old);
+
+/**
+ * 删除 table 中的一组 key，old 不是 NULL 时按顺序返回之前的值
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+ * old 必须是 NULL 或者可写。
+ */
+enum KvStatus kv_hmdel(const struct KvStore *store,
+                       const char *table,
+                       const char *const *keys,
+                       size_t len,
+                       struct KvBuffers *old);
+
+/**
+ * 查看 table 中 key 是否存在
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，out 必须可写。
+ */
+enum KvStatus kv_hexist(const struct KvStore *store,
+                        const char *table,
+                        const char *key,
+                        bool *out);
+
+/**
+ * 查看 table 中一组 key 是否都存在
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+ * out 必须可写。
+ */
+enum KvStatus kv_hmexist(const struct KvStore *store,
+                         const char *table,
+                         const char *const *keys,
+                         size_t len,
+                         bool *out);
+
+/**
+ * 向 topic 发布 data 指向的 len 个字节
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，topic 必须是以 \0 结尾的字符串，data 必须指向 len 个可读的字节。
+ */
+enum KvStatus kv_publish(const struct KvStore *store,
+                         const char *topic,
+                         const uint8_t *data,
+                         size_t len);
+
+/**
+ * 释放 kv 返回的 KvBuffer，释放之后 buf 被重置为 NULL
+ *
+ * # Safety
+ *
+ * buf 必须是 NULL 或者由 kv 返回的 KvBuffer。
+ */
+void kv_buffer_free(struct KvBuffer *buf);
+
+/**
+ * 释放 kv 返回的 KvBuffers，释放之后 bufs 被重置为 NULL
+ *
+ * # Safety
+ *
+ * bufs 必须是 NULL 或者由 kv 返回的 KvBuffers。
+ */
+void kv_buffers_free(struct KvBuffers *bufs);
+
+/**
+ * 释放 kv 返回的 KvPairs，释放之后 pairs 被重置为 NULL
+ *
+ * # Safety
+ *
+ * pairs 必须是 NULL 或者由 kv 返回的 KvPairs。
+ */
+void kv_pairs_free(struct KvPairs *pairs);
+
+/**
+ * 当前线程上一次失败的调用的错误信息，没有错误时返回 NULL
+ *
+ * 返回的字符串在当前线程下一次调用失败之前有效，不需要释放。
+ */
+const char *kv_last_error(void);
+
+/**
+ * 订阅 topic，收到的每个值都会调用一次 callback，远端的订阅在重连之后会自动恢复
+ *
+ * # Safety
+ *
+ * store 必须是有效的句柄，topic 必须是以 \0 结尾的字符串，out 必须可写。
+ * callback 和 ctx 在 kv_unsubscribe 返回之前必须有效。
+ */
+enum KvStatus kv_subscribe(const struct KvStore *store,
+                           const char *topic,
+                           KvSubscribeCallback callback,
+                           void *ctx,
+                           struct KvSubscription **out);
+
+/**
+ * 取消订阅并释放 sub，返回之后 callback 不会再被调用
+ *
+ * # Safety
+ *
+ * sub 必须是由 kv_subscribe 返回的句柄，并且只能释放一次，不能在回调里调用。
+ */
+enum KvStatus kv_unsubscribe(struct KvSubscription *sub);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* KV_H */
@@ -0,0 +1,160 @@
+use std::ptr;
+
+use kv::{value, Kvpair, Value};
+
+/// 由 kv 分配的一段字节，用完之后调用 kv_buffer_free 释放
+///
+/// data 为 NULL 表示值不存在。
+#[repr(C)]
+pub struct KvBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// 由 kv 分配的一组 KvBuffer，用完之后调用 kv_buffers_free 释放
+#[repr(C)]
+pub struct KvBuffers {
+    pub items: *mut KvBuffer,
+    pub len: usize,
+}
+
+/// 一个 kv pair，key 总是存在
+#[repr(C)]
+pub struct KvPair {
+    pub key: KvBuffer,
+    pub value: KvBuffer,
+}
+
+/// 由 kv 分配的一组 KvPair，用完之后调用 kv_pairs_free 释放
+#[repr(C)]
+pub struct KvPairs {
+    pub pairs: *mut KvPair,
+    pub len: usize,
+}
+
+impl KvBuffer {
+    pub(crate) fn null() -> Self {
+        Self {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        let data = Box::into_raw(data.into_boxed_slice());
+        Self {
+            len: data.len(),
+            data: data as *mut u8,
+        }
+    }
+
+    /// string 和 binary 返回原始的字节，其它类型返回 Value::format 的结果
+    pub(crate) fn from_value(v: Value) -> Self {
+        match v.value {
+            None | Some(value::Value::Null(_)) => Self::null(),
+            Some(value::Value::String(s)) => Self::new(s.into_bytes()),
+            Some(value::Value::Binary(b)) => Self::new(b.to_vec()),
+            _ => Self::new(v.format().into_bytes()),
+        }
+    }
+
+    unsafe fn free(&mut self) {
+        if !self.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.data, self.len,
+            )));
+        }
+        *self = Self::null();
+    }
+}
+
+impl KvBuffers {
+    pub(crate) fn new(values: Vec<Value>) -> Self {
+        let items: Box<[KvBuffer]> = values.into_iter().map(KvBuffer::from_value).collect();
+        let items = Box::into_raw(items);
+        Self {
+            len: items.len(),
+            items: items as *mut KvBuffer,
+        }
+    }
+
+    pub(crate) fn null() -> Self {
+        Self {
+            items: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+impl KvPairs {
+    pub(crate) fn new(pairs: Vec<Kvpair>) -> Self {
+        let pairs: Box<[KvPair]> = pairs
+            .into_iter()
+            .map(|p| KvPair {
+                key: KvBuffer::new(p.key.into_bytes()),
+                value: KvBuffer::from_value(p.value.unwrap_or_default()),
+            })
+            .collect();
+        let pairs = Box::into_raw(pairs);
+        Self {
+            len: pairs.len(),
+            pairs: pairs as *mut KvPair,
+        }
+    }
+
+    pub(crate) fn null() -> Self {
+        Self {
+            pairs: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+/// 释放 kv 返回的 KvBuffer，释放之后 buf 被重置为 NULL
+///
+/// # Safety
+///
+/// buf 必须是 NULL 或者由 kv 返回的 KvBuffer。
+#[no_mangle]
+pub unsafe extern "C" fn kv_buffer_free(buf: *mut KvBuffer) {
+    if let Some(buf) = buf.as_mut() {
+        buf.free();
+    }
+}
+
+/// 释放 kv 返回的 KvBuffers，释放之后 bufs 被重置为 NULL
+///
+/// # Safety
+///
+/// bufs 必须是 NULL 或者由 kv 返回的 KvBuffers。
+#[no_mangle]
+pub unsafe extern "C" fn kv_buffers_free(bufs: *mut KvBuffers) {
+    let Some(bufs) = bufs.as_mut() else {
+        return;
+    };
+    if !bufs.items.is_null() {
+        let mut items = Box::from_raw(ptr::slice_from_raw_parts_mut(bufs.items, bufs.len));
+        items.iter_mut().for_each(|b| b.free());
+    }
+    *bufs = KvBuffers::null();
+}
+
+/// 释放 kv 返回的 KvPairs，释放之后 pairs 被重置为 NULL
+///
+/// # Safety
+///
+/// pairs 必须是 NULL 或者由 kv 返回的 KvPairs。
+#[no_mangle]
+pub unsafe extern "C" fn kv_pairs_free(pairs: *mut KvPairs) {
+    let Some(pairs) = pairs.as_mut() else {
+        return;
+    };
+    if !pairs.pairs.is_null() {
+        let mut items = Box::from_raw(ptr::slice_from_raw_parts_mut(pairs.pairs, pairs.len));
+        items.iter_mut().for_each(|p| {
+            p.key.free();
+            p.value.free();
+        });
+    }
+    *pairs = KvPairs::null();
+}
@@ -0,0 +1,74 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use kv::KvError;
+
+/// 所有函数的返回值，不是 KV_STATUS_OK 时可以用 kv_last_error 获取错误信息
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvStatus {
+    Ok = 0,
+    /// key 不存在
+    NotFound = 1,
+    /// 参数是 NULL 或者不是合法的 UTF-8
+    InvalidArgument = 2,
+    /// 和服务器的连接出错，可以重试
+    Connection = 3,
+    /// 服务器或者存储返回的其它错误
+    Error = 4,
+}
+
+/// 调用失败时的状态和错误信息
+#[derive(Debug)]
+pub(crate) struct Failure(pub KvStatus, pub String);
+
+impl Failure {
+    pub(crate) fn invalid(msg: impl Into<String>) -> Self {
+        Self(KvStatus::InvalidArgument, msg.into())
+    }
+}
+
+impl From<KvError> for Failure {
+    fn from(e: KvError) -> Self {
+        let status = match e {
+            KvError::NotFound(..) => KvStatus::NotFound,
+            KvError::IoError
+            | KvError::FrameError
+            | KvError::QuicError(_)
+            | KvError::HandshakeError(_)
+            | KvError::TLSError(_) => KvStatus::Connection,
+            _ => KvStatus::Error,
+        };
+        Self(status, e.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// 执行 f，把错误记录到当前线程的 LAST_ERROR 里，panic 不会越过 FFI 的边界
+pub(crate) fn ffi(f: impl FnOnce() -> Result<(), Failure>) -> KvStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return KvStatus::Ok,
+        Ok(Err(e)) => e,
+        Err(_) => Failure(KvStatus::Error, "kv panicked".into()),
+    };
+
+    // 错误信息里不会有 \0，以防万一替换掉
+    let msg = CString::new(failure.1.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+    failure.0
+}
+
+/// 当前线程上一次失败的调用的错误信息，没有错误时返回 NULL
+///
+/// 返回的字符串在当前线程下一次调用失败之前有效，不需要释放。
+#[no_mangle]
+pub extern "C" fn kv_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
@@ -0,0 +1,717 @@
+mod buffer;
+mod error;
+mod subscription;
+
+use std::{
+    ffi::{c_char, CStr},
+    future::Future,
+    slice,
+    sync::OnceLock,
+};
+
+use kv::{
+    ClientConfig, CommandRequest, CommandResponse, GeneralConfig, KvClient, Kvpair, MemTable,
+    Service, SledTable, Store, TransportConfig, Value,
+};
+use tokio::runtime::Runtime;
+
+pub use buffer::*;
+pub use error::*;
+pub use subscription::*;
+
+const NOT_FOUND: u32 = 404;
+
+/// KV 存储的句柄，可以嵌入在进程里，也可以连接远端的 kvs
+///
+/// 句柄可以在多个线程里同时使用，用完之后调用 kv_store_free 释放。
+pub struct KvStore {
+    backend: Store,
+}
+
+/// 执行命令的 tokio 运行时，所有的 KvStore 共享
+pub(crate) fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start tokio runtime"))
+}
+
+/// 创建一个嵌入在进程里的内存存储
+#[no_mangle]
+pub extern "C" fn kv_store_memory() -> *mut KvStore {
+    KvStore::new(Store::Local(Service::new(MemTable::new())))
+}
+
+/// 打开一个嵌入在进程里的持久化存储，数据保存在 path 目录下
+///
+/// # Safety
+///
+/// path 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+#[no_mangle]
+pub unsafe extern "C" fn kv_store_open(path: *const c_char, out: *mut *mut KvStore) -> KvStatus {
+    ffi(|| {
+        let path = str_arg(path, "path")?;
+        let out = out_arg(out, "out")?;
+        let db = sled::open(path)
+            .map_err(|e| Failure(KvStatus::Error, format!("Cannot open {}: {}", path, e)))?;
+        *out = KvStore::new(Store::Local(Service::new(SledTable::new(db))));
+        Ok(())
+    })
+}
+
+/// 不使用 TLS，通过 TCP 连接远端的 kvs
+///
+/// 连接在第一次使用时建立，断开后自动重连。
+///
+/// # Safety
+///
+/// addr 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+#[no_mangle]
+pub unsafe extern "C" fn kv_store_connect(addr: *const c_char, out: *mut *mut KvStore) -> KvStatus {
+    ffi(|| {
+        let addr = str_arg(addr, "addr")?.to_owned();
+        let out = out_arg(out, "out")?;
+        let config = ClientConfig {
+            general: GeneralConfig {
+                addr,
+                transport: TransportConfig::Tcp,
+                max_frame_size: kv::DEFAULT_MAX_FRAME,
+            },
+            tls: None,
+            tracing: kv::TracingConfig::default(),
+            namespace: None,
+        };
+        *out = KvStore::remote(config);
+        Ok(())
+    })
+}
+
+/// 使用 kvc 的配置文件连接远端的 kvs
+///
+/// # Safety
+///
+/// path 必须是以 \0 结尾的字符串，out 必须指向一个可写的 KvStore 指针。
+#[no_mangle]
+pub unsafe extern "C" fn kv_store_from_config(
+    path: *const c_char,
+    out: *mut *mut KvStore,
+) -> KvStatus {
+    ffi(|| {
+        let path = str_arg(path, "path")?;
+        let out = out_arg(out, "out")?;
+        *out = KvStore::remote(ClientConfig::load(path)?);
+        Ok(())
+    })
+}
+
+/// 释放 KvStore，还在进行的订阅不受影响
+///
+/// # Safety
+///
+/// store 必须是 NULL 或者由 kv_store_* 返回的句柄，并且只能释放一次。
+#[no_mangle]
+pub unsafe extern "C" fn kv_store_free(store: *mut KvStore) {
+    if !store.is_null() {
+        let store = Box::from_raw(store);
+        // KvClient 里的连接需要在运行时里关闭
+        let _guard = runtime().enter();
+        drop(store);
+    }
+}
+
+/// 获取 table 中 key 的值，key 不存在时返回 KV_STATUS_NOT_FOUND
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，out 必须可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hget(
+    store: *const KvStore,
+    table: *const c_char,
+    key: *const c_char,
+    out: *mut KvBuffer,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hget(str_arg(table, "table")?, str_arg(key, "key")?);
+        let out = out_arg(out, "out")?;
+        *out = KvBuffer::null();
+
+        let res = store.execute(cmd)?;
+        if res.status == NOT_FOUND {
+            return Err(Failure(KvStatus::NotFound, res.message));
+        }
+        *out = KvBuffer::from_value(first_value(res.into_result()?));
+        Ok(())
+    })
+}
+
+/// 获取 table 中所有的 kv pair
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 必须是以 \0 结尾的字符串，out 必须可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hgetall(
+    store: *const KvStore,
+    table: *const c_char,
+    out: *mut KvPairs,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hgetall(str_arg(table, "table")?);
+        let out = out_arg(out, "out")?;
+        *out = KvPairs::null();
+
+        *out = KvPairs::new(store.run(cmd)?.pairs);
+        Ok(())
+    })
+}
+
+/// 获取 table 中一组 key 的值，不存在的 key 对应的 data 为 NULL
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+/// out 必须可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hmget(
+    store: *const KvStore,
+    table: *const c_char,
+    keys: *const *const c_char,
+    len: usize,
+    out: *mut KvBuffers,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hmget(str_arg(table, "table")?, strs_arg(keys, len, "keys")?);
+        let out = out_arg(out, "out")?;
+        *out = KvBuffers::null();
+
+        *out = KvBuffers::new(store.run(cmd)?.values);
+        Ok(())
+    })
+}
+
+/// 把 table 中 key 的值设置成 value 指向的 len 个字节，值以 binary 的形式保存
+///
+/// old 不是 NULL 时返回之前的值。
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，
+/// value 必须指向 len 个可读的字节，old 必须是 NULL 或者可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hset(
+    store: *const KvStore,
+    table: *const c_char,
+    key: *const c_char,
+    value: *const u8,
+    len: usize,
+    old: *mut KvBuffer,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let value = bytes_arg(value, len, "value")?;
+        let cmd = CommandRequest::new_hset(str_arg(table, "table")?, str_arg(key, "key")?, value);
+
+        let res = store.run(cmd)?;
+        if let Some(old) = old.as_mut() {
+            *old = KvBuffer::from_value(first_value(res));
+        }
+        Ok(())
+    })
+}
+
+/// 设置 table 中一组 key 的值，第 i 个 key 的值是 values[i] 指向的 lens[i] 个字节
+///
+/// old 不是 NULL 时按顺序返回之前的值。
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+/// values 和 lens 必须有 len 个元素，old 必须是 NULL 或者可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hmset(
+    store: *const KvStore,
+    table: *const c_char,
+    keys: *const *const c_char,
+    values: *const *const u8,
+    lens: *const usize,
+    len: usize,
+    old: *mut KvBuffers,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let keys = strs_arg(keys, len, "keys")?;
+        let values = slice_arg(values, len, "values")?;
+        let lens = slice_arg(lens, len, "lens")?;
+        let pairs = keys
+            .into_iter()
+            .zip(values.iter().zip(lens))
+            .map(|(k, (v, len))| Ok(Kvpair::new(k, bytes_arg(*v, *len, "values")?)))
+            .collect::<Result<Vec<_>, Failure>>()?;
+        let cmd = CommandRequest::new_hmset(str_arg(table, "table")?, pairs);
+
+        let res = store.run(cmd)?;
+        if let Some(old) = old.as_mut() {
+            *old = KvBuffers::new(res.values);
+        }
+        Ok(())
+    })
+}
+
+/// 删除 table 中的 key，old 不是 NULL 时返回之前的值
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，old 必须是 NULL 或者可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hdel(
+    store: *const KvStore,
+    table: *const c_char,
+    key: *const c_char,
+    old: *mut KvBuffer,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hdel(str_arg(table, "table")?, str_arg(key, "key")?);
+
+        let res = store.run(cmd)?;
+        if let Some(old) = old.as_mut() {
+            *old = KvBuffer::from_value(first_value(res));
+        }
+        Ok(())
+    })
+}
+
+/// 删除 table 中的一组 key，old 不是 NULL 时按顺序返回之前的值
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+/// old 必须是 NULL 或者可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hmdel(
+    store: *const KvStore,
+    table: *const c_char,
+    keys: *const *const c_char,
+    len: usize,
+    old: *mut KvBuffers,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hmdel(str_arg(table, "table")?, strs_arg(keys, len, "keys")?);
+
+        let res = store.run(cmd)?;
+        if let Some(old) = old.as_mut() {
+            *old = KvBuffers::new(res.values);
+        }
+        Ok(())
+    })
+}
+
+/// 查看 table 中 key 是否存在
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 key 必须是以 \0 结尾的字符串，out 必须可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hexist(
+    store: *const KvStore,
+    table: *const c_char,
+    key: *const c_char,
+    out: *mut bool,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd = CommandRequest::new_hexist(str_arg(table, "table")?, str_arg(key, "key")?);
+        let out = out_arg(out, "out")?;
+
+        *out = store.exists(cmd)?;
+        Ok(())
+    })
+}
+
+/// 查看 table 中一组 key 是否都存在
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，table 和 keys 里的 len 个 key 必须是以 \0 结尾的字符串，
+/// out 必须可写。
+#[no_mangle]
+pub unsafe extern "C" fn kv_hmexist(
+    store: *const KvStore,
+    table: *const c_char,
+    keys: *const *const c_char,
+    len: usize,
+    out: *mut bool,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let cmd =
+            CommandRequest::new_hmexist(str_arg(table, "table")?, strs_arg(keys, len, "keys")?);
+        let out = out_arg(out, "out")?;
+
+        *out = store.exists(cmd)?;
+        Ok(())
+    })
+}
+
+/// 向 topic 发布 data 指向的 len 个字节
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，topic 必须是以 \0 结尾的字符串，data 必须指向 len 个可读的字节。
+#[no_mangle]
+pub unsafe extern "C" fn kv_publish(
+    store: *const KvStore,
+    topic: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let data = bytes_arg(data, len, "data")?;
+        store.run(CommandRequest::new_publish(
+            str_arg(topic, "topic")?,
+            vec![data],
+        ))?;
+        Ok(())
+    })
+}
+
+impl KvStore {
+    fn new(backend: Store) -> *mut KvStore {
+        Box::into_raw(Box::new(KvStore { backend }))
+    }
+
+    fn remote(config: ClientConfig) -> *mut KvStore {
+        let _guard = runtime().enter();
+        Self::new(Store::Remote(KvClient::new(config)))
+    }
+
+    fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, Failure> {
+        Ok(block_on(self.backend.execute(cmd))??)
+    }
+
+    /// 执行命令，把非 2xx 的响应转换成错误
+    fn run(&self, cmd: CommandRequest) -> Result<CommandResponse, Failure> {
+        Ok(block_on(self.backend.run(cmd))??)
+    }
+
+    /// 存在时返回 200，不存在时返回 404
+    fn exists(&self, cmd: CommandRequest) -> Result<bool, Failure> {
+        Ok(block_on(self.backend.exists(cmd))??)
+    }
+}
+
+/// 在运行时上等待 future 完成
+fn block_on<F: Future>(fut: F) -> Result<F::Output, Failure> {
+    check_thread()?;
+    Ok(runtime().block_on(fut))
+}
+
+/// 回调在运行时的线程里执行，不能在那里调用 kv 的函数
+fn check_thread() -> Result<(), Failure> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(Failure::invalid(
+            "kv functions cannot be called from a subscription callback",
+        ));
+    }
+    Ok(())
+}
+
+/// 响应里的第一个值，没有值时返回空值
+fn first_value(res: CommandResponse) -> Value {
+    res.values.into_iter().next().unwrap_or_default()
+}
+
+unsafe fn store_arg<'a>(store: *const KvStore) -> Result<&'a KvStore, Failure> {
+    store
+        .as_ref()
+        .ok_or_else(|| Failure::invalid("store is NULL"))
+}
+
+unsafe fn out_arg<'a, T>(out: *mut T, name: &str) -> Result<&'a mut T, Failure> {
+    out.as_mut()
+        .ok_or_else(|| Failure::invalid(format!("{} is NULL", name)))
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if s.is_null() {
+        return Err(Failure::invalid(format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| Failure::invalid(format!("{} is not valid UTF-8", name)))
+}
+
+unsafe fn slice_arg<'a, T>(items: *const T, len: usize, name: &str) -> Result<&'a [T], Failure> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if items.is_null() {
+        return Err(Failure::invalid(format!("{} is NULL", name)));
+    }
+    Ok(slice::from_raw_parts(items, len))
+}
+
+unsafe fn strs_arg(
+    items: *const *const c_char,
+    len: usize,
+    name: &str,
+) -> Result<Vec<String>, Failure> {
+    slice_arg(items, len, name)?
+        .iter()
+        .map(|s| str_arg(*s, name).map(|s| s.to_owned()))
+        .collect()
+}
+
+unsafe fn bytes_arg(data: *const u8, len: usize, name: &str) -> Result<Value, Failure> {
+    Ok(slice_arg(data, len, name)?.to_vec().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{c_void, CString},
+        ptr,
+        sync::Mutex,
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn bytes(b: &KvBuffer) -> Option<Vec<u8>> {
+        if b.data.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(b.data, b.len) }.to_vec())
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(kv_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn header_should_be_up_to_date() {
+        // 不一致时用 KV_UPDATE_HEADER=1 cargo build 重新生成
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/kv.h"));
+        assert_eq!(generated, include_str!("../include/kv.h"));
+    }
+
+    #[test]
+    fn hset_and_hget_should_work() {
+        let store = kv_store_memory();
+        let (t, k) = (c("t1"), c("hello"));
+        unsafe {
+            let mut old = KvBuffer::null();
+            let status = kv_hset(
+                store,
+                t.as_ptr(),
+                k.as_ptr(),
+                b"world".as_ptr(),
+                5,
+                &mut old,
+            );
+            assert_eq!(status, KvStatus::Ok);
+            assert_eq!(bytes(&old), None);
+
+            let status = kv_hset(store, t.as_ptr(), k.as_ptr(), b"".as_ptr(), 0, &mut old);
+            assert_eq!(status, KvStatus::Ok);
+            assert_eq!(bytes(&old), Some(b"world".to_vec()));
+            kv_buffer_free(&mut old);
+            assert!(old.data.is_null());
+
+            let mut out = KvBuffer::null();
+            assert_eq!(
+                kv_hget(store, t.as_ptr(), k.as_ptr(), &mut out),
+                KvStatus::Ok
+            );
+            assert_eq!(bytes(&out), Some(vec![]));
+            kv_buffer_free(&mut out);
+
+            let missing = c("missing");
+            let status = kv_hget(store, t.as_ptr(), missing.as_ptr(), &mut out);
+            assert_eq!(status, KvStatus::NotFound);
+            assert!(out.data.is_null());
+            assert!(last_error().contains("missing"));
+
+            kv_store_free(store);
+        }
+    }
+
+    #[test]
+    fn batch_commands_should_work() {
+        let store = kv_store_memory();
+        let t = c("t1");
+        let (k1, k2, k3) = (c("k1"), c("k2"), c("k3"));
+        let keys = [k1.as_ptr(), k2.as_ptr()];
+        let values = [b"v1".as_ptr(), b"v2".as_ptr()];
+        unsafe {
+            let status = kv_hmset(
+                store,
+                t.as_ptr(),
+                keys.as_ptr(),
+                values.as_ptr(),
+                [2, 2].as_ptr(),
+                2,
+                ptr::null_mut(),
+            );
+            assert_eq!(status, KvStatus::Ok);
+
+            let mut pairs = KvPairs::null();
+            assert_eq!(kv_hgetall(store, t.as_ptr(), &mut pairs), KvStatus::Ok);
+            let mut got: Vec<_> = slice::from_raw_parts(pairs.pairs, pairs.len)
+                .iter()
+                .map(|p| (bytes(&p.key).unwrap(), bytes(&p.value).unwrap()))
+                .collect();
+            got.sort();
+            assert_eq!(
+                got,
+                vec![
+                    (b"k1".to_vec(), b"v1".to_vec()),
+                    (b"k2".to_vec(), b"v2".to_vec())
+                ]
+            );
+            kv_pairs_free(&mut pairs);
+
+            let mut out = KvBuffers::null();
+            let keys = [k1.as_ptr(), k3.as_ptr()];
+            assert_eq!(
+                kv_hmget(store, t.as_ptr(), keys.as_ptr(), 2, &mut out),
+                KvStatus::Ok
+            );
+            let got: Vec<_> = slice::from_raw_parts(out.items, out.len)
+                .iter()
+                .map(bytes)
+                .collect();
+            assert_eq!(got, vec![Some(b"v1".to_vec()), None]);
+            kv_buffers_free(&mut out);
+
+            let mut exists = true;
+            assert_eq!(
+                kv_hmexist(store, t.as_ptr(), keys.as_ptr(), 2, &mut exists),
+                KvStatus::Ok
+            );
+            assert!(!exists);
+            assert_eq!(
+                kv_hexist(store, t.as_ptr(), k1.as_ptr(), &mut exists),
+                KvStatus::Ok
+            );
+            assert!(exists);
+
+            let keys = [k1.as_ptr(), k2.as_ptr()];
+            assert_eq!(
+                kv_hmdel(store, t.as_ptr(), keys.as_ptr(), 2, &mut out),
+                KvStatus::Ok
+            );
+            assert_eq!(out.len, 2);
+            kv_buffers_free(&mut out);
+            assert_eq!(
+                kv_hexist(store, t.as_ptr(), k2.as_ptr(), &mut exists),
+                KvStatus::Ok
+            );
+            assert!(!exists);
+
+            kv_store_free(store);
+        }
+    }
+
+    #[test]
+    fn values_of_other_types_should_be_formatted() {
+        let store = kv_store_memory();
+        let cmd = CommandRequest::new_hset("t1", "age", 18.into());
+        runtime()
+            .block_on(unsafe { &(*store).backend }.run(cmd))
+            .unwrap();
+
+        let (t, k) = (c("t1"), c("age"));
+        let mut out = KvBuffer::null();
+        unsafe {
+            assert_eq!(
+                kv_hget(store, t.as_ptr(), k.as_ptr(), &mut out),
+                KvStatus::Ok
+            );
+            assert_eq!(bytes(&out), Some(b"18".to_vec()));
+            kv_buffer_free(&mut out);
+            kv_store_free(store);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_should_be_rejected() {
+        let store = kv_store_memory();
+        let k = c("k");
+        let mut out = KvBuffer::null();
+        unsafe {
+            let status = kv_hget(store, ptr::null(), k.as_ptr(), &mut out);
+            assert_eq!(status, KvStatus::InvalidArgument);
+            assert_eq!(last_error(), "table is NULL");
+
+            let bad = CString::new(vec![0xff, 0xfe]).unwrap();
+            let status = kv_hget(store, bad.as_ptr(), k.as_ptr(), &mut out);
+            assert_eq!(status, KvStatus::InvalidArgument);
+            assert_eq!(last_error(), "table is not valid UTF-8");
+
+            let status = kv_hget(ptr::null(), k.as_ptr(), k.as_ptr(), &mut out);
+            assert_eq!(status, KvStatus::InvalidArgument);
+
+            kv_store_free(store);
+        }
+    }
+
+    unsafe extern "C" fn collect(ctx: *mut c_void, data: *const u8, len: usize) {
+        let received = &*(ctx as *const Mutex<Vec<Vec<u8>>>);
+        received
+            .lock()
+            .unwrap()
+            .push(slice::from_raw_parts(data, len).to_vec());
+    }
+
+    #[test]
+    fn subscription_should_call_callback() {
+        let store = kv_store_memory();
+        let topic = c("lobby");
+        let received = Mutex::new(Vec::<Vec<u8>>::new());
+        let ctx = &received as *const _ as *mut c_void;
+        unsafe {
+            let mut sub = ptr::null_mut();
+            let status = kv_subscribe(store, topic.as_ptr(), Some(collect), ctx, &mut sub);
+            assert_eq!(status, KvStatus::Ok);
+
+            assert_eq!(
+                kv_publish(store, topic.as_ptr(), b"hello".as_ptr(), 5),
+                KvStatus::Ok
+            );
+            assert_eq!(
+                kv_publish(store, topic.as_ptr(), b"world".as_ptr(), 5),
+                KvStatus::Ok
+            );
+            for _ in 0..100 {
+                if received.lock().unwrap().len() == 2 {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            assert_eq!(kv_unsubscribe(sub), KvStatus::Ok);
+            assert_eq!(
+                kv_publish(store, topic.as_ptr(), b"late".as_ptr(), 4),
+                KvStatus::Ok
+            );
+            kv_store_free(store);
+        }
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![b"hello".to_vec(), b"world".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::ffi::{c_char, c_void};
+
+use kv::{FeedHandle, Value};
+
+use crate::{
+    block_on, check_thread, ffi, out_arg, runtime, store_arg, str_arg, Failure, KvBuffer, KvStatus,
+};
+
+/// 收到数据时的回调，data 只在回调期间有效，值不存在时 data 为 NULL
+///
+/// 回调在 kv 的后台线程里执行，应该尽快返回，并且不能在回调里调用 kv 的函数。
+pub type KvSubscribeCallback =
+    Option<unsafe extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize)>;
+
+/// 订阅的句柄，调用 kv_unsubscribe 取消订阅并释放
+pub struct KvSubscription {
+    handle: FeedHandle,
+}
+
+/// 回调函数和调用方传进来的上下文
+struct Callback {
+    f: unsafe extern "C" fn(*mut c_void, *const u8, usize),
+    ctx: *mut c_void,
+}
+
+// SAFETY: 调用方保证 ctx 可以在 kv 的后台线程里使用
+unsafe impl Send for Callback {}
+
+impl Callback {
+    fn call(&self, v: Value) {
+        let mut buf = KvBuffer::from_value(v);
+        // SAFETY: 调用方保证 f 和 ctx 在取消订阅之前有效
+        unsafe { (self.f)(self.ctx, buf.data, buf.len) };
+        // SAFETY: buf 由 KvBuffer::from_value 分配
+        unsafe { crate::kv_buffer_free(&mut buf) };
+    }
+}
+
+/// 订阅 topic，收到的每个值都会调用一次 callback，远端的订阅在重连之后会自动恢复
+///
+/// # Safety
+///
+/// store 必须是有效的句柄，topic 必须是以 \0 结尾的字符串，out 必须可写。
+/// callback 和 ctx 在 kv_unsubscribe 返回之前必须有效。
+#[no_mangle]
+pub unsafe extern "C" fn kv_subscribe(
+    store: *const crate::KvStore,
+    topic: *const c_char,
+    callback: KvSubscribeCallback,
+    ctx: *mut c_void,
+    out: *mut *mut KvSubscription,
+) -> KvStatus {
+    ffi(|| {
+        let store = store_arg(store)?;
+        let topic = str_arg(topic, "topic")?.to_owned();
+        let f = callback.ok_or_else(|| Failure::invalid("callback is NULL"))?;
+        let out = out_arg(out, "out")?;
+
+        let feed = block_on(store.backend.subscribe(topic))??;
+        let callback = Callback { f, ctx };
+        let handle = {
+            let _guard = runtime().enter();
+            feed.forward(move |v| {
+                callback.call(v);
+                async {}
+            })
+        };
+        *out = Box::into_raw(Box::new(KvSubscription { handle }));
+        Ok(())
+    })
+}
+
+/// 取消订阅并释放 sub，返回之后 callback 不会再被调用
+///
+/// # Safety
+///
+/// sub 必须是由 kv_subscribe 返回的句柄，并且只能释放一次，不能在回调里调用。
+#[no_mangle]
+pub unsafe extern "C" fn kv_unsubscribe(sub: *mut KvSubscription) -> KvStatus {
+    ffi(|| {
+        if sub.is_null() {
+            return Err(Failure::invalid("sub is NULL"));
+        }
+        check_thread()?;
+        let sub = Box::from_raw(sub);
+        Ok(block_on(sub.handle.unsubscribe())??)
+    })
+}
@@ -1,60 +1,116 @@
 import test from "ava";
+import { mkdtempSync } from "fs";
+import { tmpdir } from "os";
+import { join } from "path";
 
-import { hGetAll, hget, hset } from "../index.js";
+import { KvStore } from "../index.js";
 
+test("hset and hget should work!", async (t) => {
+  const store = KvStore.memory();
 
-test("hset should work!", (t) => {
-  let res = hset("set_t1", "name", "test");
+  t.is(await store.hget("t1", "name"), null);
+  t.is(await store.hset("t1", "name", "test"), null);
+  t.is(await store.hset("t1", "name", "world"), "test");
+  t.is(await store.hget("t1", "name"), "world");
+});
 
-  t.deepEqual(res, {
-    message: "",
-    pairs: [],
-    status: 200,
-    values: [{ value: null }],
-  });
+test("values should keep their types", async (t) => {
+  const store = KvStore.memory();
+  const value = {
+    age: 18,
+    height: 1.75,
+    admin: false,
+    tags: ["a", "b"],
+    avatar: Buffer.from([1, 2, 3]),
+    nothing: null,
+  };
+
+  await store.hset("t1", "user", value);
+  t.deepEqual(await store.hget("t1", "user"), value);
 });
 
-test("hget should work!", (t) => {
-  let res = hget("get_t1", "name");
+test("batch commands should work!", async (t) => {
+  const store = KvStore.memory();
 
-  t.deepEqual(res, {
-    message: "Not found for table: get_t1, key: name",
-    pairs: [],
-    status: 404,
-    values: [],
+  t.deepEqual(await store.hmset("t1", { name: "test", age: 18 }), {
+    name: null,
+    age: null,
+  });
+  t.deepEqual(await store.hmset("t1", { age: 19, name: "new" }), {
+    name: "test",
+    age: 18,
   });
+  await store.hmset("t1", { name: "test", age: 18 });
+  t.deepEqual(await store.hgetall("t1"), { name: "test", age: 18 });
+  t.deepEqual(await store.hmget("t1", ["age", "missing"]), [18, null]);
+  t.true(await store.hexist("t1", "name"));
+  t.false(await store.hmexist("t1", ["name", "missing"]));
+  t.is(await store.hdel("t1", "name"), "test");
+  t.deepEqual(await store.hmdel("t1", ["age"]), [18]);
+  t.deepEqual(await store.hgetall("t1"), {});
+});
+
+test("collection commands should work!", async (t) => {
+  const store = KvStore.memory();
 
-  hset("get_t1", "name", "test");
+  t.is(await store.rpush("t1", "queue", [1, 2, 3]), 3);
+  t.deepEqual(await store.lpop("t1", "queue"), [1]);
+  t.deepEqual(await store.lrange("t1", "queue", 0, -1), [2, 3]);
 
-  res = hget("get_t1", "name");
+  t.is(await store.sadd("t1", "tags", ["b", "a", "b"]), 2);
+  t.deepEqual(await store.smembers("t1", "tags"), ["a", "b"]);
 
-  t.deepEqual(res, {
-    message: "",
-    pairs: [],
-    status: 200,
-    values: [{ value: { String: "test" } }],
+  await store.zadd("t1", "board", [
+    { member: "alice", score: 2 },
+    { member: "bob", score: 1 },
+  ]);
+  t.deepEqual(await store.zrange("t1", "board", 0, -1), [
+    { member: "bob", score: 1 },
+    { member: "alice", score: 2 },
+  ]);
+});
+
+test("errors should be thrown", async (t) => {
+  const store = KvStore.memory();
+
+  await store.hset("t1", "name", "test");
+  await t.throwsAsync(store.lpush("t1", "name", [1]), {
+    message: /expected a list/,
+  });
+  t.throws(() => KvStore.connect({ addr: "127.0.0.1:9527", transport: "udp" }), {
+    message: /Unknown transport/,
   });
 });
 
-test("hGetAll should work!", (t) => {
-  let res = hGetAll("get_all_t1");
+test("subscribe should receive published values", async (t) => {
+  const store = KvStore.memory();
+  const received = [];
+  let done;
+  const both = new Promise((resolve) => (done = resolve));
 
-  t.deepEqual(res, { message: "", pairs: [], status: 200, values: [] });
+  const sub = await store.subscribe("lobby", (value) => {
+    received.push(value);
+    if (received.length === 2) done();
+  });
+  t.is(sub.topic, "lobby");
+  await store.publish("lobby", ["hello", 42]);
+  await both;
 
-  hset("get_all_t1", "name", "test");
-  hset("get_all_t1", "age", 18);
+  // unsubscribe 返回之后订阅已经从 topic 里删除，之后发布的数据收不到
+  await sub.unsubscribe();
+  await store.publish("lobby", ["late"]);
+  t.deepEqual(received, ["hello", 42]);
+});
 
-  res = hGetAll("get_all_t1");
+test("open should persist data", async (t) => {
+  const path = join(mkdtempSync(join(tmpdir(), "kv-")), "db");
 
-  console.log(JSON.stringify(res, null, 2));
+  const store = KvStore.open(path);
+  await store.hset("t1", "name", "test");
+  store.close();
+  await t.throwsAsync(store.hget("t1", "name"), { message: /closed/ });
 
-  t.deepEqual(res, {
-    message: "",
-    pairs: [
-      { key: "name", value: { value: { String: "test" } } },
-      { key: "age", value: { value: { Integer: 18 } } },
-    ],
-    status: 200,
-    values: [],
-  });
+  const reopened = KvStore.open(path);
+  t.is(await reopened.hget("t1", "name"), "test");
+  reopened.close();
 });
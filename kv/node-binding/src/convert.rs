@@ -0,0 +1,135 @@
+use kv::{value, Kvpair, Value};
+use napi::{
+  bindgen_prelude::{FromNapiValue, ToNapiValue, TypeName, ValidateNapiValue},
+  sys, Env, Error, JsBigInt, JsBuffer, JsObject, JsUnknown, NapiRaw, NapiValue, Result, Status,
+  ValueType,
+};
+
+/// JS 的值和 Value 之间的转换
+///
+/// 数字是整数时转换成 Integer，否则转换成 Float；Buffer 对应二进制数据，数组和对象对应 list 和 map。
+pub struct JsValue(pub Value);
+
+/// JS 里能安全表示的最大整数，超过的整数会被当作浮点数
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+impl FromNapiValue for JsValue {
+  unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+    let v = JsUnknown::from_raw_unchecked(env, napi_val);
+    to_value(v).map(JsValue)
+  }
+}
+
+impl ToNapiValue for JsValue {
+  unsafe fn to_napi_value(env: sys::napi_env, val: Self) -> Result<sys::napi_value> {
+    let env = Env::from_raw(env);
+    Ok(to_js(&env, &val.0)?.raw())
+  }
+}
+
+impl TypeName for JsValue {
+  fn type_name() -> &'static str {
+    "unknown"
+  }
+
+  fn value_type() -> ValueType {
+    ValueType::Unknown
+  }
+}
+
+impl ValidateNapiValue for JsValue {}
+
+fn to_value(v: JsUnknown) -> Result<Value> {
+  let value = match v.get_type()? {
+    ValueType::Undefined | ValueType::Null => Value::null(),
+    ValueType::Boolean => v.coerce_to_bool()?.get_value()?.into(),
+    ValueType::Number => {
+      let n = v.coerce_to_number()?.get_double()?;
+      if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER {
+        (n as i64).into()
+      } else {
+        n.into()
+      }
+    }
+    ValueType::BigInt => {
+      let (n, lossless) = unsafe { v.cast::<JsBigInt>() }.get_i64()?;
+      if !lossless {
+        return Err(Error::new(
+          Status::InvalidArg,
+          "BigInt does not fit in 64 bits".to_string(),
+        ));
+      }
+      n.into()
+    }
+    ValueType::String => v.coerce_to_string()?.into_utf8()?.into_owned()?.into(),
+    ValueType::Object if v.is_buffer()? => {
+      let buf = unsafe { v.cast::<JsBuffer>() }.into_value()?;
+      buf.to_vec().into()
+    }
+    ValueType::Object if v.is_array()? => {
+      let arr = unsafe { v.cast::<JsObject>() };
+      (0..arr.get_array_length()?)
+        .map(|i| to_value(arr.get_element::<JsUnknown>(i)?))
+        .collect::<Result<Vec<_>>>()?
+        .into()
+    }
+    ValueType::Object => {
+      let obj = unsafe { v.cast::<JsObject>() };
+      let names = obj.get_property_names()?;
+      (0..names.get_array_length()?)
+        .map(|i| {
+          let name = names
+            .get_element::<JsUnknown>(i)?
+            .coerce_to_string()?
+            .into_utf8()?
+            .into_owned()?;
+          let value = to_value(obj.get_named_property::<JsUnknown>(&name)?)?;
+          Ok(Kvpair::new(name, value))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into()
+    }
+    ty => {
+      return Err(Error::new(
+        Status::InvalidArg,
+        format!("Cannot store a {} in kv", ty),
+      ))
+    }
+  };
+
+  Ok(value)
+}
+
+fn to_js(env: &Env, v: &Value) -> Result<JsUnknown> {
+  let js = match &v.value {
+    None | Some(value::Value::Null(_)) => env.get_null()?.into_unknown(),
+    Some(value::Value::Bool(b)) => env.get_boolean(*b)?.into_unknown(),
+    Some(value::Value::Integer(i)) => env.create_int64(*i)?.into_unknown(),
+    Some(value::Value::Float(f)) => env.create_double(*f)?.into_unknown(),
+    Some(value::Value::String(s)) => env.create_string(s)?.into_unknown(),
+    Some(value::Value::Binary(b)) => env
+      .create_buffer_with_data(b.to_vec())?
+      .into_raw()
+      .into_unknown(),
+    Some(value::Value::List(l)) => {
+      let mut arr = env.create_array_with_length(l.values.len())?;
+      for (i, v) in l.values.iter().enumerate() {
+        arr.set_element(i as u32, to_js(env, v)?)?;
+      }
+      arr.into_unknown()
+    }
+    Some(value::Value::Map(m)) => {
+      let mut obj = env.create_object()?;
+      for p in &m.pairs {
+        let v = match &p.value {
+          Some(v) => to_js(env, v)?,
+          None => env.get_null()?.into_unknown(),
+        };
+        obj.set_named_property(&p.key, v)?;
+      }
+      obj.into_unknown()
+    }
+  };
+
+  Ok(js)
+}
@@ -3,61 +3,498 @@
 #[macro_use]
 extern crate napi_derive;
 
-#[macro_use]
-extern crate lazy_static;
+mod convert;
+mod subscription;
 
-use futures::StreamExt;
-use kv::{CommandRequest, MemTable, Service, Value};
-use napi::bindgen_prelude::Either5;
+use std::{collections::HashMap, sync::Mutex};
 
-lazy_static! {
-  static ref SVC: Service = Service::new(MemTable::new());
-}
+use kv::{
+  value, ClientConfig, ClientOptions, ClientTlsConfig, CommandRequest, CommandResponse,
+  GeneralConfig, KvClient, KvError, Kvpair, MemTable, ScriptLimits, Service, SledTable, Store,
+  TransportConfig, Value,
+};
+use napi::{bindgen_prelude::Buffer, Error, Result, Status};
+
+pub use convert::*;
+pub use subscription::*;
+
+const NOT_FOUND: u32 = 404;
 
+/// KV 存储，可以嵌入在进程里，也可以连接远端的 kvs
+///
+/// 所有的命令都返回 Promise，出错时 reject 一个 Error。
 #[napi]
-pub fn hget(table: String, key: String) -> serde_json::Value {
-  let cmd = CommandRequest::new_hget(table, key);
-  let mut res = SVC.execute(cmd);
+pub struct KvStore {
+  /// close 之后是 None
+  backend: Mutex<Option<Store>>,
+}
 
-  let data = tokio::runtime::Runtime::new()
-    .unwrap()
-    .block_on(async { res.next().await.unwrap() });
+/// 连接 kvs 的参数，证书和私钥都是 PEM 格式的内容
+#[napi(object)]
+pub struct ConnectOptions {
+  /// TCP/QUIC 是 `ip:port`，Unix domain socket 是 socket 文件的路径
+  pub addr: String,
+  /// tcp、quic 或者 unix，默认是 tcp
+  pub transport: Option<String>,
+  /// 服务器证书上的域名，设置之后使用 TLS
+  pub domain: Option<String>,
+  /// 用来验证服务器证书的 CA
+  pub ca: Option<String>,
+  /// 客户端证书，服务器要求客户端认证时使用
+  pub cert: Option<String>,
+  pub key: Option<String>,
+  /// 连接池里的连接数
+  pub pool_size: Option<u32>,
+}
 
-  serde_json::json!(*data)
+/// sorted set 里的一个 member
+#[napi(object)]
+pub struct ScoredMember {
+  pub member: String,
+  pub score: f64,
 }
 
 #[napi]
-pub fn hset(
-  table: String,
-  key: String,
-  value: Either5<String, i64, f64, bool, Vec<u8>>,
-) -> serde_json::Value {
-  let value: Value = match value {
-    Either5::A(a) => a.into(),
-    Either5::B(b) => b.into(),
-    Either5::C(c) => c.into(),
-    Either5::D(d) => d.into(),
-    Either5::E(e) => e.into(),
-  };
+impl KvStore {
+  /// 嵌入在进程里的内存存储
+  #[napi(factory)]
+  pub fn memory() -> Self {
+    Self::local(Service::new(MemTable::new()))
+  }
+
+  /// 嵌入在进程里的持久化存储，数据保存在 path 目录下
+  #[napi(factory)]
+  pub fn open(path: String) -> Result<Self> {
+    let db = sled::open(&path).map_err(|e| {
+      Error::new(
+        Status::GenericFailure,
+        format!("Cannot open {}: {}", path, e),
+      )
+    })?;
+    Ok(Self::local(Service::new(SledTable::new(db))))
+  }
+
+  /// 连接远端的 kvs，连接在第一次使用时建立，断开后自动重连
+  #[napi(factory)]
+  pub fn connect(options: ConnectOptions) -> Result<Self> {
+    let transport = match options.transport.as_deref().unwrap_or("tcp") {
+      "tcp" => TransportConfig::Tcp,
+      "quic" => TransportConfig::Quic,
+      "unix" => TransportConfig::Unix,
+      t => {
+        return Err(Error::new(
+          Status::InvalidArg,
+          format!("Unknown transport {:?}, expected tcp, quic or unix", t),
+        ))
+      }
+    };
+    let identity = match (options.cert, options.key) {
+      (Some(cert), Some(key)) => Some((cert, key)),
+      (None, None) => None,
+      _ => {
+        return Err(Error::new(
+          Status::InvalidArg,
+          "cert and key must be provided together".to_string(),
+        ))
+      }
+    };
+    let tls = options.domain.map(|domain| ClientTlsConfig {
+      domain,
+      identity,
+      ca: options.ca,
+    });
+
+    let config = ClientConfig {
+      general: GeneralConfig {
+        addr: options.addr,
+        transport,
+        max_frame_size: kv::DEFAULT_MAX_FRAME,
+      },
+      tls,
+      tracing: kv::TracingConfig::default(),
+      namespace: None,
+    };
+    let mut client_options = ClientOptions::default();
+    if let Some(size) = options.pool_size {
+      client_options.pool_size = size as usize;
+    }
+    Ok(Self::remote(KvClient::with_options(config, client_options)))
+  }
+
+  /// 使用 kvc 的配置文件连接远端的 kvs
+  #[napi(factory)]
+  pub fn from_config(path: String) -> Result<Self> {
+    let config = ClientConfig::load(&path).map_err(to_napi_error)?;
+    Ok(Self::remote(KvClient::new(config)))
+  }
+
+  /// 获取 table 中 key 的值，key 不存在时返回 null
+  #[napi(ts_return_type = "Promise<unknown>")]
+  pub async fn hget(&self, table: String, key: String) -> Result<Option<JsValue>> {
+    let res = self
+      .backend()?
+      .execute(CommandRequest::new_hget(table, key))
+      .await
+      .map_err(to_napi_error)?;
+    if res.status == NOT_FOUND {
+      return Ok(None);
+    }
+    first_value(res.into_result().map_err(to_napi_error)?).map(Some)
+  }
+
+  /// 获取 table 中所有的 kv pair
+  #[napi(ts_return_type = "Promise<Record<string, unknown>>")]
+  pub async fn hgetall(&self, table: String) -> Result<JsValue> {
+    let res = self.run(CommandRequest::new_hgetall(table)).await?;
+    Ok(JsValue(res.pairs.into()))
+  }
+
+  /// 获取 table 中一组 key 的值，不存在的 key 对应 null
+  #[napi(ts_return_type = "Promise<unknown[]>")]
+  pub async fn hmget(&self, table: String, keys: Vec<String>) -> Result<Vec<JsValue>> {
+    values(self.run(CommandRequest::new_hmget(table, keys)).await?)
+  }
+
+  /// 设置 table 中 key 的值，返回之前的值
+  #[napi(
+    ts_args_type = "table: string, key: string, value: unknown",
+    ts_return_type = "Promise<unknown>"
+  )]
+  pub async fn hset(&self, table: String, key: String, value: JsValue) -> Result<JsValue> {
+    first_value(
+      self
+        .run(CommandRequest::new_hset(table, key, value.0))
+        .await?,
+    )
+  }
+
+  /// 设置 table 中一组 key 的值，返回 key 到之前的值的对象
+  #[napi(
+    ts_args_type = "table: string, pairs: Record<string, unknown>",
+    ts_return_type = "Promise<Record<string, unknown>>"
+  )]
+  pub async fn hmset(&self, table: String, pairs: HashMap<String, JsValue>) -> Result<JsValue> {
+    let (keys, pairs): (Vec<_>, Vec<_>) = pairs
+      .into_iter()
+      .map(|(k, v)| (k.clone(), Kvpair::new(k, v.0)))
+      .unzip();
+    let res = self.run(CommandRequest::new_hmset(table, pairs)).await?;
+    // 响应里的值和请求里的 key 一一对应
+    let old: Vec<_> = keys
+      .into_iter()
+      .zip(res.values)
+      .map(|(k, v)| Kvpair::new(k, v))
+      .collect();
+    Ok(JsValue(old.into()))
+  }
+
+  /// 删除 table 中的 key，返回之前的值
+  #[napi(ts_return_type = "Promise<unknown>")]
+  pub async fn hdel(&self, table: String, key: String) -> Result<JsValue> {
+    first_value(self.run(CommandRequest::new_hdel(table, key)).await?)
+  }
+
+  /// 删除 table 中的一组 key，返回之前的值
+  #[napi(ts_return_type = "Promise<unknown[]>")]
+  pub async fn hmdel(&self, table: String, keys: Vec<String>) -> Result<Vec<JsValue>> {
+    values(self.run(CommandRequest::new_hmdel(table, keys)).await?)
+  }
+
+  /// 查看 table 中 key 是否存在
+  #[napi]
+  pub async fn hexist(&self, table: String, key: String) -> Result<bool> {
+    self.exists(CommandRequest::new_hexist(table, key)).await
+  }
+
+  /// 查看 table 中一组 key 是否都存在
+  #[napi]
+  pub async fn hmexist(&self, table: String, keys: Vec<String>) -> Result<bool> {
+    self.exists(CommandRequest::new_hmexist(table, keys)).await
+  }
+
+  /// 向 topic 发布一组数据
+  #[napi(ts_args_type = "topic: string, values: unknown[]")]
+  pub async fn publish(&self, topic: String, values: Vec<JsValue>) -> Result<()> {
+    let values = values.into_iter().map(|v| v.0).collect();
+    self.run(CommandRequest::new_publish(topic, values)).await?;
+    Ok(())
+  }
+
+  /// 订阅 topic，每收到一个值调用一次 callback
+  #[napi(ts_args_type = "topic: string, callback: (value: unknown) => void")]
+  pub async fn subscribe(&self, topic: String, callback: Callback) -> Result<Subscription> {
+    Subscription::new(&self.backend()?, topic, callback).await
+  }
+
+  /// 在 list 的头部依次插入一组值，返回 list 的长度
+  #[napi(ts_args_type = "table: string, key: string, values: unknown[]")]
+  pub async fn lpush(&self, table: String, key: String, values: Vec<JsValue>) -> Result<i64> {
+    let values = values.into_iter().map(|v| v.0).collect();
+    integer(
+      self
+        .run(CommandRequest::new_lpush(table, key, values))
+        .await?,
+    )
+  }
+
+  /// 在 list 的尾部依次添加一组值，返回 list 的长度
+  #[napi(ts_args_type = "table: string, key: string, values: unknown[]")]
+  pub async fn rpush(&self, table: String, key: String, values: Vec<JsValue>) -> Result<i64> {
+    let values = values.into_iter().map(|v| v.0).collect();
+    integer(
+      self
+        .run(CommandRequest::new_rpush(table, key, values))
+        .await?,
+    )
+  }
+
+  /// 从 list 的头部取出最多 count 个值，count 默认是 1
+  #[napi(ts_return_type = "Promise<unknown[]>")]
+  pub async fn lpop(&self, table: String, key: String, count: Option<u32>) -> Result<Vec<JsValue>> {
+    let cmd = CommandRequest::new_lpop(table, key, count.unwrap_or(1));
+    values(self.run(cmd).await?)
+  }
+
+  /// 从 list 的尾部取出最多 count 个值，count 默认是 1
+  #[napi(ts_return_type = "Promise<unknown[]>")]
+  pub async fn rpop(&self, table: String, key: String, count: Option<u32>) -> Result<Vec<JsValue>> {
+    let cmd = CommandRequest::new_rpop(table, key, count.unwrap_or(1));
+    values(self.run(cmd).await?)
+  }
+
+  /// 返回 list 中下标在 [start, stop] 之间的值，负数从尾部开始计算
+  #[napi(ts_return_type = "Promise<unknown[]>")]
+  pub async fn lrange(
+    &self,
+    table: String,
+    key: String,
+    start: i64,
+    stop: i64,
+  ) -> Result<Vec<JsValue>> {
+    values(
+      self
+        .run(CommandRequest::new_lrange(table, key, start, stop))
+        .await?,
+    )
+  }
+
+  /// 往 set 中添加一组 member，返回新加入的 member 数量
+  #[napi]
+  pub async fn sadd(&self, table: String, key: String, members: Vec<String>) -> Result<i64> {
+    integer(
+      self
+        .run(CommandRequest::new_sadd(table, key, members))
+        .await?,
+    )
+  }
 
-  let cmd = CommandRequest::new_hset(table, key, value);
-  let mut res = SVC.execute(cmd);
+  /// 从 set 中删除一组 member，返回删除的 member 数量
+  #[napi]
+  pub async fn srem(&self, table: String, key: String, members: Vec<String>) -> Result<i64> {
+    integer(
+      self
+        .run(CommandRequest::new_srem(table, key, members))
+        .await?,
+    )
+  }
 
-  let data = tokio::runtime::Runtime::new()
-    .unwrap()
-    .block_on(async { res.next().await.unwrap() });
+  /// 返回 set 中所有的 member
+  #[napi]
+  pub async fn smembers(&self, table: String, key: String) -> Result<Vec<String>> {
+    strings(self.run(CommandRequest::new_smembers(table, key)).await?)
+  }
 
-  serde_json::json!(*data)
+  /// 返回 table 中一组 set 的交集
+  #[napi]
+  pub async fn sinter(&self, table: String, keys: Vec<String>) -> Result<Vec<String>> {
+    strings(self.run(CommandRequest::new_sinter(table, keys)).await?)
+  }
+
+  /// 往 sorted set 中添加一组 member，返回新加入的 member 数量
+  #[napi]
+  pub async fn zadd(&self, table: String, key: String, members: Vec<ScoredMember>) -> Result<i64> {
+    let members = members.into_iter().map(|m| (m.member, m.score)).collect();
+    integer(
+      self
+        .run(CommandRequest::new_zadd(table, key, members))
+        .await?,
+    )
+  }
+
+  /// 按排名返回 sorted set 中 [start, stop] 之间的 member
+  #[napi]
+  pub async fn zrange(
+    &self,
+    table: String,
+    key: String,
+    start: i64,
+    stop: i64,
+  ) -> Result<Vec<ScoredMember>> {
+    scored(
+      self
+        .run(CommandRequest::new_zrange(table, key, start, stop))
+        .await?,
+    )
+  }
+
+  /// 返回 sorted set 中 score 在 [min, max] 之间的 member
+  #[napi]
+  pub async fn zrangebyscore(
+    &self,
+    table: String,
+    key: String,
+    min: f64,
+    max: f64,
+  ) -> Result<Vec<ScoredMember>> {
+    scored(
+      self
+        .run(CommandRequest::new_zrangebyscore(table, key, min, max))
+        .await?,
+    )
+  }
+
+  /// 原子地执行一个 WASM 脚本，返回脚本的输出
+  #[napi(
+    ts_args_type = "script: Buffer, args: unknown[]",
+    ts_return_type = "Promise<unknown[]>"
+  )]
+  pub async fn eval(&self, script: Buffer, args: Vec<JsValue>) -> Result<Vec<JsValue>> {
+    let args = args.into_iter().map(|v| v.0).collect();
+    values(
+      self
+        .run(CommandRequest::new_eval(script.to_vec(), args))
+        .await?,
+    )
+  }
+
+  /// 按 hash 执行之前缓存过的脚本
+  #[napi(
+    ts_args_type = "hash: string, args: unknown[]",
+    ts_return_type = "Promise<unknown[]>"
+  )]
+  pub async fn evalsha(&self, hash: String, args: Vec<JsValue>) -> Result<Vec<JsValue>> {
+    let args = args.into_iter().map(|v| v.0).collect();
+    values(self.run(CommandRequest::new_eval_hash(hash, args)).await?)
+  }
+
+  /// 编译并缓存脚本，返回脚本的 hash
+  #[napi]
+  pub async fn script_load(&self, script: Buffer) -> Result<String> {
+    let res = self
+      .run(CommandRequest::new_script_load(script.to_vec()))
+      .await?;
+    string(first_value(res)?.0)
+  }
+
+  /// 在服务器的备份目录下创建一个备份，不提供 format 时根据扩展名决定
+  #[napi]
+  pub async fn backup(&self, name: String, format: Option<String>) -> Result<()> {
+    let cmd = CommandRequest::new_backup(name, format.unwrap_or_default());
+    self.run(cmd).await?;
+    Ok(())
+  }
+
+  /// 从服务器备份目录下的备份恢复数据，clear 为 true 时先清空所有数据
+  #[napi]
+  pub async fn restore(
+    &self,
+    name: String,
+    format: Option<String>,
+    clear: Option<bool>,
+  ) -> Result<()> {
+    let cmd = CommandRequest::new_restore(name, format.unwrap_or_default(), clear.unwrap_or(false));
+    self.run(cmd).await?;
+    Ok(())
+  }
+
+  /// 存储的统计信息
+  #[napi(ts_return_type = "Promise<Record<string, unknown>>")]
+  pub async fn stats(&self) -> Result<JsValue> {
+    let res = self.run(CommandRequest::new_stats()).await?;
+    Ok(JsValue(res.pairs.into()))
+  }
+
+  /// 关闭存储，之后的命令都会失败；嵌入的持久化存储在订阅和执行中的命令都结束后关闭
+  #[napi]
+  pub fn close(&self) {
+    self.backend.lock().unwrap().take();
+  }
 }
 
-#[napi]
-pub fn h_get_all(table: String) -> serde_json::Value {
-  let cmd = CommandRequest::new_hgetall(table);
-  let mut res = SVC.execute(cmd);
+impl KvStore {
+  /// 嵌入的存储允许执行脚本
+  fn local(service: Service) -> Self {
+    let backend = Store::Local(service.with_scripting(ScriptLimits::default()));
+    Self {
+      backend: Mutex::new(Some(backend)),
+    }
+  }
+
+  fn remote(client: KvClient) -> Self {
+    Self {
+      backend: Mutex::new(Some(Store::Remote(client))),
+    }
+  }
+
+  fn backend(&self) -> Result<Store> {
+    self
+      .backend
+      .lock()
+      .unwrap()
+      .clone()
+      .ok_or_else(|| Error::new(Status::GenericFailure, "store is closed".to_string()))
+  }
+
+  /// 存在时返回 200，不存在时返回 404
+  async fn exists(&self, cmd: CommandRequest) -> Result<bool> {
+    self.backend()?.exists(cmd).await.map_err(to_napi_error)
+  }
 
-  let data = tokio::runtime::Runtime::new()
-    .unwrap()
-    .block_on(async { res.next().await.unwrap() });
+  /// 执行命令，把非 2xx 的响应转换成错误
+  async fn run(&self, cmd: CommandRequest) -> Result<CommandResponse> {
+    self.backend()?.run(cmd).await.map_err(to_napi_error)
+  }
+}
+
+pub(crate) fn to_napi_error(e: KvError) -> Error {
+  Error::new(Status::GenericFailure, e.to_string())
+}
+
+/// 响应里的第一个值，没有值时返回 null
+fn first_value(res: CommandResponse) -> Result<JsValue> {
+  Ok(JsValue(res.values.into_iter().next().unwrap_or_default()))
+}
+
+fn values(res: CommandResponse) -> Result<Vec<JsValue>> {
+  Ok(res.values.into_iter().map(JsValue).collect())
+}
+
+fn integer(res: CommandResponse) -> Result<i64> {
+  i64::try_from(&res).map_err(to_napi_error)
+}
+
+fn strings(res: CommandResponse) -> Result<Vec<String>> {
+  res.values.into_iter().map(string).collect()
+}
+
+/// sorted set 按顺序放在 pairs 里，key 是 member，value 是 score
+fn scored(res: CommandResponse) -> Result<Vec<ScoredMember>> {
+  res
+    .pairs
+    .into_iter()
+    .map(|p| {
+      let score = f64::try_from(p.value.unwrap_or_default()).map_err(to_napi_error)?;
+      Ok(ScoredMember {
+        member: p.key,
+        score,
+      })
+    })
+    .collect()
+}
 
-  serde_json::json!(*data)
+fn string(v: Value) -> Result<String> {
+  match v.value {
+    Some(value::Value::String(s)) => Ok(s),
+    _ => Err(to_napi_error(KvError::ConvertError(v.format(), "String"))),
+  }
 }
@@ -0,0 +1,43 @@
+use kv::{FeedHandle, Store};
+use napi::{
+  threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode},
+  Result,
+};
+
+use crate::{to_napi_error, JsValue};
+
+/// 订阅的回调，每收到一个值调用一次
+pub type Callback = ThreadsafeFunction<JsValue, ErrorStrategy::Fatal>;
+
+/// 一个订阅，收到的数据会传给 subscribe 时提供的回调，调用 unsubscribe 之后停止
+#[napi]
+pub struct Subscription {
+  topic: String,
+  handle: FeedHandle,
+}
+
+#[napi]
+impl Subscription {
+  #[napi(getter)]
+  pub fn topic(&self) -> String {
+    self.topic.clone()
+  }
+
+  /// 取消订阅，之后回调不会再被调用
+  #[napi]
+  pub async fn unsubscribe(&self) -> Result<()> {
+    self.handle.unsubscribe().await.map_err(to_napi_error)
+  }
+}
+
+impl Subscription {
+  /// 订阅 topic，远端的订阅在重连之后会自动恢复
+  pub(crate) async fn new(store: &Store, topic: String, callback: Callback) -> Result<Self> {
+    let feed = store.subscribe(&topic).await.map_err(to_napi_error)?;
+    let handle = feed.forward(move |v| {
+      callback.call(JsValue(v), ThreadsafeFunctionCallMode::NonBlocking);
+      async {}
+    });
+    Ok(Self { topic, handle })
+  }
+}
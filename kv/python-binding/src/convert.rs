@@ -0,0 +1,93 @@
+use kv::{value, Kvpair, Value};
+use pyo3::{
+    exceptions::PyTypeError,
+    prelude::*,
+    types::{
+        IntoPyDict, PyBool, PyByteArray, PyBytes, PyDict, PyFloat, PyList, PyLong, PyString,
+        PyTuple,
+    },
+};
+
+/// Python 的值和 Value 之间的转换
+///
+/// bytes 和 bytearray 对应二进制数据，list 和 tuple 对应 list，key 是 str 的 dict 对应 map。
+pub struct PyValue(pub Value);
+
+impl<'source> FromPyObject<'source> for PyValue {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        to_value(ob).map(PyValue)
+    }
+}
+
+impl IntoPy<PyObject> for PyValue {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        to_py(py, &self.0)
+    }
+}
+
+fn to_value(ob: &PyAny) -> PyResult<Value> {
+    // bool 是 int 的子类，要先判断
+    let value = if ob.is_none() {
+        Value::null()
+    } else if let Ok(b) = ob.downcast::<PyBool>() {
+        b.is_true().into()
+    } else if let Ok(i) = ob.downcast::<PyLong>() {
+        i.extract::<i64>()?.into()
+    } else if let Ok(f) = ob.downcast::<PyFloat>() {
+        f.value().into()
+    } else if let Ok(s) = ob.downcast::<PyString>() {
+        s.to_str()?.into()
+    } else if let Ok(b) = ob.downcast::<PyBytes>() {
+        b.as_bytes().to_vec().into()
+    } else if let Ok(b) = ob.downcast::<PyByteArray>() {
+        b.to_vec().into()
+    } else if let Ok(l) = ob.downcast::<PyList>() {
+        to_list(l.iter())?
+    } else if let Ok(t) = ob.downcast::<PyTuple>() {
+        to_list(t.iter())?
+    } else if let Ok(d) = ob.downcast::<PyDict>() {
+        to_map(d)?.into()
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "Cannot store a {} in kv",
+            ob.get_type().name()?
+        )));
+    };
+
+    Ok(value)
+}
+
+fn to_list<'a>(items: impl Iterator<Item = &'a PyAny>) -> PyResult<Value> {
+    Ok(items.map(to_value).collect::<PyResult<Vec<_>>>()?.into())
+}
+
+/// 把 dict 转换成一组 Kvpair，保持 dict 的顺序
+pub(crate) fn to_map(d: &PyDict) -> PyResult<Vec<Kvpair>> {
+    d.iter()
+        .map(|(k, v)| Ok(Kvpair::new(k.extract::<String>()?, to_value(v)?)))
+        .collect()
+}
+
+fn to_py(py: Python<'_>, v: &Value) -> PyObject {
+    match &v.value {
+        None | Some(value::Value::Null(_)) => py.None(),
+        Some(value::Value::Bool(b)) => b.into_py(py),
+        Some(value::Value::Integer(i)) => i.into_py(py),
+        Some(value::Value::Float(f)) => f.into_py(py),
+        Some(value::Value::String(s)) => s.into_py(py),
+        Some(value::Value::Binary(b)) => PyBytes::new(py, b).into(),
+        Some(value::Value::List(l)) => {
+            PyList::new(py, l.values.iter().map(|v| to_py(py, v))).into()
+        }
+        Some(value::Value::Map(m)) => m
+            .pairs
+            .iter()
+            .map(|p| {
+                let v = p.value.as_ref().map_or_else(|| py.None(), |v| to_py(py, v));
+                (p.key.as_str(), v)
+            })
+            .collect::<Vec<_>>()
+            .into_py_dict(py)
+            .into(),
+    }
+}
@@ -0,0 +1,542 @@
+mod convert;
+mod subscription;
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use kv::{
+    value, ClientConfig, ClientOptions, ClientTlsConfig, CommandRequest, CommandResponse,
+    GeneralConfig, KvClient, MemTable, ScriptLimits, Service, SledTable, Store, TransportConfig,
+    Value,
+};
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+pub use convert::*;
+pub use subscription::*;
+
+create_exception!(pykv, KvError, PyException, "kv 返回的错误");
+
+const NOT_FOUND: u32 = 404;
+
+/// KV 存储，可以嵌入在进程里，也可以连接远端的 kvs
+///
+/// 所有的命令都是 coroutine，出错时抛出 KvError。
+#[pyclass]
+pub struct KvStore {
+    /// close 之后是 None
+    backend: Mutex<Option<Arc<Backend>>>,
+}
+
+/// 把 kv::Store 的错误转换成 KvError 异常
+struct Backend(Store);
+
+#[pymethods]
+impl KvStore {
+    /// 嵌入在进程里的内存存储
+    #[staticmethod]
+    fn memory() -> Self {
+        Self::local(Service::new(MemTable::new()))
+    }
+
+    /// 嵌入在进程里的持久化存储，数据保存在 path 目录下
+    #[staticmethod]
+    fn open(path: &str) -> PyResult<Self> {
+        let db = sled::open(path)
+            .map_err(|e| KvError::new_err(format!("Cannot open {}: {}", path, e)))?;
+        Ok(Self::local(Service::new(SledTable::new(db))))
+    }
+
+    /// 连接远端的 kvs，连接在第一次使用时建立，断开后自动重连
+    ///
+    /// 设置 domain 之后使用 TLS，证书和私钥都是 PEM 格式的内容。
+    #[staticmethod]
+    #[pyo3(signature = (addr, *, transport = "tcp", domain = None, ca = None, cert = None, key = None, pool_size = None))]
+    fn connect(
+        addr: String,
+        transport: &str,
+        domain: Option<String>,
+        ca: Option<String>,
+        cert: Option<String>,
+        key: Option<String>,
+        pool_size: Option<usize>,
+    ) -> PyResult<Self> {
+        let transport = match transport {
+            "tcp" => TransportConfig::Tcp,
+            "quic" => TransportConfig::Quic,
+            "unix" => TransportConfig::Unix,
+            t => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown transport {:?}, expected tcp, quic or unix",
+                    t
+                )))
+            }
+        };
+        let identity = match (cert, key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "cert and key must be provided together",
+                ))
+            }
+        };
+        let tls = domain.map(|domain| ClientTlsConfig {
+            domain,
+            identity,
+            ca,
+        });
+
+        let config = ClientConfig {
+            general: GeneralConfig {
+                addr,
+                transport,
+                max_frame_size: kv::DEFAULT_MAX_FRAME,
+            },
+            tls,
+            tracing: kv::TracingConfig::default(),
+            namespace: None,
+        };
+        let mut options = ClientOptions::default();
+        if let Some(size) = pool_size {
+            options.pool_size = size;
+        }
+        Ok(Self::remote(KvClient::with_options(config, options)))
+    }
+
+    /// 使用 kvc 的配置文件连接远端的 kvs
+    #[staticmethod]
+    fn from_config(path: &str) -> PyResult<Self> {
+        let config = ClientConfig::load(path).map_err(to_py_err)?;
+        Ok(Self::remote(KvClient::new(config)))
+    }
+
+    /// 关闭存储，之后的命令都会抛出 KvError
+    ///
+    /// 嵌入的存储在进行中的命令结束后释放，之后可以重新 open 同一个目录。
+    fn close(&self) {
+        self.backend.lock().unwrap().take();
+    }
+
+    /// 获取 table 中 key 的值，key 不存在时返回 None
+    fn hget<'p>(&self, py: Python<'p>, table: String, key: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            let res = b.execute(CommandRequest::new_hget(table, key)).await?;
+            if res.status == NOT_FOUND {
+                return Ok(PyValue(Value::default()));
+            }
+            first_value(b.check(res)?)
+        })
+    }
+
+    /// 获取 table 中所有的 kv pair
+    fn hgetall<'p>(&self, py: Python<'p>, table: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            let res = b.run(CommandRequest::new_hgetall(table)).await?;
+            Ok(PyValue(res.pairs.into()))
+        })
+    }
+
+    /// 获取 table 中一组 key 的值，不存在的 key 对应 None
+    fn hmget<'p>(&self, py: Python<'p>, table: String, keys: Vec<String>) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_hmget(table, keys)).await?)
+        })
+    }
+
+    /// 设置 table 中 key 的值，返回之前的值
+    fn hset<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        value: PyValue,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            first_value(b.run(CommandRequest::new_hset(table, key, value.0)).await?)
+        })
+    }
+
+    /// 设置 table 中一组 key 的值，按 dict 的顺序返回之前的值
+    fn hmset<'p>(&self, py: Python<'p>, table: String, pairs: &PyDict) -> PyResult<&'p PyAny> {
+        let pairs = to_map(pairs)?;
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_hmset(table, pairs)).await?)
+        })
+    }
+
+    /// 删除 table 中的 key，返回之前的值
+    fn hdel<'p>(&self, py: Python<'p>, table: String, key: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            first_value(b.run(CommandRequest::new_hdel(table, key)).await?)
+        })
+    }
+
+    /// 删除 table 中的一组 key，返回之前的值
+    fn hmdel<'p>(&self, py: Python<'p>, table: String, keys: Vec<String>) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_hmdel(table, keys)).await?)
+        })
+    }
+
+    /// 查看 table 中 key 是否存在
+    fn hexist<'p>(&self, py: Python<'p>, table: String, key: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            b.exists(CommandRequest::new_hexist(table, key)).await
+        })
+    }
+
+    /// 查看 table 中一组 key 是否都存在
+    fn hmexist<'p>(&self, py: Python<'p>, table: String, keys: Vec<String>) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            b.exists(CommandRequest::new_hmexist(table, keys)).await
+        })
+    }
+
+    /// 向 topic 发布一组数据
+    fn publish<'p>(
+        &self,
+        py: Python<'p>,
+        topic: String,
+        values: Vec<PyValue>,
+    ) -> PyResult<&'p PyAny> {
+        let values = values.into_iter().map(|v| v.0).collect();
+        self.spawn(py, |b| async move {
+            b.run(CommandRequest::new_publish(topic, values)).await?;
+            Ok(())
+        })
+    }
+
+    /// 订阅 topic，返回的 Subscription 可以用 `async for` 读取
+    fn subscribe<'p>(&self, py: Python<'p>, topic: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move { Subscription::new(&b.0, topic).await })
+    }
+
+    /// 在 list 的头部依次插入一组值，返回 list 的长度
+    fn lpush<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        values: Vec<PyValue>,
+    ) -> PyResult<&'p PyAny> {
+        let values = values.into_iter().map(|v| v.0).collect();
+        self.spawn(py, |b| async move {
+            integer(b.run(CommandRequest::new_lpush(table, key, values)).await?)
+        })
+    }
+
+    /// 在 list 的尾部依次添加一组值，返回 list 的长度
+    fn rpush<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        values: Vec<PyValue>,
+    ) -> PyResult<&'p PyAny> {
+        let values = values.into_iter().map(|v| v.0).collect();
+        self.spawn(py, |b| async move {
+            integer(b.run(CommandRequest::new_rpush(table, key, values)).await?)
+        })
+    }
+
+    /// 从 list 的头部取出最多 count 个值
+    #[pyo3(signature = (table, key, count = 1))]
+    fn lpop<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        count: u32,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_lpop(table, key, count)).await?)
+        })
+    }
+
+    /// 从 list 的尾部取出最多 count 个值
+    #[pyo3(signature = (table, key, count = 1))]
+    fn rpop<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        count: u32,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_rpop(table, key, count)).await?)
+        })
+    }
+
+    /// 返回 list 中下标在 [start, stop] 之间的值，负数从尾部开始计算
+    fn lrange<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        start: i64,
+        stop: i64,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            values(
+                b.run(CommandRequest::new_lrange(table, key, start, stop))
+                    .await?,
+            )
+        })
+    }
+
+    /// 往 set 中添加一组 member，返回新加入的 member 数量
+    fn sadd<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        members: Vec<String>,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            integer(b.run(CommandRequest::new_sadd(table, key, members)).await?)
+        })
+    }
+
+    /// 从 set 中删除一组 member，返回删除的 member 数量
+    fn srem<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        members: Vec<String>,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            integer(b.run(CommandRequest::new_srem(table, key, members)).await?)
+        })
+    }
+
+    /// 返回 set 中所有的 member
+    fn smembers<'p>(&self, py: Python<'p>, table: String, key: String) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            strings(b.run(CommandRequest::new_smembers(table, key)).await?)
+        })
+    }
+
+    /// 返回 table 中一组 set 的交集
+    fn sinter<'p>(&self, py: Python<'p>, table: String, keys: Vec<String>) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            strings(b.run(CommandRequest::new_sinter(table, keys)).await?)
+        })
+    }
+
+    /// 往 sorted set 中添加一组 member，mapping 是 member 到 score 的 dict
+    fn zadd<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        mapping: &PyDict,
+    ) -> PyResult<&'p PyAny> {
+        let members = mapping
+            .iter()
+            .map(|(m, score)| Ok((m.extract::<String>()?, score.extract::<f64>()?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        self.spawn(py, |b| async move {
+            integer(b.run(CommandRequest::new_zadd(table, key, members)).await?)
+        })
+    }
+
+    /// 按排名返回 sorted set 中 [start, stop] 之间的 (member, score)
+    fn zrange<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        start: i64,
+        stop: i64,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            scored(
+                b.run(CommandRequest::new_zrange(table, key, start, stop))
+                    .await?,
+            )
+        })
+    }
+
+    /// 返回 sorted set 中 score 在 [min, max] 之间的 (member, score)
+    fn zrangebyscore<'p>(
+        &self,
+        py: Python<'p>,
+        table: String,
+        key: String,
+        min: f64,
+        max: f64,
+    ) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            scored(
+                b.run(CommandRequest::new_zrangebyscore(table, key, min, max))
+                    .await?,
+            )
+        })
+    }
+
+    /// 原子地执行一个 WASM 脚本，返回脚本的输出
+    fn eval<'p>(&self, py: Python<'p>, script: &[u8], args: Vec<PyValue>) -> PyResult<&'p PyAny> {
+        let script = script.to_vec();
+        let args = args.into_iter().map(|v| v.0).collect();
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_eval(script, args)).await?)
+        })
+    }
+
+    /// 按 hash 执行之前缓存过的脚本
+    fn evalsha<'p>(&self, py: Python<'p>, hash: String, args: Vec<PyValue>) -> PyResult<&'p PyAny> {
+        let args = args.into_iter().map(|v| v.0).collect();
+        self.spawn(py, |b| async move {
+            values(b.run(CommandRequest::new_eval_hash(hash, args)).await?)
+        })
+    }
+
+    /// 编译并缓存脚本，返回脚本的 hash
+    fn script_load<'p>(&self, py: Python<'p>, script: &[u8]) -> PyResult<&'p PyAny> {
+        let script = script.to_vec();
+        self.spawn(py, |b| async move {
+            let res = b.run(CommandRequest::new_script_load(script)).await?;
+            string(first_value(res)?.0)
+        })
+    }
+
+    /// 在服务器的备份目录下创建一个备份，不提供 format 时根据扩展名决定
+    #[pyo3(signature = (name, format = ""))]
+    fn backup<'p>(&self, py: Python<'p>, name: String, format: &str) -> PyResult<&'p PyAny> {
+        let cmd = CommandRequest::new_backup(name, format);
+        self.spawn(py, |b| async move {
+            b.run(cmd).await?;
+            Ok(())
+        })
+    }
+
+    /// 从服务器备份目录下的备份恢复数据，clear 为 True 时先清空所有数据
+    #[pyo3(signature = (name, format = "", clear = false))]
+    fn restore<'p>(
+        &self,
+        py: Python<'p>,
+        name: String,
+        format: &str,
+        clear: bool,
+    ) -> PyResult<&'p PyAny> {
+        let cmd = CommandRequest::new_restore(name, format, clear);
+        self.spawn(py, |b| async move {
+            b.run(cmd).await?;
+            Ok(())
+        })
+    }
+
+    /// 存储的统计信息
+    fn stats<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        self.spawn(py, |b| async move {
+            let res = b.run(CommandRequest::new_stats()).await?;
+            Ok(PyValue(res.pairs.into()))
+        })
+    }
+}
+
+impl KvStore {
+    /// 嵌入的存储允许执行脚本
+    fn local(service: Service) -> Self {
+        let service = service.with_scripting(ScriptLimits::default());
+        Self {
+            backend: Mutex::new(Some(Arc::new(Backend(Store::Local(service))))),
+        }
+    }
+
+    fn remote(client: KvClient) -> Self {
+        Self {
+            backend: Mutex::new(Some(Arc::new(Backend(Store::Remote(client))))),
+        }
+    }
+
+    /// 在 tokio 运行时上执行命令，返回一个 asyncio 的 awaitable
+    fn spawn<'p, T, F>(
+        &self,
+        py: Python<'p>,
+        f: impl FnOnce(Arc<Backend>) -> F,
+    ) -> PyResult<&'p PyAny>
+    where
+        T: IntoPy<PyObject> + Send + 'static,
+        F: Future<Output = PyResult<T>> + Send + 'static,
+    {
+        let backend = self.backend.lock().unwrap().clone();
+        let backend = backend.ok_or_else(|| KvError::new_err("store is closed"))?;
+        pyo3_asyncio::tokio::future_into_py(py, f(backend))
+    }
+}
+
+impl Backend {
+    async fn execute(&self, cmd: CommandRequest) -> PyResult<CommandResponse> {
+        self.0.execute(cmd).await.map_err(to_py_err)
+    }
+
+    /// 执行命令，把非 2xx 的响应转换成错误
+    async fn run(&self, cmd: CommandRequest) -> PyResult<CommandResponse> {
+        self.0.run(cmd).await.map_err(to_py_err)
+    }
+
+    fn check(&self, res: CommandResponse) -> PyResult<CommandResponse> {
+        res.into_result().map_err(to_py_err)
+    }
+
+    /// 存在时返回 200，不存在时返回 404
+    async fn exists(&self, cmd: CommandRequest) -> PyResult<bool> {
+        self.0.exists(cmd).await.map_err(to_py_err)
+    }
+}
+
+pub(crate) fn to_py_err(e: kv::KvError) -> PyErr {
+    KvError::new_err(e.to_string())
+}
+
+/// 响应里的第一个值，没有值时返回 None
+fn first_value(res: CommandResponse) -> PyResult<PyValue> {
+    Ok(PyValue(res.values.into_iter().next().unwrap_or_default()))
+}
+
+fn values(res: CommandResponse) -> PyResult<Vec<PyValue>> {
+    Ok(res.values.into_iter().map(PyValue).collect())
+}
+
+fn integer(res: CommandResponse) -> PyResult<i64> {
+    i64::try_from(&res).map_err(to_py_err)
+}
+
+fn string(v: Value) -> PyResult<String> {
+    match v.value {
+        Some(value::Value::String(s)) => Ok(s),
+        _ => Err(to_py_err(kv::KvError::ConvertError(v.format(), "String"))),
+    }
+}
+
+fn strings(res: CommandResponse) -> PyResult<Vec<String>> {
+    res.values.into_iter().map(string).collect()
+}
+
+/// sorted set 按顺序放在 pairs 里，key 是 member，value 是 score
+fn scored(res: CommandResponse) -> PyResult<Vec<(String, f64)>> {
+    res.pairs
+        .into_iter()
+        .map(|p| {
+            let score = f64::try_from(p.value.unwrap_or_default()).map_err(to_py_err)?;
+            Ok((p.key, score))
+        })
+        .collect()
+}
+
+#[pymodule]
+fn pykv(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<KvStore>()?;
+    m.add_class::<Subscription>()?;
+    m.add("KvError", py.get_type::<KvError>())?;
+    Ok(())
+}
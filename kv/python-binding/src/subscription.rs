@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use kv::{FeedHandle, Store, Value};
+use pyo3::{exceptions::PyStopAsyncIteration, prelude::*};
+use tokio::sync::mpsc;
+
+use crate::{to_py_err, PyValue};
+
+/// 订阅的数据在本地缓存的条数
+const SUBSCRIPTION_BUF_SIZE: usize = 128;
+
+/// 一个订阅，用 `async for` 读取收到的数据，调用 unsubscribe 之后结束
+///
+/// 订阅对象被回收时也会取消订阅。
+#[pyclass]
+pub struct Subscription {
+    #[pyo3(get)]
+    topic: String,
+    rx: Arc<tokio::sync::Mutex<mpsc::Receiver<Value>>>,
+    handle: Arc<FeedHandle>,
+}
+
+#[pymethods]
+impl Subscription {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'p>(&self, py: Python<'p>) -> PyResult<Option<&'p PyAny>> {
+        let rx = self.rx.clone();
+        let next = pyo3_asyncio::tokio::future_into_py(py, async move {
+            match rx.lock().await.recv().await {
+                Some(v) => Ok(PyValue(v)),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })?;
+        Ok(Some(next))
+    }
+
+    /// 取消订阅，已经收到的数据还可以继续读取
+    fn unsubscribe<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let handle = self.handle.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            handle.unsubscribe().await.map_err(to_py_err)
+        })
+    }
+}
+
+impl Subscription {
+    /// 订阅 topic，收到的数据先缓存在 channel 里，channel 满了之后暂停读取
+    pub(crate) async fn new(store: &Store, topic: String) -> PyResult<Self> {
+        let feed = store.subscribe(&topic).await.map_err(to_py_err)?;
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUF_SIZE);
+        let handle = feed.forward(move |v| {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(v).await;
+            }
+        });
+
+        Ok(Self {
+            topic,
+            rx: Arc::new(tokio::sync::Mutex::new(rx)),
+            handle: Arc::new(handle),
+        })
+    }
+}
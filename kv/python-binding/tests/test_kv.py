@@ -0,0 +1,107 @@
+import asyncio
+import tempfile
+import unittest
+
+from pykv import KvError, KvStore
+
+
+class KvStoreTest(unittest.IsolatedAsyncioTestCase):
+    async def test_hset_and_hget(self):
+        store = KvStore.memory()
+
+        self.assertIsNone(await store.hget("t1", "name"))
+        self.assertIsNone(await store.hset("t1", "name", "test"))
+        self.assertEqual(await store.hset("t1", "name", "world"), "test")
+        self.assertEqual(await store.hget("t1", "name"), "world")
+
+    async def test_values_keep_their_types(self):
+        store = KvStore.memory()
+        value = {
+            "age": 18,
+            "height": 1.75,
+            "admin": False,
+            "tags": ["a", "b"],
+            "avatar": b"\x01\x02\x03",
+            "nothing": None,
+        }
+
+        await store.hset("t1", "user", value)
+        self.assertEqual(await store.hget("t1", "user"), value)
+
+        with self.assertRaises(TypeError):
+            await store.hset("t1", "user", object())
+        with self.assertRaises(OverflowError):
+            await store.hset("t1", "user", 1 << 64)
+
+    async def test_batch_commands(self):
+        store = KvStore.memory()
+
+        self.assertEqual(await store.hmset("t1", {"name": "test", "age": 18}), [None, None])
+        self.assertEqual(await store.hgetall("t1"), {"name": "test", "age": 18})
+        self.assertEqual(await store.hmget("t1", ["age", "missing"]), [18, None])
+        self.assertTrue(await store.hexist("t1", "name"))
+        self.assertFalse(await store.hmexist("t1", ["name", "missing"]))
+        self.assertEqual(await store.hdel("t1", "name"), "test")
+        self.assertEqual(await store.hmdel("t1", ["age"]), [18])
+        self.assertEqual(await store.hgetall("t1"), {})
+
+    async def test_collection_commands(self):
+        store = KvStore.memory()
+
+        self.assertEqual(await store.rpush("t1", "queue", [1, 2, 3]), 3)
+        self.assertEqual(await store.lpop("t1", "queue"), [1])
+        self.assertEqual(await store.lrange("t1", "queue", 0, -1), [2, 3])
+
+        self.assertEqual(await store.sadd("t1", "tags", ["b", "a", "b"]), 2)
+        self.assertEqual(await store.smembers("t1", "tags"), ["a", "b"])
+
+        await store.zadd("t1", "board", {"alice": 2, "bob": 1})
+        self.assertEqual(
+            await store.zrange("t1", "board", 0, -1), [("bob", 1.0), ("alice", 2.0)]
+        )
+
+    async def test_errors_are_raised(self):
+        store = KvStore.memory()
+
+        await store.hset("t1", "name", "test")
+        with self.assertRaisesRegex(KvError, "expected a list"):
+            await store.lpush("t1", "name", [1])
+        with self.assertRaisesRegex(ValueError, "Unknown transport"):
+            KvStore.connect("127.0.0.1:9527", transport="udp")
+
+    async def test_subscription_is_an_async_iterator(self):
+        store = KvStore.memory()
+        sub = await store.subscribe("lobby")
+        self.assertEqual(sub.topic, "lobby")
+
+        await store.publish("lobby", ["hello", 42])
+        received = []
+        async for value in sub:
+            received.append(value)
+            if len(received) == 2:
+                await sub.unsubscribe()
+
+        await store.publish("lobby", ["late"])
+        self.assertEqual(received, ["hello", 42])
+
+    async def test_concurrent_commands(self):
+        store = KvStore.memory()
+
+        await asyncio.gather(*(store.hset("t1", f"k{i}", i) for i in range(100)))
+        self.assertEqual(len(await store.hgetall("t1")), 100)
+
+    async def test_open_persists_data(self):
+        with tempfile.TemporaryDirectory() as path:
+            store = KvStore.open(path)
+            await store.hset("t1", "name", "test")
+            store.close()
+            with self.assertRaisesRegex(KvError, "closed"):
+                await store.hget("t1", "name")
+
+            store = KvStore.open(path)
+            self.assertEqual(await store.hget("t1", "name"), "test")
+            store.close()
+
+
+if __name__ == "__main__":
+    unittest.main()
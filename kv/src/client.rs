@@ -1,69 +1,937 @@
-use std::time::Duration;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use futures::StreamExt;
-use kv::{start_client_with_config, ClientConfig, CommandRequest, KvError, ProstClientStream};
-use tokio::time;
-use tokio_util::compat::Compat;
-use tracing::info;
+use kv::{
+    enable_request_spans, init_tracer, parse_override, start_client_with_config, to_value, value,
+    ClientConfig, ClientConnection, CommandRequest, CommandResponse, ConfigLoader, Kvpair, Value,
+};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    history::DefaultHistory,
+    Context, Editor, Helper, Highlighter, Hinter, Validator,
+};
+use serde_json::json;
+use tokio::{signal, task};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+/// KV 命令行客户端，不带子命令时进入交互模式
+#[derive(Parser, Debug)]
+#[command(name = "kvc", version, about)]
+struct Cli {
+    /// 配置文件路径，不指定时使用内置的配置；`KV_CLIENT_*` 环境变量会覆盖其中的字段
+    #[arg(short, long, env = "KV_CLIENT_CONFIG")]
+    config: Option<String>,
+
+    /// 服务器地址，覆盖配置文件里的地址
+    #[arg(short, long)]
+    addr: Option<String>,
+
+    /// 传输方式，覆盖配置文件里的传输方式
+    #[arg(short, long, value_enum)]
+    transport: Option<Transport>,
+
+    /// 覆盖任意字段，格式为 key=value，比如 `tls.domain=kv.acme.inc`
+    #[arg(long = "set", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+
+    /// 输出格式
+    #[arg(short, long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// REPL 里的一行命令
+#[derive(Parser, Debug)]
+#[command(name = "", no_binary_name = true)]
+struct Line {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+enum Command {
+    /// 获取 table 中 key 的值
+    Hget { table: String, key: String },
+    /// 获取 table 中所有的 kv pair
+    Hgetall { table: String },
+    /// 获取 table 中一组 key 的值
+    Hmget {
+        table: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// 设置 table 中 key 的值，返回之前的值
+    Hset {
+        table: String,
+        key: String,
+        value: String,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 设置 table 中一组 key 的值，参数格式为 key=value
+    Hmset {
+        table: String,
+        #[arg(required = true, value_parser = parse_pair)]
+        pairs: Vec<(String, String)>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 删除 table 中的 key，返回之前的值
+    Hdel { table: String, key: String },
+    /// 删除 table 中的一组 key，返回它们之前的值
+    Hmdel {
+        table: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// 查看 table 中 key 是否存在
+    Hexist { table: String, key: String },
+    /// 查看 table 中一组 key 是否存在
+    Hmexist {
+        table: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// 订阅 topic，持续输出收到的数据，Ctrl-C 取消订阅
+    Subscribe { topic: String },
+    /// 取消订阅
+    Unsubscribe { topic: String, id: u32 },
+    /// 向 topic 发布一组数据
+    Publish {
+        topic: String,
+        #[arg(required = true)]
+        values: Vec<String>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 把所有数据备份到服务器备份目录下的文件
+    Backup {
+        name: String,
+        /// jsonl 或者 protobuf，不指定时根据文件扩展名决定
+        #[arg(long, default_value = "")]
+        format: String,
+    },
+    /// 从服务器备份目录下的文件恢复数据
+    Restore {
+        name: String,
+        /// jsonl 或者 protobuf，不指定时根据文件扩展名决定
+        #[arg(long, default_value = "")]
+        format: String,
+        /// 恢复之前先清空所有数据
+        #[arg(long)]
+        clear: bool,
+    },
+    /// 查看存储的统计信息，比如内存占用和淘汰的 key 数量
+    Stats,
+    /// 在 list 的头部依次插入一组值，返回 list 的长度
+    Lpush {
+        table: String,
+        key: String,
+        #[arg(required = true)]
+        values: Vec<String>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 在 list 的尾部依次添加一组值，返回 list 的长度
+    Rpush {
+        table: String,
+        key: String,
+        #[arg(required = true)]
+        values: Vec<String>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 从 list 的头部取出值
+    Lpop {
+        table: String,
+        key: String,
+        /// 最多取出多少个值
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+    /// 从 list 的尾部取出值
+    Rpop {
+        table: String,
+        key: String,
+        /// 最多取出多少个值
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+    /// 返回 list 中下标在 [start, stop] 之间的值，负数从尾部开始计算
+    Lrange {
+        table: String,
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        start: i64,
+        #[arg(allow_hyphen_values = true)]
+        stop: i64,
+    },
+    /// 往 set 中添加一组 member
+    Sadd {
+        table: String,
+        key: String,
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// 从 set 中删除一组 member
+    Srem {
+        table: String,
+        key: String,
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// 返回 set 中所有的 member
+    Smembers { table: String, key: String },
+    /// 返回 table 中一组 set 的交集
+    Sinter {
+        table: String,
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// 往 sorted set 中添加一组 member，参数格式为 member=score
+    Zadd {
+        table: String,
+        key: String,
+        #[arg(required = true, value_parser = parse_scored)]
+        members: Vec<(String, f64)>,
+    },
+    /// 按排名返回 sorted set 中 [start, stop] 之间的 member
+    Zrange {
+        table: String,
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        start: i64,
+        #[arg(allow_hyphen_values = true)]
+        stop: i64,
+    },
+    /// 返回 sorted set 中 score 在 [min, max] 之间的 member，可以使用 -inf 和 inf
+    Zrangebyscore {
+        table: String,
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        min: f64,
+        #[arg(allow_hyphen_values = true)]
+        max: f64,
+    },
+    /// 在服务器上原子地执行一个 WASM 脚本，脚本会被缓存
+    Eval {
+        /// 编译好的 .wasm 文件
+        script: PathBuf,
+        /// 传给脚本的参数
+        args: Vec<String>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 按 hash 执行之前缓存过的脚本
+    Evalsha {
+        hash: String,
+        args: Vec<String>,
+        #[command(flatten)]
+        ty: ValueType,
+    },
+    /// 编译并缓存 WASM 脚本，返回脚本的 hash
+    ScriptLoad { script: PathBuf },
+}
+
+/// 值的类型，默认是字符串
+#[derive(Args, Debug, Clone, Copy, Default, PartialEq)]
+#[group(multiple = false)]
+struct ValueType {
+    /// 值是整数
+    #[arg(long)]
+    int: bool,
+    /// 值是浮点数
+    #[arg(long)]
+    float: bool,
+    /// 值是 true/false
+    #[arg(long = "bool")]
+    boolean: bool,
+    /// 值是二进制数据，`@path` 表示从文件读取
+    #[arg(long)]
+    bytes: bool,
+    /// 值是 JSON，数组和对象会存成 list 和 map
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Transport {
+    Tcp,
+    Quic,
+    Unix,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Output {
+    /// 类似 redis-cli 的文本
+    Text,
+    /// 每个响应输出一行 JSON
+    Json,
+    /// 表格
+    Table,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
+    let cli = Cli::parse();
+    let config = load_config(&cli)?;
 
-    // 打开一个 yamux ctrl
-    let mut ctrl = start_client_with_config(&config).await?;
+    // 配置了 exporter 时，请求会带上 trace context，服务器的 span 和客户端的 span 在同一个 trace 里
+    let opentelemetry = init_tracer(&config.tracing, "kv-client")?
+        .map(|t| tracing_opentelemetry::layer().with_tracer(t));
+    let filter = match opentelemetry.is_some() {
+        true => enable_request_spans(EnvFilter::from_default_env()),
+        false => EnvFilter::from_default_env(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(opentelemetry)
+        .init();
 
-    let channel = "lobby";
-    start_publishing(ctrl.open_stream().await?.into(), channel).await?;
+    let mut conn = start_client_with_config(&config).await?;
+    let res = match cli.command {
+        Some(cmd) => run(&mut conn, cmd, cli.output).await,
+        None => repl(&mut conn, cli.output).await,
+    };
 
-    let mut stream: ProstClientStream<_> = ctrl.open_stream().await?.into();
+    opentelemetry::global::shutdown_tracer_provider();
+    res
+}
+
+fn load_config(cli: &Cli) -> Result<ClientConfig> {
+    let mut loader = match &cli.config {
+        Some(path) => ConfigLoader::new().with_file(path),
+        None => ConfigLoader::new().with_embedded(include_str!("../fixtures/client.conf")),
+    };
+    loader = loader.with_env("KV_CLIENT_");
+
+    if let Some(addr) = &cli.addr {
+        loader = loader.with_override("general.addr", addr);
+    }
+    if let Some(transport) = cli.transport {
+        loader = loader.with_override("general.transport", format!("{:?}", transport));
+    }
+    for (key, value) in &cli.overrides {
+        loader = loader.with_override(key, value);
+    }
 
-    // 生成一个 HSET 命令
-    let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
+    Ok(loader.load()?)
+}
 
-    // 发送 HSET 命令
-    let data = stream.execute_unary(&cmd).await?;
-    info!("Got response {:?}", data);
+/// 执行一个命令并输出结果
+async fn run(conn: &mut ClientConnection, cmd: Command, output: Output) -> Result<()> {
+    let mut stream = conn.open_stream().await?;
 
-    // 生成一个 Subscribe 命令
-    let cmd = CommandRequest::new_subscribe(channel);
-    let mut stream = stream.execute_streaming(&cmd).await?;
-    let id = stream.id;
+    if let Command::Subscribe { topic } = &cmd {
+        let topic = topic.clone();
+        let mut result = stream
+            .execute_streaming(CommandRequest::new_subscribe(&topic))
+            .await?;
+        let id = result.id;
+        println!(
+            "Subscribed to {} with id {}, press Ctrl-C to stop",
+            topic, id
+        );
 
-    start_unsubscribe(ctrl.open_stream().await?.into(), channel, id).await?;
+        loop {
+            tokio::select! {
+                res = result.next() => match res {
+                    Some(res) => println!("{}", format_response(&res?, &[], output)),
+                    None => return Ok(()),
+                },
+                _ = signal::ctrl_c() => break,
+            }
+        }
 
-    while let Some(Ok(data)) = stream.next().await {
-        println!("Got published data: {:?}", data);
+        // 在新的 stream 上取消订阅
+        let mut stream = conn.open_stream().await?;
+        let res = stream
+            .execute_unary(CommandRequest::new_unsubscribe(&topic, id))
+            .await?;
+        println!("{}", format_response(&res, &[], output));
+        return Ok(());
     }
 
-    println!("Done!");
+    let keys = cmd.keys();
+    let res = stream.execute_unary(cmd.into_request()?).await?;
+    println!("{}", format_response(&res, &keys, output));
 
     Ok(())
 }
 
-async fn start_publishing(
-    mut stream: ProstClientStream<Compat<yamux::Stream>>,
-    name: &str,
-) -> Result<(), KvError> {
-    let cmd = CommandRequest::new_publish(name, vec![1.into(), 2.into(), "hello".into()]);
-    time::sleep(Duration::from_millis(1000)).await;
-    let res = stream.execute_unary(&cmd).await.unwrap();
-    println!("Finished publishing: {:?}", res);
+/// 交互模式，支持历史记录和命令补全
+async fn repl(conn: &mut ClientConnection, output: Output) -> Result<()> {
+    let mut rl: Editor<KvcHelper, DefaultHistory> = Editor::new()?;
+    rl.set_helper(Some(KvcHelper::new()));
+
+    let history = env::var("HOME")
+        .map(|home| PathBuf::from(home).join(".kvc_history"))
+        .ok();
+    if let Some(path) = &history {
+        let _ = rl.load_history(path);
+    }
+
+    loop {
+        let line = match task::block_in_place(|| rl.readline("kvc> ")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        rl.add_history_entry(line)?;
+
+        if matches!(line, "exit" | "quit") {
+            break;
+        }
+
+        let words = match shell_words::split(line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("(error) {}", e);
+                continue;
+            }
+        };
+        match Line::try_parse_from(words) {
+            Ok(Line { command }) => {
+                if let Err(e) = run(conn, command, output).await {
+                    eprintln!("(error) {}", e);
+                }
+            }
+            Err(e) => {
+                let _ = e.print();
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        rl.save_history(path)?;
+    }
 
     Ok(())
 }
 
-async fn start_unsubscribe(
-    mut stream: ProstClientStream<Compat<yamux::Stream>>,
-    name: &str,
-    id: u32,
-) -> Result<(), KvError> {
-    let cmd = CommandRequest::new_unsubscribe(name, id as _);
-    time::sleep(Duration::from_millis(2000)).await;
-    let res = stream.execute_unary(&cmd).await.unwrap();
-    println!("Finished unsubscribing: {:?}", res);
+#[derive(Helper, Highlighter, Hinter, Validator)]
+struct KvcHelper {
+    commands: Vec<String>,
+}
 
-    Ok(())
+impl KvcHelper {
+    fn new() -> Self {
+        let mut commands: Vec<_> = Line::command()
+            .get_subcommands()
+            .map(|c| c.get_name().to_string())
+            .collect();
+        commands.extend(["help", "exit", "quit"].map(String::from));
+        commands.sort();
+        commands.dedup();
+
+        Self { commands }
+    }
+}
+
+impl Completer for KvcHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = line[..pos].trim_start();
+        let start = pos - prefix.len();
+
+        // 只补全命令名
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+
+        let candidates = self
+            .commands
+            .iter()
+            .filter(|c| c.starts_with(prefix))
+            .map(|c| Pair {
+                display: c.clone(),
+                replacement: format!("{} ", c),
+            })
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Command {
+    /// 命令涉及的 key，用来标注返回的 values
+    fn keys(&self) -> Vec<String> {
+        match self {
+            Command::Hget { key, .. }
+            | Command::Hset { key, .. }
+            | Command::Hdel { key, .. }
+            | Command::Hexist { key, .. } => vec![key.clone()],
+            Command::Hmget { keys, .. }
+            | Command::Hmdel { keys, .. }
+            | Command::Hmexist { keys, .. } => keys.clone(),
+            Command::Hmset { pairs, .. } => pairs.iter().map(|(k, _)| k.clone()).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn into_request(self) -> Result<CommandRequest> {
+        let cmd = match self {
+            Command::Hget { table, key } => CommandRequest::new_hget(table, key),
+            Command::Hgetall { table } => CommandRequest::new_hgetall(table),
+            Command::Hmget { table, keys } => CommandRequest::new_hmget(table, keys),
+            Command::Hset {
+                table,
+                key,
+                value,
+                ty,
+            } => CommandRequest::new_hset(table, key, ty.parse(&value)?),
+            Command::Hmset { table, pairs, ty } => {
+                let pairs = pairs
+                    .into_iter()
+                    .map(|(k, v)| Ok(Kvpair::new(k, ty.parse(&v)?)))
+                    .collect::<Result<_>>()?;
+                CommandRequest::new_hmset(table, pairs)
+            }
+            Command::Hdel { table, key } => CommandRequest::new_hdel(table, key),
+            Command::Hmdel { table, keys } => CommandRequest::new_hmdel(table, keys),
+            Command::Hexist { table, key } => CommandRequest::new_hexist(table, key),
+            Command::Hmexist { table, keys } => CommandRequest::new_hmexist(table, keys),
+            Command::Subscribe { topic } => CommandRequest::new_subscribe(topic),
+            Command::Unsubscribe { topic, id } => CommandRequest::new_unsubscribe(topic, id),
+            Command::Publish { topic, values, ty } => {
+                let values = values.iter().map(|v| ty.parse(v)).collect::<Result<_>>()?;
+                CommandRequest::new_publish(topic, values)
+            }
+            Command::Backup { name, format } => CommandRequest::new_backup(name, format),
+            Command::Restore {
+                name,
+                format,
+                clear,
+            } => CommandRequest::new_restore(name, format, clear),
+            Command::Stats => CommandRequest::new_stats(),
+            Command::Lpush {
+                table,
+                key,
+                values,
+                ty,
+            } => {
+                let values = values.iter().map(|v| ty.parse(v)).collect::<Result<_>>()?;
+                CommandRequest::new_lpush(table, key, values)
+            }
+            Command::Rpush {
+                table,
+                key,
+                values,
+                ty,
+            } => {
+                let values = values.iter().map(|v| ty.parse(v)).collect::<Result<_>>()?;
+                CommandRequest::new_rpush(table, key, values)
+            }
+            Command::Lpop { table, key, count } => CommandRequest::new_lpop(table, key, count),
+            Command::Rpop { table, key, count } => CommandRequest::new_rpop(table, key, count),
+            Command::Lrange {
+                table,
+                key,
+                start,
+                stop,
+            } => CommandRequest::new_lrange(table, key, start, stop),
+            Command::Sadd {
+                table,
+                key,
+                members,
+            } => CommandRequest::new_sadd(table, key, members),
+            Command::Srem {
+                table,
+                key,
+                members,
+            } => CommandRequest::new_srem(table, key, members),
+            Command::Smembers { table, key } => CommandRequest::new_smembers(table, key),
+            Command::Sinter { table, keys } => CommandRequest::new_sinter(table, keys),
+            Command::Zadd {
+                table,
+                key,
+                members,
+            } => CommandRequest::new_zadd(table, key, members),
+            Command::Zrange {
+                table,
+                key,
+                start,
+                stop,
+            } => CommandRequest::new_zrange(table, key, start, stop),
+            Command::Zrangebyscore {
+                table,
+                key,
+                min,
+                max,
+            } => CommandRequest::new_zrangebyscore(table, key, min, max),
+            Command::Eval { script, args, ty } => {
+                let args = args.iter().map(|v| ty.parse(v)).collect::<Result<_>>()?;
+                CommandRequest::new_eval(read_script(&script)?, args)
+            }
+            Command::Evalsha { hash, args, ty } => {
+                let args = args.iter().map(|v| ty.parse(v)).collect::<Result<_>>()?;
+                CommandRequest::new_eval_hash(hash, args)
+            }
+            Command::ScriptLoad { script } => {
+                CommandRequest::new_script_load(read_script(&script)?)
+            }
+        };
+
+        Ok(cmd)
+    }
+}
+
+impl ValueType {
+    /// 按照类型解析命令行上的值
+    fn parse(&self, s: &str) -> Result<Value> {
+        let value = if self.int {
+            s.parse::<i64>()
+                .map_err(|e| anyhow!("invalid integer {:?}: {}", s, e))?
+                .into()
+        } else if self.float {
+            s.parse::<f64>()
+                .map_err(|e| anyhow!("invalid float {:?}: {}", s, e))?
+                .into()
+        } else if self.boolean {
+            s.parse::<bool>()
+                .map_err(|e| anyhow!("invalid bool {:?}: {}", s, e))?
+                .into()
+        } else if self.json {
+            let json: serde_json::Value =
+                serde_json::from_str(s).map_err(|e| anyhow!("invalid json {:?}: {}", s, e))?;
+            to_value(&json)?
+        } else if self.bytes {
+            match s.strip_prefix('@') {
+                Some(path) => fs::read(path)
+                    .map_err(|e| anyhow!("cannot read {}: {}", path, e))?
+                    .into(),
+                None => s.as_bytes().to_vec().into(),
+            }
+        } else {
+            s.into()
+        };
+
+        Ok(value)
+    }
+}
+
+fn read_script(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))
+}
+
+fn parse_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.into(), v.into())),
+        _ => Err(format!("expected key=value, got {:?}", s)),
+    }
+}
+
+fn parse_scored(s: &str) -> Result<(String, f64), String> {
+    match s.rsplit_once('=') {
+        Some((m, score)) if !m.is_empty() => score
+            .parse()
+            .map(|score| (m.into(), score))
+            .map_err(|e| format!("invalid score {:?}: {}", score, e)),
+        _ => Err(format!("expected member=score, got {:?}", s)),
+    }
+}
+
+/// 按照输出格式格式化响应，keys 用来标注 values
+fn format_response(res: &CommandResponse, keys: &[String], output: Output) -> String {
+    match output {
+        Output::Json => json_response(res).to_string(),
+        _ if res.status != 200 => format!("(error) {} {}", res.status, res.message),
+        Output::Text => text_response(res),
+        Output::Table => table_response(res, keys),
+    }
+}
+
+fn text_response(res: &CommandResponse) -> String {
+    if !res.pairs.is_empty() {
+        return res
+            .pairs
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("{}) {:?} => {}", i + 1, p.key, text_value(p.value.as_ref())))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    match res.values.as_slice() {
+        [] => "OK".into(),
+        [v] => text_value(Some(v)),
+        values => values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("{}) {}", i + 1, text_value(Some(v))))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn text_value(v: Option<&Value>) -> String {
+    match v.and_then(|v| v.value.as_ref()) {
+        None => "(nil)".into(),
+        Some(value::Value::String(s)) => format!("{:?}", s),
+        Some(value::Value::Integer(i)) => format!("(integer) {}", i),
+        Some(value::Value::Float(f)) => format!("(float) {}", f),
+        Some(value::Value::Bool(b)) => format!("(bool) {}", b),
+        Some(value::Value::Binary(b)) if b.len() <= 32 => format!("(binary) 0x{}", hex(b)),
+        Some(value::Value::Binary(b)) => format!("(binary) {} bytes", b.len()),
+        Some(value::Value::List(_)) => format!("(list) {}", v.unwrap().format()),
+        Some(value::Value::Map(_)) => format!("(map) {}", v.unwrap().format()),
+        Some(value::Value::Null(_)) => "(null)".into(),
+    }
+}
+
+fn json_response(res: &CommandResponse) -> serde_json::Value {
+    let pairs: Vec<_> = res
+        .pairs
+        .iter()
+        .map(|p| json!({ "key": p.key, "value": json_value(p.value.as_ref()) }))
+        .collect();
+    let values: Vec<_> = res.values.iter().map(|v| json_value(Some(v))).collect();
+
+    json!({
+        "status": res.status,
+        "message": res.message,
+        "values": values,
+        "pairs": pairs,
+    })
+}
+
+fn json_value(v: Option<&Value>) -> serde_json::Value {
+    match v.and_then(|v| v.value.as_ref()) {
+        None => serde_json::Value::Null,
+        Some(value::Value::String(s)) => json!(s),
+        Some(value::Value::Integer(i)) => json!(i),
+        Some(value::Value::Float(f)) => json!(f),
+        Some(value::Value::Bool(b)) => json!(b),
+        // JSON 没有二进制类型，用 base64 表示
+        Some(value::Value::Binary(b)) => json!({ "binary": STANDARD.encode(b) }),
+        Some(value::Value::List(l)) => l.values.iter().map(|v| json_value(Some(v))).collect(),
+        Some(value::Value::Map(m)) => m
+            .pairs
+            .iter()
+            .map(|p| (p.key.clone(), json_value(p.value.as_ref())))
+            .collect(),
+        Some(value::Value::Null(_)) => serde_json::Value::Null,
+    }
+}
+
+fn table_response(res: &CommandResponse, keys: &[String]) -> String {
+    let rows: Vec<[String; 3]> = if res.pairs.is_empty() {
+        res.values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let key = keys.get(i).cloned().unwrap_or_else(|| i.to_string());
+                table_row(key, Some(v))
+            })
+            .collect()
+    } else {
+        res.pairs
+            .iter()
+            .map(|p| table_row(p.key.clone(), p.value.as_ref()))
+            .collect()
+    };
+
+    let header = ["KEY", "TYPE", "VALUE"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (w, col) in widths.iter_mut().zip(row) {
+            *w = (*w).max(col.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(col, w)| format!("{:<w$}", col, w = w))
+                .collect::<Vec<_>>()
+                .join("  ");
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn table_row(key: String, v: Option<&Value>) -> [String; 3] {
+    let (ty, value) = match v.and_then(|v| v.value.as_ref()) {
+        None => ("nil", "".into()),
+        Some(value::Value::String(s)) => ("string", s.clone()),
+        Some(value::Value::Integer(i)) => ("integer", i.to_string()),
+        Some(value::Value::Float(f)) => ("float", f.to_string()),
+        Some(value::Value::Bool(b)) => ("bool", b.to_string()),
+        Some(value::Value::Binary(b)) if b.len() <= 32 => ("binary", format!("0x{}", hex(b))),
+        Some(value::Value::Binary(b)) => ("binary", format!("{} bytes", b.len())),
+        Some(value::Value::List(_)) => ("list", v.unwrap().format()),
+        Some(value::Value::Map(_)) => ("map", v.unwrap().format()),
+        Some(value::Value::Null(_)) => ("null", "".into()),
+    };
+    [key, ty.into(), value]
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_should_parse_typed_values() {
+        let cli = Cli::try_parse_from(["kvc", "hset", "t1", "k1", "42", "--int"]).unwrap();
+        let cmd = cli.command.unwrap().into_request().unwrap();
+        assert_eq!(cmd, CommandRequest::new_hset("t1", "k1", 42.into()));
+
+        let cli = Cli::try_parse_from(["kvc", "hmset", "t1", "a=1.5", "b=2", "--float"]).unwrap();
+        let cmd = cli.command.unwrap().into_request().unwrap();
+        let pairs = vec![Kvpair::new("a", 1.5.into()), Kvpair::new("b", 2.0.into())];
+        assert_eq!(cmd, CommandRequest::new_hmset("t1", pairs));
+
+        // 类型参数只能选一个
+        assert!(Cli::try_parse_from(["kvc", "hset", "t1", "k1", "1", "--int", "--bool"]).is_err());
+        assert!(Cli::try_parse_from(["kvc", "hmset", "t1", "oops"]).is_err());
+
+        let cli =
+            Cli::try_parse_from(["kvc", "hset", "t1", "k1", r#"{"a":[1]}"#, "--json"]).unwrap();
+        let cmd = cli.command.unwrap().into_request().unwrap();
+        let value: Value = vec![Kvpair::new("a", vec![Value::from(1)].into())].into();
+        assert_eq!(cmd, CommandRequest::new_hset("t1", "k1", value));
+    }
+
+    #[test]
+    fn cli_should_parse_collection_commands() {
+        let parse = |args: &[&str]| {
+            let cli = Cli::try_parse_from(args).unwrap();
+            cli.command.unwrap().into_request().unwrap()
+        };
+
+        assert_eq!(
+            parse(&["kvc", "rpush", "t1", "q", "1", "2", "--int"]),
+            CommandRequest::new_rpush("t1", "q", vec![1.into(), 2.into()])
+        );
+        assert_eq!(
+            parse(&["kvc", "lrange", "t1", "q", "0", "-1"]),
+            CommandRequest::new_lrange("t1", "q", 0, -1)
+        );
+        assert_eq!(
+            parse(&["kvc", "zadd", "t1", "board", "alice=1.5", "a=b=2"]),
+            CommandRequest::new_zadd("t1", "board", vec![("alice", 1.5), ("a=b", 2.0)])
+        );
+        assert_eq!(
+            parse(&["kvc", "zrangebyscore", "t1", "board", "-inf", "10"]),
+            CommandRequest::new_zrangebyscore("t1", "board", f64::NEG_INFINITY, 10.0)
+        );
+        assert!(Cli::try_parse_from(["kvc", "zadd", "t1", "board", "alice=high"]).is_err());
+    }
+
+    #[test]
+    fn cli_should_read_script_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("swap.wasm");
+        fs::write(&path, b"\0asm").unwrap();
+        let path = path.to_str().unwrap();
+
+        let cli = Cli::try_parse_from(["kvc", "eval", path, "1", "--int"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_request().unwrap(),
+            CommandRequest::new_eval(&b"\0asm"[..], vec![1.into()])
+        );
+        let cli = Cli::try_parse_from(["kvc", "evalsha", "abc", "x"]).unwrap();
+        assert_eq!(
+            cli.command.unwrap().into_request().unwrap(),
+            CommandRequest::new_eval_hash("abc", vec!["x".into()])
+        );
+        let cli = Cli::try_parse_from(["kvc", "script-load", "missing.wasm"]).unwrap();
+        assert!(cli.command.unwrap().into_request().is_err());
+    }
+
+    #[test]
+    fn bytes_value_should_read_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        fs::write(&path, [0u8, 1, 2]).unwrap();
+
+        let ty = ValueType {
+            bytes: true,
+            ..Default::default()
+        };
+        let value = ty.parse(&format!("@{}", path.display())).unwrap();
+        assert_eq!(value, vec![0u8, 1, 2].into());
+        assert_eq!(ty.parse("abc").unwrap(), b"abc".to_vec().into());
+        assert!(ty.parse("@/no/such/file").is_err());
+    }
+
+    #[test]
+    fn repl_line_should_parse() {
+        let words = shell_words::split("publish lobby 'hello world' 1").unwrap();
+        let Line { command } = Line::try_parse_from(words).unwrap();
+        let values = vec!["hello world".into(), "1".into()];
+        assert_eq!(
+            command.into_request().unwrap(),
+            CommandRequest::new_publish("lobby", values)
+        );
+
+        let helper = KvcHelper::new();
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let (start, candidates) = helper.complete("  hm", 4, &ctx).unwrap();
+        assert_eq!(start, 2);
+        let names: Vec<_> = candidates.iter().map(|c| c.display.as_str()).collect();
+        assert_eq!(names, ["hmdel", "hmexist", "hmget", "hmset"]);
+    }
+
+    #[test]
+    fn response_should_be_formatted() {
+        let values: Vec<Value> = vec!["v1".into(), 1.into(), Value::default()];
+        let res: CommandResponse = values.into();
+        let keys = ["k1", "k2", "k3"].map(String::from);
+
+        assert_eq!(
+            format_response(&res, &keys, Output::Text),
+            "1) \"v1\"\n2) (integer) 1\n3) (nil)"
+        );
+        assert_eq!(
+            format_response(&res, &keys, Output::Table),
+            "KEY  TYPE     VALUE\nk1   string   v1\nk2   integer  1\nk3   nil"
+        );
+
+        let res: CommandResponse = vec![Kvpair::new("k1", b"\x01".to_vec().into())].into();
+        assert_eq!(
+            format_response(&res, &[], Output::Json),
+            r#"{"message":"","pairs":[{"key":"k1","value":{"binary":"AQ=="}}],"status":200,"values":[]}"#
+        );
+
+        let res = CommandResponse {
+            status: 404,
+            message: "Not found".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_response(&res, &[], Output::Table),
+            "(error) 404 Not found"
+        );
+    }
 }
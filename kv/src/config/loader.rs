@@ -0,0 +1,357 @@
+use std::{env, fs, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+use toml::{Table, Value};
+
+use crate::KvError;
+
+/// 可以分层加载的配置
+pub trait LayeredConfig: Serialize + DeserializeOwned {
+    /// 内置的默认值（TOML）
+    const DEFAULTS: &'static str;
+
+    /// 检查每个字段，返回所有的错误，格式是 `字段: 原因`
+    fn validate(&self) -> Vec<String>;
+}
+
+/// 配置的一层
+#[derive(Clone, Debug)]
+enum Layer {
+    /// 内置的 TOML，比如编译进二进制的配置
+    Embedded(&'static str),
+    File(PathBuf),
+    /// 前缀是 prefix 的环境变量
+    Env(String),
+    /// 命令行参数，`a.b = value`
+    Override(String, String),
+}
+
+/// 分层加载配置：默认值 → 配置文件 → 环境变量 → 命令行参数，后面的覆盖前面的
+///
+/// 环境变量去掉前缀之后用 `__` 分隔层级，比如 `KV_SERVER_LOG__LEVEL=debug`
+/// 对应 `log.level`。环境变量和命令行参数的值如果是合法的 TOML 值（数字、布尔、
+/// 数组等）就按 TOML 解析，否则当作字符串。
+#[derive(Clone, Debug, Default)]
+pub struct ConfigLoader {
+    layers: Vec<Layer>,
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一个内置的 TOML 配置
+    pub fn with_embedded(mut self, content: &'static str) -> Self {
+        self.layers.push(Layer::Embedded(content));
+        self
+    }
+
+    /// 添加一个配置文件，多个文件按添加顺序覆盖
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File(path.into()));
+        self
+    }
+
+    /// 读取前缀是 prefix 的环境变量
+    pub fn with_env(mut self, prefix: impl Into<String>) -> Self {
+        self.layers.push(Layer::Env(prefix.into()));
+        self
+    }
+
+    /// 覆盖某个字段，key 用 `.` 分隔层级，比如 `general.addr`
+    pub fn with_override(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.layers.push(Layer::Override(key.into(), value.into()));
+        self
+    }
+
+    /// 所有的配置文件，热更新时需要监控它们
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.layers
+            .iter()
+            .filter_map(|layer| match layer {
+                Layer::File(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 合并所有的层，生成配置并检查每个字段
+    pub fn load<T: LayeredConfig>(&self) -> Result<T, KvError> {
+        let mut table = parse("defaults", T::DEFAULTS)?;
+        for layer in &self.layers {
+            match layer {
+                Layer::Embedded(content) => merge(&mut table, parse("embedded config", content)?),
+                Layer::File(path) => {
+                    let name = path.display().to_string();
+                    let content = fs::read_to_string(path).map_err(|e| {
+                        KvError::InvalidConfig(format!("cannot read {}: {}", name, e))
+                    })?;
+                    merge(&mut table, parse(&name, &content)?);
+                }
+                Layer::Env(prefix) => {
+                    for (key, value) in env::vars() {
+                        // 没有 `__` 的变量不是字段，比如 KV_SERVER_CONFIG
+                        match key.strip_prefix(prefix.as_str()) {
+                            Some(key) if key.contains("__") => {
+                                let key = key.to_lowercase().replace("__", ".");
+                                set(&mut table, &key, &value)?;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Layer::Override(key, value) => set(&mut table, key, value)?,
+            }
+        }
+
+        let config: T =
+            serde_path_to_error::deserialize(Value::Table(table)).map_err(|e| {
+                match e.path().to_string().as_str() {
+                    "." => KvError::InvalidConfig(e.inner().to_string()),
+                    path => KvError::InvalidConfig(format!("{}: {}", path, e.inner())),
+                }
+            })?;
+
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(KvError::InvalidConfig(errors.join("; ")));
+        }
+
+        Ok(config)
+    }
+}
+
+/// 解析命令行里 `key=value` 形式的覆盖项，可以作为 clap 的 value_parser
+pub fn parse_override(s: &str) -> Result<(String, String), KvError> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| KvError::InvalidConfig(format!("expect key=value, got `{}`", s)))?;
+    Ok((key.trim().into(), value.trim().into()))
+}
+
+fn parse(name: &str, content: &str) -> Result<Table, KvError> {
+    content
+        .parse()
+        .map_err(|e| KvError::InvalidConfig(format!("{}: {}", name, e)))
+}
+
+/// 把 other 合并到 table 里，子表递归合并，其他的值直接覆盖
+fn merge(table: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(old)), Value::Table(new)) => merge(old, new),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// 设置 `a.b.c` 对应的字段
+fn set(table: &mut Table, key: &str, value: &str) -> Result<(), KvError> {
+    let mut parts: Vec<_> = key.split('.').collect();
+    let last = parts.pop().unwrap_or_default();
+    if last.is_empty() || parts.iter().any(|p| p.is_empty()) {
+        return Err(KvError::InvalidConfig(format!("invalid key `{}`", key)));
+    }
+
+    let mut table = table;
+    for part in parts {
+        let entry = table
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(t) => t,
+            _ => {
+                return Err(KvError::InvalidConfig(format!(
+                    "{}: `{}` is not a table",
+                    key, part
+                )))
+            }
+        };
+    }
+    table.insert(last.into(), parse_value(value));
+
+    Ok(())
+}
+
+/// 整数、布尔值、带引号的字符串、数组和 inline table 按 TOML 解析，其他的都当作字符串，
+/// 这样 `inf`、`nan` 和 `2024-01-01` 之类的值不会变成浮点数或者日期
+fn parse_value(value: &str) -> Value {
+    let parsed = format!("v = {}", value)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut t| t.remove("v"));
+    match parsed {
+        Some(
+            v @ (Value::Integer(_)
+            | Value::Boolean(_)
+            | Value::String(_)
+            | Value::Array(_)
+            | Value::Table(_)),
+        ) => v,
+        _ => Value::String(value.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tls_utils::CA_CERT, RateLimitConfig, ServerConfig, StorageConfig, TransportConfig,
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn loader_should_merge_layers_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let file1 = dir.path().join("1.toml");
+        let file2 = dir.path().join("2.toml");
+        fs::write(
+            &file1,
+            "[general]\naddr = \"/tmp/kv.sock\"\ntransport = \"Unix\"",
+        )
+        .unwrap();
+        fs::write(&file2, "[log]\nlevel = \"info\"").unwrap();
+
+        env::set_var("KV_TEST_MERGE_LOG__LEVEL", "debug");
+        env::set_var("KV_TEST_MERGE_RATE_LIMIT__REQUESTS_PER_SECOND", "100");
+        let config: ServerConfig = ConfigLoader::new()
+            .with_file(&file1)
+            .with_file(&file2)
+            .with_env("KV_TEST_MERGE_")
+            .with_override("log.level", "warn")
+            .load()
+            .unwrap();
+
+        // 默认值
+        assert_eq!(config.storage, StorageConfig::MemTable);
+        // 配置文件
+        assert_eq!(config.general.addr, "/tmp/kv.sock");
+        assert_eq!(config.general.transport, TransportConfig::Unix);
+        // 环境变量，数字按 TOML 解析
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimitConfig {
+                requests_per_second: 100,
+                burst: None
+            })
+        );
+        // 命令行参数
+        assert_eq!(config.log.level.as_deref(), Some("warn"));
+    }
+
+    #[test]
+    fn loader_should_report_field_errors() {
+        let load = |key: &str, value: &str| {
+            ConfigLoader::new()
+                .with_override("general.transport", "Unix")
+                .with_override(key, value)
+                .load::<ServerConfig>()
+                .unwrap_err()
+                .to_string()
+        };
+
+        let err = load("general.transport", "Udp");
+        assert!(
+            err.contains("general.transport: unknown variant `Udp`"),
+            "{}",
+            err
+        );
+
+        let err = load("general.adr", "/tmp/kv.sock");
+        assert!(err.contains("general.adr: unknown field `adr`"), "{}", err);
+
+        let err = load("general.max_frame_size", "0");
+        assert!(
+            err.contains("general.max_frame_size: must be between 1 and"),
+            "{}",
+            err
+        );
+
+        let err = load("rate_limit.requests_per_second", "0");
+        assert!(err.contains("rate_limit.requests_per_second: must be greater than 0"));
+
+        let err = load("memory.max_bytes", "0");
+        assert!(
+            err.contains("memory.max_bytes: must be greater than 0"),
+            "{}",
+            err
+        );
+
+        let err = load("memory.eviction", "Fifo");
+        assert!(
+            err.contains("memory.eviction: unknown variant `Fifo`"),
+            "{}",
+            err
+        );
+
+        let err = load("script.fuel", "0");
+        assert!(
+            err.contains("script.fuel: must be greater than 0"),
+            "{}",
+            err
+        );
+
+        let err = load("log.level", "kv=loud");
+        assert!(
+            err.contains("log.level: invalid filter `kv=loud`"),
+            "{}",
+            err
+        );
+
+        // 只有 BEGIN 行的 PEM 或者类型不对的 PEM 都不行
+        let err = ConfigLoader::new()
+            .with_override("general.transport", "Unix")
+            .with_override(
+                "tls.cert",
+                "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----",
+            )
+            .with_override("tls.key", CA_CERT)
+            .load::<ServerConfig>()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("tls.cert: not a PEM encoded certificate"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("tls.key: not a PEM encoded private key"),
+            "{}",
+            err
+        );
+
+        let err = ConfigLoader::new()
+            .with_file("/no/such/file.toml")
+            .load::<ServerConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("cannot read /no/such/file.toml"));
+    }
+
+    #[test]
+    fn parse_override_should_split_at_first_equal_sign() {
+        assert_eq!(
+            parse_override(" log.level = info,kv=debug ").unwrap(),
+            ("log.level".into(), "info,kv=debug".into())
+        );
+        assert!(parse_override("log.level").is_err());
+    }
+
+    #[test]
+    fn parse_value_should_fallback_to_string() {
+        assert_eq!(parse_value("42"), Value::Integer(42));
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(
+            parse_value(r#"["a", "b"]"#),
+            Value::Array(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(parse_value("127.0.0.1:9527"), "127.0.0.1:9527".into());
+        assert_eq!(parse_value("MemTable"), "MemTable".into());
+        assert_eq!(parse_value(r#""42""#), "42".into());
+        for value in ["inf", "nan", "1.5", "1e3", "2024-01-01", "07:32:00"] {
+            assert_eq!(parse_value(value), value.into());
+        }
+    }
+}
@@ -0,0 +1,794 @@
+mod loader;
+
+pub use loader::*;
+
+use crate::{EvictionPolicy, KvError, ScriptLimits, DEFAULT_MAX_FRAME, MAX_FRAME};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub general: GeneralConfig,
+    pub storage: StorageConfig,
+    /// Unix domain socket 上可以不使用 TLS
+    #[serde(default)]
+    pub tls: Option<ServerTlsConfig>,
+    pub log: LogConfig,
+    /// 访问控制，不配置时允许所有客户端访问
+    #[serde(default)]
+    pub acl: Option<AclConfig>,
+    /// 每个连接的请求速率限制，不配置时不限速
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Backup / Restore 命令使用的目录，不配置时不允许备份和恢复
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// MemTable 的内存上限，不配置时不限制
+    #[serde(default)]
+    pub memory: Option<MemoryConfig>,
+    /// Eval 命令的资源限制，不配置时不允许执行脚本
+    #[serde(default)]
+    pub script: Option<ScriptConfig>,
+    /// OpenTelemetry span 的导出方式，不配置时不导出
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// 多租户的命名空间，不配置时所有客户端共享同一组 table；
+    /// 配置之后不能通过命令备份、恢复数据或者执行脚本
+    #[serde(default)]
+    pub namespaces: Vec<NamespaceConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub general: GeneralConfig,
+    /// Unix domain socket 上可以不使用 TLS
+    #[serde(default)]
+    pub tls: Option<ClientTlsConfig>,
+    /// OpenTelemetry span 的导出方式，不配置时不导出
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// 握手时要求使用的命名空间，不配置时由服务器根据 TLS 身份决定
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GeneralConfig {
+    /// TCP/QUIC 是 `ip:port`，Unix domain socket 是 socket 文件的路径
+    pub addr: String,
+    #[serde(default)]
+    pub transport: TransportConfig,
+    /// 能接收的最大 frame，单位是字节，超过时断开连接
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+}
+
+fn default_max_frame_size() -> usize {
+    DEFAULT_MAX_FRAME
+}
+
+/// 网络传输方式
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum TransportConfig {
+    /// TCP + TLS + yamux
+    #[default]
+    Tcp,
+    /// QUIC，自带 TLS 和多路复用
+    Quic,
+    /// Unix domain socket + yamux，TLS 可选
+    Unix,
+}
+
+impl TransportConfig {
+    /// 传输方式是否必须使用 TLS
+    pub fn require_tls(&self) -> bool {
+        !matches!(self, TransportConfig::Unix)
+    }
+}
+
+/// 没有 Unix domain socket 的平台上使用 Unix 传输时的错误
+pub(crate) const UNIX_UNSUPPORTED: &str = "unix domain socket is not supported on this platform";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    pub path: String,
+    pub rotation: RotationConfig,
+    /// 日志级别，语法和 `RUST_LOG` 相同，不配置时使用 `RUST_LOG`
+    #[serde(default)]
+    pub level: Option<String>,
+    /// 把写命令记录到同一个目录下的 `audit.log`，和 `server.log` 按同样的规则滚动
+    #[serde(default)]
+    pub audit: bool,
+}
+
+impl LogConfig {
+    /// 在日志目录下创建按 rotation 滚动的文件
+    pub fn appender(&self, file_name: &str) -> RollingFileAppender {
+        match self.rotation {
+            RotationConfig::Hourly => rolling::hourly(&self.path, file_name),
+            RotationConfig::Daily => rolling::daily(&self.path, file_name),
+            RotationConfig::Never => rolling::never(&self.path, file_name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum RotationConfig {
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConfig {
+    #[serde(default)]
+    pub exporter: TraceExporter,
+    /// Jaeger agent 的 `host:port`，或者 OTLP collector 的 URL，不配置时使用默认地址
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// 在后台批量导出 span；为 false 时每个 span 结束时同步导出
+    #[serde(default = "default_trace_batch")]
+    pub batch: bool,
+    /// 上报的服务名，不配置时服务器是 `kv-server`，客户端是 `kv-client`
+    #[serde(default)]
+    pub service_name: Option<String>,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            exporter: TraceExporter::default(),
+            endpoint: None,
+            batch: default_trace_batch(),
+            service_name: None,
+        }
+    }
+}
+
+fn default_trace_batch() -> bool {
+    true
+}
+
+/// span 导出到哪里
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum TraceExporter {
+    /// 不导出
+    #[default]
+    None,
+    /// 打印到标准输出，用于调试
+    Stdout,
+    /// 通过 UDP 发送给 Jaeger agent
+    Jaeger,
+    /// 通过 gRPC 发送给 OTLP collector
+    Otlp,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "args", deny_unknown_fields)]
+pub enum StorageConfig {
+    MemTable,
+    SledTable(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AclConfig {
+    /// 允许访问的客户端身份，也就是 TLS 客户端证书里的 CN
+    pub identities: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// 每秒允许的请求数
+    pub requests_per_second: u32,
+    /// 允许突发的请求数，不配置时等于 requests_per_second
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceConfig {
+    /// 只能包含字母、数字、`-` 和 `_`
+    pub name: String,
+    /// 绑定到这个命名空间的客户端身份（TLS 客户端证书里的 CN）；
+    /// 为空时任何客户端都可以在握手时选择这个命名空间
+    #[serde(default)]
+    pub identities: Vec<String>,
+    /// 最多保存的 key 数量，不配置时不限制
+    #[serde(default)]
+    pub max_keys: Option<u64>,
+    /// key 和 value 最多占用的字节数（估算值），不配置时不限制
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// 整个命名空间的请求速率限制，不配置时不限速
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BackupConfig {
+    /// 备份文件都放在这个目录下
+    pub dir: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryConfig {
+    /// 所有 table 加起来最多使用的内存（估算值），超过时淘汰 key
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptConfig {
+    /// 每次执行可以消耗的 fuel，大致相当于执行的指令数
+    #[serde(default = "default_script_fuel")]
+    pub fuel: u64,
+    /// 每次执行可以使用的内存
+    #[serde(default = "default_script_memory")]
+    pub max_memory: u64,
+}
+
+impl ScriptConfig {
+    pub fn limits(&self) -> ScriptLimits {
+        ScriptLimits {
+            fuel: self.fuel,
+            max_memory: self.max_memory as usize,
+        }
+    }
+}
+
+fn default_script_fuel() -> u64 {
+    ScriptLimits::default().fuel
+}
+
+fn default_script_memory() -> u64 {
+    ScriptLimits::default().max_memory as u64
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTlsConfig {
+    pub cert: String,
+    pub key: String,
+    pub ca: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClientTlsConfig {
+    pub domain: String,
+    pub identity: Option<(String, String)>,
+    pub ca: Option<String>,
+}
+
+impl ServerConfig {
+    /// 从默认值和配置文件加载配置
+    pub fn load(path: &str) -> Result<Self, KvError> {
+        ConfigLoader::new().with_file(path).load()
+    }
+
+    /// 列出 new 里必须重启服务器才能生效的修改
+    pub fn restart_required(&self, new: &ServerConfig) -> Vec<&'static str> {
+        let mut fields = vec![];
+        if self.general.addr != new.general.addr {
+            fields.push("general.addr");
+        }
+        if self.general.transport != new.general.transport {
+            fields.push("general.transport");
+        }
+        if self.general.max_frame_size != new.general.max_frame_size {
+            fields.push("general.max_frame_size");
+        }
+        if self.storage != new.storage {
+            fields.push("storage");
+        }
+        if self.log.path != new.log.path {
+            fields.push("log.path");
+        }
+        if self.log.rotation != new.log.rotation {
+            fields.push("log.rotation");
+        }
+        if self.log.audit != new.log.audit {
+            fields.push("log.audit");
+        }
+        if self.backup != new.backup {
+            fields.push("backup");
+        }
+        if self.memory != new.memory {
+            fields.push("memory");
+        }
+        if self.script != new.script {
+            fields.push("script");
+        }
+        if self.tracing != new.tracing {
+            fields.push("tracing");
+        }
+        if self.namespaces != new.namespaces {
+            fields.push("namespaces");
+        }
+        // 证书可以热更新，但是不能打开或者关闭 TLS；QUIC 的 endpoint 创建后证书就固定了
+        match (&self.tls, &new.tls) {
+            (Some(_), None) | (None, Some(_)) => fields.push("tls"),
+            (old, new) if old != new && self.general.transport == TransportConfig::Quic => {
+                fields.push("tls")
+            }
+            _ => {}
+        }
+        fields
+    }
+
+    /// 隐藏私钥，用于打印配置
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if let Some(tls) = config.tls.as_mut() {
+            tls.key = REDACTED.into();
+        }
+        config
+    }
+}
+
+impl ClientConfig {
+    /// 从默认值和配置文件加载配置
+    pub fn load(path: &str) -> Result<Self, KvError> {
+        ConfigLoader::new().with_file(path).load()
+    }
+
+    /// 隐藏私钥，用于打印配置
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if let Some((_, key)) = config.tls.as_mut().and_then(|tls| tls.identity.as_mut()) {
+            *key = REDACTED.into();
+        }
+        config
+    }
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl LayeredConfig for ServerConfig {
+    const DEFAULTS: &'static str = r#"
+        [general]
+        addr = "127.0.0.1:9527"
+        transport = "Tcp"
+
+        [storage]
+        type = "MemTable"
+
+        [log]
+        path = "/tmp/kv-log"
+        rotation = "Daily"
+    "#;
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = validate_general(&self.general, self.tls.is_some());
+
+        if let StorageConfig::SledTable(path) = &self.storage {
+            if path.is_empty() {
+                errors.push("storage.args: path must not be empty".into());
+            }
+        }
+
+        if self.log.path.is_empty() {
+            errors.push("log.path: must not be empty".into());
+        }
+        if let Some(level) = &self.log.level {
+            if let Err(e) = EnvFilter::try_new(level) {
+                errors.push(format!("log.level: invalid filter `{}`: {}", level, e));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            validate_pem(&mut errors, "tls.cert", &tls.cert, "CERTIFICATE");
+            validate_pem(&mut errors, "tls.key", &tls.key, "PRIVATE KEY");
+            if let Some(ca) = &tls.ca {
+                validate_pem(&mut errors, "tls.ca", ca, "CERTIFICATE");
+            }
+        }
+
+        if matches!(&self.backup, Some(backup) if backup.dir.is_empty()) {
+            errors.push("backup.dir: must not be empty".into());
+        }
+
+        if let Some(memory) = &self.memory {
+            if memory.max_bytes == 0 {
+                errors.push("memory.max_bytes: must be greater than 0".into());
+            }
+            if self.storage != StorageConfig::MemTable {
+                errors.push("memory: only applies to MemTable storage".into());
+            }
+        }
+
+        if let Some(script) = &self.script {
+            if script.fuel == 0 {
+                errors.push("script.fuel: must be greater than 0".into());
+            }
+            // 至少要能放下一页（64KiB）内存
+            if script.max_memory < 1 << 16 {
+                errors.push("script.max_memory: must be at least 65536".into());
+            }
+        }
+
+        validate_tracing(&mut errors, &self.tracing);
+
+        if let Some(limit) = &self.rate_limit {
+            validate_rate_limit(&mut errors, "rate_limit", limit);
+        }
+
+        validate_namespaces(&mut errors, &self.namespaces);
+
+        errors
+    }
+}
+
+impl LayeredConfig for ClientConfig {
+    const DEFAULTS: &'static str = r#"
+        [general]
+        addr = "127.0.0.1:9527"
+        transport = "Tcp"
+    "#;
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = validate_general(&self.general, self.tls.is_some());
+
+        if let Some(tls) = &self.tls {
+            if tls.domain.is_empty() {
+                errors.push("tls.domain: must not be empty".into());
+            }
+            if let Some((cert, key)) = &tls.identity {
+                validate_pem(&mut errors, "tls.identity", cert, "CERTIFICATE");
+                validate_pem(&mut errors, "tls.identity", key, "PRIVATE KEY");
+            }
+            if let Some(ca) = &tls.ca {
+                validate_pem(&mut errors, "tls.ca", ca, "CERTIFICATE");
+            }
+        }
+
+        validate_tracing(&mut errors, &self.tracing);
+
+        if matches!(&self.namespace, Some(ns) if !is_valid_namespace(ns)) {
+            errors.push("namespace: must contain only letters, digits, `-` and `_`".into());
+        }
+
+        errors
+    }
+}
+
+fn validate_rate_limit(errors: &mut Vec<String>, field: &str, limit: &RateLimitConfig) {
+    if limit.requests_per_second == 0 {
+        errors.push(format!(
+            "{}.requests_per_second: must be greater than 0",
+            field
+        ));
+    }
+    if limit.burst == Some(0) {
+        errors.push(format!("{}.burst: must be greater than 0", field));
+    }
+}
+
+/// 命名空间的名字会成为 table 的前缀，名字和绑定的身份都不能重复
+fn validate_namespaces(errors: &mut Vec<String>, namespaces: &[NamespaceConfig]) {
+    let mut names = HashSet::new();
+    let mut identities = HashSet::new();
+
+    for (i, ns) in namespaces.iter().enumerate() {
+        let field = format!("namespaces[{}]", i);
+        if !is_valid_namespace(&ns.name) {
+            errors.push(format!(
+                "{}.name: `{}` must be non-empty and contain only letters, digits, `-` and `_`",
+                field, ns.name
+            ));
+        }
+        if !names.insert(ns.name.as_str()) {
+            errors.push(format!("{}.name: `{}` is duplicated", field, ns.name));
+        }
+        for id in &ns.identities {
+            if !identities.insert(id.as_str()) {
+                errors.push(format!(
+                    "{}.identities: `{}` is bound to more than one namespace",
+                    field, id
+                ));
+            }
+        }
+        if ns.max_keys == Some(0) {
+            errors.push(format!("{}.max_keys: must be greater than 0", field));
+        }
+        if ns.max_bytes == Some(0) {
+            errors.push(format!("{}.max_bytes: must be greater than 0", field));
+        }
+        if let Some(limit) = &ns.rate_limit {
+            validate_rate_limit(errors, &format!("{}.rate_limit", field), limit);
+        }
+    }
+}
+
+fn is_valid_namespace(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn validate_tracing(errors: &mut Vec<String>, tracing: &TracingConfig) {
+    let endpoint = match &tracing.endpoint {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+    match tracing.exporter {
+        TraceExporter::Jaeger if !is_host_port(endpoint) => errors.push(format!(
+            "tracing.endpoint: `{}` is not a valid `host:port` address",
+            endpoint
+        )),
+        TraceExporter::Otlp
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") =>
+        {
+            errors.push(format!(
+                "tracing.endpoint: `{}` is not an http(s) URL",
+                endpoint
+            ))
+        }
+        TraceExporter::None | TraceExporter::Stdout => {
+            errors.push("tracing.endpoint: only applies to Jaeger and Otlp exporters".into())
+        }
+        _ => {}
+    }
+}
+
+fn validate_general(general: &GeneralConfig, has_tls: bool) -> Vec<String> {
+    let mut errors = vec![];
+    let addr = &general.addr;
+
+    match general.transport {
+        TransportConfig::Unix if !cfg!(unix) => {
+            errors.push(format!("general.transport: {}", UNIX_UNSUPPORTED))
+        }
+        TransportConfig::Unix if addr.is_empty() => {
+            errors.push("general.addr: socket path must not be empty".into())
+        }
+        TransportConfig::Unix => {}
+        _ => {
+            if !is_host_port(addr) {
+                errors.push(format!(
+                    "general.addr: `{}` is not a valid `host:port` address",
+                    addr
+                ));
+            }
+        }
+    }
+
+    if general.max_frame_size == 0 || general.max_frame_size > MAX_FRAME {
+        errors.push(format!(
+            "general.max_frame_size: must be between 1 and {}",
+            MAX_FRAME
+        ));
+    }
+
+    if general.transport.require_tls() && !has_tls {
+        errors.push(format!(
+            "tls: required for {:?} transport",
+            general.transport
+        ));
+    }
+
+    errors
+}
+
+/// 只检查 `host:port` 的格式，不解析域名，校验配置时不应该依赖 DNS
+fn is_host_port(addr: &str) -> bool {
+    matches!(addr.rsplit_once(':'), Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+/// PEM 里至少要有一个 label 类型的条目，私钥可以是 PKCS#1、PKCS#8 或者 SEC1 格式
+fn validate_pem(errors: &mut Vec<String>, field: &str, pem: &str, label: &str) {
+    let items = rustls_pemfile::read_all(&mut pem.as_bytes()).unwrap_or_default();
+    let found = items.iter().any(|item| match item {
+        Item::X509Certificate(_) => label == "CERTIFICATE",
+        Item::RSAKey(_) | Item::PKCS8Key(_) | Item::ECKey(_) => label == "PRIVATE KEY",
+        _ => false,
+    });
+    if !found {
+        errors.push(format!(
+            "{}: not a PEM encoded {}",
+            field,
+            label.to_lowercase()
+        ));
+    }
+}
+
+/// TCP 和 QUIC 必须配置 TLS
+pub(crate) fn check_tls(general: &GeneralConfig, has_tls: bool) -> Result<(), KvError> {
+    if general.transport.require_tls() && !has_tls {
+        return Err(KvError::InvalidConfig(format!(
+            "tls is required for {:?} transport",
+            general.transport
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_config_should_be_loaded() {
+        let result: Result<ServerConfig, toml::de::Error> =
+            toml::from_str(include_str!("../../fixtures/server.conf"));
+
+        println!("{:?}", result);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn transport_should_default_to_tcp() {
+        let config: GeneralConfig = toml::from_str(r#"addr = "127.0.0.1:9527""#).unwrap();
+        assert_eq!(config.transport, TransportConfig::Tcp);
+        assert_eq!(config.max_frame_size, DEFAULT_MAX_FRAME);
+
+        let config: GeneralConfig =
+            toml::from_str("addr = \"127.0.0.1:9527\"\ntransport = \"Quic\"").unwrap();
+        assert_eq!(config.transport, TransportConfig::Quic);
+    }
+
+    #[test]
+    fn tls_should_be_optional_for_unix_socket() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [general]
+            addr = "/tmp/kv.sock"
+            transport = "Unix"
+
+            [storage]
+            type = "MemTable"
+
+            [log]
+            path = "/tmp/kv-log"
+            rotation = "Daily"
+            "#,
+        )
+        .unwrap();
+        assert!(config.tls.is_none());
+        assert!(check_tls(&config.general, false).is_ok());
+
+        let general = GeneralConfig {
+            transport: TransportConfig::Tcp,
+            ..config.general
+        };
+        assert!(check_tls(&general, false).is_err());
+    }
+
+    #[test]
+    fn unix_transport_should_depend_on_platform() {
+        let general: GeneralConfig =
+            toml::from_str("addr = \"/tmp/kv.sock\"\ntransport = \"Unix\"").unwrap();
+        let errors = validate_general(&general, false);
+        assert_eq!(errors.is_empty(), cfg!(unix), "{:?}", errors);
+    }
+
+    #[test]
+    fn restart_required_should_list_static_fields() {
+        let config: ServerConfig =
+            toml::from_str(include_str!("../../fixtures/server.conf")).unwrap();
+
+        let mut new = config.clone();
+        new.log.level = Some("debug".into());
+        new.acl = Some(AclConfig {
+            identities: vec!["awesome-device-id".into()],
+        });
+        new.tls.as_mut().unwrap().ca = Some("ca".into());
+        assert!(config.restart_required(&new).is_empty());
+
+        new.general.addr = "127.0.0.1:9528".into();
+        new.storage = StorageConfig::MemTable;
+        new.log.audit = true;
+        new.namespaces = vec![NamespaceConfig {
+            name: "team-a".into(),
+            identities: vec![],
+            max_keys: None,
+            max_bytes: None,
+            rate_limit: None,
+        }];
+        new.tls = None;
+        assert_eq!(
+            config.restart_required(&new),
+            vec!["general.addr", "storage", "log.audit", "namespaces", "tls"]
+        );
+    }
+
+    #[test]
+    fn namespaces_should_be_validated() {
+        let config = r#"
+            [general]
+            addr = "/tmp/kv.sock"
+            transport = "Unix"
+
+            [storage]
+            type = "MemTable"
+
+            [log]
+            path = "/tmp/kv-log"
+            rotation = "Daily"
+
+            [[namespaces]]
+            name = "team-a"
+            identities = ["device-a"]
+            max_keys = 1000
+            rate_limit = { requests_per_second = 100 }
+
+            [[namespaces]]
+            name = "team-a"
+            identities = ["device-a"]
+
+            [[namespaces]]
+            name = "team/b"
+            max_bytes = 0
+        "#;
+        let err = ConfigLoader::new()
+            .with_embedded(config)
+            .load::<ServerConfig>()
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            err.contains("namespaces[1].name: `team-a` is duplicated"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("namespaces[1].identities: `device-a` is bound to more than one"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("namespaces[2].name: `team/b` must be non-empty"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("namespaces[2].max_bytes: must be greater than 0"),
+            "{}",
+            err
+        );
+        assert!(!err.contains("namespaces[0]"), "{}", err);
+    }
+
+    #[test]
+    fn jaeger_endpoint_should_be_checked_without_resolving() {
+        let errors = |endpoint: &str| {
+            let tracing = TracingConfig {
+                exporter: TraceExporter::Jaeger,
+                endpoint: Some(endpoint.into()),
+                ..Default::default()
+            };
+            let mut errors = vec![];
+            validate_tracing(&mut errors, &tracing);
+            errors
+        };
+
+        // 校验时域名可能还解析不了
+        assert!(errors("jaeger-agent.invalid:6831").is_empty());
+        assert!(errors("[::1]:6831").is_empty());
+        assert_eq!(errors("jaeger-agent").len(), 1);
+        assert_eq!(errors(":6831").len(), 1);
+    }
+
+    #[test]
+    fn client_config_should_be_loaded() {
+        let result: Result<ClientConfig, toml::de::Error> =
+            toml::from_str(include_str!("../../fixtures/client.conf"));
+        assert!(result.is_ok());
+    }
+}
@@ -12,6 +12,9 @@ pub enum KvError {
     #[error("Cannot parse command: `{0}`")]
     InvalidCommand(String),
 
+    #[error("Wrong type for table: {0}, key: {1}, expected a {2}")]
+    WrongType(String, String, &'static str),
+
     #[error("Cannot convert value {0:?} to {1}")]
     ConvertError(String, &'static str),
 
@@ -25,6 +28,24 @@ pub enum KvError {
     #[error("Failed to decode protobuf message")]
     DecodeError(#[from] prost::DecodeError),
 
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Too many requests: {0}")]
+    RateLimited(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("QUIC error: {0}")]
+    QuicError(String),
+
+    #[error("Handshake error: {0}")]
+    HandshakeError(String),
+
+    #[error("Server returned {0}: {1}")]
+    ServerError(u32, String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 
@@ -33,6 +54,8 @@ pub enum KvError {
 
     #[error("Frame error")]
     FrameError,
+    #[error("Frame exceeds the limit of {0} bytes")]
+    FrameTooLarge(usize),
     #[error("Io error")]
     IoError,
 
@@ -42,6 +65,15 @@ pub enum KvError {
     #[error("TLS error: {0} {1}")]
     CertifcateParseError(&'static str, &'static str),
 
+    #[error("Script error: {0}")]
+    ScriptError(String),
+
+    #[error("Invalid dump: {0}")]
+    DumpError(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
     #[error("Parse config error")]
     ConfigError(#[from] toml::de::Error),
 }
@@ -51,3 +83,15 @@ impl From<std::io::Error> for KvError {
         KvError::IoError
     }
 }
+
+impl From<quinn::ConnectionError> for KvError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        KvError::QuicError(e.to_string())
+    }
+}
+
+impl From<quinn::ConnectError> for KvError {
+    fn from(e: quinn::ConnectError) -> Self {
+        KvError::QuicError(e.to_string())
+    }
+}
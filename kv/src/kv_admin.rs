@@ -0,0 +1,850 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use kv::{
+    quic_client_config, server_certificates, ClientConfig, ClientTlsConfig, ConfigLoader,
+    GeneralConfig, LayeredConfig, LogConfig, QuicCtrl, RotationConfig, ServerConfig,
+    ServerTlsConfig, StorageConfig, TlsClientConnector, TracingConfig, TransportConfig,
+    DEFAULT_MAX_FRAME,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType, PKCS_ED25519,
+};
+use time::{Duration, OffsetDateTime};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{lookup_host, TcpStream};
+use x509_parser::{extensions::GeneralName, pem::Pem, prelude::X509Certificate};
+
+/// KV 的运维工具：签发证书、生成配置、检查证书的有效期
+///
+/// 证书和私钥总是成对出现，`--out certs/ca` 会写入 `certs/ca.cert` 和 `certs/ca.key`。
+#[derive(Parser, Debug)]
+#[command(name = "kv-admin", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 创建一个新的 CA
+    Ca {
+        #[command(flatten)]
+        subject: Subject,
+        /// 有效天数
+        #[arg(long, default_value_t = 3650)]
+        days: i64,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 用 CA 签发服务器证书
+    Server {
+        /// CA 的文件前缀
+        #[arg(long)]
+        ca: PathBuf,
+        #[command(flatten)]
+        subject: Subject,
+        /// 服务器的域名或者 IP，可以指定多次
+        #[arg(long = "san", required = true)]
+        sans: Vec<String>,
+        /// 有效天数
+        #[arg(long, default_value_t = 825)]
+        days: i64,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 用 CA 签发客户端证书，CN 就是客户端的身份
+    Client {
+        /// CA 的文件前缀
+        #[arg(long)]
+        ca: PathBuf,
+        #[command(flatten)]
+        subject: Subject,
+        /// 有效天数
+        #[arg(long, default_value_t = 365)]
+        days: i64,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 生成互相匹配的 server.conf 和 client.conf
+    Config(ConfigArgs),
+    /// 轮换 CA：创建新的 CA，并把新旧 CA 合并成 `<out>-bundle.cert`
+    ///
+    /// 1. 把 bundle 作为两边的 `tls.ca` 部署出去，服务器和客户端同时信任新旧 CA；
+    /// 2. 用新的 CA 重新签发服务器和客户端的证书；
+    /// 3. 所有证书都换完之后，`tls.ca` 只保留新的 CA。
+    RotateCa {
+        /// 旧 CA 的文件前缀
+        #[arg(long)]
+        old: PathBuf,
+        #[command(flatten)]
+        subject: Subject,
+        /// 新 CA 的有效天数
+        #[arg(long, default_value_t = 3650)]
+        days: i64,
+        #[command(flatten)]
+        out: Output,
+    },
+    /// 检查证书的有效期，快要过期时返回错误
+    Check(CheckArgs),
+}
+
+/// 证书的 subject
+#[derive(Args, Debug)]
+struct Subject {
+    /// common name，客户端证书的 CN 是客户端的身份
+    #[arg(long)]
+    cn: String,
+    /// 组织名称
+    #[arg(long, default_value = "Acme Inc.")]
+    org: String,
+    /// 国家代码
+    #[arg(long, default_value = "CN")]
+    country: String,
+}
+
+/// 输出的文件
+#[derive(Args, Debug)]
+struct Output {
+    /// 输出的文件前缀
+    #[arg(short, long)]
+    out: PathBuf,
+    /// 覆盖已经存在的文件
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args, Debug)]
+struct ConfigArgs {
+    /// 服务器地址
+    #[arg(long, default_value = "127.0.0.1:9527")]
+    addr: String,
+    /// 传输方式
+    #[arg(long, value_enum, default_value_t = Transport::Tcp)]
+    transport: Transport,
+    /// 服务器证书的文件前缀
+    #[arg(long)]
+    server: PathBuf,
+    /// 信任的 CA 证书文件，可以指定多次，轮换 CA 的时候同时信任新旧两个 CA
+    #[arg(long = "ca", required = true)]
+    cas: Vec<PathBuf>,
+    /// 客户端证书的文件前缀，指定时服务器要求客户端提供证书
+    #[arg(long)]
+    client: Option<PathBuf>,
+    /// 客户端验证服务器时使用的域名，不指定时使用服务器证书里的第一个域名
+    #[arg(long)]
+    domain: Option<String>,
+    /// sled 数据库的目录，不指定时使用 MemTable
+    #[arg(long)]
+    sled: Option<String>,
+    /// 日志目录
+    #[arg(long, default_value = "/tmp/kv-log")]
+    log: String,
+    /// 输出目录
+    #[arg(short, long, default_value = ".")]
+    out_dir: PathBuf,
+    /// 覆盖已经存在的文件
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Transport {
+    Tcp,
+    Quic,
+    Unix,
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    /// 客户端配置文件，连接服务器检查它正在使用的证书
+    #[arg(short, long, conflicts_with = "cert", required_unless_present = "cert")]
+    config: Option<PathBuf>,
+    /// 本地的证书文件，可以是包含多个证书的 bundle，可以指定多次
+    #[arg(long)]
+    cert: Vec<PathBuf>,
+    /// 剩余天数少于这个值时返回错误
+    #[arg(long, default_value_t = 30)]
+    warn_days: i64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Ca { subject, days, out } => {
+            let ca = new_ca(&subject, days)?;
+            write_pair(&out, &ca.serialize_pem()?, &ca.serialize_private_key_pem())?;
+        }
+        Command::Server {
+            ca,
+            subject,
+            sans,
+            days,
+            out,
+        } => {
+            let ca = load_ca(&ca)?;
+            let cert = new_leaf(&subject, &sans, days, ExtendedKeyUsagePurpose::ServerAuth)?;
+            let pem = cert.serialize_pem_with_signer(&ca)?;
+            write_pair(&out, &pem, &cert.serialize_private_key_pem())?;
+        }
+        Command::Client {
+            ca,
+            subject,
+            days,
+            out,
+        } => {
+            let ca = load_ca(&ca)?;
+            let cert = new_leaf(&subject, &[], days, ExtendedKeyUsagePurpose::ClientAuth)?;
+            let pem = cert.serialize_pem_with_signer(&ca)?;
+            write_pair(&out, &pem, &cert.serialize_private_key_pem())?;
+        }
+        Command::Config(args) => {
+            let (server, client) = build_configs(&args)?;
+            let server_path = args.out_dir.join("server.conf");
+            let client_path = args.out_dir.join("client.conf");
+            check_writable(&[&server_path, &client_path], args.force)?;
+            write_file(
+                &server_path,
+                &toml::to_string_pretty(&server)?,
+                args.force,
+                false,
+            )?;
+            write_file(
+                &client_path,
+                &toml::to_string_pretty(&client)?,
+                args.force,
+                false,
+            )?;
+        }
+        Command::RotateCa {
+            old,
+            subject,
+            days,
+            out,
+        } => {
+            let bundle = rotate_ca(&old, &subject, days, &out)?;
+            eprintln!(
+                "Deploy {} as tls.ca on both sides, then reissue certificates with --ca {}",
+                bundle.display(),
+                out.out.display()
+            );
+        }
+        Command::Check(args) => {
+            let certs = match &args.config {
+                Some(path) => fetch_server_certs(path).await?,
+                None => args
+                    .cert
+                    .iter()
+                    .map(|path| parse_pem_certs(&fs::read_to_string(path)?))
+                    .collect::<Result<Vec<_>>>()?
+                    .concat(),
+            };
+            check(&certs, args.warn_days, now())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn new_ca(subject: &Subject, days: i64) -> Result<Certificate> {
+    let mut params = base_params(subject, days);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    Ok(Certificate::from_params(params)?)
+}
+
+fn new_leaf(
+    subject: &Subject,
+    sans: &[String],
+    days: i64,
+    usage: ExtendedKeyUsagePurpose,
+) -> Result<Certificate> {
+    let mut params = base_params(subject, days);
+    params.subject_alt_names = sans.iter().map(|san| parse_san(san)).collect();
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![usage];
+    Ok(Certificate::from_params(params)?)
+}
+
+fn base_params(subject: &Subject, days: i64) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.alg = &PKCS_ED25519;
+    params.serial_number = Some(rand::random());
+
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CountryName, &subject.country);
+    dn.push(DnType::OrganizationName, &subject.org);
+    dn.push(DnType::CommonName, &subject.cn);
+    params.distinguished_name = dn;
+
+    // 留一点余量，避免两边的时钟不一致时证书还没生效
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::hours(1);
+    params.not_after = now + Duration::days(days);
+    params
+}
+
+/// IP 地址用 IP SAN，其它的都当作域名
+fn parse_san(san: &str) -> SanType {
+    match san.parse::<IpAddr>() {
+        Ok(ip) => SanType::IpAddress(ip),
+        Err(_) => SanType::DnsName(san.into()),
+    }
+}
+
+fn load_ca(prefix: &Path) -> Result<Certificate> {
+    let (cert, key) = read_pair(prefix)?;
+    let key = KeyPair::from_pem(&key)?;
+    let params = CertificateParams::from_ca_cert_pem(&cert, key)?;
+    Ok(Certificate::from_params(params)?)
+}
+
+fn read_pair(prefix: &Path) -> Result<(String, String)> {
+    let read = |path: PathBuf| {
+        fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))
+    };
+    Ok((
+        read(suffixed(prefix, ".cert"))?,
+        read(suffixed(prefix, ".key"))?,
+    ))
+}
+
+/// 创建新的 CA，写入 `<out>.cert`、`<out>.key` 和新旧 CA 合并成的 bundle，返回 bundle 的路径
+fn rotate_ca(old: &Path, subject: &Subject, days: i64, out: &Output) -> Result<PathBuf> {
+    let (old_cert, _) = read_pair(old)?;
+    let ca = new_ca(subject, days)?;
+    let pem = ca.serialize_pem()?;
+
+    // 三个文件都检查过之后再写，避免只写了一部分
+    let bundle = suffixed(&out.out, "-bundle.cert");
+    let cert_path = suffixed(&out.out, ".cert");
+    let key_path = suffixed(&out.out, ".key");
+    check_writable(&[&cert_path, &key_path, &bundle], out.force)?;
+
+    write_file(&cert_path, &pem, true, false)?;
+    write_file(&key_path, &ca.serialize_private_key_pem(), true, true)?;
+    write_file(&bundle, &format!("{}{}", pem, old_cert), true, false)?;
+    Ok(bundle)
+}
+
+fn write_pair(out: &Output, cert: &str, key: &str) -> Result<()> {
+    // 先检查两个文件，避免只写了一半
+    let cert_path = suffixed(&out.out, ".cert");
+    let key_path = suffixed(&out.out, ".key");
+    check_writable(&[&cert_path, &key_path], out.force)?;
+    write_file(&cert_path, cert, true, false)?;
+    write_file(&key_path, key, true, true)?;
+    Ok(())
+}
+
+/// 不允许覆盖时，要写的文件都不能已经存在
+fn check_writable(paths: &[&Path], force: bool) -> Result<()> {
+    match paths.iter().find(|path| !force && path.exists()) {
+        Some(path) => Err(anyhow!(
+            "{} exists, use --force to overwrite",
+            path.display()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// 写入文件，在 Unix 上私钥只有自己能读写
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_file(path: &Path, content: &str, force: bool, private: bool) -> Result<()> {
+    check_writable(&[path], force)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(if private { 0o600 } else { 0o644 });
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("cannot write {}", path.display()))?;
+    file.write_all(content.as_bytes())?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn suffixed(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+fn build_configs(args: &ConfigArgs) -> Result<(ServerConfig, ClientConfig)> {
+    let (cert, key) = read_pair(&args.server)?;
+    let ca = args
+        .cas
+        .iter()
+        .map(|path| {
+            fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .concat();
+    let identity = args.client.as_deref().map(read_pair).transpose()?;
+
+    let domain = match &args.domain {
+        Some(domain) => domain.clone(),
+        None => first_dns_name(&cert)?,
+    };
+
+    let general = GeneralConfig {
+        addr: args.addr.clone(),
+        transport: match args.transport {
+            Transport::Tcp => TransportConfig::Tcp,
+            Transport::Quic => TransportConfig::Quic,
+            Transport::Unix => TransportConfig::Unix,
+        },
+        max_frame_size: DEFAULT_MAX_FRAME,
+    };
+    let server = ServerConfig {
+        general: general.clone(),
+        storage: match &args.sled {
+            Some(path) => StorageConfig::SledTable(path.clone()),
+            None => StorageConfig::MemTable,
+        },
+        tls: Some(ServerTlsConfig {
+            cert,
+            key,
+            // 有客户端证书时，服务器用同样的 CA 验证客户端
+            ca: identity.as_ref().map(|_| ca.clone()),
+        }),
+        log: LogConfig {
+            path: args.log.clone(),
+            rotation: RotationConfig::Daily,
+            level: None,
+            audit: false,
+        },
+        acl: None,
+        rate_limit: None,
+        backup: None,
+        memory: None,
+        script: None,
+        tracing: TracingConfig::default(),
+        namespaces: vec![],
+    };
+    let client = ClientConfig {
+        general,
+        tls: Some(ClientTlsConfig {
+            domain,
+            identity,
+            ca: Some(ca),
+        }),
+        tracing: TracingConfig::default(),
+        namespace: None,
+    };
+
+    let errors = [server.validate(), client.validate()].concat();
+    if !errors.is_empty() {
+        return Err(anyhow!("invalid config: {}", errors.join("; ")));
+    }
+    Ok((server, client))
+}
+
+/// 证书里的第一个 DNS SAN
+fn first_dns_name(pem: &str) -> Result<String> {
+    let der = parse_pem_certs(pem)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no certificate found"))?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&der)?;
+    let names = cert.subject_alternative_name()?;
+    names
+        .into_iter()
+        .flat_map(|ext| ext.value.general_names.iter())
+        .find_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("server certificate has no DNS name, please specify --domain"))
+}
+
+/// 把 PEM 里所有的证书转换成 DER
+fn parse_pem_certs(pem: &str) -> Result<Vec<Vec<u8>>> {
+    let mut certs = vec![];
+    for pem in Pem::iter_from_buffer(pem.as_bytes()) {
+        let pem = pem?;
+        if pem.label == "CERTIFICATE" {
+            certs.push(pem.contents);
+        }
+    }
+    Ok(certs)
+}
+
+/// 按照客户端的配置连接服务器，取出服务器的证书链
+async fn fetch_server_certs(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let config: ClientConfig = ConfigLoader::new()
+        .with_file(path)
+        .with_env("KV_CLIENT_")
+        .load()?;
+    let tls = config
+        .tls
+        .as_ref()
+        .ok_or_else(|| anyhow!("the server does not use TLS"))?;
+    let identity = tls.identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
+    let addr = &config.general.addr;
+
+    let certs = match config.general.transport {
+        TransportConfig::Quic => {
+            let quic = quic_client_config(identity, tls.ca.as_deref())?;
+            let addr = lookup_host(addr)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("cannot resolve {}", addr))?;
+            QuicCtrl::connect(addr, &tls.domain, quic)
+                .await?
+                .server_certificates()
+        }
+        transport => {
+            let connector = TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())?;
+            // 证书过期时握手会失败，直接把 TLS 的错误报告出来
+            let res = match transport {
+                #[cfg(not(unix))]
+                TransportConfig::Unix => {
+                    return Err(anyhow!(
+                        "unix domain socket is not supported on this platform"
+                    ))
+                }
+                #[cfg(unix)]
+                TransportConfig::Unix => {
+                    let stream = UnixStream::connect(addr).await?;
+                    connector
+                        .connect(stream)
+                        .await
+                        .map(|s| server_certificates(&s))
+                }
+                _ => {
+                    let stream = TcpStream::connect(addr).await?;
+                    connector
+                        .connect(stream)
+                        .await
+                        .map(|s| server_certificates(&s))
+                }
+            };
+            res.map_err(|e| anyhow!("TLS handshake with {} failed: {}", addr, e))?
+        }
+    };
+    Ok(certs)
+}
+
+/// 打印每个证书的有效期，剩余天数少于 warn_days 时返回错误
+fn check(certs: &[Vec<u8>], warn_days: i64, now: i64) -> Result<()> {
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found"));
+    }
+
+    let mut expiring = vec![];
+    for der in certs {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)?;
+        let days = (cert.validity().not_after.timestamp() - now).div_euclid(86400);
+        println!(
+            "{}\n  issuer:  {}\n  expires: {} ({} days left)",
+            cert.subject(),
+            cert.issuer(),
+            cert.validity().not_after,
+            days
+        );
+        if days < warn_days {
+            expiring.push(format!("`{}` ({} days left)", common_name(&cert), days));
+        }
+    }
+
+    match expiring.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "certificates expiring within {} days: {}",
+            warn_days,
+            expiring.join(", ")
+        )),
+    }
+}
+
+fn common_name(cert: &X509Certificate) -> String {
+    let cn = cert.subject().iter_common_name().next();
+    cn.and_then(|cn| cn.as_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use kv::TlsServerAcceptor;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn subject(cn: &str) -> Subject {
+        Subject {
+            cn: cn.into(),
+            org: "Acme Inc.".into(),
+            country: "CN".into(),
+        }
+    }
+
+    struct Pair {
+        cert: String,
+        key: String,
+    }
+
+    fn ca(cn: &str) -> (Certificate, Pair) {
+        let ca = new_ca(&subject(cn), 3650).unwrap();
+        let pair = Pair {
+            cert: ca.serialize_pem().unwrap(),
+            key: ca.serialize_private_key_pem(),
+        };
+        (ca, pair)
+    }
+
+    fn leaf(ca: &Certificate, cn: &str, sans: &[&str], usage: ExtendedKeyUsagePurpose) -> Pair {
+        let sans: Vec<_> = sans.iter().map(|s| s.to_string()).collect();
+        let cert = new_leaf(&subject(cn), &sans, 365, usage).unwrap();
+        Pair {
+            cert: cert.serialize_pem_with_signer(ca).unwrap(),
+            key: cert.serialize_private_key_pem(),
+        }
+    }
+
+    fn server(ca: &Certificate) -> Pair {
+        leaf(
+            ca,
+            "kv server",
+            &["kv.acme.inc", "127.0.0.1"],
+            ExtendedKeyUsagePurpose::ServerAuth,
+        )
+    }
+
+    fn client(ca: &Certificate, cn: &str) -> Pair {
+        leaf(ca, cn, &[], ExtendedKeyUsagePurpose::ClientAuth)
+    }
+
+    /// 在内存里做一次 TLS 握手，返回服务器看到的客户端身份
+    async fn handshake(
+        server: &Pair,
+        client_ca: Option<&str>,
+        client: Option<&Pair>,
+        server_ca: &str,
+    ) -> Result<Option<String>> {
+        let acceptor = TlsServerAcceptor::new(&server.cert, &server.key, client_ca)?;
+        let identity = client.map(|c| (c.cert.as_str(), c.key.as_str()));
+        let connector = TlsClientConnector::new("kv.acme.inc", identity, Some(server_ca))?;
+
+        let (c, s) = duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(s).await?;
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await?;
+            anyhow::Ok(kv::peer_identity(&stream))
+        });
+        let mut stream = connector.connect(c).await?;
+        stream.write_all(b"hello").await?;
+        stream.flush().await?;
+        server.await?
+    }
+
+    #[test]
+    fn parse_san_should_detect_ip_addresses() {
+        assert_eq!(
+            parse_san("127.0.0.1"),
+            SanType::IpAddress("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(parse_san("::1"), SanType::IpAddress("::1".parse().unwrap()));
+        assert_eq!(
+            parse_san("kv.acme.inc"),
+            SanType::DnsName("kv.acme.inc".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn issued_certificates_should_work_with_tls() -> Result<()> {
+        let (ca, ca_pair) = ca("Acme KV CA");
+        let server = server(&ca);
+        let client = client(&ca, "awesome-device-id");
+
+        let identity =
+            handshake(&server, Some(&ca_pair.cert), Some(&client), &ca_pair.cert).await?;
+        assert_eq!(identity.as_deref(), Some("awesome-device-id"));
+
+        let certs = parse_pem_certs(&server.cert)?;
+        let (_, cert) = x509_parser::parse_x509_certificate(&certs[0])?;
+        assert_eq!(common_name(&cert), "kv server");
+        assert_eq!(first_dns_name(&server.cert)?, "kv.acme.inc");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn loaded_ca_should_sign_certificates() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let prefix = dir.path().join("ca");
+        let (_, ca_pair) = ca("Acme KV CA");
+        let out = Output {
+            out: prefix.clone(),
+            force: false,
+        };
+        write_pair(&out, &ca_pair.cert, &ca_pair.key)?;
+        // 不会覆盖已经存在的文件
+        assert!(write_pair(&out, &ca_pair.cert, &ca_pair.key).is_err());
+
+        let ca = load_ca(&prefix)?;
+        let server = server(&ca);
+        assert!(handshake(&server, None, None, &ca_pair.cert).await.is_ok());
+
+        #[cfg(unix)]
+        {
+            let mode = fs::metadata(suffixed(&prefix, ".key"))?.permissions();
+            assert_eq!(
+                std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+                0o600
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_ca_should_not_write_anything_when_bundle_exists() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let old = dir.path().join("ca");
+        let (_, old_pair) = ca("Acme KV CA");
+        fs::write(suffixed(&old, ".cert"), &old_pair.cert)?;
+        fs::write(suffixed(&old, ".key"), &old_pair.key)?;
+
+        let prefix = dir.path().join("ca2");
+        fs::write(suffixed(&prefix, "-bundle.cert"), "")?;
+        let mut out = Output {
+            out: prefix.clone(),
+            force: false,
+        };
+        assert!(rotate_ca(&old, &subject("Acme KV CA 2"), 365, &out).is_err());
+        assert!(!suffixed(&prefix, ".cert").exists());
+        assert!(!suffixed(&prefix, ".key").exists());
+
+        out.force = true;
+        let bundle = rotate_ca(&old, &subject("Acme KV CA 2"), 365, &out)?;
+        assert!(fs::read_to_string(bundle)?.ends_with(&old_pair.cert));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ca_rotation_should_overlap_on_both_sides() -> Result<()> {
+        let (old, old_pair) = ca("Acme KV CA");
+        let (new, new_pair) = ca("Acme KV CA 2");
+        let bundle = format!("{}{}", new_pair.cert, old_pair.cert);
+
+        // 只信任旧 CA 的客户端不认新的服务器证书
+        let new_server = server(&new);
+        assert!(handshake(&new_server, None, None, &old_pair.cert)
+            .await
+            .is_err());
+
+        // 部署 bundle 之后，新旧证书可以混用
+        let old_server = server(&old);
+        let old_client = client(&old, "old-device");
+        let new_client = client(&new, "new-device");
+        for server in [&old_server, &new_server] {
+            for client in [&old_client, &new_client] {
+                let identity = handshake(server, Some(&bundle), Some(client), &bundle).await?;
+                assert!(identity.is_some());
+            }
+        }
+
+        // 旧 CA 撤掉之后，旧的客户端证书就不能用了
+        let res = handshake(
+            &new_server,
+            Some(&new_pair.cert),
+            Some(&old_client),
+            &bundle,
+        )
+        .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_should_match_the_certificates() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (ca, ca_pair) = ca("Acme KV CA");
+        let server = server(&ca);
+        let client = client(&ca, "awesome-device-id");
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("ca.cert"), &ca_pair.cert)?;
+        fs::write(path("server.cert"), &server.cert)?;
+        fs::write(path("server.key"), &server.key)?;
+        fs::write(path("client.cert"), &client.cert)?;
+        fs::write(path("client.key"), &client.key)?;
+
+        let mut args = ConfigArgs {
+            addr: "127.0.0.1:9527".into(),
+            transport: Transport::Tcp,
+            server: path("server"),
+            cas: vec![path("ca.cert")],
+            client: None,
+            domain: None,
+            sled: None,
+            log: "/tmp/kv-log".into(),
+            out_dir: dir.path().into(),
+            force: false,
+        };
+        let (server_config, client_config) = build_configs(&args)?;
+        assert_eq!(server_config.tls.unwrap().ca, None);
+        let tls = client_config.tls.unwrap();
+        assert_eq!(tls.domain, "kv.acme.inc");
+        assert_eq!(tls.ca.unwrap(), ca_pair.cert);
+        assert_eq!(tls.identity, None);
+
+        // 有客户端证书时服务器要验证客户端
+        args.client = Some(path("client"));
+        let (server_config, client_config) = build_configs(&args)?;
+        assert_eq!(server_config.tls.as_ref().unwrap().ca, Some(ca_pair.cert));
+        let identity = client_config.tls.unwrap().identity.unwrap();
+        assert_eq!(identity.0, client.cert);
+
+        // 生成的配置可以被服务器和客户端加载
+        fs::write(path("server.conf"), toml::to_string_pretty(&server_config)?)?;
+        let loaded: ServerConfig = ConfigLoader::new().with_file(path("server.conf")).load()?;
+        assert_eq!(loaded, server_config);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_should_report_expiring_certificates() -> Result<()> {
+        let (ca, _) = ca("Acme KV CA");
+        let server = server(&ca);
+        let certs = parse_pem_certs(&server.cert)?;
+
+        assert!(check(&certs, 30, now()).is_ok());
+
+        // 350 天零 1 小时之后，剩余的时间不到 15 天
+        let later = now() + 350 * 86400 + 3600;
+        let err = check(&certs, 30, later).unwrap_err().to_string();
+        assert!(err.contains("`kv server` (14 days left)"), "{}", err);
+
+        assert!(check(&[], 30, now()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    fmt, fs,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use clap::{Args, Parser, ValueEnum};
+use kv::{
+    parse_override, start_client_with_config, ClientConfig, ClientConnection, CommandRequest,
+    CommandResponse, ConfigLoader, ProstClientStream,
+};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+/// 对运行中的 kvs 施加负载，输出吞吐量和延迟
+#[derive(Parser, Debug)]
+#[command(name = "kv-bench", version, about)]
+struct Cli {
+    /// 客户端配置文件路径，不指定时使用内置的配置；`KV_CLIENT_*` 环境变量会覆盖其中的字段
+    #[arg(short, long, env = "KV_CLIENT_CONFIG")]
+    config: Option<String>,
+
+    /// 服务器地址，覆盖配置文件里的地址
+    #[arg(short, long)]
+    addr: Option<String>,
+
+    /// 覆盖任意字段，格式为 key=value，比如 `tls.domain=kv.acme.inc`
+    #[arg(long = "set", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+
+    /// 把结果以 JSON 写入这个文件，方便对比多次运行
+    #[arg(short, long)]
+    output: Option<String>,
+
+    #[command(flatten)]
+    workload: Workload,
+}
+
+/// 负载的参数，会原样写进结果里
+#[derive(Args, Debug, Clone, Serialize)]
+struct Workload {
+    /// 建立的连接数
+    #[arg(long, default_value_t = 4)]
+    connections: usize,
+    /// 每个连接上并发的 stream 数
+    #[arg(long, default_value_t = 4)]
+    streams: usize,
+    /// 每个 stream 一次发出的请求数
+    #[arg(long, default_value_t = 1)]
+    pipeline: usize,
+    /// 运行的秒数
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+    /// 读请求 (HGET) 的比例，其余是写请求 (HSET)
+    #[arg(long, default_value_t = 0.9)]
+    read_ratio: f64,
+    /// key 的数量
+    #[arg(long, default_value_t = 10000)]
+    keys: usize,
+    /// key 的分布
+    #[arg(long, value_enum, default_value_t = Distribution::Uniform)]
+    distribution: Distribution,
+    /// zipfian 分布的参数，越大越集中在少数 key 上
+    #[arg(long, default_value_t = 0.99)]
+    zipf_theta: f64,
+    /// value 的字节数，可以是固定的 `64`，也可以是范围 `16-1024`
+    #[arg(long, default_value = "64")]
+    value_size: SizeRange,
+    /// 使用的 table
+    #[arg(long, default_value = "bench")]
+    table: String,
+    /// 开始之前先写入所有的 key，这样读请求都能命中
+    #[arg(long)]
+    prefill: bool,
+    /// 随机数种子，相同的种子产生相同的请求序列
+    #[arg(long, default_value_t = 9527)]
+    seed: u64,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Distribution {
+    Uniform,
+    Zipfian,
+}
+
+/// value 大小的范围，包含两端
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SizeRange {
+    min: usize,
+    max: usize,
+}
+
+impl FromStr for SizeRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (min.trim().parse()?, max.trim().parse()?),
+            None => {
+                let size = s.trim().parse()?;
+                (size, size)
+            }
+        };
+        if min > max {
+            return Err(anyhow!("invalid value size range `{}`", s));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+impl fmt::Display for SizeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min == self.max {
+            true => write!(f, "{}", self.min),
+            false => write!(f, "{}-{}", self.min, self.max),
+        }
+    }
+}
+
+impl Serialize for SizeRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// 按照分布选出 key 的序号
+enum KeyChooser {
+    Uniform(usize),
+    /// 序号 i 被选中的概率正比于 1 / (i + 1)^theta，这里存的是累积的权重
+    Zipfian(Vec<f64>),
+}
+
+impl KeyChooser {
+    fn new(workload: &Workload) -> Self {
+        match workload.distribution {
+            Distribution::Uniform => Self::Uniform(workload.keys),
+            Distribution::Zipfian => {
+                let mut total = 0.0;
+                let cdf = (0..workload.keys)
+                    .map(|i| {
+                        total += 1.0 / ((i + 1) as f64).powf(workload.zipf_theta);
+                        total
+                    })
+                    .collect();
+                Self::Zipfian(cdf)
+            }
+        }
+    }
+
+    fn choose(&self, rng: &mut impl Rng) -> usize {
+        match self {
+            Self::Uniform(n) => rng.gen_range(0..*n),
+            Self::Zipfian(cdf) => {
+                let x = rng.gen::<f64>() * cdf[cdf.len() - 1];
+                cdf.partition_point(|&w| w < x).min(cdf.len() - 1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Read,
+    Write,
+}
+
+/// 生成请求，每个 worker 一个
+struct Generator {
+    workload: Arc<Workload>,
+    keys: Arc<KeyChooser>,
+    /// 随机的数据，value 取其中的一段，避免被压缩
+    data: Bytes,
+    rng: StdRng,
+}
+
+impl Generator {
+    fn new(workload: Arc<Workload>, keys: Arc<KeyChooser>, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut data = vec![0; workload.value_size.max];
+        rng.fill_bytes(&mut data);
+        Self {
+            workload,
+            keys,
+            data: data.into(),
+            rng,
+        }
+    }
+
+    fn next(&mut self) -> (Op, CommandRequest) {
+        let key = key_name(self.keys.choose(&mut self.rng));
+        if self.rng.gen::<f64>() < self.workload.read_ratio {
+            (
+                Op::Read,
+                CommandRequest::new_hget(&self.workload.table, key),
+            )
+        } else {
+            let value = self.value();
+            (
+                Op::Write,
+                CommandRequest::new_hset(&self.workload.table, key, value),
+            )
+        }
+    }
+
+    fn value(&mut self) -> kv::Value {
+        let size = self.workload.value_size;
+        let len = self.rng.gen_range(size.min..=size.max);
+        self.data.slice(..len).into()
+    }
+}
+
+fn key_name(i: usize) -> String {
+    format!("key-{}", i)
+}
+
+/// 一个 worker 记录下来的延迟，单位是微秒
+#[derive(Debug, Default)]
+struct Recorder {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    errors: u64,
+}
+
+impl Recorder {
+    fn record(&mut self, op: Op, latency: u64, res: &CommandResponse) {
+        // 读不到 key 不算错误
+        if res.status != 200 && res.status != 404 {
+            self.errors += 1;
+        }
+        match op {
+            Op::Read => self.reads.push(latency),
+            Op::Write => self.writes.push(latency),
+        }
+    }
+
+    fn merge(&mut self, other: Recorder) {
+        self.reads.extend(other.reads);
+        self.writes.extend(other.writes);
+        self.errors += other.errors;
+    }
+}
+
+/// 一组延迟的统计，单位是微秒
+#[derive(Debug, Serialize, PartialEq)]
+struct Latency {
+    count: usize,
+    mean_us: f64,
+    p50_us: u64,
+    p99_us: u64,
+    p999_us: u64,
+    max_us: u64,
+}
+
+impl Latency {
+    fn new(mut samples: Vec<u64>) -> Self {
+        samples.sort_unstable();
+        let count = samples.len();
+        let mean_us = match count {
+            0 => 0.0,
+            _ => samples.iter().sum::<u64>() as f64 / count as f64,
+        };
+        Self {
+            count,
+            mean_us,
+            p50_us: percentile(&samples, 0.5),
+            p99_us: percentile(&samples, 0.99),
+            p999_us: percentile(&samples, 0.999),
+            max_us: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// sorted 必须已经排好序，返回不小于 p 比例样本的最小值
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    workload: Workload,
+    elapsed_secs: f64,
+    ops: usize,
+    errors: u64,
+    /// 每秒完成的请求数
+    throughput: f64,
+    all: Latency,
+    read: Latency,
+    write: Latency,
+}
+
+impl Report {
+    fn new(workload: Workload, elapsed: Duration, recorder: Recorder) -> Self {
+        let all = Latency::new([&recorder.reads[..], &recorder.writes[..]].concat());
+        let elapsed_secs = elapsed.as_secs_f64();
+        Self {
+            workload,
+            elapsed_secs,
+            ops: all.count,
+            errors: recorder.errors,
+            throughput: all.count as f64 / elapsed_secs,
+            all,
+            read: Latency::new(recorder.reads),
+            write: Latency::new(recorder.writes),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ops in {:.2}s, {:.0} ops/s, {} errors",
+            self.ops, self.elapsed_secs, self.throughput, self.errors
+        )?;
+        writeln!(
+            f,
+            "{:<6}{:>10}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            "", "count", "mean", "p50", "p99", "p999", "max"
+        )?;
+        for (name, l) in [
+            ("all", &self.all),
+            ("read", &self.read),
+            ("write", &self.write),
+        ] {
+            writeln!(
+                f,
+                "{:<6}{:>10}{:>10.0}{:>10}{:>10}{:>10}{:>10}",
+                name, l.count, l.mean_us, l.p50_us, l.p99_us, l.p999_us, l.max_us
+            )?;
+        }
+        write!(f, "(latencies in microseconds)")
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+
+    let config = load_config(&cli)?;
+    let report = bench(&config, cli.workload).await?;
+    println!("{}", report);
+
+    if let Some(path) = &cli.output {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        eprintln!("Results written to {}", path);
+    }
+
+    Ok(())
+}
+
+fn load_config(cli: &Cli) -> Result<ClientConfig> {
+    let mut loader = match &cli.config {
+        Some(path) => ConfigLoader::new().with_file(path),
+        None => ConfigLoader::new().with_embedded(include_str!("../fixtures/client.conf")),
+    };
+    loader = loader.with_env("KV_CLIENT_");
+
+    if let Some(addr) = &cli.addr {
+        loader = loader.with_override("general.addr", addr);
+    }
+    for (key, value) in &cli.overrides {
+        loader = loader.with_override(key, value);
+    }
+
+    Ok(loader.load()?)
+}
+
+async fn bench(config: &ClientConfig, workload: Workload) -> Result<Report> {
+    let mut conns = Vec::with_capacity(workload.connections);
+    for _ in 0..workload.connections {
+        conns.push(start_client_with_config(config).await?);
+    }
+    run(conns, workload).await
+}
+
+/// 在给定的连接上运行负载，连接在结束之前保持打开
+async fn run(mut conns: Vec<ClientConnection>, workload: Workload) -> Result<Report> {
+    if workload.keys == 0 || workload.connections == 0 || workload.streams == 0 {
+        return Err(anyhow!("keys, connections and streams must be positive"));
+    }
+    if workload.pipeline == 0 {
+        return Err(anyhow!("pipeline must be positive"));
+    }
+
+    let keys = Arc::new(KeyChooser::new(&workload));
+    let shared = Arc::new(workload.clone());
+    if workload.prefill {
+        let stream = conns[0].open_stream().await?;
+        prefill(
+            stream,
+            Generator::new(shared.clone(), keys.clone(), workload.seed),
+        )
+        .await?;
+    }
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(workload.duration);
+    let mut tasks = vec![];
+    for conn in conns.iter_mut() {
+        for _ in 0..workload.streams {
+            let stream = conn.open_stream().await?;
+            // 每个 worker 使用不同的种子，避免发出一样的请求
+            let seed = workload.seed + tasks.len() as u64 + 1;
+            let gen = Generator::new(shared.clone(), keys.clone(), seed);
+            tasks.push(tokio::spawn(worker(stream, gen, deadline)));
+        }
+    }
+
+    let mut recorder = Recorder::default();
+    for task in tasks {
+        recorder.merge(task.await??);
+    }
+
+    Ok(Report::new(workload, start.elapsed(), recorder))
+}
+
+/// 写入所有的 key，每次发出 pipeline 个请求
+async fn prefill(mut stream: ProstClientStream<kv::BoxedStream>, mut gen: Generator) -> Result<()> {
+    let workload = gen.workload.clone();
+    let keys: Vec<_> = (0..workload.keys).collect();
+    for chunk in keys.chunks(workload.pipeline) {
+        let cmds: Vec<_> = chunk
+            .iter()
+            .map(|&i| CommandRequest::new_hset(&workload.table, key_name(i), gen.value()))
+            .collect();
+        stream.execute_pipelined(cmds).await?;
+    }
+    Ok(())
+}
+
+/// 不断发出请求直到 deadline，pipeline 里的请求都记作整组的延迟
+async fn worker(
+    mut stream: ProstClientStream<kv::BoxedStream>,
+    mut gen: Generator,
+    deadline: Instant,
+) -> Result<Recorder> {
+    let mut recorder = Recorder::default();
+    while Instant::now() < deadline {
+        let (ops, cmds): (Vec<_>, Vec<_>) = (0..gen.workload.pipeline).map(|_| gen.next()).unzip();
+
+        let start = Instant::now();
+        let res = stream.execute_pipelined(cmds).await?;
+        let latency = start.elapsed().as_micros() as u64;
+
+        for (op, res) in ops.into_iter().zip(res) {
+            recorder.record(op, latency, &res);
+        }
+    }
+    Ok(recorder)
+}
+
+#[cfg(test)]
+mod tests {
+    use kv::{MemTable, Service};
+
+    use super::*;
+
+    fn workload() -> Workload {
+        let cli = Cli::parse_from(["kv-bench"]);
+        cli.workload
+    }
+
+    #[test]
+    fn size_range_should_parse() {
+        assert_eq!(
+            "64".parse::<SizeRange>().unwrap(),
+            SizeRange { min: 64, max: 64 }
+        );
+        let range: SizeRange = "16-1024".parse().unwrap();
+        assert_eq!(range, SizeRange { min: 16, max: 1024 });
+        assert_eq!(range.to_string(), "16-1024");
+        assert!("1024-16".parse::<SizeRange>().is_err());
+        assert!("abc".parse::<SizeRange>().is_err());
+    }
+
+    #[test]
+    fn percentile_should_work() {
+        let samples: Vec<u64> = (1..=1000).collect();
+        assert_eq!(percentile(&samples, 0.5), 500);
+        assert_eq!(percentile(&samples, 0.99), 990);
+        assert_eq!(percentile(&samples, 0.999), 999);
+        assert_eq!(percentile(&[], 0.5), 0);
+        assert_eq!(percentile(&[7], 0.999), 7);
+    }
+
+    #[test]
+    fn zipfian_should_prefer_small_keys() {
+        let mut workload = workload();
+        workload.keys = 1000;
+        workload.distribution = Distribution::Zipfian;
+        let keys = KeyChooser::new(&workload);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples: Vec<_> = (0..10000).map(|_| keys.choose(&mut rng)).collect();
+        assert!(samples.iter().all(|&i| i < 1000));
+        // theta 为 0.99 时，前 1% 的 key 占了大约 40% 的请求
+        let hot = samples.iter().filter(|&&i| i < 10).count();
+        assert!(hot > 3000, "hot keys got {} requests", hot);
+    }
+
+    #[test]
+    fn generator_should_follow_the_workload() {
+        let mut workload = workload();
+        workload.read_ratio = 0.0;
+        workload.value_size = "8-16".parse().unwrap();
+        let workload = Arc::new(workload);
+        let keys = Arc::new(KeyChooser::new(&workload));
+
+        let mut gen = Generator::new(workload, keys, 1);
+        for _ in 0..100 {
+            let (op, cmd) = gen.next();
+            assert_eq!(op, Op::Write);
+            let Some(kv::RequestData::Hset(hset)) = cmd.request_data else {
+                panic!("expect HSET, got {:?}", cmd);
+            };
+            let value: Bytes = hset.pair.unwrap().value.unwrap().try_into().unwrap();
+            assert!((8..=16).contains(&value.len()));
+        }
+    }
+
+    #[tokio::test]
+    async fn run_should_report_all_requests() -> Result<()> {
+        let service = Service::new(MemTable::new());
+        let conns = (0..2)
+            .map(|_| ClientConnection::in_process(service.clone()))
+            .collect();
+
+        let mut workload = workload();
+        workload.duration = 1;
+        workload.keys = 100;
+        workload.pipeline = 8;
+        workload.prefill = true;
+        let report = run(conns, workload).await?;
+
+        assert!(report.ops > 0);
+        assert_eq!(report.ops, report.read.count + report.write.count);
+        assert_eq!(report.errors, 0);
+        assert!(report.all.p50_us <= report.all.p999_us);
+
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(json["workload"]["value_size"], "64");
+        assert_eq!(json["workload"]["distribution"], "uniform");
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use kv::{
+    apply_dump, clear, export, read_dump, ConfigLoader, DumpFormat, ServerConfig, SledTable,
+    StorageConfig,
+};
+
+/// 离线导出、导入 KV 的数据，使用之前需要先停止服务器
+#[derive(Parser, Debug)]
+#[command(name = "kv-dump", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 把所有数据导出到文件
+    Export {
+        #[command(flatten)]
+        store: StoreArgs,
+        /// 输出文件，不指定时输出到 stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// jsonl 或者 protobuf，不指定时根据文件扩展名决定
+        #[arg(short, long, default_value = "")]
+        format: String,
+    },
+    /// 从文件导入数据，已有的 key 会被覆盖
+    Import {
+        #[command(flatten)]
+        store: StoreArgs,
+        /// 输入文件，不指定时从 stdin 读取
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// jsonl 或者 protobuf，不指定时根据文件扩展名决定
+        #[arg(short, long, default_value = "")]
+        format: String,
+        /// 导入之前先清空所有数据
+        #[arg(long)]
+        clear: bool,
+    },
+}
+
+/// 要操作的存储
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct StoreArgs {
+    /// 服务器的配置文件，使用其中的 storage
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// sled 数据库的目录
+    #[arg(long)]
+    sled: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Export {
+            store,
+            output,
+            format,
+        } => {
+            let store = open(&store)?;
+            let format = DumpFormat::detect(&format, output.clone().unwrap_or_default())?;
+            let count = match output {
+                Some(path) => export(&store, BufWriter::new(File::create(path)?), format)?,
+                None => export(&store, BufWriter::new(io::stdout().lock()), format)?,
+            };
+            eprintln!("Exported {} pairs ({})", count, format.name());
+        }
+        Command::Import {
+            store,
+            input,
+            format,
+            clear: clear_first,
+        } => {
+            let store = open(&store)?;
+            let format = DumpFormat::detect(&format, input.clone().unwrap_or_default())?;
+            // 读完所有的记录再清空，文件损坏时不会丢掉已有的数据
+            let records = match input {
+                Some(path) => read_dump(BufReader::new(File::open(path)?), format)?,
+                None => read_dump(io::stdin().lock(), format)?,
+            };
+            if clear_first {
+                clear(&store)?;
+            }
+            let count = apply_dump(&store, records)?;
+            store.flush()?;
+            eprintln!("Imported {} pairs ({})", count, format.name());
+        }
+    }
+
+    Ok(())
+}
+
+fn open(args: &StoreArgs) -> Result<SledTable> {
+    let path = match (&args.sled, &args.config) {
+        (Some(path), _) => path.clone(),
+        (None, Some(config)) => {
+            let config: ServerConfig = ConfigLoader::new().with_file(config).load()?;
+            match config.storage {
+                StorageConfig::SledTable(path) => path.into(),
+                StorageConfig::MemTable => {
+                    return Err(anyhow!(
+                        "MemTable only keeps data in memory, use the backup command on the running server"
+                    ))
+                }
+            }
+        }
+        (None, None) => unreachable!("clap requires one of --config and --sled"),
+    };
+
+    let db = sled::open(&path).map_err(|e| anyhow!("cannot open {:?}: {}", path, e))?;
+    Ok(SledTable::new(db))
+}
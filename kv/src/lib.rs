@@ -2,31 +2,88 @@ mod config;
 mod error;
 mod network;
 mod pb;
+mod reload;
+mod script;
 mod service;
 mod storage;
+mod store;
+mod telemetry;
 
 use anyhow::Result;
 pub use config::*;
 pub use error::KvError;
 pub use network::*;
 pub use pb::*;
+pub use reload::*;
+pub use script::*;
 pub use service::*;
 pub use storage::*;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_rustls::client;
+pub use store::*;
+pub use telemetry::*;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{lookup_host, TcpStream};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tracing::{info, instrument, span};
+use tracing::{info, instrument, warn};
 
 #[instrument(skip_all)]
 pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
-    let acceptor =
-        TlsServerAcceptor::new(&config.tls.cert, &config.tls.key, config.tls.ca.as_deref())?;
+    start_server_with_runtime(ServerRuntime::new(config)?).await
+}
 
+/// 使用可以热更新的运行时启动服务器
+#[instrument(skip_all)]
+pub async fn start_server_with_runtime(runtime: ServerRuntime) -> Result<()> {
+    let config = runtime.config();
     let addr = &config.general.addr;
-    match &config.storage {
-        StorageConfig::MemTable => start_tls_server(addr, MemTable::new(), acceptor).await?,
-        StorageConfig::SledTable(path) => {
-            start_tls_server(addr, SledTable::open_path(path), acceptor).await?
+    let max_frame = config.general.max_frame_size;
+
+    let service = match &config.storage {
+        StorageConfig::MemTable => match &config.memory {
+            Some(memory) => Service::new(MemTable::with_memory_limit(
+                memory.max_bytes as usize,
+                memory.eviction,
+            )),
+            None => Service::new(MemTable::new()),
+        },
+        StorageConfig::SledTable(path) => Service::new(SledTable::open_path(path)),
+    };
+    let service = match &config.backup {
+        Some(backup) => service.with_backup_dir(&backup.dir),
+        None => service,
+    };
+    let service = match &config.script {
+        Some(script) => service.with_scripting(script.limits()),
+        None => service,
+    };
+    let service = match config.log.audit {
+        true => service.with_audit(AuditLog::from_config(&config.log)),
+        false => service,
+    };
+    let service = match config.namespaces.is_empty() {
+        true => service,
+        false => service.with_namespaces(&config.namespaces),
+    };
+    let service = service
+        .with_middleware(LoggingMiddleware)
+        .with_middleware(runtime.rate_limit())
+        .with_middleware(runtime.auth());
+
+    match (&config.general.transport, &config.tls) {
+        (TransportConfig::Quic, Some(tls)) => {
+            let quic = quic_server_config(&tls.cert, &tls.key, tls.ca.as_deref())?;
+            start_quic_server(addr, service, quic, max_frame).await?
+        }
+        (transport, _) => {
+            let listener = match transport {
+                #[cfg(unix)]
+                TransportConfig::Unix => Listener::bind_unix(addr).await?,
+                #[cfg(not(unix))]
+                TransportConfig::Unix => return Err(unix_unsupported().into()),
+                _ => Listener::bind_tcp(addr).await?,
+            };
+            info!("Start listening on {} ({:?})", addr, transport);
+            start_yamux_server(listener, service, runtime.acceptor(), max_frame).await?
         }
     };
 
@@ -35,47 +92,169 @@ pub async fn start_server_with_config(config: &ServerConfig) -> Result<()> {
 
 /// 通过配置创建 KV 客户端
 #[instrument(skip_all)]
-pub async fn start_client_with_config(
-    config: &ClientConfig,
-) -> Result<YamuxCtrl<client::TlsStream<TcpStream>>> {
+pub async fn start_client_with_config(config: &ClientConfig) -> Result<ClientConnection> {
+    check_tls(&config.general, config.tls.is_some())?;
+
     let addr = &config.general.addr;
-    let tls = &config.tls;
+    let tls = config.tls.as_ref();
+    let identity = tls
+        .and_then(|tls| tls.identity.as_ref())
+        .map(|(c, k)| (c.as_str(), k.as_str()));
+
+    // 握手，协商协议版本和压缩算法
+    let mut codecs = vec![Codec::default()];
+    codecs.extend(Codec::ALL.into_iter().filter(|c| *c != Codec::default()));
+    let mut hello = Hello::new(&codecs, vec![]).with_max_frame_size(config.general.max_frame_size);
+    if let Some(namespace) = &config.namespace {
+        hello = hello.with_namespace(namespace);
+    }
+
+    let stream: BoxedStream = match (&config.general.transport, tls) {
+        (TransportConfig::Quic, Some(tls)) => {
+            let quic = quic_client_config(identity, tls.ca.as_deref())?;
+            let addr = lookup_host(addr)
+                .await?
+                .next()
+                .ok_or_else(|| KvError::Internal(format!("Cannot resolve {}", addr)))?;
+            let ctrl = QuicCtrl::connect(addr, &tls.domain, quic).await?;
+
+            // QUIC 没有连接级别的 stream，用第一个 stream 握手
+            let mut stream = ctrl.open_stream().await?;
+            let handshake = client_handshake(&mut stream, hello).await?;
 
-    let identity = tls.identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
-    let connector = TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())?;
-    let stream = TcpStream::connect(addr).await?;
-    let stream = connector.connect(stream).await?;
+            return Ok(ClientConnection::Quic(ctrl, handshake));
+        }
+        #[cfg(unix)]
+        (TransportConfig::Unix, _) => Box::new(UnixStream::connect(addr).await?),
+        #[cfg(not(unix))]
+        (TransportConfig::Unix, _) => return Err(unix_unsupported().into()),
+        _ => Box::new(TcpStream::connect(addr).await?),
+    };
+
+    let mut stream: BoxedStream = match tls {
+        Some(tls) => {
+            let connector = TlsClientConnector::new(&tls.domain, identity, tls.ca.as_deref())?;
+            Box::new(connector.connect(stream).await?)
+        }
+        None => stream,
+    };
+    let handshake = client_handshake(&mut stream, hello).await?;
 
-    // 打开一个 stream
-    Ok(YamuxCtrl::new_client(stream, None))
+    Ok(ClientConnection::Yamux(
+        YamuxCtrl::new_client(stream, None),
+        handshake,
+    ))
 }
 
-async fn start_tls_server<Store: Storage>(
-    addr: &str,
-    store: Store,
-    acceptor: TlsServerAcceptor,
+/// 当前平台没有 Unix domain socket
+#[cfg(not(unix))]
+fn unix_unsupported() -> KvError {
+    KvError::InvalidConfig(UNIX_UNSUPPORTED.into())
+}
+
+async fn start_yamux_server(
+    listener: Listener,
+    service: Service,
+    acceptor: Option<TlsServerAcceptor>,
+    max_frame: usize,
 ) -> Result<()> {
-    let service = Service::new(store);
-    let listener = TcpListener::bind(addr).await?;
-    info!("Start listening on {}", addr);
     loop {
-        let root = span!(tracing::Level::INFO, "server_process");
-        let _enter = root.enter();
         let tls = acceptor.clone();
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
 
         let svc = service.clone();
         tokio::spawn(async move {
-            let stream = tls.accept(stream).await.unwrap();
+            let (mut stream, identity): (BoxedStream, _) = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => {
+                        let identity = peer_identity(&stream);
+                        (Box::new(stream), identity)
+                    }
+                    Err(e) => {
+                        warn!("Client {:?} TLS failed: {}", addr, e);
+                        return;
+                    }
+                },
+                None => (stream, None),
+            };
+            let handshake = match server_handshake(&mut stream, max_frame).await {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    warn!("Client {:?} handshake failed: {}", addr, e);
+                    return;
+                }
+            };
+            let ctx = ConnContext::new(addr, identity).with_handshake(handshake);
             YamuxCtrl::new_server(stream, None, move |stream| {
                 let svc1 = svc.clone();
+                let ctx = ctx.clone();
                 async move {
-                    let stream = ProstServerStream::new(stream.compat(), svc1.clone());
-                    stream.process().await.unwrap();
+                    let stream =
+                        ProstServerStream::new(stream.compat(), svc1.clone()).with_context(ctx);
+                    if let Err(e) = stream.process().await {
+                        warn!("Failed to process stream: {}", e);
+                    }
                     Ok(())
                 }
             });
         });
     }
 }
+
+async fn start_quic_server(
+    addr: &str,
+    service: Service,
+    config: quinn::ServerConfig,
+    max_frame: usize,
+) -> Result<()> {
+    let addr = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| KvError::Internal(format!("Cannot resolve {}", addr)))?;
+    let endpoint = quinn::Endpoint::server(config, addr)?;
+    info!("Start listening on {} (QUIC)", addr);
+    while let Some(connecting) = endpoint.accept().await {
+        let svc = service.clone();
+        tokio::spawn(async move {
+            let conn = match connecting.await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("QUIC connection failed: {}", e);
+                    return;
+                }
+            };
+            let addr = conn.remote_address();
+            info!("Client {:?} connected", addr);
+
+            // 第一个 stream 用于握手
+            let handshake = match conn.accept_bi().await {
+                Ok((send, recv)) => {
+                    server_handshake(&mut QuicStream::new(send, recv), max_frame).await
+                }
+                Err(e) => Err(e.into()),
+            };
+            let handshake = match handshake {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    warn!("Client {:?} handshake failed: {}", addr, e);
+                    return;
+                }
+            };
+            let ctx =
+                ConnContext::new(Some(addr), quic_peer_identity(&conn)).with_handshake(handshake);
+
+            while let Ok((send, recv)) = conn.accept_bi().await {
+                let stream = ProstServerStream::new(QuicStream::new(send, recv), svc.clone())
+                    .with_context(ctx.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = stream.process().await {
+                        warn!("Failed to process stream: {}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    Ok(())
+}
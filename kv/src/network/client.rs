@@ -0,0 +1,625 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+    time,
+};
+use tracing::warn;
+
+use crate::{
+    from_value, start_client_with_config, to_value, BoxedStream, ClientConfig, ClientConnection,
+    CommandRequest, CommandResponse, KvError, Kvpair, ProstClientStream, StreamResult, Value,
+};
+
+/// 订阅的数据在本地缓存的条数
+const SUBSCRIPTION_BUF_SIZE: usize = 128;
+
+/// 高层的 KV 客户端
+///
+/// 内部维护一个连接池，连接断开后会按照指数退避重新连接。
+/// 发送之前就失败的命令总是会重试，发送之后失败的命令只有幂等的才会重试。
+/// clone 之后共享同一个连接池。
+#[derive(Clone)]
+pub struct KvClient {
+    config: Arc<ClientConfig>,
+    options: ClientOptions,
+    pool: Arc<Vec<Mutex<Slot>>>,
+    next: Arc<AtomicUsize>,
+}
+
+/// 连接池和重试的参数
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// 连接池里的连接数
+    pub pool_size: usize,
+    /// 第一次重试前等待的时间，之后每次翻倍
+    pub initial_backoff: Duration,
+    /// 重试前最多等待的时间
+    pub max_backoff: Duration,
+    /// 最多重试的次数
+    pub max_retries: usize,
+}
+
+/// 订阅的数据流，连接断开后会自动重新订阅
+pub struct Subscription {
+    topic: String,
+    id: Arc<AtomicU32>,
+    rx: mpsc::Receiver<Value>,
+    task: JoinHandle<()>,
+    client: KvClient,
+}
+
+/// 连接池里的一个连接，generation 每次重连加一
+#[derive(Default)]
+struct Slot {
+    conn: Option<ClientConnection>,
+    generation: u64,
+}
+
+/// 指数退避
+struct Backoff {
+    delay: Duration,
+    max_delay: Duration,
+    retries: usize,
+    max_retries: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            pool_size: 4,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_retries: 5,
+        }
+    }
+}
+
+impl KvClient {
+    /// 创建客户端，连接在第一次使用时建立
+    pub fn new(config: ClientConfig) -> Self {
+        Self::with_options(config, ClientOptions::default())
+    }
+
+    pub fn with_options(config: ClientConfig, options: ClientOptions) -> Self {
+        let pool = (0..options.pool_size.max(1))
+            .map(|_| Mutex::new(Slot::default()))
+            .collect();
+
+        Self {
+            config: Arc::new(config),
+            options,
+            pool: Arc::new(pool),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    /// 执行一个命令，返回服务器的响应
+    pub async fn execute(&self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let mut backoff = Backoff::new(&self.options);
+        loop {
+            let (idx, generation, mut stream) = match self.open_stream().await {
+                Ok(v) => v,
+                // 命令还没有发送出去，总是可以重试
+                Err(e) => {
+                    backoff.wait(e).await?;
+                    continue;
+                }
+            };
+
+            // 失败时可能要重试，所以发送的是一份拷贝
+            match stream.execute_unary(cmd.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    self.reset(idx, generation).await;
+                    if !cmd.is_idempotent() {
+                        return Err(e);
+                    }
+                    warn!("Failed to execute {}, retrying: {}", cmd.name(), e);
+                    backoff.wait(e).await?;
+                }
+            }
+        }
+    }
+
+    /// 获取 table 中 key 的值，key 不存在时返回 None
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<T>, KvError> {
+        let res = self.execute(&CommandRequest::new_hget(table, key)).await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(None);
+        }
+        first_value(res)
+    }
+
+    /// 获取 table 中一组 key 的值，不存在的 key 对应 None
+    pub async fn mget<T: DeserializeOwned>(
+        &self,
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Vec<Option<T>>, KvError> {
+        let keys: Vec<String> = keys.into_iter().map(|k| k.into()).collect();
+        let res = self
+            .execute(&CommandRequest::new_hmget(table, keys))
+            .await?;
+        res.into_result()?
+            .values
+            .into_iter()
+            .map(optional)
+            .collect()
+    }
+
+    /// 获取 table 中所有的 kv pair
+    pub async fn getall<T: DeserializeOwned>(
+        &self,
+        table: impl Into<String>,
+    ) -> Result<HashMap<String, T>, KvError> {
+        let res = self.execute(&CommandRequest::new_hgetall(table)).await?;
+        res.into_result()?
+            .pairs
+            .into_iter()
+            .map(|p| Ok((p.key, from_value(p.value.unwrap_or_default())?)))
+            .collect()
+    }
+
+    /// 设置 table 中 key 的值
+    pub async fn set<T: Serialize + ?Sized>(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), KvError> {
+        let cmd = CommandRequest::new_hset(table, key, to_value(value)?);
+        self.execute(&cmd).await?.into_result()?;
+        Ok(())
+    }
+
+    /// 设置 table 中一组 key 的值
+    pub async fn mset<T: Serialize>(
+        &self,
+        table: impl Into<String>,
+        pairs: impl IntoIterator<Item = (impl Into<String>, T)>,
+    ) -> Result<(), KvError> {
+        let pairs = pairs
+            .into_iter()
+            .map(|(k, v)| Ok(Kvpair::new(k, to_value(&v)?)))
+            .collect::<Result<_, KvError>>()?;
+        self.execute(&CommandRequest::new_hmset(table, pairs))
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// 删除 table 中的 key，返回之前的值
+    pub async fn del<T: DeserializeOwned>(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<T>, KvError> {
+        let res = self.execute(&CommandRequest::new_hdel(table, key)).await?;
+        first_value(res)
+    }
+
+    /// 删除 table 中的一组 key
+    pub async fn mdel(
+        &self,
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<(), KvError> {
+        let keys: Vec<String> = keys.into_iter().map(|k| k.into()).collect();
+        self.execute(&CommandRequest::new_hmdel(table, keys))
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// 查看 table 中 key 是否存在
+    pub async fn exists(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        let res = self
+            .execute(&CommandRequest::new_hexist(table, key))
+            .await?;
+        if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+            return Ok(false);
+        }
+        res.into_result()?;
+        Ok(true)
+    }
+
+    /// 向 topic 发布一组数据
+    pub async fn publish<T: Serialize>(
+        &self,
+        topic: impl Into<String>,
+        values: &[T],
+    ) -> Result<(), KvError> {
+        let values = values.iter().map(to_value).collect::<Result<_, _>>()?;
+        self.execute(&CommandRequest::new_publish(topic, values))
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// 订阅 topic，连接断开后会自动重新订阅
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<Subscription, KvError> {
+        let topic = topic.into();
+        let stream = self.subscribe_stream(&topic).await?;
+
+        let id = Arc::new(AtomicU32::new(stream.id));
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUF_SIZE);
+        let task = tokio::spawn(forward(self.clone(), topic.clone(), id.clone(), stream, tx));
+
+        Ok(Subscription {
+            topic,
+            id,
+            rx,
+            task,
+            client: self.clone(),
+        })
+    }
+
+    async fn subscribe_stream(&self, topic: &str) -> Result<StreamResult, KvError> {
+        let cmd = CommandRequest::new_subscribe(topic);
+        let mut backoff = Backoff::new(&self.options);
+        loop {
+            let err = match self.open_stream().await {
+                Ok((idx, generation, stream)) => {
+                    match stream.execute_streaming(cmd.clone()).await {
+                        Ok(stream) => return Ok(stream),
+                        Err(e) => {
+                            self.reset(idx, generation).await;
+                            e
+                        }
+                    }
+                }
+                Err(e) => e,
+            };
+            // 旧连接上的订阅会在服务器发送失败时被清理掉，所以订阅可以重试
+            backoff.wait(err).await?;
+        }
+    }
+
+    /// 从连接池里挑一个连接打开 stream，连接不存在或者已经断开时重新连接
+    async fn open_stream(&self) -> Result<(usize, u64, ProstClientStream<BoxedStream>), KvError> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        let mut slot = self.pool[idx].lock().await;
+
+        if slot.conn.is_none() {
+            let conn = start_client_with_config(&self.config)
+                .await
+                .map_err(|e| KvError::Internal(e.to_string()))?;
+            slot.conn = Some(conn);
+            slot.generation += 1;
+        }
+
+        let generation = slot.generation;
+        match slot.conn.as_mut().unwrap().open_stream().await {
+            Ok(stream) => Ok((idx, generation, stream)),
+            Err(e) => {
+                slot.conn = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// 丢弃出错的连接，下次使用时重新连接；如果连接已经被替换了就什么都不做
+    async fn reset(&self, idx: usize, generation: u64) {
+        let mut slot = self.pool[idx].lock().await;
+        if slot.generation == generation {
+            slot.conn = None;
+        }
+    }
+}
+
+impl Subscription {
+    /// 当前的订阅 id，重新订阅后会改变
+    pub fn id(&self) -> u32 {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// 取消订阅
+    pub async fn unsubscribe(self) -> Result<CommandResponse, KvError> {
+        // 先停止转发，避免取消之后又重新订阅
+        self.task.abort();
+        let cmd = CommandRequest::new_unsubscribe(&self.topic, self.id());
+        self.client.execute(&cmd).await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Value;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 取出响应里的第一个值，空的 Value 代表 None
+fn first_value<T: DeserializeOwned>(res: CommandResponse) -> Result<Option<T>, KvError> {
+    match res.into_result()?.values.into_iter().next() {
+        Some(v) => optional(v),
+        None => Ok(None),
+    }
+}
+
+fn optional<T: DeserializeOwned>(v: Value) -> Result<Option<T>, KvError> {
+    match v.value {
+        Some(_) => from_value(v).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// 把订阅的数据转发给 Subscription，订阅断开后重新订阅
+async fn forward(
+    client: KvClient,
+    topic: String,
+    id: Arc<AtomicU32>,
+    mut stream: StreamResult,
+    tx: mpsc::Sender<Value>,
+) {
+    loop {
+        while let Some(Ok(res)) = stream.next().await {
+            for v in res.values {
+                if tx.send(v).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        warn!(
+            "Subscription {} on {} lost, resubscribing",
+            stream.id, topic
+        );
+        stream = match client.subscribe_stream(&topic).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to resubscribe {}: {}", topic, e);
+                return;
+            }
+        };
+        id.store(stream.id, Ordering::Relaxed);
+    }
+}
+
+impl Backoff {
+    fn new(options: &ClientOptions) -> Self {
+        Self {
+            delay: options.initial_backoff,
+            max_delay: options.max_backoff,
+            retries: 0,
+            max_retries: options.max_retries,
+        }
+    }
+
+    /// 等待下一次重试，重试次数用完时返回最后一次的错误
+    async fn wait(&mut self, err: KvError) -> Result<(), KvError> {
+        if self.retries >= self.max_retries {
+            return Err(err);
+        }
+        self.retries += 1;
+
+        time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(self.max_delay);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_res_ok, start_server_with_config, ServerConfig, StorageConfig, TransportConfig,
+        Value,
+    };
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// 在客户端和服务器之间转发数据，可以随时切断所有连接
+    struct Proxy {
+        conns: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
+    }
+
+    impl Proxy {
+        fn start(listen: &Path, upstream: PathBuf) -> Self {
+            let listener = UnixListener::bind(listen).unwrap();
+            let conns = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let conns1 = conns.clone();
+
+            tokio::spawn(async move {
+                while let Ok((mut client, _)) = listener.accept().await {
+                    let upstream = upstream.clone();
+                    let handle = tokio::spawn(async move {
+                        let mut server = UnixStream::connect(upstream).await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                    });
+                    conns1.lock().unwrap().push(handle);
+                }
+            });
+
+            Self { conns }
+        }
+
+        /// 模拟网络断开，返回时所有转发的连接都已经关闭
+        async fn cut(&self) {
+            let conns: Vec<_> = self.conns.lock().unwrap().drain(..).collect();
+            for handle in conns {
+                handle.abort();
+                let _ = handle.await;
+            }
+        }
+    }
+
+    /// 服务器开始监听之前连接会失败，重试直到连上
+    async fn wait_for_listener(path: &Path) {
+        while UnixStream::connect(path).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn start_server() -> Result<(TempDir, Proxy, KvClient)> {
+        let dir = tempfile::tempdir()?;
+        let server_addr = dir.path().join("server.sock");
+        let proxy_addr = dir.path().join("proxy.sock");
+
+        let mut config: ServerConfig = toml::from_str(include_str!("../../fixtures/server.conf"))?;
+        config.general.addr = server_addr.to_string_lossy().into();
+        config.general.transport = TransportConfig::Unix;
+        config.storage = StorageConfig::MemTable;
+        config.tls = None;
+        tokio::spawn(async move { start_server_with_config(&config).await.unwrap() });
+        wait_for_listener(&server_addr).await;
+
+        let proxy = Proxy::start(&proxy_addr, server_addr);
+
+        let mut config: ClientConfig = toml::from_str(include_str!("../../fixtures/client.conf"))?;
+        config.general.addr = proxy_addr.to_string_lossy().into();
+        config.general.transport = TransportConfig::Unix;
+        config.tls = None;
+        let options = ClientOptions {
+            pool_size: 2,
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+
+        Ok((dir, proxy, KvClient::with_options(config, options)))
+    }
+
+    #[tokio::test]
+    async fn client_should_reconnect_and_retry() -> Result<()> {
+        let (_dir, proxy, client) = start_server().await?;
+
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        proxy.cut().await;
+
+        // 所有的连接都断开了，读命令会重连之后重试
+        for _ in 0..4 {
+            let res = client
+                .execute(&CommandRequest::new_hget("t1", "k1"))
+                .await?;
+            assert_res_ok(&res, &["v1".into()], &[]);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_resubscribe_after_reconnect() -> Result<()> {
+        let (_dir, proxy, client) = start_server().await?;
+
+        let mut sub = client.subscribe("lobby").await?;
+        let publish = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        client.execute(&publish).await?;
+        assert_eq!(sub.next().await, Some("hello".into()));
+
+        proxy.cut().await;
+
+        // 重新订阅之前发布的数据会丢失，所以一直发布直到收到为止
+        let received = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if client.execute(&publish).await.is_ok() {
+                    if let Ok(Some(res)) =
+                        time::timeout(Duration::from_millis(50), sub.next()).await
+                    {
+                        return res;
+                    }
+                }
+            }
+        })
+        .await?;
+        assert_eq!(received, "hello".into());
+
+        let res = sub.unsubscribe().await?;
+        assert_eq!(res.status, 200);
+
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[tokio::test]
+    async fn typed_api_should_work() -> Result<()> {
+        let (_dir, _proxy, client) = start_server().await?;
+
+        let user = User {
+            name: "tyr".into(),
+            age: 30,
+        };
+        client.set("users", "u1", &user).await?;
+        client.mset("scores", [("a", 1), ("b", 2)]).await?;
+
+        assert_eq!(client.get::<User>("users", "u1").await?, Some(user));
+        assert_eq!(client.get::<User>("users", "u2").await?, None);
+        assert_eq!(
+            client.mget::<i64>("scores", ["a", "c"]).await?,
+            vec![Some(1), None]
+        );
+
+        let all = client.getall::<i64>("scores").await?;
+        assert_eq!(all, HashMap::from([("a".into(), 1), ("b".into(), 2)]));
+
+        assert!(client.exists("scores", "a").await?);
+        assert_eq!(client.del::<i64>("scores", "a").await?, Some(1));
+        assert!(!client.exists("scores", "a").await?);
+
+        // 类型不匹配时返回转换错误
+        let result = client.get::<i64>("users", "u1").await;
+        assert!(matches!(result, Err(KvError::ConvertError(..))));
+
+        // 取消不存在的订阅，状态码被转换成 KvError
+        let cmd = CommandRequest::new_unsubscribe("lobby", 9527);
+        let result = client.execute(&cmd).await?.into_result();
+        assert!(matches!(result, Err(KvError::ServerError(404, _))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_read_commands_should_be_idempotent() {
+        assert!(CommandRequest::new_hget("t1", "k1").is_idempotent());
+        assert!(CommandRequest::new_hmexist("t1", vec!["k1"]).is_idempotent());
+        assert!(!CommandRequest::new_hset("t1", "k1", "v1".into()).is_idempotent());
+        assert!(!CommandRequest::new_publish("lobby", vec![]).is_idempotent());
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{self, Read, Write};
+
+use bytes::{BufMut, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::KvError;
+
+/// 代表压缩的 bit（整个长度 4 字节的最高位）
+const COMPRESSION_BIT: u32 = 1 << 31;
+/// 压缩算法占用 COMPRESSION_BIT 之后的 2 个 bit
+const CODEC_SHIFT: u32 = 29;
+const CODEC_MASK: u32 = 0b11 << CODEC_SHIFT;
+/// header 中除去压缩信息之后的部分是长度
+pub const LEN_MASK: u32 = !(COMPRESSION_BIT | CODEC_MASK);
+
+/// frame payload 使用的压缩算法
+///
+/// 压缩算法记录在 frame header 的最高 3 位：最高位代表是否压缩，
+/// 之后 2 位代表压缩算法。gzip 是 `00`，所以和之前只支持 gzip 的 frame 兼容
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Codec {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+    Lz4,
+    Snappy,
+}
+
+impl Codec {
+    /// 所有支持的压缩算法
+    pub const ALL: [Codec; 5] = [
+        Codec::None,
+        Codec::Gzip,
+        Codec::Zstd,
+        Codec::Lz4,
+        Codec::Snappy,
+    ];
+
+    /// 压缩算法的名字，用于握手
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::Snappy => "snappy",
+        }
+    }
+
+    /// 从名字得到压缩算法，不认识的名字返回 None
+    pub fn from_name(name: &str) -> Option<Self> {
+        Codec::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    /// 从 frame header 中取出压缩算法
+    pub fn from_header(header: u32) -> Self {
+        if header & COMPRESSION_BIT == 0 {
+            return Codec::None;
+        }
+
+        match (header & CODEC_MASK) >> CODEC_SHIFT {
+            0 => Codec::Gzip,
+            1 => Codec::Zstd,
+            2 => Codec::Lz4,
+            _ => Codec::Snappy,
+        }
+    }
+
+    /// 压缩算法在 frame header 中对应的 bit
+    pub fn header_bits(self) -> u32 {
+        let id = match self {
+            Codec::None => return 0,
+            Codec::Gzip => 0,
+            Codec::Zstd => 1,
+            Codec::Lz4 => 2,
+            Codec::Snappy => 3,
+        };
+        COMPRESSION_BIT | (id << CODEC_SHIFT)
+    }
+
+    /// 压缩 src，并把结果追加到 dst 之后
+    pub fn compress(self, src: &[u8], dst: &mut BytesMut) -> Result<(), KvError> {
+        let writer = dst.writer();
+        match self {
+            Codec::None => finish(writer, src, Ok),
+            Codec::Gzip => finish(GzEncoder::new(writer, Compression::default()), src, |e| {
+                e.finish()
+            }),
+            Codec::Zstd => finish(zstd::Encoder::new(writer, 0)?, src, |e| e.finish()),
+            Codec::Lz4 => finish(lz4_flex::frame::FrameEncoder::new(writer), src, |e| {
+                e.finish().map_err(io::Error::from)
+            }),
+            Codec::Snappy => finish(snap::write::FrameEncoder::new(writer), src, |e| {
+                e.into_inner().map_err(|e| e.into_error())
+            }),
+        }
+    }
+
+    /// 解压缩 src，解压后的数据超过 limit 字节时返回错误，避免被很小的 frame 撑爆内存
+    pub fn decompress(self, src: &[u8], limit: usize) -> Result<BytesMut, KvError> {
+        let mut dst = BytesMut::with_capacity((src.len() * 2).min(limit));
+        let mut writer = (&mut dst).writer();
+        let n = match self {
+            Codec::None => copy(src, &mut writer, limit)?,
+            Codec::Gzip => copy(GzDecoder::new(src), &mut writer, limit)?,
+            Codec::Zstd => copy(zstd::Decoder::with_buffer(src)?, &mut writer, limit)?,
+            Codec::Lz4 => copy(lz4_flex::frame::FrameDecoder::new(src), &mut writer, limit)?,
+            Codec::Snappy => copy(snap::read::FrameDecoder::new(src), &mut writer, limit)?,
+        };
+
+        if n > limit as u64 {
+            return Err(KvError::FrameTooLarge(limit));
+        }
+        Ok(dst)
+    }
+}
+
+fn finish<W, T>(
+    mut encoder: W,
+    src: &[u8],
+    f: impl FnOnce(W) -> io::Result<T>,
+) -> Result<(), KvError>
+where
+    W: Write,
+{
+    encoder.write_all(src)?;
+    f(encoder)?;
+    Ok(())
+}
+
+/// 最多读取 limit + 1 个字节，多出来的一个字节用来判断是否超过了 limit
+fn copy(decoder: impl Read, writer: &mut impl Write, limit: usize) -> io::Result<u64> {
+    io::copy(&mut decoder.take(limit as u64 + 1), writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codec_header_bits_should_roundtrip() {
+        for codec in Codec::ALL {
+            let header = codec.header_bits() | 1024;
+            assert_eq!(Codec::from_header(header), codec);
+            assert_eq!(header & LEN_MASK, 1024);
+        }
+
+        // 只有最高位的 header 是之前的 gzip frame
+        assert_eq!(Codec::from_header(COMPRESSION_BIT | 1024), Codec::Gzip);
+    }
+
+    #[test]
+    fn codec_compress_decompress_should_work() {
+        let data = b"hello world! ".repeat(1024);
+        for codec in Codec::ALL {
+            let mut buf = BytesMut::new();
+            codec.compress(&data, &mut buf).unwrap();
+            if codec != Codec::None {
+                assert!(buf.len() < data.len());
+            }
+
+            let result = codec.decompress(&buf, data.len()).unwrap();
+            assert_eq!(&result[..], &data[..]);
+        }
+    }
+
+    #[test]
+    fn decompress_should_respect_limit() {
+        let data = vec![0u8; 1024 * 1024];
+        for codec in Codec::ALL {
+            let mut buf = BytesMut::new();
+            codec.compress(&data, &mut buf).unwrap();
+
+            let result = codec.decompress(&buf, data.len() - 1);
+            assert_eq!(result, Err(KvError::FrameTooLarge(data.len() - 1)));
+        }
+    }
+}
@@ -1,28 +1,32 @@
-use std::io::{Read, Write};
-
-use crate::{CommandRequest, CommandResponse, KvError};
+use crate::{Codec, CommandRequest, CommandResponse, KvError, LEN_MASK};
 use bytes::{Buf, BufMut, BytesMut};
-use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::debug;
 
 /// 长度整个占用 4 个字节
 pub const LEN_LEN: usize = 4;
-/// 长度占 31 bit，所以最大的 frame 是 2G
-const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
+/// 长度占 29 bit，所以协议上最大的 frame 是 512M
+pub const MAX_FRAME: usize = LEN_MASK as usize + 1;
+/// 默认能接收的最大 frame，可以通过 `general.max_frame_size` 修改
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+/// 读取时每次最多预先分配的内存，大的 frame 要等数据真的到了才继续分配
+const READ_RESERVE: usize = 64 * 1024;
 /// 如果 payload 超过了 1436 字节，就做压缩
 const COMPRESSION_LIMIT: usize = 1436;
-/// 代表压缩的 bit（整个长度 4 字节的最高位）
-const COMPRESSION_BIT: usize = 1 << 31;
 
 /// 处理 Frame 的 encode/decode
 pub trait FrameCoder
 where
     Self: Message + Sized + Default,
 {
-    /// 把一个 Message encode 成一个 frame
+    /// 把一个 Message encode 成一个 frame，需要压缩时使用 gzip
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(Codec::Gzip, buf)
+    }
+
+    /// 把一个 Message encode 成一个 frame，需要压缩时使用 codec
+    fn encode_frame_with(&self, codec: Codec, buf: &mut BytesMut) -> Result<(), KvError> {
         let size = self.encoded_len();
 
         if size >= MAX_FRAME {
@@ -30,59 +34,64 @@ where
         }
 
         // 我们先写入长度，如果需要压缩，再重写压缩后的长度
+        let start = buf.len();
+        buf.reserve(LEN_LEN + size);
         buf.put_u32(size as _);
 
-        if size > COMPRESSION_LIMIT {
-            let mut buf1 = Vec::with_capacity(size);
-            self.encode(&mut buf1)?;
-
-            // BytesMut 支持逻辑上的 split（之后还能 unsplit）
-            // 所以我们先把长度这 4 字节拿走，清除
-            let payload = buf.split_off(LEN_LEN);
-            buf.clear();
-
-            // 处理 gzip 压缩，具体可以参考 flate2 文档
-            let mut encoder = GzEncoder::new(payload.writer(), Compression::default());
-            encoder.write_all(&buf1[..])?;
+        // 直接 encode 到 buf 里，不经过临时的 Vec
+        self.encode(buf)?;
 
-            // 压缩完成后，从 gzip encoder 中把 BytesMut 再拿回来
-            let payload = encoder.finish()?.into_inner();
-            debug!("Encode a frame: size {}({})", size, payload.len());
+        if size > COMPRESSION_LIMIT && codec != Codec::None {
+            // BytesMut 支持逻辑上的 split，所以把刚 encode 好的 payload 拿出来（不拷贝），
+            // 压缩后追加到长度之后
+            let payload = buf.split_off(start + LEN_LEN);
+            codec.compress(&payload, buf)?;
 
-            // 写入压缩后的长度
-            buf.put_u32((payload.len() | COMPRESSION_BIT) as _);
+            let len = buf.len() - start - LEN_LEN;
+            debug!("Encode a frame: size {}({}) with {:?}", size, len, codec);
+            if len >= MAX_FRAME {
+                return Err(KvError::FrameError);
+            }
 
-            // 把 BytesMut 再合并回来
-            buf.unsplit(payload);
-
-            Ok(())
-        } else {
-            self.encode(buf)?;
-            Ok(())
+            // 写入压缩后的长度和压缩算法
+            let header = len as u32 | codec.header_bits();
+            buf[start..start + LEN_LEN].copy_from_slice(&header.to_be_bytes());
         }
+
+        Ok(())
     }
 
-    /// 把一个完整的 frame decode 成一个 Message
+    /// 把一个完整的 frame decode 成一个 Message，frame 不能超过 DEFAULT_MAX_FRAME
     fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
-        // 先取 4 字节，从中拿出长度和 compression bit
-        let header = buf.get_u32() as usize;
-        let (len, compressed) = decode_header(header);
-        debug!("Got a frame: msg len {}, compressed {}", len, compressed);
+        Self::decode_frame_within(buf, DEFAULT_MAX_FRAME)
+    }
+
+    /// 把一个完整的 frame decode 成一个 Message，frame 和解压后的数据都不能超过 max_frame
+    fn decode_frame_within(buf: &mut BytesMut, max_frame: usize) -> Result<Self, KvError> {
+        if buf.len() < LEN_LEN {
+            return Err(KvError::FrameError);
+        }
 
-        if compressed {
-            // 解压缩
-            let mut decoder = GzDecoder::new(&buf[..len]);
-            let mut buf1 = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut buf1)?;
+        // 先取 4 字节，从中拿出长度和压缩算法
+        let header = buf.get_u32();
+        let (len, codec) = decode_header(header);
+        debug!("Got a frame: msg len {}, codec {:?}", len, codec);
 
-            buf.advance(len);
+        if len > max_frame {
+            return Err(KvError::FrameTooLarge(max_frame));
+        }
+        if len > buf.len() {
+            return Err(KvError::FrameError);
+        }
 
-            // decode 成相应的消息
-            Ok(Self::decode(&buf1[..buf1.len()])?)
-        } else {
-            let msg = Self::decode(&buf[..len])?;
-            buf.advance(len);
-            Ok(msg)
+        let payload = buf.split_to(len);
+        match codec {
+            // 不压缩的 frame 直接从 Bytes decode，bytes 字段会引用 frame 的内存，不再拷贝
+            Codec::None => Ok(Self::decode(payload.freeze())?),
+            codec => {
+                let data = codec.decompress(&payload, max_frame)?;
+                Ok(Self::decode(data.freeze())?)
+            }
         }
     }
 }
@@ -90,36 +99,77 @@ where
 impl FrameCoder for CommandRequest {}
 impl FrameCoder for CommandResponse {}
 
-fn decode_header(header: usize) -> (usize, bool) {
-    let len = header & !COMPRESSION_BIT;
-    let compressed = header & COMPRESSION_BIT == COMPRESSION_BIT;
-    (len, compressed)
+/// 从 header 中拿出长度和压缩算法
+fn decode_header(header: u32) -> (usize, Codec) {
+    ((header & LEN_MASK) as usize, Codec::from_header(header))
 }
 
-pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
-where
-    S: AsyncRead + Unpin + Send,
-{
-    let header = stream.read_u32().await? as usize;
+/// 对端使用的压缩算法
+///
+/// 压缩算法只在 payload 超过 COMPRESSION_LIMIT 时才能看出来，小的 frame 返回 None
+pub(crate) fn peer_codec(header: u32) -> Option<Codec> {
+    match decode_header(header) {
+        (len, Codec::None) if len <= COMPRESSION_LIMIT => None,
+        (_, codec) => Some(codec),
+    }
+}
 
-    let (len, _compressed) = decode_header(header);
+/// 从 buf 的开头切出一个完整的 frame（包括 header），数据还不够时返回 None
+///
+/// 只要看到 header 就能判断 frame 是否超过 max_frame，这时不会为它分配内存。
+/// 数据不够时 buf 保持不变，并且为接下来的读取预留一部分空间。
+pub fn split_frame(buf: &mut BytesMut, max_frame: usize) -> Result<Option<BytesMut>, KvError> {
+    if buf.len() < LEN_LEN {
+        buf.reserve(LEN_LEN - buf.len());
+        return Ok(None);
+    }
 
-    buf.reserve(LEN_LEN + len);
-    buf.put_u32(header as u32);
+    let header = u32::from_be_bytes(buf[..LEN_LEN].try_into().unwrap());
+    let (len, _codec) = decode_header(header);
+    if len > max_frame {
+        return Err(KvError::FrameTooLarge(max_frame));
+    }
 
-    unsafe {
-        buf.advance_mut(len);
+    let total = LEN_LEN + len;
+    if buf.len() < total {
+        buf.reserve((total - buf.len()).min(READ_RESERVE));
+        return Ok(None);
     }
+    Ok(Some(buf.split_to(total)))
+}
 
-    stream.read_exact(&mut buf[LEN_LEN..]).await?;
-    Ok(())
+/// 从 stream 中读取一个完整的 frame，stream 在 frame 之间结束时返回 None
+///
+/// 读到的数据都保存在 buf 里，future 被取消之后用同一个 buf 再次调用不会丢失数据。
+pub async fn read_frame<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    max_frame: usize,
+) -> Result<Option<BytesMut>, KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    loop {
+        if let Some(frame) = split_frame(buf, max_frame)? {
+            return Ok(Some(frame));
+        }
+        if stream.read_buf(buf).await? == 0 {
+            // 读到一半的 frame 说明连接被意外断开了
+            return match buf.is_empty() {
+                true => Ok(None),
+                false => Err(KvError::FrameError),
+            };
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{utils::DummyStream, Value};
+    use crate::{arb_value, Value};
     use bytes::Bytes;
+    use proptest::{collection::vec, prelude::*, sample::select};
+    use tokio::io::{duplex, AsyncWriteExt};
 
     #[test]
     fn command_request_encode_decode_should_work() {
@@ -129,7 +179,7 @@ mod tests {
         cmd.encode_frame(&mut buf).unwrap();
 
         // 最高位没设置
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
 
         let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
         assert_eq!(cmd, cmd1);
@@ -144,7 +194,7 @@ mod tests {
         res.encode_frame(&mut buf).unwrap();
 
         // 最高位没设置
-        assert_eq!(is_compressed(&buf), false);
+        assert!(!is_compressed(&buf));
 
         let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res1);
@@ -159,7 +209,7 @@ mod tests {
         res.encode_frame(&mut buf).unwrap();
 
         // 最高位设置了
-        assert_eq!(is_compressed(&buf), true);
+        assert!(is_compressed(&buf));
 
         let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
         assert_eq!(res, res1);
@@ -173,6 +223,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn command_response_encode_decode_with_codecs_should_work() {
+        for codec in Codec::ALL {
+            let mut buf = BytesMut::new();
+
+            let value: Value = Bytes::from(vec![0u8; COMPRESSION_LIMIT + 1]).into();
+            let res: CommandResponse = value.into();
+            res.encode_frame_with(codec, &mut buf).unwrap();
+
+            let header = u32::from_be_bytes(buf[..LEN_LEN].try_into().unwrap());
+            assert_eq!(Codec::from_header(header), codec);
+            assert_eq!(is_compressed(&buf), codec != Codec::None);
+
+            let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+            assert_eq!(res, res1);
+            assert!(buf.is_empty());
+        }
+    }
+
     #[tokio::test]
     async fn read_frame_should_work() {
         let mut buf = BytesMut::new();
@@ -180,12 +249,223 @@ mod tests {
 
         cmd.encode_frame(&mut buf).unwrap();
 
-        let mut stream = DummyStream { buf };
+        let (mut client, mut server) = duplex(4096);
+        client.write_all(&buf).await.unwrap();
+        drop(client);
 
         let mut data = BytesMut::new();
-        read_frame(&mut stream, &mut data).await.unwrap();
+        let mut frame = read_frame(&mut server, &mut data, DEFAULT_MAX_FRAME)
+            .await
+            .unwrap()
+            .unwrap();
 
-        let cmd1 = CommandRequest::decode_frame(&mut data).unwrap();
+        let cmd1 = CommandRequest::decode_frame(&mut frame).unwrap();
         assert_eq!(cmd, cmd1);
+
+        // 在 frame 之间结束
+        let res = read_frame(&mut server, &mut data, DEFAULT_MAX_FRAME).await;
+        assert_eq!(res, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_keep_data_when_cancelled() {
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hset("t1", "k1", Bytes::from(vec![1u8; 1024]).into());
+        cmd.encode_frame_with(Codec::None, &mut buf).unwrap();
+
+        let (mut client, mut server) = duplex(4096);
+        let mut data = BytesMut::new();
+        for piece in buf.chunks(100) {
+            client.write_all(piece).await.unwrap();
+            // 数据不够一个 frame，future 被取消，已经读到的数据留在 data 里
+            let res = futures::FutureExt::now_or_never(read_frame(
+                &mut server,
+                &mut data,
+                DEFAULT_MAX_FRAME,
+            ));
+            if let Some(res) = res {
+                let mut frame = res.unwrap().unwrap();
+                assert_eq!(CommandRequest::decode_frame(&mut frame).unwrap(), cmd);
+                return;
+            }
+        }
+        panic!("frame should be complete");
+    }
+
+    #[test]
+    fn split_frame_should_reject_large_frame_before_allocating() {
+        let mut buf = BytesMut::new();
+        buf.put_u32(LEN_MASK);
+
+        assert_eq!(
+            split_frame(&mut buf, 1024),
+            Err(KvError::FrameTooLarge(1024))
+        );
+        assert!(buf.capacity() < 1024);
+
+        // 在限制之内的大 frame 也只是预留一部分内存
+        let mut buf = BytesMut::new();
+        buf.put_u32(LEN_MASK);
+        assert_eq!(split_frame(&mut buf, MAX_FRAME), Ok(None));
+        assert!(buf.capacity() <= LEN_LEN + READ_RESERVE * 2);
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_fail_on_truncated_frame() {
+        let mut buf = BytesMut::new();
+        CommandRequest::new_hdel("t1", "k1")
+            .encode_frame(&mut buf)
+            .unwrap();
+
+        let (mut client, mut server) = duplex(4096);
+        client.write_all(&buf[..buf.len() - 1]).await.unwrap();
+        drop(client);
+
+        let mut data = BytesMut::new();
+        let res = read_frame(&mut server, &mut data, DEFAULT_MAX_FRAME).await;
+        assert_eq!(res, Err(KvError::FrameError));
+    }
+
+    #[test]
+    fn decode_frame_within_should_limit_decompressed_size() {
+        let mut buf = BytesMut::new();
+        let value: Value = Bytes::from(vec![0u8; 64 * 1024]).into();
+        let res: CommandResponse = value.into();
+        res.encode_frame_with(Codec::Zstd, &mut buf).unwrap();
+        // 压缩之后 frame 很小，解压之后超过了限制
+        assert!(buf.len() < 1024);
+
+        let err = CommandResponse::decode_frame_within(&mut buf, 1024).unwrap_err();
+        assert_eq!(err, KvError::FrameTooLarge(1024));
+    }
+
+    #[test]
+    fn decode_frame_should_use_default_limit() {
+        let mut buf = BytesMut::new();
+        buf.put_u32(DEFAULT_MAX_FRAME as u32 + 1);
+        let err = CommandRequest::decode_frame(&mut buf).unwrap_err();
+        assert_eq!(err, KvError::FrameTooLarge(DEFAULT_MAX_FRAME));
+    }
+
+    fn arb_command() -> impl Strategy<Value = CommandRequest> {
+        prop_oneof![
+            ("[a-z]{1,8}", "[a-z]{1,8}", arb_value())
+                .prop_map(|(t, k, v)| CommandRequest::new_hset(t, k, v)),
+            ("[a-z]{1,8}", "[a-z]{1,8}").prop_map(|(t, k)| CommandRequest::new_hget(t, k)),
+            ("[a-z]{1,8}", vec(arb_value(), 0..8))
+                .prop_map(|(topic, values)| CommandRequest::new_publish(topic, values)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn any_response_should_survive_encode_decode(
+            values in vec(arb_value(), 0..8),
+            padding in vec(any::<u8>(), 0..4096),
+            codec in select(Codec::ALL.to_vec()),
+        ) {
+            // padding 让一部分 frame 超过 COMPRESSION_LIMIT，走压缩的路径
+            let mut values = values;
+            values.push(Bytes::from(padding).into());
+            let res: CommandResponse = values.into();
+
+            let mut buf = BytesMut::new();
+            res.encode_frame_with(codec, &mut buf).unwrap();
+            let header = u32::from_be_bytes(buf[..LEN_LEN].try_into().unwrap());
+            prop_assert_eq!(decode_header(header).0, buf.len() - LEN_LEN);
+
+            prop_assert_eq!(CommandResponse::decode_frame(&mut buf).unwrap(), res);
+            prop_assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn read_frame_should_reassemble_frames_split_anywhere(
+            cmds in vec(arb_command(), 1..8),
+            chunk in 1usize..64,
+        ) {
+            let mut data = BytesMut::new();
+            for cmd in &cmds {
+                cmd.encode_frame(&mut data).unwrap();
+            }
+
+            let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let decoded = rt.block_on(async {
+                // 对端每次只写 chunk 个字节
+                let (mut client, mut server) = duplex(chunk);
+                tokio::spawn(async move {
+                    for piece in data.chunks(chunk) {
+                        client.write_all(piece).await.unwrap();
+                    }
+                });
+
+                let mut buf = BytesMut::new();
+                let mut decoded = vec![];
+                while let Some(mut frame) = read_frame(&mut server, &mut buf, DEFAULT_MAX_FRAME)
+                    .await
+                    .unwrap()
+                {
+                    decoded.push(CommandRequest::decode_frame(&mut frame).unwrap());
+                }
+                decoded
+            });
+
+            prop_assert_eq!(decoded, cmds);
+        }
+
+        #[test]
+        fn random_bytes_should_never_panic_the_decoder(
+            data in vec(any::<u8>(), 0..4096),
+            max_frame in 0usize..8192,
+        ) {
+            // 随机的字节要么被切成 frame 再 decode，要么在某一步出错，不能 panic
+            let mut buf = BytesMut::from(&data[..]);
+            while let Ok(Some(mut frame)) = split_frame(&mut buf, max_frame) {
+                let _ = CommandRequest::decode_frame_within(&mut frame, max_frame);
+            }
+            prop_assert!(buf.len() <= data.len());
+        }
+
+        #[test]
+        fn random_bytes_should_never_panic_the_stream(
+            prefix in vec(arb_command(), 0..4),
+            data in vec(any::<u8>(), 0..4096),
+            chunk in 1usize..256,
+        ) {
+            // 合法的 frame 之后跟着随机的字节，合法的部分应该都能 decode 出来
+            let mut bytes = BytesMut::new();
+            for cmd in &prefix {
+                cmd.encode_frame(&mut bytes).unwrap();
+            }
+            bytes.extend_from_slice(&data);
+
+            let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let items = rt.block_on(async {
+                let (mut client, server) = duplex(chunk);
+                tokio::spawn(async move {
+                    for piece in bytes.chunks(chunk) {
+                        if client.write_all(piece).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                let mut stream =
+                    crate::ProstStream::<_, CommandRequest, CommandResponse>::new(server);
+                stream.set_max_frame(64 * 1024);
+                let mut items = vec![];
+                while let Some(item) = futures::StreamExt::next(&mut stream).await {
+                    let is_err = item.is_err();
+                    items.push(item);
+                    if is_err {
+                        break;
+                    }
+                }
+                items
+            });
+
+            for (cmd, item) in prefix.iter().zip(&items) {
+                prop_assert_eq!(item.as_ref().unwrap(), cmd);
+            }
+        }
     }
 }
@@ -0,0 +1,279 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use http::StatusCode;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+use tracing::{info, warn};
+
+use crate::{
+    Codec, FrameCoder, Hello, HelloResponse, KvError, ProstStream, DEFAULT_MAX_FRAME, MAX_FRAME,
+};
+
+/// 当前的协议版本
+pub const PROTOCOL_VERSION: u32 = 1;
+/// 服务器能兼容的最老的协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// 服务器支持的功能
+pub const SERVER_FEATURES: &[&str] = &["pubsub", "multiplex"];
+/// Hello 里的 magic
+const HELLO_MAGIC: &str = "kv";
+/// 服务器等待客户端发送 Hello 的时间，超时后断开连接
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl FrameCoder for Hello {}
+impl FrameCoder for HelloResponse {}
+
+/// 握手协商出的连接参数
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    /// 双方都支持的协议版本
+    pub version: u32,
+    /// 服务器发送响应时使用的压缩算法
+    pub codec: Codec,
+    /// 对端能解压的压缩算法
+    pub peer_codecs: Vec<Codec>,
+    /// 双方都能接受的最大 frame
+    pub max_frame_size: usize,
+    /// 客户端看到的是服务器支持的功能，服务器看到的是客户端需要的功能
+    pub features: Vec<String>,
+    /// 客户端要求使用的命名空间，只有服务器一侧有值
+    pub namespace: Option<String>,
+}
+
+impl Hello {
+    /// 创建客户端的 Hello，codecs 按优先级排序
+    pub fn new(codecs: &[Codec], features: Vec<String>) -> Self {
+        Self {
+            magic: HELLO_MAGIC.into(),
+            version: PROTOCOL_VERSION,
+            codecs: codecs.iter().map(|c| c.name().into()).collect(),
+            max_frame_size: DEFAULT_MAX_FRAME as _,
+            features,
+            namespace: String::new(),
+        }
+    }
+
+    /// 设置客户端能接收的最大 frame
+    pub fn with_max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size as _;
+        self
+    }
+
+    /// 要求使用某个命名空间
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+}
+
+/// 客户端握手：发送 Hello，等待服务器的回应
+pub async fn client_handshake<S>(stream: &mut S, hello: Hello) -> Result<Handshake, KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut stream = ProstStream::<_, HelloResponse, Hello>::new(stream);
+    stream.set_max_frame(hello.max_frame_size as _);
+    stream.send(&hello).await?;
+
+    let res = match stream.next().await {
+        Some(Ok(res)) => res,
+        Some(Err(e)) => {
+            return Err(KvError::HandshakeError(format!(
+                "failed to read server hello ({}), the server may not support the handshake",
+                e
+            )))
+        }
+        None => return Err(KvError::HandshakeError("connection closed".into())),
+    };
+
+    if res.status != StatusCode::OK.as_u16() as u32 {
+        return Err(KvError::HandshakeError(res.message));
+    }
+
+    let handshake = Handshake {
+        version: res.version,
+        codec: Codec::from_name(&res.codec).unwrap_or(Codec::None),
+        peer_codecs: parse_codecs(&res.codecs),
+        max_frame_size: res.max_frame_size as _,
+        features: res.features,
+        namespace: None,
+    };
+    info!("Handshake with server: {:?}", handshake);
+
+    Ok(handshake)
+}
+
+/// 服务器握手：读取客户端的 Hello，协商连接参数，不兼容的客户端返回错误
+///
+/// max_frame_size 是服务器能接收的最大 frame，协商的结果不会超过它。
+/// 客户端在 HANDSHAKE_TIMEOUT 内没有发送 Hello 时返回错误。
+pub async fn server_handshake<S>(
+    stream: &mut S,
+    max_frame_size: usize,
+) -> Result<Handshake, KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut stream = ProstStream::<_, Hello, HelloResponse>::new(stream);
+    stream.set_max_frame(max_frame_size);
+
+    let hello = time::timeout(HANDSHAKE_TIMEOUT, stream.next())
+        .await
+        .map_err(|_| KvError::HandshakeError("timed out waiting for hello".into()))?;
+    let result = match hello {
+        Some(Ok(hello)) if hello.magic == HELLO_MAGIC => negotiate(hello, max_frame_size),
+        // 旧客户端发过来的是 CommandRequest，decode 失败或者 magic 对不上
+        Some(_) => Err(KvError::HandshakeError(
+            "protocol handshake required, please upgrade the client".into(),
+        )),
+        None => return Err(KvError::HandshakeError("connection closed".into())),
+    };
+
+    let res = match &result {
+        Ok(handshake) => HelloResponse {
+            status: StatusCode::OK.as_u16() as _,
+            message: "".into(),
+            version: handshake.version,
+            codec: handshake.codec.name().into(),
+            codecs: Codec::ALL.iter().map(|c| c.name().into()).collect(),
+            max_frame_size: handshake.max_frame_size as _,
+            features: SERVER_FEATURES.iter().map(|f| f.to_string()).collect(),
+        },
+        Err(e) => {
+            warn!("Reject client: {}", e);
+            HelloResponse {
+                status: StatusCode::UPGRADE_REQUIRED.as_u16() as _,
+                message: e.to_string(),
+                ..Default::default()
+            }
+        }
+    };
+    stream.send(&res).await?;
+
+    result
+}
+
+fn negotiate(hello: Hello, max_frame_size: usize) -> Result<Handshake, KvError> {
+    if hello.version < MIN_PROTOCOL_VERSION {
+        return Err(KvError::HandshakeError(format!(
+            "unsupported protocol version {}, server supports {} to {}",
+            hello.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        )));
+    }
+
+    let missing: Vec<_> = hello
+        .features
+        .iter()
+        .filter(|f| !SERVER_FEATURES.contains(&f.as_str()))
+        .map(|f| f.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(KvError::HandshakeError(format!(
+            "unsupported features: {}",
+            missing.join(", ")
+        )));
+    }
+
+    // 使用客户端最想要的、服务器也支持的压缩算法；都不支持的话就不压缩
+    let peer_codecs = parse_codecs(&hello.codecs);
+    let codec = peer_codecs.first().copied().unwrap_or(Codec::None);
+
+    // 老的客户端不告诉我们它的限制
+    let max_frame_size = match hello.max_frame_size as usize {
+        0 => max_frame_size,
+        size => size.min(max_frame_size),
+    }
+    .min(MAX_FRAME);
+
+    Ok(Handshake {
+        version: hello.version.min(PROTOCOL_VERSION),
+        codec,
+        peer_codecs,
+        max_frame_size,
+        features: hello.features,
+        namespace: Some(hello.namespace).filter(|ns| !ns.is_empty()),
+    })
+}
+
+fn parse_codecs(names: &[String]) -> Vec<Codec> {
+    names.iter().filter_map(|n| Codec::from_name(n)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRequest, CommandResponse};
+    use anyhow::Result;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn handshake_should_negotiate_codec_and_frame_size() -> Result<()> {
+        let (mut client, mut server) = duplex(4096);
+        let server =
+            tokio::spawn(async move { server_handshake(&mut server, DEFAULT_MAX_FRAME).await });
+
+        let mut hello =
+            Hello::new(&[Codec::Zstd, Codec::Gzip], vec!["pubsub".into()]).with_namespace("team-a");
+        hello.max_frame_size = 1024 * 1024;
+        let handshake = client_handshake(&mut client, hello).await?;
+
+        assert_eq!(handshake.version, PROTOCOL_VERSION);
+        assert_eq!(handshake.codec, Codec::Zstd);
+        assert_eq!(handshake.peer_codecs, Codec::ALL.to_vec());
+        assert_eq!(handshake.max_frame_size, 1024 * 1024);
+        assert!(handshake.features.contains(&"pubsub".to_string()));
+        assert_eq!(handshake.namespace, None);
+
+        let handshake = server.await??;
+        assert_eq!(handshake.codec, Codec::Zstd);
+        assert_eq!(handshake.peer_codecs, vec![Codec::Zstd, Codec::Gzip]);
+        assert_eq!(handshake.namespace.as_deref(), Some("team-a"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_should_reject_incompatible_client() -> Result<()> {
+        let (mut client, mut server) = duplex(4096);
+        tokio::spawn(async move { server_handshake(&mut server, DEFAULT_MAX_FRAME).await });
+
+        let mut hello = Hello::new(&Codec::ALL, vec!["time-travel".into()]);
+        hello.version = 0;
+        let result = client_handshake(&mut client, hello).await;
+
+        assert!(matches!(result, Err(KvError::HandshakeError(msg)) if msg.contains("version 0")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_should_reject_legacy_client() -> Result<()> {
+        let (mut client, mut server) = duplex(4096);
+        let server =
+            tokio::spawn(async move { server_handshake(&mut server, DEFAULT_MAX_FRAME).await });
+
+        // 旧客户端直接发送 CommandRequest，它应该能看懂服务器的错误
+        let mut stream = ProstStream::<_, CommandResponse, CommandRequest>::new(&mut client);
+        stream.send(&CommandRequest::new_hget("t1", "k1")).await?;
+        let res = stream.next().await.unwrap()?;
+
+        assert_eq!(res.status, 426);
+        assert!(res.message.contains("handshake required"));
+        assert!(server.await?.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_should_time_out_when_client_is_silent() -> Result<()> {
+        let (_client, mut server) = duplex(4096);
+        let result = server_handshake(&mut server, DEFAULT_MAX_FRAME).await;
+
+        assert!(matches!(result, Err(KvError::HandshakeError(msg)) if msg.contains("timed out")));
+
+        Ok(())
+    }
+}
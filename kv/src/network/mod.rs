@@ -1,25 +1,39 @@
+mod client;
+mod codec;
 mod frame;
+mod handshake;
 mod multiplex;
+mod quic;
 mod stream;
 mod stream_result;
 mod tls;
 mod topic;
+mod transport;
 
+pub use client::*;
+pub use codec::*;
 pub use frame::*;
-use futures::{SinkExt, StreamExt};
+use futures::{future, SinkExt, StreamExt};
+pub use handshake::*;
 pub use multiplex::*;
+pub use quic::*;
 pub use stream::*;
 pub use stream_result::*;
 pub use tls::*;
 use tokio::io::{AsyncRead, AsyncWrite};
 pub use topic::*;
-use tracing::info;
+use tracing::{info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+pub use transport::*;
 
-use crate::{CommandRequest, CommandResponse, KvError, Service};
+use crate::{
+    CommandRequest, CommandResponse, ConnContext, KvError, Service, TraceContext, REQUEST_TARGET,
+};
 
 pub struct ProstServerStream<S> {
     inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service,
+    ctx: ConnContext,
 }
 
 impl<S> ProstServerStream<S>
@@ -30,20 +44,59 @@ where
         Self {
             inner: ProstStream::new(stream),
             service,
+            ctx: ConnContext::default(),
         }
     }
 
+    /// 设置这个 stream 所属连接的上下文，如果连接握手过，使用协商好的压缩算法和最大 frame
+    pub fn with_context(mut self, ctx: ConnContext) -> Self {
+        if let Some(handshake) = &ctx.handshake {
+            self.inner.set_codec(handshake.codec);
+            self.inner.set_max_frame(handshake.max_frame_size);
+        }
+        self.ctx = ctx;
+        self
+    }
+
     pub async fn process(mut self) -> Result<(), KvError> {
         let stream = &mut self.inner;
-        while let Some(Ok(cmd)) = stream.next().await {
-            info!("Got a new command: {:?}", cmd);
-            let mut res = self.service.execute(cmd);
-            let data = res.next().await.unwrap();
+        loop {
+            let cmd = match stream.next().await {
+                Some(Ok(cmd)) => cmd,
+                // 连接已经断了，没法再回复
+                Some(Err(KvError::IoError)) => return Err(KvError::IoError),
+                // frame 太大或者 decode 失败，之后的数据没法再按 frame 读取，回复错误之后断开
+                Some(Err(e)) => {
+                    warn!("Conn {} sent a bad frame: {}", self.ctx.id, e);
+                    let _ = stream.send(&(&e).into()).await;
+                    return Err(e);
+                }
+                None => return Ok(()),
+            };
+
+            // 客户端带了 trace context 的话，处理请求的 span 是客户端 span 的子节点
+            let span = info_span!(target: REQUEST_TARGET, "server_process", conn = self.ctx.id, cmd = cmd.name());
+            if let Some(trace) = &cmd.trace {
+                span.set_parent(trace.extract());
+            }
 
-            stream.send(&data).await.unwrap();
+            async {
+                // 没有握手的连接，客户端用什么算法压缩，我们就用什么算法压缩响应
+                if let (None, Some(codec)) = (&self.ctx.handshake, stream.peer_codec()) {
+                    stream.set_codec(codec);
+                }
+
+                // 订阅会持续返回数据，直到取消订阅或者客户端断开
+                let mut res = self.service.execute_with(&self.ctx, cmd);
+                while let Some(data) = res.next().await {
+                    stream.send(&data).await?;
+                    self.service.on_sent(&self.ctx);
+                }
+                Ok::<_, KvError>(())
+            }
+            .instrument(span)
+            .await?;
         }
-
-        Ok(())
     }
 }
 
@@ -61,27 +114,90 @@ where
         }
     }
 
-    pub async fn execute_unary(
-        &mut self,
-        cmd: &CommandRequest,
-    ) -> Result<CommandResponse, KvError> {
+    /// 设置发送请求时使用的压缩算法，服务器会使用同样的算法压缩响应
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.inner.set_codec(codec);
+        self
+    }
+
+    /// 设置能接收的最大响应，不设置时是 DEFAULT_MAX_FRAME
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.inner.set_max_frame(max_frame);
+        self
+    }
+
+    pub async fn execute_unary(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let span = info_span!(target: REQUEST_TARGET, "client_request", cmd = cmd.name());
+        let cmd = with_trace(cmd, &span);
         let stream = &mut self.inner;
-        stream.send(cmd).await?;
 
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
+        async {
+            stream.send(&cmd).await?;
+            match stream.next().await {
+                Some(v) => v,
+                None => Err(KvError::Internal("Didn't get any response".into())),
+            }
         }
+        .instrument(span)
+        .await
     }
 
-    pub async fn execute_streaming(self, cmd: &CommandRequest) -> Result<StreamResult, KvError> {
+    /// 一次发出一组请求，再按顺序读取每个请求的响应，请求之间不用等待往返
+    ///
+    /// 写请求的同时读取响应，响应堆满连接的缓冲区时不会和服务器互相等待。
+    pub async fn execute_pipelined(
+        &mut self,
+        cmds: Vec<CommandRequest>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        let span = info_span!(target: REQUEST_TARGET, "client_pipeline", count = cmds.len());
+        let cmds: Vec<_> = cmds.into_iter().map(|cmd| with_trace(cmd, &span)).collect();
+        let (mut sink, mut stream) = (&mut self.inner).split();
+
+        let send = async {
+            for cmd in &cmds {
+                sink.feed(cmd).await?;
+            }
+            sink.flush().await
+        };
+        let recv = async {
+            let mut res = Vec::with_capacity(cmds.len());
+            for _ in &cmds {
+                match stream.next().await {
+                    Some(v) => res.push(v?),
+                    None => return Err(KvError::Internal("Didn't get any response".into())),
+                }
+            }
+            Ok(res)
+        };
+
+        let (_, res) = future::try_join(send, recv)
+            .instrument(span.clone())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn execute_streaming(self, cmd: CommandRequest) -> Result<StreamResult, KvError> {
+        let span = info_span!(target: REQUEST_TARGET, "client_request", cmd = cmd.name());
+        let cmd = with_trace(cmd, &span);
         let mut stream = self.inner;
 
-        stream.send(cmd).await?;
-        stream.close().await?;
+        async {
+            stream.send(&cmd).await?;
+            stream.close().await?;
+
+            StreamResult::new(stream).await
+        }
+        .instrument(span)
+        .await
+    }
+}
 
-        StreamResult::new(stream).await
+/// 如果 span 被 OpenTelemetry 记录，把它的 trace context 放到请求里
+fn with_trace(mut cmd: CommandRequest, span: &Span) -> CommandRequest {
+    if let Some(trace) = TraceContext::from_span(span) {
+        cmd.trace = Some(trace);
     }
+    cmd
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> From<S> for ProstClientStream<S> {
@@ -92,33 +208,31 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> From<S> for ProstClient
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Result;
+    use std::sync::{Arc, Mutex};
+
     use bytes::Bytes;
-    use std::net::SocketAddr;
-    use tokio::net::{TcpListener, TcpStream};
+    use opentelemetry::trace::TraceId;
 
-    use crate::{assert_res_ok, MemTable, Value};
+    use crate::{assert_res_ok, otel_subscriber, trace_id, MemTable, Middleware, Value};
 
     use super::*;
 
     #[tokio::test]
     async fn client_server_basic_communication_should_work() -> anyhow::Result<()> {
-        let addr = start_server().await?;
-
-        let stream = TcpStream::connect(addr).await?;
+        let stream = start_server();
         let mut client = ProstClientStream::new(stream);
 
         // 发送 HSET，等待回应
 
         let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
-        let res = client.execute_unary(&cmd).await.unwrap();
+        let res = client.execute_unary(cmd).await.unwrap();
 
         // 第一次 HSET 服务器应该返回 None
         assert_res_ok(&res, &[Value::default()], &[]);
 
         // 再发一个 HSET
         let cmd = CommandRequest::new_hget("t1", "k1");
-        let res = client.execute_unary(&cmd).await?;
+        let res = client.execute_unary(cmd).await?;
 
         // 服务器应该返回上一次的结果
         assert_res_ok(&res, &["v1".into()], &[]);
@@ -128,87 +242,191 @@ mod tests {
 
     #[tokio::test]
     async fn client_server_compression_should_work() -> anyhow::Result<()> {
-        let addr = start_server().await?;
-
-        let stream = TcpStream::connect(addr).await?;
+        let stream = start_server();
         let mut client = ProstClientStream::new(stream);
 
         let v: Value = Bytes::from(vec![0u8; 16384]).into();
-        let cmd = CommandRequest::new_hset("t2", "k2", v.clone().into());
-        let res = client.execute_unary(&cmd).await?;
+        let cmd = CommandRequest::new_hset("t2", "k2", v.clone());
+        let res = client.execute_unary(cmd).await?;
 
         assert_res_ok(&res, &[Value::default()], &[]);
 
         let cmd = CommandRequest::new_hget("t2", "k2");
-        let res = client.execute_unary(&cmd).await?;
+        let res = client.execute_unary(cmd).await?;
 
-        assert_res_ok(&res, &[v.into()], &[]);
+        assert_res_ok(&res, &[v], &[]);
 
         Ok(())
     }
 
-    async fn start_server() -> Result<SocketAddr> {
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
+    #[tokio::test]
+    async fn client_server_codec_negotiation_should_work() -> anyhow::Result<()> {
+        for codec in Codec::ALL {
+            let stream = start_server();
+            let mut client = ProstClientStream::new(stream).with_codec(codec);
 
-        tokio::spawn(async move {
-            loop {
-                let (stream, _) = listener.accept().await.unwrap();
-                let service: Service = Service::new(MemTable::new()).into();
-                let server = ProstServerStream::new(stream, service);
-                tokio::spawn(server.process());
-            }
-        });
+            let v: Value = Bytes::from(vec![1u8; 16384]).into();
+            let cmd = CommandRequest::new_hset("t3", "k3", v.clone());
+            client.execute_unary(cmd).await?;
 
-        Ok(addr)
-    }
-}
+            let cmd = CommandRequest::new_hget("t3", "k3");
+            let res = client.execute_unary(cmd).await?;
+            assert_res_ok(&res, &[v], &[]);
 
-#[cfg(test)]
-pub mod utils {
-    use bytes::{BufMut, BytesMut};
-    use std::task::Poll;
-    use tokio::io::{AsyncRead, AsyncWrite};
-
-    pub struct DummyStream {
-        pub buf: BytesMut,
-    }
-
-    impl AsyncRead for DummyStream {
-        fn poll_read(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &mut tokio::io::ReadBuf<'_>,
-        ) -> Poll<std::io::Result<()>> {
-            let len = buf.capacity();
-            let data = self.get_mut().buf.split_to(len);
-            buf.put_slice(&data);
-            Poll::Ready(Ok(()))
+            // 服务器使用客户端的压缩算法
+            assert_eq!(client.inner.peer_codec(), Some(codec));
         }
+
+        Ok(())
     }
 
-    impl AsyncWrite for DummyStream {
-        fn poll_write(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &[u8],
-        ) -> Poll<Result<usize, std::io::Error>> {
-            self.get_mut().buf.put_slice(buf);
-            Poll::Ready(Ok(buf.len()))
-        }
+    #[tokio::test]
+    async fn client_server_pipelining_should_work() -> anyhow::Result<()> {
+        let stream = start_server();
+        let mut client = ProstClientStream::new(stream);
 
-        fn poll_flush(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
-        }
+        let cmds: Vec<_> = (0..10)
+            .map(|i| CommandRequest::new_hset("t4", format!("k{}", i), i.into()))
+            .chain([CommandRequest::new_hget("t4", "k9")])
+            .collect();
+        let res = client.execute_pipelined(cmds).await?;
+
+        // 响应的顺序和请求一致
+        assert_eq!(res.len(), 11);
+        res[..10]
+            .iter()
+            .for_each(|res| assert_res_ok(res, &[Value::default()], &[]));
+        assert_res_ok(&res[10], &[9.into()], &[]);
+
+        // 之后还可以继续使用
+        let res = client
+            .execute_unary(CommandRequest::new_hget("t4", "k0"))
+            .await?;
+        assert_res_ok(&res, &[0.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deep_pipeline_should_not_lose_frames() -> anyhow::Result<()> {
+        let stream = start_server();
+        let mut client = ProstClientStream::new(stream);
 
-        fn poll_shutdown(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
+        // 服务器一次读到很多 frame，中间会因为 tokio 的协作调度返回 Pending
+        let value: Value = Bytes::from(vec![7u8; 64]).into();
+        let cmds: Vec<_> = (0..1000)
+            .map(|i| CommandRequest::new_hset("t5", format!("k{}", i), value.clone()))
+            .collect();
+        let res = client.execute_pipelined(cmds).await?;
+        assert_eq!(res.len(), 1000);
+
+        let res = client
+            .execute_unary(CommandRequest::new_hget("t5", "k999"))
+            .await?;
+        assert_res_ok(&res, &[value], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn trace_context_should_link_client_and_server_spans() -> anyhow::Result<()> {
+        /// 记录服务器处理请求时所在的 trace
+        #[derive(Clone, Default)]
+        struct TraceRecorder(Arc<Mutex<Vec<TraceId>>>);
+
+        impl Middleware for TraceRecorder {
+            fn on_request(
+                &self,
+                _: &ConnContext,
+                _: &mut CommandRequest,
+            ) -> Option<CommandResponse> {
+                self.0.lock().unwrap().push(trace_id(&Span::current()));
+                None
+            }
         }
+
+        // 单线程的 runtime 里，服务器的任务也使用这个 subscriber
+        let _guard = tracing::subscriber::set_default(otel_subscriber());
+        let recorder = TraceRecorder::default();
+        let service = Service::new(MemTable::new()).with_middleware(recorder.clone());
+        let mut client =
+            ProstClientStream::new(connect_in_process(service, ConnContext::default()));
+
+        let parent = info_span!("test");
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client
+            .execute_unary(cmd.clone())
+            .instrument(parent.clone())
+            .await?;
+        client
+            .execute_pipelined(vec![cmd.clone(), cmd])
+            .instrument(parent.clone())
+            .await?;
+
+        let traces = recorder.0.lock().unwrap();
+        assert_eq!(traces.len(), 3);
+        assert_ne!(trace_id(&parent), TraceId::invalid());
+        assert!(traces.iter().all(|id| *id == trace_id(&parent)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_larger_than_buffer_should_not_deadlock() -> anyhow::Result<()> {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = ProstServerStream::new(server, Service::new(MemTable::new()));
+        tokio::spawn(server.process());
+        let mut client = ProstClientStream::new(client);
+
+        // 第二次写入返回之前的值，请求和响应都远远超过连接的缓冲区
+        let value: Value = Bytes::from(vec![7u8; 1024]).into();
+        let cmds: Vec<_> = (0..2)
+            .flat_map(|_| (0..100).map(|i| format!("k{}", i)))
+            .map(|key| CommandRequest::new_hset("t6", key, value.clone()))
+            .collect();
+        let res = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            client.execute_pipelined(cmds),
+        )
+        .await??;
+
+        assert_eq!(res.len(), 200);
+        assert_res_ok(&res[199], &[value], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_should_get_error_response() -> anyhow::Result<()> {
+        let ctx = ConnContext::default().with_handshake(Handshake {
+            version: PROTOCOL_VERSION,
+            codec: Codec::None,
+            peer_codecs: vec![],
+            max_frame_size: 1024,
+            features: vec![],
+            namespace: None,
+        });
+        let stream = connect_in_process(Service::new(MemTable::new()), ctx);
+        let mut client = ProstClientStream::new(stream).with_codec(Codec::None);
+
+        let v: Value = Bytes::from(vec![0u8; 4096]).into();
+        let res = client
+            .execute_unary(CommandRequest::new_hset("t1", "k1", v))
+            .await?;
+        assert_eq!(res.status, 413);
+        assert_eq!(res.message, KvError::FrameTooLarge(1024).to_string());
+
+        // 回复错误之后服务器断开连接
+        let res = client
+            .execute_unary(CommandRequest::new_hget("t1", "k1"))
+            .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    fn start_server() -> impl AsyncRead + AsyncWrite + Unpin + Send {
+        let service: Service = Service::new(MemTable::new());
+        connect_in_process(service, ConnContext::default())
     }
 }
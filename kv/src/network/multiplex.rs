@@ -58,7 +58,7 @@ where
 
         Self {
             ctrl,
-            _conn: PhantomData::default(),
+            _conn: PhantomData,
         }
     }
 
@@ -156,10 +156,10 @@ mod tests {
         let mut client = ProstClientStream::new(stream);
 
         let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
-        client.execute_unary(&cmd).await.unwrap();
+        client.execute_unary(cmd).await.unwrap();
 
         let cmd = CommandRequest::new_hget("t1", "k1");
-        let res = client.execute_unary(&cmd).await.unwrap();
+        let res = client.execute_unary(cmd).await.unwrap();
         assert_res_ok(&res, &["v1".into()], &[]);
 
         Ok(())
@@ -0,0 +1,341 @@
+use std::{
+    io::{self, Cursor},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use quinn_rustls::{
+    server::AllowAnyAuthenticatedClient, version::TLS13, Certificate, PrivateKey, RootCertStore,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+use crate::{identity_from_cert, KvError, ALPN_KV};
+
+/// 把 QUIC 的双向 stream 包装成 AsyncRead + AsyncWrite，这样可以直接用于 ProstStream
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+/// QUIC 客户端连接，用于创建新的 stream
+pub struct QuicCtrl {
+    conn: Connection,
+    /// endpoint 负责收发 UDP 包，需要和连接活得一样久
+    _endpoint: Endpoint,
+}
+
+impl QuicStream {
+    pub fn new(send: SendStream, recv: RecvStream) -> Self {
+        Self { send, recv }
+    }
+}
+
+impl QuicCtrl {
+    /// 连接 QUIC 服务器
+    pub async fn connect(
+        addr: SocketAddr,
+        domain: &str,
+        config: quinn::ClientConfig,
+    ) -> Result<Self, KvError> {
+        let bind: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(config);
+
+        let conn = endpoint.connect(addr, domain)?.await?;
+
+        Ok(Self {
+            conn,
+            _endpoint: endpoint,
+        })
+    }
+
+    /// 打开一个新的 stream
+    pub async fn open_stream(&self) -> Result<QuicStream, KvError> {
+        let (send, recv) = self.conn.open_bi().await?;
+        Ok(QuicStream::new(send, recv))
+    }
+
+    /// 服务器的证书链（DER 格式），第一个是服务器自己的证书
+    pub fn server_certificates(&self) -> Vec<Vec<u8>> {
+        let certs = self
+            .conn
+            .peer_identity()
+            .and_then(|id| id.downcast::<Vec<Certificate>>().ok());
+        certs
+            .map(|certs| certs.into_iter().map(|c| c.0).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
+
+/// 加载 server cert / CA cert，生成 QUIC ServerConfig
+pub fn quic_server_config(
+    cert: &str,
+    key: &str,
+    client_ca: Option<&str>,
+) -> Result<quinn::ServerConfig, KvError> {
+    let certs = load_certs(cert)?;
+    let key = load_key(key)?;
+
+    // QUIC 只能使用 TLS 1.3
+    let builder = quinn_rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&TLS13])
+        .map_err(|_| KvError::CertifcateParseError("server", "config"))?;
+
+    let builder = match client_ca {
+        None => builder.with_no_client_auth(),
+        Some(ca) => {
+            // 如果客户端证书是某个 CA 证书签发的，则把这个 CA 证书加载到信任链中
+            let roots = load_roots(ca)?;
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|_| KvError::CertifcateParseError("server", "cert"))?;
+    config.alpn_protocols = vec![Vec::from(ALPN_KV)];
+
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(config)))
+}
+
+/// 加载 client cert / CA cert，生成 QUIC ClientConfig
+pub fn quic_client_config(
+    identity: Option<(&str, &str)>,
+    server_ca: Option<&str>,
+) -> Result<quinn::ClientConfig, KvError> {
+    // 加载本地信任的根证书链
+    let mut roots = RootCertStore::empty();
+    match quinn_native_certs::load_native_certs() {
+        Ok(certs) => {
+            for cert in certs {
+                let _ = roots.add(&Certificate(cert.0));
+            }
+        }
+        Err(e) => warn!("Failed to load native certs: {:?}", e),
+    }
+
+    // 如果有签署服务器的 CA 证书，则加载它
+    if let Some(ca) = server_ca {
+        for cert in load_certs(ca)? {
+            roots
+                .add(&cert)
+                .map_err(|_| KvError::CertifcateParseError("CA", "cert"))?;
+        }
+    }
+
+    let builder = quinn_rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&TLS13])
+        .map_err(|_| KvError::CertifcateParseError("client", "config"))?
+        .with_root_certificates(roots);
+
+    // 如果有客户端证书，加载之
+    let mut config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|_| KvError::CertifcateParseError("client", "cert"))?,
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![Vec::from(ALPN_KV)];
+
+    Ok(quinn::ClientConfig::new(Arc::new(config)))
+}
+
+/// 从 QUIC 连接中取出客户端证书的 CN，作为客户端的身份
+pub fn quic_peer_identity(conn: &Connection) -> Option<String> {
+    let certs = conn.peer_identity()?.downcast::<Vec<Certificate>>().ok()?;
+    identity_from_cert(&certs.first()?.0)
+}
+
+fn load_certs(cert: &str) -> Result<Vec<Certificate>, KvError> {
+    let mut cert = Cursor::new(cert);
+    let certs = rustls_pemfile::certs(&mut cert)
+        .map_err(|_| KvError::CertifcateParseError("server", "cert"))?;
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_roots(ca: &str) -> Result<RootCertStore, KvError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots
+            .add(&cert)
+            .map_err(|_| KvError::CertifcateParseError("CA", "cert"))?;
+    }
+    Ok(roots)
+}
+
+fn load_key(key: &str) -> Result<PrivateKey, KvError> {
+    let mut cursor = Cursor::new(key);
+
+    // 先尝试用 PKCS8 加载私钥
+    if let Ok(mut keys) = rustls_pemfile::pkcs8_private_keys(&mut cursor) {
+        if !keys.is_empty() {
+            return Ok(PrivateKey(keys.remove(0)));
+        }
+    }
+
+    // 再尝试加载 RSA key
+    cursor.set_position(0);
+    if let Ok(mut keys) = rustls_pemfile::rsa_private_keys(&mut cursor) {
+        if !keys.is_empty() {
+            return Ok(PrivateKey(keys.remove(0)));
+        }
+    }
+
+    // 不支持的私钥类型
+    Err(KvError::CertifcateParseError("private", "key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert_res_ok, quic_utils, CommandRequest, MemTable, ProstClientStream, ProstServerStream,
+        Service,
+    };
+    use anyhow::Result;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn quic_stream_should_work() -> Result<()> {
+        let endpoint =
+            Endpoint::server(quic_utils::server_config(true)?, ([127, 0, 0, 1], 0).into())?;
+        let addr = endpoint.local_addr()?;
+
+        let server = tokio::spawn(async move {
+            let conn = endpoint.accept().await.unwrap().await.unwrap();
+            let (send, recv) = conn.accept_bi().await.unwrap();
+            let mut stream = QuicStream::new(send, recv);
+
+            let mut buf = [0; 12];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            stream.shutdown().await.unwrap();
+            quic_peer_identity(&conn)
+        });
+
+        let ctrl =
+            QuicCtrl::connect(addr, "kvserver.acme.inc", quic_utils::client_config(true)?).await?;
+        let mut stream = ctrl.open_stream().await?;
+        stream.write_all(b"hello world!").await?;
+
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello world!");
+        assert_eq!(server.await?, Some("awesome-device-id".into()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quic_with_bad_domain_should_not_work() -> Result<()> {
+        let endpoint = Endpoint::server(
+            quic_utils::server_config(false)?,
+            ([127, 0, 0, 1], 0).into(),
+        )?;
+        let addr = endpoint.local_addr()?;
+        tokio::spawn(async move { endpoint.accept().await.unwrap().await });
+
+        let result = QuicCtrl::connect(
+            addr,
+            "kvserver1.acme.inc",
+            quic_utils::client_config(false)?,
+        )
+        .await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quic_client_server_should_work() -> Result<()> {
+        let endpoint = Endpoint::server(
+            quic_utils::server_config(false)?,
+            ([127, 0, 0, 1], 0).into(),
+        )?;
+        let addr = endpoint.local_addr()?;
+
+        tokio::spawn(async move {
+            let service = Service::new(MemTable::new());
+            let conn = endpoint.accept().await.unwrap().await.unwrap();
+            while let Ok((send, recv)) = conn.accept_bi().await {
+                let stream = ProstServerStream::new(QuicStream::new(send, recv), service.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+
+        let ctrl =
+            QuicCtrl::connect(addr, "kvserver.acme.inc", quic_utils::client_config(false)?).await?;
+
+        // 多个 stream 共享同一个连接
+        let mut client1 = ProstClientStream::new(ctrl.open_stream().await?);
+        let mut client2 = ProstClientStream::new(ctrl.open_stream().await?);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client1.execute_unary(cmd).await?;
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client2.execute_unary(cmd).await?;
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod quic_utils {
+    use crate::{tls_utils::*, KvError};
+
+    pub fn client_config(client_cert: bool) -> Result<quinn::ClientConfig, KvError> {
+        let identity = Some((CLIENT_CERT, CLIENT_KEY));
+        match client_cert {
+            false => super::quic_client_config(None, Some(CA_CERT)),
+            true => super::quic_client_config(identity, Some(CA_CERT)),
+        }
+    }
+
+    pub fn server_config(client_cert: bool) -> Result<quinn::ServerConfig, KvError> {
+        match client_cert {
+            true => super::quic_server_config(SERVER_CERT, SERVER_KEY, Some(CA_CERT)),
+            false => super::quic_server_config(SERVER_CERT, SERVER_KEY, None),
+        }
+    }
+}
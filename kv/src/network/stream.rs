@@ -5,16 +5,25 @@ use std::{
 };
 
 use bytes::BytesMut;
-use futures::{FutureExt, Sink, Stream};
+use futures::{Sink, Stream};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::io::poll_read_buf;
 
-use crate::{read_frame, FrameCoder, KvError};
+use super::frame::peer_codec;
+use crate::{split_frame, Codec, FrameCoder, KvError, DEFAULT_MAX_FRAME, LEN_LEN};
 
 pub struct ProstStream<S, In, Out> {
     stream: S,
     wbuf: BytesMut,
+    /// 已经读到、还没有 decode 的数据，poll_next 返回 Pending 时留在这里
     rbuf: BytesMut,
     written: usize,
+    /// 能接收的最大 frame
+    max_frame: usize,
+    /// 发送时使用的压缩算法
+    codec: Codec,
+    /// 从收到的 frame 中看到的对端压缩算法
+    peer_codec: Option<Codec>,
     _in: PhantomData<In>,
     _out: PhantomData<Out>,
 }
@@ -29,10 +38,28 @@ where
             written: 0,
             wbuf: BytesMut::new(),
             rbuf: BytesMut::new(),
-            _in: PhantomData::default(),
-            _out: PhantomData::default(),
+            max_frame: DEFAULT_MAX_FRAME,
+            codec: Codec::default(),
+            peer_codec: None,
+            _in: PhantomData,
+            _out: PhantomData,
         }
     }
+
+    /// 设置发送时使用的压缩算法
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// 设置能接收的最大 frame，超过时 poll_next 返回错误
+    pub fn set_max_frame(&mut self, max_frame: usize) {
+        self.max_frame = max_frame;
+    }
+
+    /// 对端最近使用的压缩算法，还没收到足够大的 frame 时是 None
+    pub fn peer_codec(&self) -> Option<Codec> {
+        self.peer_codec
+    }
 }
 
 impl<S, In, Out> Stream for ProstStream<S, In, Out>
@@ -42,18 +69,32 @@ where
     Out: Unpin + Send,
 {
     type Item = Result<In, KvError>;
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        assert!(self.rbuf.is_empty());
-
-        let mut rest = self.rbuf.split_off(0);
-
-        let fut = read_frame(&mut self.stream, &mut rest);
-
-        ready!(Box::pin(fut).poll_unpin(cx))?;
-
-        self.rbuf.unsplit(rest);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
 
-        Poll::Ready(Some(In::decode_frame(&mut self.rbuf)))
+        loop {
+            if let Some(mut frame) = split_frame(&mut this.rbuf, this.max_frame)? {
+                let header = u32::from_be_bytes(frame[..LEN_LEN].try_into().unwrap());
+                if let Some(codec) = peer_codec(header) {
+                    this.peer_codec = Some(codec);
+                }
+                return Poll::Ready(Some(In::decode_frame_within(&mut frame, this.max_frame)));
+            }
+
+            // 读到的数据追加在 rbuf 之后，返回 Pending 时不会丢失
+            if ready!(poll_read_buf(
+                Pin::new(&mut this.stream),
+                cx,
+                &mut this.rbuf
+            ))? == 0
+            {
+                return match this.rbuf.is_empty() {
+                    true => Poll::Ready(None),
+                    // 读到一半的 frame 说明连接被意外断开了
+                    false => Poll::Ready(Some(Err(KvError::FrameError))),
+                };
+            }
+        }
     }
 }
 
@@ -71,7 +112,7 @@ where
     fn start_send(self: Pin<&mut Self>, item: &Out) -> Result<(), Self::Error> {
         let this = self.get_mut();
 
-        item.encode_frame(&mut this.wbuf)?;
+        item.encode_frame_with(this.codec, &mut this.wbuf)?;
 
         Ok(())
     }
@@ -104,23 +145,23 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{utils::DummyStream, CommandRequest};
+    use crate::CommandRequest;
     use anyhow::Result;
     use futures::prelude::*;
 
     #[tokio::test]
     async fn prost_stream_should_work() -> Result<()> {
-        let buf = BytesMut::new();
-        let stream = DummyStream { buf };
-        let mut stream = ProstStream::<_, CommandRequest, CommandRequest>::new(stream);
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client = ProstStream::<_, CommandRequest, CommandRequest>::new(client);
+        let mut server = ProstStream::<_, CommandRequest, CommandRequest>::new(server);
         let cmd = CommandRequest::new_hdel("t1", "k1");
 
-        stream.send(&cmd).await?;
+        client.send(&cmd).await?;
 
-        if let Some(Ok(s)) = stream.next().await {
+        if let Some(Ok(s)) = server.next().await {
             assert_eq!(s, cmd);
         } else {
-            assert!(false);
+            panic!("expected a decoded command");
         }
         Ok(())
     }
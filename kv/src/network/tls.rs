@@ -1,9 +1,11 @@
 use std::io::Cursor;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::rustls::{internal::pemfile, Certificate, ClientConfig, ServerConfig};
-use tokio_rustls::rustls::{AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore};
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore, Session,
+};
 use tokio_rustls::webpki::DNSNameRef;
 use tokio_rustls::TlsConnector;
 use tokio_rustls::{
@@ -13,12 +15,14 @@ use tokio_rustls::{
 use crate::KvError;
 
 /// KV Server 自己的 ALPN (Application-Layer Protocol Negotiation)
-const ALPN_KV: &str = "kv";
+pub(crate) const ALPN_KV: &str = "kv";
 
 /// 存放 TLS ServerConfig 并提供方法 accept 把底层的协议转换成 TLS
+///
+/// clone 之后共享同一个 ServerConfig，`reload` 之后新的连接会使用新的证书
 #[derive(Clone)]
 pub struct TlsServerAcceptor {
-    inner: Arc<ServerConfig>,
+    inner: Arc<RwLock<Arc<ServerConfig>>>,
 }
 
 /// 存放 TLS Client 并提供方法 connect 把底层的协议转换成 TLS
@@ -51,10 +55,13 @@ impl TlsClientConnector {
         };
 
         // 如果有签署服务器的 CA 证书，则加载它，这样服务器证书不在根证书链
-        // 但是这个 CA 证书能验证它，也可以
+        // 但是这个 CA 证书能验证它，也可以。轮换 CA 时这里会同时有新旧两个 CA
         if let Some(cert) = server_ca {
             let mut buf = Cursor::new(cert);
-            config.root_store.add_pem_file(&mut buf).unwrap();
+            match config.root_store.add_pem_file(&mut buf) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => return Err(KvError::CertifcateParseError("CA", "cert")),
+            }
         }
 
         Ok(Self {
@@ -82,45 +89,85 @@ impl TlsClientConnector {
 impl TlsServerAcceptor {
     /// 加载 server cert / CA cert，生成 ServerConfig
     pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
-        let certs = load_certs(cert)?;
-        let key = load_key(key)?;
-
-        let mut config = match client_ca {
-            None => ServerConfig::new(NoClientAuth::new()),
-            Some(cert) => {
-                // 如果客户端证书是某个 CA 证书签发的，则把这个 CA 证书加载到信任链中
-                let mut cert = Cursor::new(cert);
-                let mut client_root_cert_store = RootCertStore::empty();
-                client_root_cert_store
-                    .add_pem_file(&mut cert)
-                    .map_err(|_| KvError::CertifcateParseError("CA", "cert"))?;
-
-                let client_auth = AllowAnyAuthenticatedClient::new(client_root_cert_store);
-                ServerConfig::new(client_auth)
-            }
-        };
-
-        // 加载服务器证书
-        config
-            .set_single_cert(certs, key)
-            .map_err(|_| KvError::CertifcateParseError("server", "cert"))?;
-        config.set_protocols(&[Vec::from(&ALPN_KV[..])]);
-
+        let config = server_config(cert, key, client_ca)?;
         Ok(Self {
-            inner: Arc::new(config),
+            inner: Arc::new(RwLock::new(Arc::new(config))),
         })
     }
 
+    /// 换成新的证书，已经建立的连接不受影响；加载失败时继续使用旧的证书
+    pub fn reload(&self, cert: &str, key: &str, client_ca: Option<&str>) -> Result<(), KvError> {
+        self.replace(&Self::new(cert, key, client_ca)?);
+        Ok(())
+    }
+
+    /// 换成另一个 acceptor 当前使用的证书
+    pub fn replace(&self, other: &TlsServerAcceptor) {
+        let config = other.inner.read().unwrap().clone();
+        *self.inner.write().unwrap() = config;
+    }
+
     /// 触发 TLS 协议，把底层的 stream 转换成 TLS stream
     pub async fn accept<S>(&self, stream: S) -> Result<ServerTlsStream<S>, KvError>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send,
     {
-        let acceptor = TlsAcceptor::from(self.inner.clone());
-        Ok(acceptor.accept(stream).await?)
+        let config = self.inner.read().unwrap().clone();
+        Ok(TlsAcceptor::from(config).accept(stream).await?)
     }
 }
 
+fn server_config(cert: &str, key: &str, client_ca: Option<&str>) -> Result<ServerConfig, KvError> {
+    // 证书文件可能正在被替换，读到空的证书链时不要使用
+    let certs = load_certs(cert)?;
+    if certs.is_empty() {
+        return Err(KvError::CertifcateParseError("server", "cert"));
+    }
+    let key = load_key(key)?;
+
+    let mut config = match client_ca {
+        None => ServerConfig::new(NoClientAuth::new()),
+        Some(cert) => {
+            // 如果客户端证书是某个 CA 证书签发的，则把这个 CA 证书加载到信任链中
+            let mut cert = Cursor::new(cert);
+            let mut client_root_cert_store = RootCertStore::empty();
+            client_root_cert_store
+                .add_pem_file(&mut cert)
+                .map_err(|_| KvError::CertifcateParseError("CA", "cert"))?;
+
+            let client_auth = AllowAnyAuthenticatedClient::new(client_root_cert_store);
+            ServerConfig::new(client_auth)
+        }
+    };
+
+    // 加载服务器证书
+    config
+        .set_single_cert(certs, key)
+        .map_err(|_| KvError::CertifcateParseError("server", "cert"))?;
+    config.set_protocols(&[Vec::from(ALPN_KV)]);
+
+    Ok(config)
+}
+
+/// 从 TLS stream 中取出客户端证书的 CN，作为客户端的身份
+pub fn peer_identity<S>(stream: &ServerTlsStream<S>) -> Option<String> {
+    let certs = stream.get_ref().1.get_peer_certificates()?;
+    identity_from_cert(&certs.first()?.0)
+}
+
+/// 从 TLS stream 中取出服务器的证书链（DER 格式），第一个是服务器自己的证书
+pub fn server_certificates<S>(stream: &ClientTlsStream<S>) -> Vec<Vec<u8>> {
+    let certs = stream.get_ref().1.get_peer_certificates();
+    certs.unwrap_or_default().into_iter().map(|c| c.0).collect()
+}
+
+/// 从 DER 格式的证书中取出 CN
+pub(crate) fn identity_from_cert(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|v| v.to_string())
+}
+
 fn load_certs(cert: &str) -> Result<Vec<Certificate>, KvError> {
     let mut cert = Cursor::new(cert);
     pemfile::certs(&mut cert).map_err(|_| KvError::CertifcateParseError("server", "cert"))
@@ -171,6 +218,9 @@ mod tests {
         let stream = TcpStream::connect(addr).await?;
         let mut stream = connector.connect(stream).await?;
 
+        let certs = server_certificates(&stream);
+        assert_eq!(identity_from_cert(&certs[0]).unwrap(), "Acme KV server");
+
         stream.write_all(b"hello world!").await?;
 
         let mut buf = [0; 12];
@@ -199,6 +249,29 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn tls_peer_identity_should_work() -> Result<()> {
+        let acceptor = tls_acceptor(true)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut buf = [0; 1];
+            stream.read_exact(&mut buf).await.unwrap();
+            peer_identity(&stream)
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = tls_connector(true)?.connect(stream).await?;
+        stream.write_all(b"!").await?;
+
+        assert_eq!(server.await?, Some("awesome-device-id".into()));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn tls_with_bad_domain_should_not_work() -> Result<()> {
         let addr = start_server(false).await?;
@@ -213,6 +286,49 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn tls_acceptor_reload_should_apply_to_new_connections() -> Result<()> {
+        use tls_utils::*;
+
+        let acceptor = tls_acceptor(false)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let server = acceptor.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let mut stream = server.accept(stream).await?;
+                    let mut buf = [0; 1];
+                    stream.read_exact(&mut buf).await?;
+                    stream.write_all(&buf).await?;
+                    Ok::<_, KvError>(())
+                });
+            }
+        });
+
+        // 加载失败时继续使用旧的证书
+        assert!(acceptor.reload("bad cert", SERVER_KEY, None).is_err());
+        assert!(echo(addr, false).await.is_ok());
+
+        // 换成要求客户端证书的配置之后，匿名客户端无法再连接
+        acceptor.reload(SERVER_CERT, SERVER_KEY, Some(CA_CERT))?;
+        assert!(echo(addr, true).await.is_ok());
+        assert!(echo(addr, false).await.is_err());
+
+        Ok(())
+    }
+
+    async fn echo(addr: SocketAddr, client_cert: bool) -> Result<()> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = tls_connector(client_cert)?.connect(stream).await?;
+        stream.write_all(b"!").await?;
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf).await?;
+        Ok(())
+    }
+
     async fn start_server(client_cert: bool) -> Result<SocketAddr> {
         let acceptor = tls_acceptor(client_cert)?;
 
@@ -235,11 +351,11 @@ mod tests {
 pub mod tls_utils {
     use crate::{KvError, TlsClientConnector, TlsServerAcceptor};
 
-    const CA_CERT: &str = include_str!("../../fixtures/ca.cert");
-    const CLIENT_CERT: &str = include_str!("../../fixtures/client.cert");
-    const CLIENT_KEY: &str = include_str!("../../fixtures/client.key");
-    const SERVER_CERT: &str = include_str!("../../fixtures/server.cert");
-    const SERVER_KEY: &str = include_str!("../../fixtures/server.key");
+    pub const CA_CERT: &str = include_str!("../../fixtures/ca.cert");
+    pub const CLIENT_CERT: &str = include_str!("../../fixtures/client.cert");
+    pub const CLIENT_KEY: &str = include_str!("../../fixtures/client.key");
+    pub const SERVER_CERT: &str = include_str!("../../fixtures/server.cert");
+    pub const SERVER_KEY: &str = include_str!("../../fixtures/server.key");
 
     pub fn tls_connector(client_cert: bool) -> Result<TlsClientConnector, KvError> {
         let ca = Some(CA_CERT);
@@ -129,6 +129,10 @@ impl Topic for Arc<Broadcaster> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use proptest::{collection::vec, prelude::*};
+    use tokio::sync::mpsc::error::TryRecvError;
 
     use crate::assert_res_ok;
 
@@ -157,7 +161,7 @@ mod tests {
         let res2 = stream2.recv().await.unwrap();
 
         assert_eq!(res1, res2);
-        assert_res_ok(&res1, &[v.clone()], &[]);
+        assert_res_ok(&res1, std::slice::from_ref(&v), &[]);
 
         // 如果 subscriber 取消订阅，则收不到新数据
         b.clone().unsubscribe(lobby.clone(), id1 as _).unwrap();
@@ -168,6 +172,140 @@ mod tests {
 
         assert!(stream1.recv().await.is_none());
         let res2 = stream2.recv().await.unwrap();
-        assert_res_ok(&res2, &[v.clone()], &[]);
+        assert_res_ok(&res2, std::slice::from_ref(&v), &[]);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Subscribe(usize),
+        Unsubscribe(usize),
+        /// 不取消订阅，直接丢弃 receiver，模拟客户端异常退出
+        Drop(usize),
+        Publish(usize, i64),
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum State {
+        Live,
+        Unsubscribed,
+        Dropped,
+        /// 丢弃之后又有 publish，订阅已经被清理
+        Removed,
+    }
+
+    struct Sub {
+        topic: String,
+        id: u32,
+        rx: Option<mpsc::Receiver<Arc<CommandResponse>>>,
+        expected: Vec<i64>,
+        state: State,
+    }
+
+    fn arb_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..3usize).prop_map(Op::Subscribe),
+            any::<usize>().prop_map(Op::Unsubscribe),
+            any::<usize>().prop_map(Op::Drop),
+            (0..3usize, any::<i64>()).prop_map(|(t, v)| Op::Publish(t, v)),
+        ]
+    }
+
+    /// 时钟暂停时，sleep 只有在其它 task 都执行完之后才会返回
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    fn drain(rx: &mut mpsc::Receiver<Arc<CommandResponse>>) -> (Vec<i64>, bool) {
+        let mut values = vec![];
+        loop {
+            match rx.try_recv() {
+                Ok(res) => values.push(res.values[0].clone().try_into().unwrap()),
+                Err(TryRecvError::Empty) => return (values, false),
+                Err(TryRecvError::Disconnected) => return (values, true),
+            }
+        }
+    }
+
+    async fn run_ops(ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let b = Arc::new(Broadcaster::default());
+        let mut subs: Vec<Sub> = vec![];
+
+        for op in ops {
+            match op {
+                Op::Subscribe(t) => {
+                    let topic = format!("topic{}", t);
+                    let mut rx = b.clone().subscribe(topic.clone());
+                    settle().await;
+                    let id: i64 = rx.try_recv().unwrap().as_ref().try_into().unwrap();
+                    subs.push(Sub {
+                        topic,
+                        id: id as _,
+                        rx: Some(rx),
+                        expected: vec![],
+                        state: State::Live,
+                    });
+                }
+                Op::Unsubscribe(i) if !subs.is_empty() => {
+                    let n = subs.len();
+                    let sub = &mut subs[i % n];
+                    let result = b.clone().unsubscribe(sub.topic.clone(), sub.id);
+                    let subscribed = matches!(sub.state, State::Live | State::Dropped);
+                    prop_assert_eq!(result.is_ok(), subscribed);
+                    match sub.state {
+                        State::Live => sub.state = State::Unsubscribed,
+                        State::Dropped => sub.state = State::Removed,
+                        _ => {}
+                    }
+                }
+                Op::Drop(i) if !subs.is_empty() => {
+                    let n = subs.len();
+                    let sub = &mut subs[i % n];
+                    if sub.state == State::Live {
+                        sub.rx = None;
+                        sub.state = State::Dropped;
+                    }
+                }
+                Op::Publish(t, v) => {
+                    let topic = format!("topic{}", t);
+                    b.clone()
+                        .publish(topic.clone(), Arc::new(Value::from(v).into()));
+                    settle().await;
+                    for sub in subs.iter_mut().filter(|s| s.topic == topic) {
+                        match sub.state {
+                            State::Live => sub.expected.push(v),
+                            State::Dropped => sub.state = State::Removed,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for mut sub in subs {
+            match sub.state {
+                State::Live | State::Unsubscribed => {
+                    let (values, closed) = drain(sub.rx.as_mut().unwrap());
+                    prop_assert_eq!(values, sub.expected);
+                    prop_assert_eq!(closed, sub.state == State::Unsubscribed);
+                }
+                State::Dropped => prop_assert!(b.clone().unsubscribe(sub.topic, sub.id).is_ok()),
+                State::Removed => prop_assert!(b.clone().unsubscribe(sub.topic, sub.id).is_err()),
+            }
+        }
+
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn broadcaster_should_deliver_to_live_subscribers_in_order(ops in vec(arb_op(), 0..64)) {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .start_paused(true)
+                .build()
+                .unwrap();
+            rt.block_on(run_ops(ops))?;
+        }
     }
 }
@@ -0,0 +1,184 @@
+use std::{io, net::SocketAddr};
+#[cfg(unix)]
+use std::{os::unix::fs::FileTypeExt, path::Path};
+
+#[cfg(unix)]
+use tokio::{fs, net::UnixListener};
+use tokio::{
+    io::{duplex, AsyncRead, AsyncWrite, DuplexStream},
+    net::TcpListener,
+};
+use tracing::warn;
+
+use crate::{
+    ConnContext, Handshake, KvError, ProstClientStream, ProstServerStream, QuicCtrl, Service,
+    YamuxCtrl,
+};
+
+/// 进程内连接的缓冲区大小
+const IN_PROCESS_BUF_SIZE: usize = 64 * 1024;
+
+/// 可以用于 ProstStream 的双向 stream
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// 擦除了具体类型的 stream，不同的传输方式都可以转换成它
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// 客户端到服务器的连接，根据配置使用不同的传输方式
+pub enum ClientConnection {
+    /// TCP 或者 Unix domain socket 上的 yamux，TCP 一定有 TLS
+    Yamux(YamuxCtrl<BoxedStream>, Handshake),
+    /// QUIC
+    Quic(QuicCtrl, Handshake),
+    /// 进程内直接连接 Service，不经过任何 socket
+    InProcess(Service, ConnContext),
+}
+
+impl ClientConnection {
+    /// 创建一个进程内的连接
+    pub fn in_process(service: Service) -> Self {
+        ClientConnection::InProcess(service, ConnContext::new(None, None))
+    }
+
+    /// 握手协商出来的连接参数，进程内连接没有握手
+    pub fn handshake(&self) -> Option<&Handshake> {
+        match self {
+            ClientConnection::Yamux(_, handshake) | ClientConnection::Quic(_, handshake) => {
+                Some(handshake)
+            }
+            ClientConnection::InProcess(..) => None,
+        }
+    }
+
+    /// 打开一个新的 stream，使用握手协商出来的压缩算法和最大 frame
+    pub async fn open_stream(&mut self) -> Result<ProstClientStream<BoxedStream>, KvError> {
+        let stream: BoxedStream = match self {
+            ClientConnection::Yamux(ctrl, _) => {
+                let stream = ctrl
+                    .open_stream()
+                    .await
+                    .map_err(|e| KvError::Internal(e.to_string()))?;
+                Box::new(stream)
+            }
+            ClientConnection::Quic(ctrl, _) => Box::new(ctrl.open_stream().await?),
+            ClientConnection::InProcess(service, ctx) => {
+                Box::new(connect_in_process(service.clone(), ctx.clone()))
+            }
+        };
+
+        let stream = ProstClientStream::new(stream);
+        Ok(match self.handshake() {
+            Some(handshake) => stream
+                .with_codec(handshake.codec)
+                .with_max_frame(handshake.max_frame_size),
+            None => stream,
+        })
+    }
+}
+
+/// 在进程内连接 Service，返回的 stream 可以直接用于 ProstClientStream
+pub fn connect_in_process(service: Service, ctx: ConnContext) -> DuplexStream {
+    let (client, server) = duplex(IN_PROCESS_BUF_SIZE);
+    let server = ProstServerStream::new(server, service).with_context(ctx);
+    tokio::spawn(async move {
+        if let Err(e) = server.process().await {
+            warn!("Failed to process in-process stream: {}", e);
+        }
+    });
+    client
+}
+
+/// 服务器监听的 socket
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// 监听 TCP 地址
+    pub async fn bind_tcp(addr: &str) -> Result<Self, KvError> {
+        Ok(Listener::Tcp(TcpListener::bind(addr).await?))
+    }
+
+    /// 监听 Unix domain socket，之前遗留的 socket 文件会被删除
+    #[cfg(unix)]
+    pub async fn bind_unix(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref();
+        if let Ok(meta) = fs::symlink_metadata(path).await {
+            if !meta.file_type().is_socket() {
+                return Err(KvError::Internal(format!(
+                    "{} exists and is not a socket",
+                    path.display()
+                )));
+            }
+            fs::remove_file(path).await?;
+        }
+        Ok(Listener::Unix(UnixListener::bind(path)?))
+    }
+
+    /// 接受一个新的连接，Unix domain socket 没有对端的网络地址
+    pub async fn accept(&self) -> io::Result<(BoxedStream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), Some(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), None))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_res_ok, CommandRequest, MemTable, Value};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn in_process_connection_should_work() -> Result<()> {
+        let mut conn = ClientConnection::in_process(Service::new(MemTable::new()));
+        let mut client1 = conn.open_stream().await?;
+        let mut client2 = conn.open_stream().await?;
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client1.execute_unary(cmd).await?;
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client2.execute_unary(cmd).await?;
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_listener_should_replace_stale_socket() -> Result<()> {
+        use tokio::net::UnixStream;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kv.sock");
+
+        // 第一次监听之后留下的 socket 文件不影响第二次监听
+        drop(Listener::bind_unix(&path).await?);
+        let listener = Listener::bind_unix(&path).await?;
+
+        tokio::spawn(async move { UnixStream::connect(path).await });
+        let (_, addr) = listener.accept().await?;
+        assert!(addr.is_none());
+
+        // 不是 socket 的文件不能被删除
+        let file = dir.path().join("kv.conf");
+        std::fs::write(&file, "")?;
+        assert!(Listener::bind_unix(&file).await.is_err());
+
+        Ok(())
+    }
+}
@@ -3,9 +3,12 @@
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
+    /// 客户端 span 的 W3C trace context，服务器处理请求的 span 以它为父节点
+    #[prost(message, optional, tag = "100")]
+    pub trace: ::core::option::Option<TraceContext>,
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
@@ -39,8 +42,52 @@ pub mod command_request {
         Unsubscribe(super::Unsubscribe),
         #[prost(message, tag = "12")]
         Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Backup(super::Backup),
+        #[prost(message, tag = "14")]
+        Restore(super::Restore),
+        #[prost(message, tag = "15")]
+        Stats(super::Stats),
+        #[prost(message, tag = "16")]
+        Lpush(super::Lpush),
+        #[prost(message, tag = "17")]
+        Rpush(super::Rpush),
+        #[prost(message, tag = "18")]
+        Lpop(super::Lpop),
+        #[prost(message, tag = "19")]
+        Rpop(super::Rpop),
+        #[prost(message, tag = "20")]
+        Lrange(super::Lrange),
+        #[prost(message, tag = "21")]
+        Sadd(super::Sadd),
+        #[prost(message, tag = "22")]
+        Srem(super::Srem),
+        #[prost(message, tag = "23")]
+        Smembers(super::Smembers),
+        #[prost(message, tag = "24")]
+        Sinter(super::Sinter),
+        #[prost(message, tag = "25")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "26")]
+        Zrange(super::Zrange),
+        #[prost(message, tag = "27")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag = "28")]
+        Eval(super::Eval),
+        #[prost(message, tag = "29")]
+        ScriptLoad(super::ScriptLoad),
     }
 }
+/// W3C trace context，见 <https://www.w3.org/TR/trace-context/>
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TraceContext {
+    #[prost(string, tag = "1")]
+    pub traceparent: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub tracestate: ::prost::alloc::string::String,
+}
 /// 服务器的响应
 #[derive(PartialOrd, serde::Serialize)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -117,7 +164,7 @@ pub struct Hmget {
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -129,15 +176,45 @@ pub mod value {
         #[prost(string, tag = "1")]
         String(::prost::alloc::string::String),
         #[prost(bytes, tag = "2")]
-        Binary(::prost::alloc::vec::Vec<u8>),
+        Binary(::prost::bytes::Bytes),
         #[prost(int64, tag = "3")]
         Integer(i64),
         #[prost(double, tag = "4")]
         Float(f64),
         #[prost(bool, tag = "5")]
         Bool(bool),
+        #[prost(message, tag = "6")]
+        List(super::ValueList),
+        #[prost(message, tag = "7")]
+        Map(super::ValueMap),
+        #[prost(message, tag = "8")]
+        Null(super::Null),
     }
 }
+/// 一组值
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 字符串到值的映射，保持插入的顺序
+/// kind 为空时是普通的 map，用 map 表示的集合是 "set" 或者 "sorted set"
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ValueMap {
+    #[prost(message, repeated, tag = "1")]
+    pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    #[prost(string, tag = "2")]
+    pub kind: ::prost::alloc::string::String,
+}
+/// 显式的空值，和没有设置的 Value 区分开
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Null {}
 /// 返回的 kvpair
 #[derive(PartialOrd, serde::Serialize)]
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -210,3 +287,271 @@ pub struct Hmexist {
     #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 在 list 的头部依次插入一组值，返回 list 的长度
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 在 list 的尾部依次添加一组值，返回 list 的长度
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从 list 的头部取出最多 count 个值，count 为 0 时取出一个
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 从 list 的尾部取出最多 count 个值，count 为 0 时取出一个
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 返回 list 中下标在 \[start, stop\] 之间的值，负数从尾部开始计算，-1 是最后一个
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// 往 set 中添加一组 member，返回新添加的数量
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 从 set 中删除一组 member，返回删除的数量
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Srem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 返回 set 中所有的 member
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Smembers {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 返回 table 中一组 set 的交集
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Sinter {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScoredMember {
+    #[prost(string, tag = "1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub score: f64,
+}
+/// 往 sorted set 中添加一组 member，已有的 member 更新 score，返回新添加的数量
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+/// 按排名返回 sorted set 中 \[start, stop\] 之间的 member 和 score，下标规则和 Lrange 相同
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// 返回 sorted set 中 score 在 \[min, max\] 之间的 member 和 score
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub min: f64,
+    #[prost(double, tag = "4")]
+    pub max: f64,
+}
+/// 执行 WASM 脚本，返回脚本的输出
+/// 脚本执行期间其他写命令会等待，脚本失败时它的修改都不会生效
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Eval {
+    /// 脚本的内容，为空时使用 hash 对应的已缓存的脚本
+    #[prost(bytes = "bytes", tag = "1")]
+    pub script: ::prost::bytes::Bytes,
+    /// 脚本的 sha256，script 不为空时忽略
+    #[prost(string, tag = "2")]
+    pub hash: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+}
+/// 编译并缓存脚本，返回脚本的 sha256，之后可以只用 hash 执行
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ScriptLoad {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub script: ::prost::bytes::Bytes,
+}
+/// 把所有 table 备份到服务器备份目录下的文件，返回备份的 kvpair 数量
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Backup {
+    /// 备份目录下的文件名
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// jsonl 或者 protobuf，为空时根据文件扩展名决定
+    #[prost(string, tag = "2")]
+    pub format: ::prost::alloc::string::String,
+}
+/// 从服务器备份目录下的文件恢复数据，返回恢复的 kvpair 数量
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Restore {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub format: ::prost::alloc::string::String,
+    /// 恢复之前先清空所有的 table，否则只覆盖备份里有的 key
+    #[prost(bool, tag = "3")]
+    pub clear: bool,
+}
+/// 存储的统计信息，比如 MemTable 的内存占用和淘汰的 key 数量，结果在 pairs 里
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Stats {}
+/// 备份文件里的一条记录，protobuf 格式的备份是一组 length-delimited 的 DumpRecord
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DumpRecord {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// 连接建立后客户端发送的第一个 frame
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hello {
+    /// 固定为 "kv"，用来识别不支持握手的旧客户端
+    #[prost(string, tag = "1")]
+    pub magic: ::prost::alloc::string::String,
+    /// 客户端的协议版本
+    #[prost(uint32, tag = "2")]
+    pub version: u32,
+    /// 客户端能解压的压缩算法（gzip/zstd/lz4/snappy/none），按优先级排序
+    #[prost(string, repeated, tag = "3")]
+    pub codecs: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 客户端能接受的最大 frame
+    #[prost(uint32, tag = "4")]
+    pub max_frame_size: u32,
+    /// 客户端需要的功能
+    #[prost(string, repeated, tag = "5")]
+    pub features: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 要使用的命名空间，为空时由服务器根据 TLS 身份决定
+    #[prost(string, tag = "6")]
+    pub namespace: ::prost::alloc::string::String,
+}
+/// 服务器对 Hello 的回应
+/// 前两个字段和 CommandResponse 一致，这样旧客户端也能看到错误信息
+#[derive(PartialOrd, serde::Serialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HelloResponse {
+    #[prost(uint32, tag = "1")]
+    pub status: u32,
+    #[prost(string, tag = "2")]
+    pub message: ::prost::alloc::string::String,
+    /// 双方都支持的协议版本
+    #[prost(uint32, tag = "5")]
+    pub version: u32,
+    /// 服务器发送响应时使用的压缩算法
+    #[prost(string, tag = "6")]
+    pub codec: ::prost::alloc::string::String,
+    /// 服务器能解压的压缩算法
+    #[prost(string, repeated, tag = "7")]
+    pub codecs: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 双方都能接受的最大 frame
+    #[prost(uint32, tag = "8")]
+    pub max_frame_size: u32,
+    /// 服务器支持的功能
+    #[prost(string, repeated, tag = "9")]
+    pub features: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
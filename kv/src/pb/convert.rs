@@ -0,0 +1,194 @@
+use std::any::type_name;
+
+use http::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Number, Value as Json};
+
+use crate::{value, CommandResponse, KvError, Kvpair, Value};
+
+/// 把任意实现了 Serialize 的类型转换成 Value
+///
+/// 标量映射到对应的 Value 类型，数组映射到 list，结构体和 map 映射到 map。
+pub fn to_value<T: Serialize + ?Sized>(v: &T) -> Result<Value, KvError> {
+    let json =
+        serde_json::to_value(v).map_err(|e| KvError::ConvertError(e.to_string(), "Value"))?;
+
+    let value = match json {
+        Json::Null => Value::null(),
+        Json::Bool(b) => b.into(),
+        Json::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => i.into(),
+            (None, Some(f)) => f.into(),
+            _ => return Err(KvError::ConvertError(n.to_string(), "Value")),
+        },
+        Json::String(s) => s.into(),
+        Json::Array(values) => values
+            .iter()
+            .map(to_value)
+            .collect::<Result<Vec<_>, _>>()?
+            .into(),
+        Json::Object(map) => map
+            .iter()
+            .map(|(k, v)| Ok(Kvpair::new(k, to_value(v)?)))
+            .collect::<Result<Vec<_>, KvError>>()?
+            .into(),
+    };
+
+    Ok(value)
+}
+
+/// 把 Value 转换成任意实现了 Deserialize 的类型，是 `to_value` 的逆操作
+pub fn from_value<T: DeserializeOwned>(v: Value) -> Result<T, KvError> {
+    serde_json::from_value(to_json(&v))
+        .map_err(|_| KvError::ConvertError(v.format(), type_name::<T>()))
+}
+
+/// 把 Value 转换成 JSON，二进制数据转换成字节数组
+pub fn to_json(v: &Value) -> Json {
+    match &v.value {
+        None | Some(value::Value::Null(_)) => Json::Null,
+        Some(value::Value::Bool(b)) => Json::Bool(*b),
+        Some(value::Value::Integer(i)) => Json::Number((*i).into()),
+        Some(value::Value::Float(f)) => Number::from_f64(*f).map_or(Json::Null, Json::Number),
+        Some(value::Value::String(s)) => Json::String(s.clone()),
+        Some(value::Value::Binary(b)) => b.iter().map(|v| Json::from(*v)).collect(),
+        Some(value::Value::List(l)) => l.values.iter().map(to_json).collect(),
+        Some(value::Value::Map(m)) => m
+            .pairs
+            .iter()
+            .map(|p| (p.key.clone(), p.value.as_ref().map_or(Json::Null, to_json)))
+            .collect(),
+    }
+}
+
+impl CommandResponse {
+    /// 把非 2xx 的响应转换成 KvError
+    pub fn into_result(self) -> Result<Self, KvError> {
+        let msg = self.message;
+        let strip = |prefix: &str| msg.strip_prefix(prefix).unwrap_or(&msg).to_string();
+
+        match StatusCode::from_u16(self.status as _) {
+            Ok(status) if status.is_success() => Ok(Self {
+                message: msg,
+                ..self
+            }),
+            Ok(StatusCode::BAD_REQUEST) => Err(KvError::InvalidCommand(
+                strip("Cannot parse command: `")
+                    .trim_end_matches('`')
+                    .into(),
+            )),
+            Ok(StatusCode::FORBIDDEN) => {
+                Err(KvError::PermissionDenied(strip("Permission denied: ")))
+            }
+            Ok(StatusCode::TOO_MANY_REQUESTS) => {
+                Err(KvError::RateLimited(strip("Too many requests: ")))
+            }
+            Ok(StatusCode::INSUFFICIENT_STORAGE) => {
+                Err(KvError::QuotaExceeded(strip("Quota exceeded: ")))
+            }
+            Ok(StatusCode::UPGRADE_REQUIRED) => {
+                Err(KvError::HandshakeError(strip("Handshake error: ")))
+            }
+            _ => Err(KvError::ServerError(self.status, msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn scalars_should_map_to_value_types() {
+        assert_eq!(to_value(&42).unwrap(), 42.into());
+        assert_eq!(to_value(&1.5).unwrap(), 1.5.into());
+        assert_eq!(to_value("hello").unwrap(), "hello".into());
+        assert_eq!(to_value(&true).unwrap(), true.into());
+        assert_eq!(to_value(&None::<i64>).unwrap(), Value::null());
+
+        assert_eq!(from_value::<i64>(42.into()).unwrap(), 42);
+        assert_eq!(from_value::<String>("hello".into()).unwrap(), "hello");
+        assert_eq!(from_value::<Option<bool>>(Value::default()).unwrap(), None);
+        assert_eq!(from_value::<Option<bool>>(Value::null()).unwrap(), None);
+        assert_eq!(from_value::<Vec<u8>>(b"abc".into()).unwrap(), b"abc");
+        assert!(from_value::<i64>("hello".into()).is_err());
+        assert!(from_value::<i64>("42".into()).is_err());
+        assert!(from_value::<bool>("true".into()).is_err());
+    }
+
+    #[test]
+    fn user_types_should_roundtrip() {
+        let user = User {
+            name: "tyr".into(),
+            age: 30,
+            tags: vec!["admin".into()],
+        };
+        let v = to_value(&user).unwrap();
+        let expected: Value = vec![
+            Kvpair::new("age", 30.into()),
+            Kvpair::new("name", "tyr".into()),
+            Kvpair::new("tags", vec![Value::from("admin")].into()),
+        ]
+        .into();
+        assert_eq!(v, expected);
+        assert_eq!(
+            v.format(),
+            r#"{"age": 30, "name": "tyr", "tags": ["admin"]}"#
+        );
+        assert_eq!(from_value::<User>(v).unwrap(), user);
+
+        // 字符串不会被当成 JSON 解析
+        let json: Value = serde_json::to_string(&user).unwrap().into();
+        assert!(matches!(
+            from_value::<User>(json),
+            Err(KvError::ConvertError(..))
+        ));
+        assert!(from_value::<Vec<i64>>("[1,2]".into()).is_err());
+
+        let map = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let v = to_value(&map).unwrap();
+        assert_eq!(from_value::<HashMap<String, i32>>(v).unwrap(), map);
+    }
+
+    #[test]
+    fn status_should_map_to_kv_error() {
+        let res: CommandResponse =
+            KvError::PermissionDenied("anonymous cannot run hget".into()).into();
+        assert_eq!(
+            res.into_result(),
+            Err(KvError::PermissionDenied(
+                "anonymous cannot run hget".into()
+            ))
+        );
+
+        let res: CommandResponse = KvError::InvalidCommand("Request has no data".into()).into();
+        assert_eq!(
+            res.into_result(),
+            Err(KvError::InvalidCommand("Request has no data".into()))
+        );
+
+        let res: CommandResponse = KvError::QuotaExceeded("team-a has 10 keys".into()).into();
+        assert_eq!(res.status, 507);
+        assert_eq!(
+            res.into_result(),
+            Err(KvError::QuotaExceeded("team-a has 10 keys".into()))
+        );
+
+        let res: CommandResponse = KvError::NotFound("t1".into(), "k1".into()).into();
+        assert!(matches!(
+            res.into_result(),
+            Err(KvError::ServerError(404, _))
+        ));
+
+        assert!(CommandResponse::ok().into_result().is_ok());
+    }
+}
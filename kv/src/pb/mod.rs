@@ -1,9 +1,12 @@
 pub mod abi;
+mod convert;
 
 pub use abi::{command_request::RequestData, *};
 use bytes::Bytes;
+pub use convert::*;
 use http::StatusCode;
 use prost::Message;
+use std::mem;
 
 use crate::KvError;
 
@@ -15,6 +18,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -24,6 +28,7 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -34,6 +39,7 @@ impl CommandRequest {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
             })),
+            ..Default::default()
         }
     }
 
@@ -43,6 +49,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -52,6 +59,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -62,6 +70,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|k| k.into()).collect(),
             })),
+            ..Default::default()
         }
     }
 
@@ -72,6 +81,7 @@ impl CommandRequest {
                 table: table.into(),
                 pairs,
             })),
+            ..Default::default()
         }
     }
 
@@ -81,6 +91,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: keys.into_iter().map(|k| k.into()).collect(),
             })),
+            ..Default::default()
         }
     }
 
@@ -90,6 +101,7 @@ impl CommandRequest {
                 table: table.into(),
                 keys: key.into_iter().map(|k| k.into()).collect(),
             })),
+            ..Default::default()
         }
     }
 }
@@ -161,7 +173,7 @@ impl From<bool> for Value {
 impl From<Vec<u8>> for Value {
     fn from(v: Vec<u8>) -> Self {
         Self {
-            value: Some(value::Value::Binary(v)),
+            value: Some(value::Value::Binary(v.into())),
         }
     }
 }
@@ -169,7 +181,7 @@ impl From<Vec<u8>> for Value {
 impl From<sled::IVec> for Value {
     fn from(v: sled::IVec) -> Self {
         Self {
-            value: Some(value::Value::Binary(v.to_vec())),
+            value: Some(value::Value::Binary(Bytes::copy_from_slice(&v))),
         }
     }
 }
@@ -210,6 +222,12 @@ impl From<Vec<Kvpair>> for CommandResponse {
 /// 从 KvError 转换成 CommandResponse
 impl From<KvError> for CommandResponse {
     fn from(e: KvError) -> Self {
+        (&e).into()
+    }
+}
+
+impl From<&KvError> for CommandResponse {
+    fn from(e: &KvError) -> Self {
         let mut result = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
@@ -221,7 +239,17 @@ impl From<KvError> for CommandResponse {
             KvError::NotFound(_, _) | KvError::NotSubscription(_) => {
                 result.status = StatusCode::NOT_FOUND.as_u16() as _
             }
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::InvalidCommand(_) | KvError::WrongType(..) | KvError::ScriptError(_) => {
+                result.status = StatusCode::BAD_REQUEST.as_u16() as _
+            }
+            KvError::PermissionDenied(_) => result.status = StatusCode::FORBIDDEN.as_u16() as _,
+            KvError::RateLimited(_) => result.status = StatusCode::TOO_MANY_REQUESTS.as_u16() as _,
+            KvError::QuotaExceeded(_) => {
+                result.status = StatusCode::INSUFFICIENT_STORAGE.as_u16() as _
+            }
+            KvError::FrameTooLarge(_) => {
+                result.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as _
+            }
             _ => {}
         }
 
@@ -252,19 +280,93 @@ impl<const N: usize> From<&[u8; N]> for Value {
 impl From<Bytes> for Value {
     fn from(buf: Bytes) -> Self {
         Self {
-            value: Some(value::Value::Binary(buf.into())),
+            value: Some(value::Value::Binary(buf)),
+        }
+    }
+}
+
+/// 从一组 Value 转换成 list
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self {
+            value: Some(value::Value::List(ValueList { values })),
+        }
+    }
+}
+
+/// 从一组 Kvpair 转换成 map
+impl From<Vec<Kvpair>> for Value {
+    fn from(pairs: Vec<Kvpair>) -> Self {
+        Self {
+            value: Some(value::Value::Map(ValueMap {
+                pairs,
+                kind: String::new(),
+            })),
         }
     }
 }
 
 impl Value {
+    /// 显式的空值
+    pub fn null() -> Self {
+        Self {
+            value: Some(value::Value::Null(Null {})),
+        }
+    }
+
+    /// 估算占用的内存字节数，包括 list 和 map 里嵌套的值
+    pub fn approx_size(&self) -> usize {
+        mem::size_of::<Self>() + self.heap_size()
+    }
+
+    fn heap_size(&self) -> usize {
+        match &self.value {
+            Some(value::Value::String(s)) => s.len(),
+            Some(value::Value::Binary(b)) => b.len(),
+            Some(value::Value::List(l)) => l.values.iter().map(|v| v.approx_size()).sum(),
+            Some(value::Value::Map(m)) => m
+                .pairs
+                .iter()
+                .map(|p| {
+                    let value = p.value.as_ref().map(|v| v.heap_size()).unwrap_or(0);
+                    mem::size_of::<Kvpair>() + p.key.len() + value
+                })
+                .sum(),
+            _ => 0,
+        }
+    }
+
     pub fn format(&self) -> String {
         match &self.value {
             Some(value::Value::String(s)) => s.clone(),
+            _ => self.format_nested(),
+        }
+    }
+
+    /// list 和 map 里的字符串加上引号，以免和其它类型混淆
+    fn format_nested(&self) -> String {
+        match &self.value {
+            Some(value::Value::String(s)) => format!("{:?}", s),
             Some(value::Value::Integer(i)) => i.to_string(),
             Some(value::Value::Float(f)) => f.to_string(),
             Some(value::Value::Binary(b)) => format!("{:?}", b),
             Some(value::Value::Bool(b)) => b.to_string(),
+            Some(value::Value::List(l)) => {
+                let values: Vec<_> = l.values.iter().map(|v| v.format_nested()).collect();
+                format!("[{}]", values.join(", "))
+            }
+            Some(value::Value::Map(m)) => {
+                let pairs: Vec<_> = m
+                    .pairs
+                    .iter()
+                    .map(|p| {
+                        let v = p.value.as_ref().map(|v| v.format_nested());
+                        format!("{:?}: {}", p.key, v.as_deref().unwrap_or("None"))
+                    })
+                    .collect();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Some(value::Value::Null(_)) => "null".to_string(),
             None => "None".to_string(),
         }
     }
@@ -274,6 +376,7 @@ impl CommandRequest {
     pub fn new_subscribe(name: impl Into<String>) -> Self {
         Self {
             request_data: Some(RequestData::Subscribe(Subscribe { topic: name.into() })),
+            ..Default::default()
         }
     }
 
@@ -283,6 +386,7 @@ impl CommandRequest {
                 topic: name.into(),
                 id,
             })),
+            ..Default::default()
         }
     }
 
@@ -292,6 +396,233 @@ impl CommandRequest {
                 topic: name.into(),
                 data,
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_backup(name: impl Into<String>, format: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Backup(Backup {
+                name: name.into(),
+                format: format.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_restore(name: impl Into<String>, format: impl Into<String>, clear: bool) -> Self {
+        Self {
+            request_data: Some(RequestData::Restore(Restore {
+                name: name.into(),
+                format: format.into(),
+                clear,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_stats() -> Self {
+        Self {
+            request_data: Some(RequestData::Stats(Stats {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Sadd(Sadd {
+                table: table.into(),
+                key: key.into(),
+                members: members.into_iter().map(|m| m.into()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_srem(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Srem(Srem {
+                table: table.into(),
+                key: key.into(),
+                members: members.into_iter().map(|m| m.into()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_smembers(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Smembers(Smembers {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sinter(table: impl Into<String>, keys: Vec<impl Into<String>>) -> Self {
+        Self {
+            request_data: Some(RequestData::Sinter(Sinter {
+                table: table.into(),
+                keys: keys.into_iter().map(|k| k.into()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: Vec<(impl Into<String>, f64)>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members: members
+                    .into_iter()
+                    .map(|(member, score)| ScoredMember {
+                        member: member.into(),
+                        score,
+                    })
+                    .collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrangebyscore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        min: f64,
+        max: f64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into(),
+                min,
+                max,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_eval(script: impl Into<Bytes>, args: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: script.into(),
+                hash: String::new(),
+                args,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 执行已缓存的脚本
+    pub fn new_eval_hash(hash: impl Into<String>, args: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: Bytes::new(),
+                hash: hash.into(),
+                args,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_script_load(script: impl Into<Bytes>) -> Self {
+        Self {
+            request_data: Some(RequestData::ScriptLoad(ScriptLoad {
+                script: script.into(),
+            })),
+            ..Default::default()
         }
     }
 
@@ -299,13 +630,71 @@ impl CommandRequest {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// 命令的名字，用于日志和统计
+    pub fn name(&self) -> &'static str {
+        match &self.request_data {
+            Some(RequestData::Hget(_)) => "hget",
+            Some(RequestData::Hgetall(_)) => "hgetall",
+            Some(RequestData::Hmget(_)) => "hmget",
+            Some(RequestData::Hset(_)) => "hset",
+            Some(RequestData::Hmset(_)) => "hmset",
+            Some(RequestData::Hdel(_)) => "hdel",
+            Some(RequestData::Hmdel(_)) => "hmdel",
+            Some(RequestData::Hexist(_)) => "hexist",
+            Some(RequestData::Hmexist(_)) => "hmexist",
+            Some(RequestData::Subscribe(_)) => "subscribe",
+            Some(RequestData::Unsubscribe(_)) => "unsubscribe",
+            Some(RequestData::Publish(_)) => "publish",
+            Some(RequestData::Backup(_)) => "backup",
+            Some(RequestData::Restore(_)) => "restore",
+            Some(RequestData::Stats(_)) => "stats",
+            Some(RequestData::Lpush(_)) => "lpush",
+            Some(RequestData::Rpush(_)) => "rpush",
+            Some(RequestData::Lpop(_)) => "lpop",
+            Some(RequestData::Rpop(_)) => "rpop",
+            Some(RequestData::Lrange(_)) => "lrange",
+            Some(RequestData::Sadd(_)) => "sadd",
+            Some(RequestData::Srem(_)) => "srem",
+            Some(RequestData::Smembers(_)) => "smembers",
+            Some(RequestData::Sinter(_)) => "sinter",
+            Some(RequestData::Zadd(_)) => "zadd",
+            Some(RequestData::Zrange(_)) => "zrange",
+            Some(RequestData::Zrangebyscore(_)) => "zrangebyscore",
+            Some(RequestData::Eval(_)) => "eval",
+            Some(RequestData::ScriptLoad(_)) => "script_load",
+            None => "unknown",
+        }
+    }
+
+    /// 命令是否可以安全地重复执行，只读的命令重复执行不会改变结果
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self.request_data,
+            Some(
+                RequestData::Hget(_)
+                    | RequestData::Hgetall(_)
+                    | RequestData::Hmget(_)
+                    | RequestData::Hexist(_)
+                    | RequestData::Hmexist(_)
+                    | RequestData::Stats(_)
+                    | RequestData::Lrange(_)
+                    | RequestData::Smembers(_)
+                    | RequestData::Sinter(_)
+                    | RequestData::Zrange(_)
+                    | RequestData::Zrangebyscore(_)
+                    | RequestData::ScriptLoad(_)
+            )
+        )
+    }
 }
 
 impl CommandResponse {
     pub fn ok() -> Self {
-        let mut result = CommandResponse::default();
-        result.status = StatusCode::OK.as_u16() as _;
-        result
+        CommandResponse {
+            status: StatusCode::OK.as_u16() as _,
+            ..Default::default()
+        }
     }
 
     pub fn format(&self) -> String {
@@ -351,7 +740,7 @@ impl TryFrom<Value> for Bytes {
 
     fn try_from(v: Value) -> Result<Self, Self::Error> {
         match v.value {
-            Some(value::Value::Binary(b)) => Ok(b.into()),
+            Some(value::Value::Binary(b)) => Ok(b),
             _ => Err(KvError::ConvertError(v.format(), "Binary")),
         }
     }
@@ -384,7 +773,7 @@ impl TryFrom<&CommandResponse> for i64 {
         if value.status != StatusCode::OK.as_u16() as u32 {
             return Err(KvError::ConvertError(value.format(), "CommandResponse"));
         }
-        match value.values.get(0) {
+        match value.values.first() {
             Some(v) => v.try_into(),
             None => Err(KvError::ConvertError(value.format(), "CommandResponse")),
         }
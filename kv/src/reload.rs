@@ -0,0 +1,331 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::{fs, time};
+use tracing::{error, info};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::{
+    check_tls, log_filter, AuthMiddleware, ConfigLoader, KvError, RateLimitMiddleware,
+    ServerConfig, TlsServerAcceptor,
+};
+
+/// 检查配置文件是否修改的间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 修改日志过滤规则的 handle
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// 服务器运行时可以热更新的部分，clone 之后共享同样的状态
+#[derive(Clone)]
+pub struct ServerRuntime {
+    /// 当前生效的配置
+    config: Arc<Mutex<ServerConfig>>,
+    acceptor: Option<TlsServerAcceptor>,
+    auth: AuthMiddleware,
+    rate_limit: RateLimitMiddleware,
+    /// 日志过滤规则的 handle，以及是否总是记录请求的 span
+    log_filter: Option<(LogFilterHandle, bool)>,
+}
+
+impl ServerRuntime {
+    /// 根据配置创建 TLS acceptor 和需要热更新的中间件
+    pub fn new(config: &ServerConfig) -> Result<Self, KvError> {
+        check_tls(&config.general, config.tls.is_some())?;
+
+        let acceptor = config
+            .tls
+            .as_ref()
+            .map(|tls| TlsServerAcceptor::new(&tls.cert, &tls.key, tls.ca.as_deref()))
+            .transpose()?;
+
+        let runtime = Self {
+            config: Arc::new(Mutex::new(config.clone())),
+            acceptor,
+            auth: AuthMiddleware::allow_all(),
+            rate_limit: RateLimitMiddleware::default(),
+            log_filter: None,
+        };
+        runtime.apply_acl(config);
+        runtime.apply_rate_limit(config);
+
+        Ok(runtime)
+    }
+
+    /// 设置可以热更新的日志过滤规则，trace_requests 和生成初始规则时保持一致
+    pub fn with_log_filter(mut self, handle: LogFilterHandle, trace_requests: bool) -> Self {
+        self.log_filter = Some((handle, trace_requests));
+        self
+    }
+
+    /// 当前生效的配置
+    pub fn config(&self) -> ServerConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    pub fn acceptor(&self) -> Option<TlsServerAcceptor> {
+        self.acceptor.clone()
+    }
+
+    pub fn auth(&self) -> AuthMiddleware {
+        self.auth.clone()
+    }
+
+    pub fn rate_limit(&self) -> RateLimitMiddleware {
+        self.rate_limit.clone()
+    }
+
+    /// 应用新的配置
+    ///
+    /// 包含需要重启才能生效的修改，或者证书、日志级别加载失败时，整个配置都会被拒绝。
+    /// 所有的修改都先加载好，全部成功之后才会应用。
+    pub fn reload(&self, new: ServerConfig) -> Result<(), KvError> {
+        let mut config = self.config.lock().unwrap();
+
+        let fields = config.restart_required(&new);
+        if !fields.is_empty() {
+            return Err(KvError::InvalidConfig(format!(
+                "{} cannot be changed at runtime, restart the server to apply",
+                fields.join(", ")
+            )));
+        }
+
+        let acceptor = match (&self.acceptor, &new.tls) {
+            (Some(_), Some(tls)) if config.tls != new.tls => Some(TlsServerAcceptor::new(
+                &tls.cert,
+                &tls.key,
+                tls.ca.as_deref(),
+            )?),
+            _ => None,
+        };
+
+        // 去掉 log.level 时恢复成 RUST_LOG
+        let filter = match &self.log_filter {
+            Some((_, trace_requests)) if config.log.level != new.log.level => {
+                Some(log_filter(new.log.level.as_deref(), *trace_requests)?)
+            }
+            _ => None,
+        };
+
+        if let (Some(current), Some(acceptor)) = (&self.acceptor, acceptor) {
+            current.replace(&acceptor);
+            info!("TLS certificate reloaded");
+        }
+
+        if let (Some((handle, _)), Some(filter)) = (&self.log_filter, filter) {
+            handle
+                .reload(filter)
+                .map_err(|e| KvError::Internal(e.to_string()))?;
+            info!(
+                "Log level changed to {}",
+                new.log.level.as_deref().unwrap_or("RUST_LOG")
+            );
+        }
+
+        if config.acl != new.acl {
+            self.apply_acl(&new);
+            info!("ACL changed to {:?}", new.acl);
+        }
+
+        if config.rate_limit != new.rate_limit {
+            self.apply_rate_limit(&new);
+            info!("Rate limit changed to {:?}", new.rate_limit);
+        }
+
+        *config = new;
+        Ok(())
+    }
+
+    fn apply_acl(&self, config: &ServerConfig) {
+        match &config.acl {
+            Some(acl) => self.auth.set_identities(&acl.identities),
+            None => self.auth.clear(),
+        }
+    }
+
+    fn apply_rate_limit(&self, config: &ServerConfig) {
+        let limit = config.rate_limit.as_ref().map(|limit| {
+            let burst = limit.burst.unwrap_or(limit.requests_per_second);
+            (limit.requests_per_second, burst)
+        });
+        self.rate_limit.set_limit(limit);
+    }
+}
+
+/// 监控 loader 里的配置文件，文件被修改或者收到 SIGHUP 时重新加载
+///
+/// 每次都重新合并所有的层，所以环境变量和命令行参数的覆盖仍然有效。
+/// 不是 Unix 的平台上没有 SIGHUP，只能通过修改文件触发。
+pub async fn watch_config(loader: ConfigLoader, runtime: ServerRuntime) -> Result<(), KvError> {
+    let files = loader.files();
+    let mut hangup = Hangup::new()?;
+    let mut ticker = time::interval(WATCH_INTERVAL);
+    let mut modified = modified_times(&files).await;
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("Got SIGHUP, reloading {:?}", files),
+            _ = ticker.tick() => {
+                if modified_times(&files).await == modified {
+                    continue;
+                }
+                info!("{:?} changed, reloading", files);
+            }
+        }
+        modified = modified_times(&files).await;
+
+        match loader.load().and_then(|config| runtime.reload(config)) {
+            Ok(()) => info!("Config reloaded from {:?}", files),
+            Err(e) => error!("Failed to reload config from {:?}: {}", files, e),
+        }
+    }
+}
+
+/// SIGHUP 信号，不是 Unix 的平台上永远不会收到
+struct Hangup {
+    #[cfg(unix)]
+    signal: Signal,
+}
+
+impl Hangup {
+    fn new() -> Result<Self, KvError> {
+        Ok(Self {
+            #[cfg(unix)]
+            signal: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    let mut times = Vec::with_capacity(files.len());
+    for path in files {
+        times.push(modified_time(path).await);
+    }
+    times
+}
+
+async fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).await.ok()?.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tls_utils::*, AclConfig, RateLimitConfig, StorageConfig};
+    use anyhow::Result;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn config() -> ServerConfig {
+        toml::from_str(include_str!("../fixtures/server.conf")).unwrap()
+    }
+
+    #[test]
+    fn reload_should_reject_restart_required_changes() {
+        let runtime = ServerRuntime::new(&config()).unwrap();
+
+        let mut new = config();
+        new.storage = StorageConfig::MemTable;
+        new.acl = Some(AclConfig {
+            identities: vec!["awesome-device-id".into()],
+        });
+        let err = runtime.reload(new).unwrap_err();
+
+        assert_eq!(
+            err,
+            KvError::InvalidConfig(
+                "storage cannot be changed at runtime, restart the server to apply".into()
+            )
+        );
+        // 整个配置被拒绝，acl 也没有生效
+        assert_eq!(runtime.config(), config());
+    }
+
+    fn current_filter(handle: &LogFilterHandle) -> String {
+        handle.with_current(|filter| filter.to_string()).unwrap()
+    }
+
+    #[test]
+    fn reload_should_apply_runtime_changes() -> Result<()> {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+        let runtime = ServerRuntime::new(&config())?.with_log_filter(handle.clone(), false);
+
+        let mut new = config();
+        new.log.level = Some("kv=debug".into());
+        new.acl = Some(AclConfig {
+            identities: vec!["awesome-device-id".into()],
+        });
+        new.rate_limit = Some(RateLimitConfig {
+            requests_per_second: 100,
+            burst: None,
+        });
+        new.tls.as_mut().unwrap().ca = Some(CA_CERT.into());
+        runtime.reload(new.clone())?;
+
+        assert_eq!(runtime.config(), new);
+        assert_eq!(current_filter(&handle), "kv=debug");
+
+        // 证书加载失败时，其他的修改也不会生效
+        let mut bad = new.clone();
+        bad.tls.as_mut().unwrap().cert = "bad cert".into();
+        bad.log.level = Some("warn".into());
+        bad.acl = None;
+        assert!(runtime.reload(bad).is_err());
+        assert_eq!(runtime.config(), new);
+        assert_eq!(current_filter(&handle), "kv=debug");
+
+        // 日志级别不合法时，证书也不会被替换
+        let mut bad = new.clone();
+        bad.tls.as_mut().unwrap().ca = None;
+        bad.log.level = Some("kv=[".into());
+        assert!(runtime.reload(bad).is_err());
+        assert_eq!(runtime.config(), new);
+
+        // 去掉日志级别之后使用 RUST_LOG
+        new.log.level = None;
+        runtime.reload(new.clone())?;
+        assert_eq!(runtime.config(), new);
+        assert_eq!(
+            current_filter(&handle),
+            EnvFilter::from_default_env().to_string()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn watch_config_should_reload_changed_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("server.conf");
+        std::fs::write(&path, include_str!("../fixtures/server.conf"))?;
+
+        let runtime = ServerRuntime::new(&config())?;
+        let loader = ConfigLoader::new().with_file(&path);
+        tokio::spawn(watch_config(loader, runtime.clone()));
+        time::sleep(Duration::from_millis(100)).await;
+
+        let mut new = config();
+        new.rate_limit = Some(RateLimitConfig {
+            requests_per_second: 10,
+            burst: Some(20),
+        });
+        std::fs::write(&path, toml::to_string(&new)?)?;
+
+        time::sleep(WATCH_INTERVAL * 2).await;
+        assert_eq!(runtime.config(), new);
+
+        Ok(())
+    }
+}
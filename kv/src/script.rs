@@ -0,0 +1,555 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use prost::Message;
+use sha2::{Digest, Sha256};
+use wasmi::{
+    core::{Trap, TrapCode},
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use tracing::warn;
+
+use crate::{KvError, Storage, Value, ValueList};
+
+/// 最多缓存的脚本数量，超过时丢掉最久没有用过的
+const MAX_CACHED_SCRIPTS: usize = 1024;
+
+/// 每次执行脚本的资源限制
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// 可以消耗的 fuel，大致相当于执行的指令数
+    pub fuel: u64,
+    /// 线性内存的上限
+    pub max_memory: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            max_memory: 16 << 20,
+        }
+    }
+}
+
+/// 执行 Eval 命令的 WASM 脚本
+///
+/// 脚本需要导出 `memory` 和 `run: () -> ()`，通过 `kv` 模块下的函数访问数据，
+/// Value 和参数都用 protobuf 编码：
+///
+/// - `get(table_ptr, table_len, key_ptr, key_len) -> i32`：值放到结果缓冲区，返回长度，不存在时返回 -1
+/// - `set(table_ptr, table_len, key_ptr, key_len, value_ptr, value_len)`
+/// - `del(table_ptr, table_len, key_ptr, key_len) -> i32`：之前的值放到结果缓冲区，和 get 一样
+/// - `args() -> i32`：参数（ValueList）放到结果缓冲区，返回长度
+/// - `read(ptr)`：把结果缓冲区复制到 ptr
+/// - `output(ptr, len)`：设置返回值（ValueList）
+/// - `fail(ptr, len)`：以 ptr 处的字符串为错误信息中止脚本
+///
+/// 脚本的修改先保存在内存里，执行成功后才写入存储；失败时所有的修改都不会生效。
+pub struct ScriptEngine {
+    engine: Engine,
+    linker: Linker<ScriptState>,
+    limits: ScriptLimits,
+    cache: Mutex<ScriptCache>,
+}
+
+/// 编译好的脚本，按最近使用的顺序淘汰
+struct ScriptCache {
+    capacity: usize,
+    /// 每次访问加一，用来比较访问的先后
+    clock: u64,
+    /// sha256 -> (编译好的脚本, 最后访问的时间)
+    scripts: HashMap<String, (Arc<Module>, u64)>,
+    /// 按最后访问的时间排序，第一个最先被淘汰
+    order: BTreeMap<u64, String>,
+}
+
+/// 脚本执行时的状态
+struct ScriptState {
+    store: Arc<dyn Storage>,
+    /// 脚本的修改，None 代表删除
+    writes: BTreeMap<(String, String), Option<Value>>,
+    args: Vec<Value>,
+    /// host 函数的结果，脚本调用 read 取走
+    result: Vec<u8>,
+    output: Vec<Value>,
+    limits: StoreLimits,
+}
+
+impl ScriptEngine {
+    pub fn new(limits: ScriptLimits) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let mut linker = Linker::new(&engine);
+        define_host_functions(&mut linker);
+
+        Self {
+            engine,
+            linker,
+            limits,
+            cache: Mutex::new(ScriptCache::new(MAX_CACHED_SCRIPTS)),
+        }
+    }
+
+    pub fn limits(&self) -> ScriptLimits {
+        self.limits
+    }
+
+    /// 编译并缓存脚本，返回脚本的 sha256
+    pub fn load(&self, script: &[u8]) -> Result<String, KvError> {
+        let hash = script_hash(script);
+        if self.cache.lock().unwrap().get(&hash).is_some() {
+            return Ok(hash);
+        }
+
+        // 编译可能比较慢，不持有锁
+        let module = Module::new(&self.engine, script)
+            .map_err(|e| KvError::ScriptError(format!("invalid script: {}", e)))?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(hash.clone(), Arc::new(module));
+
+        Ok(hash)
+    }
+
+    /// 执行缓存里的脚本，返回脚本的输出
+    pub fn eval(
+        &self,
+        store: &Arc<dyn Storage>,
+        hash: &str,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, KvError> {
+        let module = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(hash)
+            .ok_or_else(|| KvError::NotFound("script".into(), hash.into()))?;
+
+        let state = ScriptState {
+            store: store.clone(),
+            writes: BTreeMap::new(),
+            args,
+            result: vec![],
+            output: vec![],
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.max_memory)
+                .instances(1)
+                .build(),
+        };
+        let mut wasm = Store::new(&self.engine, state);
+        wasm.limiter(|state| &mut state.limits);
+        wasm.add_fuel(self.limits.fuel)
+            .map_err(|e| KvError::Internal(e.to_string()))?;
+
+        let instance = self
+            .linker
+            .instantiate(&mut wasm, &module)
+            .and_then(|pre| pre.start(&mut wasm))
+            .map_err(|e| KvError::ScriptError(e.to_string()))?;
+        let run = instance
+            .get_typed_func::<(), ()>(&wasm, "run")
+            .map_err(|_| KvError::ScriptError("script must export `run: () -> ()`".into()))?;
+        run.call(&mut wasm, ())
+            .map_err(|trap| match trap.trap_code() {
+                Some(TrapCode::OutOfFuel) => KvError::ScriptError(format!(
+                    "script exceeded the fuel limit {}",
+                    self.limits.fuel
+                )),
+                _ => KvError::ScriptError(trap.to_string()),
+            })?;
+
+        let state = wasm.into_data();
+        apply_writes(&**store, state.writes)?;
+
+        Ok(state.output)
+    }
+}
+
+/// 依次写入脚本的修改，某个写入失败时把已经写入的 key 恢复成之前的值
+///
+/// 调用者需要保证执行期间没有其它的写操作。
+fn apply_writes(
+    store: &dyn Storage,
+    writes: BTreeMap<(String, String), Option<Value>>,
+) -> Result<(), KvError> {
+    let mut applied = vec![];
+    for ((table, key), value) in writes {
+        let result = match value {
+            Some(value) => store.set(&table, key.clone(), value),
+            None => store.del(&table, &key),
+        };
+        match result {
+            Ok(old) => applied.push((table, key, old)),
+            Err(e) => {
+                for (table, key, old) in applied.into_iter().rev() {
+                    let result = match old {
+                        Some(old) => store.set(&table, key.clone(), old),
+                        None => store.del(&table, &key),
+                    };
+                    if let Err(e) = result {
+                        warn!("Failed to roll back {}/{}: {}", table, key, e);
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl ScriptCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            scripts: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// 取出脚本，并把它标记为最近使用过
+    fn get(&mut self, hash: &str) -> Option<Arc<Module>> {
+        self.clock += 1;
+        let (module, used) = self.scripts.get_mut(hash)?;
+        let name = self.order.remove(used).unwrap();
+        *used = self.clock;
+        self.order.insert(self.clock, name);
+        Some(module.clone())
+    }
+
+    /// 放入脚本，超过容量时淘汰最久没有用过的
+    fn insert(&mut self, hash: String, module: Arc<Module>) {
+        self.clock += 1;
+        if let Some((_, used)) = self.scripts.remove(&hash) {
+            self.order.remove(&used);
+        }
+        while self.scripts.len() >= self.capacity {
+            let Some((_, victim)) = self.order.pop_first() else {
+                break;
+            };
+            self.scripts.remove(&victim);
+        }
+        self.order.insert(self.clock, hash.clone());
+        self.scripts.insert(hash, (module, self.clock));
+    }
+}
+
+/// 脚本的 sha256，十六进制
+pub fn script_hash(script: &[u8]) -> String {
+    Sha256::digest(script)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl ScriptState {
+    /// 先看脚本自己的修改，再看存储
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, Trap> {
+        match self.writes.get(&(table.to_string(), key.to_string())) {
+            Some(value) => Ok(value.clone()),
+            None => self.store.get(table, key).map_err(to_trap),
+        }
+    }
+
+    /// 把结果放到缓冲区，返回长度，None 返回 -1
+    fn set_result(&mut self, value: Option<Vec<u8>>) -> i32 {
+        match value {
+            Some(data) => {
+                self.result = data;
+                self.result.len() as i32
+            }
+            None => {
+                self.result.clear();
+                -1
+            }
+        }
+    }
+}
+
+fn define_host_functions(linker: &mut Linker<ScriptState>) {
+    linker
+        .func_wrap(
+            "kv",
+            "get",
+            |mut caller: Caller<'_, ScriptState>, tp: i32, tl: i32, kp: i32, kl: i32| {
+                let (table, key) = (read_str(&caller, tp, tl)?, read_str(&caller, kp, kl)?);
+                let value = caller.data().get(&table, &key)?;
+                Ok(caller
+                    .data_mut()
+                    .set_result(value.map(|v| v.encode_to_vec())))
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "kv",
+            "set",
+            |mut caller: Caller<'_, ScriptState>,
+             tp: i32,
+             tl: i32,
+             kp: i32,
+             kl: i32,
+             vp: i32,
+             vl: i32|
+             -> Result<(), Trap> {
+                let (table, key) = (read_str(&caller, tp, tl)?, read_str(&caller, kp, kl)?);
+                let value = Value::decode(read(&caller, vp, vl)?)
+                    .map_err(|e| Trap::new(format!("invalid value: {}", e)))?;
+                caller.data_mut().writes.insert((table, key), Some(value));
+                Ok(())
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "kv",
+            "del",
+            |mut caller: Caller<'_, ScriptState>, tp: i32, tl: i32, kp: i32, kl: i32| {
+                let (table, key) = (read_str(&caller, tp, tl)?, read_str(&caller, kp, kl)?);
+                let old = caller.data().get(&table, &key)?;
+                let state = caller.data_mut();
+                state.writes.insert((table, key), None);
+                Ok(state.set_result(old.map(|v| v.encode_to_vec())))
+            },
+        )
+        .unwrap()
+        .func_wrap("kv", "args", |mut caller: Caller<'_, ScriptState>| {
+            let state = caller.data_mut();
+            let args = ValueList {
+                values: state.args.clone(),
+            };
+            state.set_result(Some(args.encode_to_vec()))
+        })
+        .unwrap()
+        .func_wrap(
+            "kv",
+            "read",
+            |mut caller: Caller<'_, ScriptState>, ptr: i32| -> Result<(), Trap> {
+                let memory = memory(&caller)?;
+                let (data, state) = memory.data_and_store_mut(&mut caller);
+                let len = state.result.len();
+                let dest = usize::try_from(ptr)
+                    .ok()
+                    .and_then(|ptr| data.get_mut(ptr..ptr.checked_add(len)?))
+                    .ok_or_else(out_of_bounds)?;
+                dest.copy_from_slice(&state.result);
+                Ok(())
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "kv",
+            "output",
+            |mut caller: Caller<'_, ScriptState>, ptr: i32, len: i32| -> Result<(), Trap> {
+                let output = ValueList::decode(read(&caller, ptr, len)?)
+                    .map_err(|e| Trap::new(format!("invalid output: {}", e)))?;
+                caller.data_mut().output = output.values;
+                Ok(())
+            },
+        )
+        .unwrap()
+        .func_wrap(
+            "kv",
+            "fail",
+            |caller: Caller<'_, ScriptState>, ptr: i32, len: i32| -> Result<(), Trap> {
+                Err(Trap::new(read_str(&caller, ptr, len)?))
+            },
+        )
+        .unwrap();
+}
+
+fn memory(caller: &Caller<'_, ScriptState>) -> Result<Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("script must export `memory`"))
+}
+
+/// 读取脚本内存里 [ptr, ptr + len) 的数据
+fn read<'a>(caller: &'a Caller<'_, ScriptState>, ptr: i32, len: i32) -> Result<&'a [u8], Trap> {
+    let data = memory(caller)?.data(caller);
+    let (ptr, len) = match (usize::try_from(ptr), usize::try_from(len)) {
+        (Ok(ptr), Ok(len)) => (ptr, len),
+        _ => return Err(out_of_bounds()),
+    };
+    ptr.checked_add(len)
+        .and_then(|end| data.get(ptr..end))
+        .ok_or_else(out_of_bounds)
+}
+
+fn read_str(caller: &Caller<'_, ScriptState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    String::from_utf8(read(caller, ptr, len)?.to_vec())
+        .map_err(|_| Trap::new("string is not valid UTF-8"))
+}
+
+fn out_of_bounds() -> Trap {
+    Trap::new("memory access out of bounds")
+}
+
+fn to_trap(e: KvError) -> Trap {
+    Trap::new(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    /// 把 table t1 里 src 的值复制到 dst，再把 src 删除，返回参数
+    const MOVE: &str = r#"
+        (module
+          (import "kv" "get" (func $get (param i32 i32 i32 i32) (result i32)))
+          (import "kv" "set" (func $set (param i32 i32 i32 i32 i32 i32)))
+          (import "kv" "del" (func $del (param i32 i32 i32 i32) (result i32)))
+          (import "kv" "args" (func $args (result i32)))
+          (import "kv" "read" (func $read (param i32)))
+          (import "kv" "output" (func $output (param i32 i32)))
+          (import "kv" "fail" (func $fail (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "t1srcdst")
+          (data (i32.const 8) "src not found")
+          (func (export "run")
+            (local $len i32)
+            (local.set $len (call $get (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 3)))
+            (if (i32.lt_s (local.get $len) (i32.const 0))
+              (then (call $fail (i32.const 8) (i32.const 13))))
+            (call $read (i32.const 1024))
+            (call $set (i32.const 0) (i32.const 2) (i32.const 5) (i32.const 3)
+                       (i32.const 1024) (local.get $len))
+            (drop (call $del (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 3)))
+            (local.set $len (call $args))
+            (call $read (i32.const 2048))
+            (call $output (i32.const 2048) (local.get $len))))
+    "#;
+
+    /// 写入之后再陷入死循环
+    const LOOP: &str = r#"
+        (module
+          (import "kv" "set" (func $set (param i32 i32 i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "t1k1\0a\05hello")
+          (func (export "run")
+            (call $set (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 2)
+                       (i32.const 4) (i32.const 7))
+            (loop $forever (br $forever))))
+    "#;
+
+    /// 把 t1 里的 a 改成 "new"，再给 b 写入一个 200 字节的字符串
+    const SET_TWO: &str = r#"
+        (module
+          (import "kv" "set" (func $set (param i32 i32 i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "t1ab\0a\03new")
+          (data (i32.const 16) "\0a\c8\01")
+          (func (export "run")
+            (call $set (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 1)
+                       (i32.const 4) (i32.const 5))
+            (call $set (i32.const 0) (i32.const 2) (i32.const 3) (i32.const 1)
+                       (i32.const 16) (i32.const 203))))
+    "#;
+
+    fn engine() -> ScriptEngine {
+        ScriptEngine::new(ScriptLimits {
+            fuel: 100_000,
+            max_memory: 1 << 20,
+        })
+    }
+
+    fn wasm(wat: &str) -> Vec<u8> {
+        wat::parse_str(wat).unwrap()
+    }
+
+    #[test]
+    fn eval_should_read_and_write_storage() {
+        let engine = engine();
+        let store: Arc<dyn Storage> = Arc::new(MemTable::new());
+        store.set("t1", "src".into(), 42.into()).unwrap();
+
+        let hash = engine.load(&wasm(MOVE)).unwrap();
+        assert_eq!(hash, script_hash(&wasm(MOVE)));
+        let output = engine
+            .eval(&store, &hash, vec!["a".into(), 1.into()])
+            .unwrap();
+
+        assert_eq!(output, vec!["a".into(), 1.into()]);
+        assert_eq!(store.get("t1", "dst"), Ok(Some(42.into())));
+        assert_eq!(store.get("t1", "src"), Ok(None));
+    }
+
+    #[test]
+    fn failed_script_should_not_change_storage() {
+        let engine = engine();
+        let store: Arc<dyn Storage> = Arc::new(MemTable::new());
+
+        let hash = engine.load(&wasm(MOVE)).unwrap();
+        let err = engine.eval(&store, &hash, vec![]).unwrap_err();
+        assert!(err.to_string().contains("src not found"), "{}", err);
+
+        // 写入之后耗尽 fuel，写入不生效
+        let hash = engine.load(&wasm(LOOP)).unwrap();
+        let err = engine.eval(&store, &hash, vec![]).unwrap_err();
+        assert_eq!(
+            err,
+            KvError::ScriptError("script exceeded the fuel limit 100000".into())
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn failed_write_should_roll_back_earlier_writes() {
+        let engine = engine();
+        // 放得下 a，放不下 200 字节的 b
+        let store: Arc<dyn Storage> =
+            Arc::new(MemTable::with_memory_limit(200, crate::EvictionPolicy::Lru));
+        store.set("t1", "a".into(), "old".into()).unwrap();
+
+        let hash = engine.load(&wasm(SET_TWO)).unwrap();
+        let err = engine.eval(&store, &hash, vec![]).unwrap_err();
+        assert!(matches!(err, KvError::StorageError("set", ..)), "{}", err);
+
+        assert_eq!(store.get("t1", "a"), Ok(Some("old".into())));
+        assert_eq!(store.get("t1", "b"), Ok(None));
+    }
+
+    #[test]
+    fn cache_should_evict_least_recently_used_script() {
+        let engine = engine();
+        let module = Arc::new(Module::new(&engine.engine, &wasm(LOOP)[..]).unwrap());
+        let mut cache = ScriptCache::new(2);
+
+        cache.insert("a".into(), module.clone());
+        cache.insert("b".into(), module.clone());
+        assert!(cache.get("a").is_some());
+        cache.insert("c".into(), module);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn eval_should_reject_unknown_or_invalid_script() {
+        let engine = engine();
+        let store: Arc<dyn Storage> = Arc::new(MemTable::new());
+
+        assert_eq!(
+            engine.eval(&store, "abc", vec![]),
+            Err(KvError::NotFound("script".into(), "abc".into()))
+        );
+        assert!(engine.load(b"not wasm").is_err());
+
+        // 超过内存上限的脚本不能实例化
+        let hash = engine
+            .load(&wasm(
+                r#"(module (memory (export "memory") 100) (func (export "run")))"#,
+            ))
+            .unwrap();
+        assert!(engine.eval(&store, &hash, vec![]).is_err());
+    }
+}
@@ -1,44 +1,86 @@
-use std::env;
+use std::path::PathBuf;
 
 use anyhow::Result;
-use kv::{start_server_with_config, RotationConfig, ServerConfig};
-use tokio::fs;
-use tracing::span;
+use clap::{Parser, ValueEnum};
+use kv::{
+    init_tracer, log_filter, parse_override, start_server_with_runtime, watch_config, ConfigLoader,
+    ServerConfig, ServerRuntime,
+};
+use tracing::{span, warn};
 use tracing_subscriber::{
     fmt::{self, format},
     layer::SubscriberExt,
     prelude::*,
-    EnvFilter,
+    reload,
 };
 
+/// KV 服务器
+///
+/// 配置按 默认值 → 配置文件 → `KV_SERVER_*` 环境变量 → 命令行参数 的顺序合并，
+/// 环境变量用 `__` 分隔层级，比如 `KV_SERVER_LOG__LEVEL=debug`
+#[derive(Parser, Debug)]
+#[command(name = "kvs", version, about)]
+struct Cli {
+    /// 配置文件，可以指定多个，后面的覆盖前面的；不指定时使用内置的配置
+    #[arg(short, long, env = "KV_SERVER_CONFIG", value_delimiter = ',')]
+    config: Vec<PathBuf>,
+
+    /// 监听地址
+    #[arg(short, long)]
+    addr: Option<String>,
+
+    /// 传输方式
+    #[arg(short, long, value_enum)]
+    transport: Option<Transport>,
+
+    /// 日志级别，语法和 RUST_LOG 相同
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// 覆盖任意字段，格式为 key=value，比如 `rate_limit.requests_per_second=100`
+    #[arg(long = "set", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+
+    /// 打印合并之后的配置并退出
+    #[arg(long)]
+    print_config: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Transport {
+    Tcp,
+    Quic,
+    Unix,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = match env::var("KV_SERVER_CONFIG") {
-        Ok(path) => fs::read_to_string(&path).await?,
-        Err(_) => include_str!("../fixtures/server.conf").to_string(),
-    };
-    let config: ServerConfig = toml::from_str(&config)?;
-
-    let tracer = opentelemetry_jaeger::new_pipeline()
-        .with_service_name("kv-server")
-        .install_simple()?;
-    let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-
-    // 添加
-    let log = &config.log;
-    let file_appender = match log.rotation {
-        RotationConfig::Hourly => tracing_appender::rolling::hourly(&log.path, "server.log"),
-        RotationConfig::Daily => tracing_appender::rolling::daily(&log.path, "server.log"),
-        RotationConfig::Never => tracing_appender::rolling::never(&log.path, "server.log"),
-    };
+    let cli = Cli::parse();
+    let loader = config_loader(&cli);
+    let config: ServerConfig = loader.load()?;
+
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
 
-    let (non_blocking, _guard1) = tracing_appender::non_blocking(file_appender);
+    // 不导出 span 时不需要 OpenTelemetry 的 layer
+    let opentelemetry = init_tracer(&config.tracing, "kv-server")?
+        .map(|t| tracing_opentelemetry::layer().with_tracer(t));
+
+    let log = &config.log;
+    let (non_blocking, _guard1) = tracing_appender::non_blocking(log.appender("server.log"));
     let fmt_layer = fmt::layer()
         .event_format(format().compact())
         .with_writer(non_blocking);
 
+    // 日志级别可以热更新，配置里没有的话使用 RUST_LOG
+    let trace_requests = opentelemetry.is_some();
+    let filter = log_filter(log.level.as_deref(), trace_requests)?;
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
     tracing_subscriber::registry()
-        .with(EnvFilter::from_default_env())
+        .with(filter)
         .with(fmt_layer)
         .with(opentelemetry)
         .init();
@@ -46,7 +88,70 @@ async fn main() -> Result<()> {
     let root = span!(tracing::Level::INFO, "app_start", work_units = 2);
     let _enter = root.enter();
 
-    start_server_with_config(&config).await?;
+    let runtime = ServerRuntime::new(&config)?.with_log_filter(filter_handle, trace_requests);
+
+    // 内置的配置没有文件可以监控
+    if !loader.files().is_empty() {
+        let runtime = runtime.clone();
+        tokio::spawn(async move {
+            if let Err(e) = watch_config(loader, runtime).await {
+                warn!("Failed to watch config: {}", e);
+            }
+        });
+    }
+
+    let res = start_server_with_runtime(runtime).await;
+    // 导出还在缓冲区里的 span
+    opentelemetry::global::shutdown_tracer_provider();
+    res
+}
+
+fn config_loader(cli: &Cli) -> ConfigLoader {
+    let mut loader = ConfigLoader::new();
+    if cli.config.is_empty() {
+        loader = loader.with_embedded(include_str!("../fixtures/server.conf"));
+    }
+    for path in &cli.config {
+        loader = loader.with_file(path);
+    }
+    loader = loader.with_env("KV_SERVER_");
+
+    if let Some(addr) = &cli.addr {
+        loader = loader.with_override("general.addr", addr);
+    }
+    if let Some(transport) = cli.transport {
+        loader = loader.with_override("general.transport", format!("{:?}", transport));
+    }
+    if let Some(level) = &cli.log_level {
+        loader = loader.with_override("log.level", level);
+    }
+    for (key, value) in &cli.overrides {
+        loader = loader.with_override(key, value);
+    }
+
+    loader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_flags_should_override_config() {
+        let cli = Cli::parse_from([
+            "kvs",
+            "--addr",
+            "/tmp/kvs-test.sock",
+            "--transport",
+            "unix",
+            "--set",
+            "rate_limit.requests_per_second = 10",
+        ]);
+        let config: ServerConfig = config_loader(&cli).load().unwrap();
 
-    Ok(())
+        assert_eq!(config.general.addr, "/tmp/kvs-test.sock");
+        assert_eq!(config.rate_limit.unwrap().requests_per_second, 10);
+        // 其他字段来自内置的配置
+        assert!(config.tls.is_some());
+    }
 }
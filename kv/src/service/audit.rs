@@ -0,0 +1,395 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io::Write,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use prost::Message;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+
+use crate::*;
+
+/// 审计日志，每个写命令记录一行 JSON
+///
+/// 只记录 value 的 sha256，不记录 value 本身。旧值和新值是在命令执行前后从存储里读出来的，
+/// 读取和修改时持有 key 所在分片的锁，所以同一个 key 的记录前后衔接。
+pub struct AuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// 按 table 和 key 分片的锁
+    locks: Vec<Mutex<()>>,
+    /// 后台写文件的线程，drop 时把缓冲的记录写完
+    _guard: Option<WorkerGuard>,
+}
+
+/// key 锁的分片数
+const LOCK_STRIPES: usize = 64;
+
+/// 一条审计记录
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    /// RFC 3339 格式的 UTC 时间
+    ts: String,
+    conn: u64,
+    identity: Option<&'a str>,
+    peer: Option<String>,
+    cmd: &'static str,
+    table: &'a str,
+    keys: Vec<&'a str>,
+    /// 每个 key 旧值的 sha256，key 不存在时为 null
+    old: Vec<Option<String>>,
+    /// 每个 key 新值的 sha256，key 不存在时为 null
+    new: Vec<Option<String>>,
+    status: u32,
+    /// Eval 的脚本 hash，Restore 的备份名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl AuditLog {
+    /// 把审计记录写入 writer
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            locks: stripes(),
+            _guard: None,
+        }
+    }
+
+    /// 写入日志目录下的 `audit.log`，和 `server.log` 按同样的规则滚动
+    ///
+    /// 写文件在后台线程中进行，缓冲满了会等待而不是丢弃记录。
+    pub fn from_config(log: &LogConfig) -> Self {
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .finish(log.appender("audit.log"));
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            locks: stripes(),
+            _guard: Some(guard),
+        }
+    }
+
+    /// 执行命令，如果是写命令，记录执行前后 key 的变化
+    pub(crate) fn execute(
+        &self,
+        ctx: &ConnContext,
+        cmd: &CommandRequest,
+        store: &Arc<dyn Storage>,
+        f: impl FnOnce() -> CommandResponse,
+    ) -> CommandResponse {
+        let target = match AuditTarget::of(cmd) {
+            Some(target) => target,
+            None => return f(),
+        };
+
+        // 写完记录再释放锁，同一个 key 的记录按修改的顺序出现在日志里
+        let _guards = self.lock(&target);
+        let old = target.hashes(store);
+        let res = f();
+        let new = target.hashes(store);
+
+        let record = AuditRecord {
+            ts: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            conn: ctx.id,
+            identity: ctx.identity.as_deref(),
+            peer: ctx.peer_addr.map(|addr| addr.to_string()),
+            cmd: cmd.name(),
+            table: target.table,
+            keys: target.keys,
+            old,
+            new,
+            status: res.status,
+            detail: target.detail,
+        };
+        self.write(&record);
+
+        res
+    }
+
+    /// 锁住 target 涉及的所有分片，按下标的顺序加锁以免互相等待
+    fn lock(&self, target: &AuditTarget) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<_> = target
+            .keys
+            .iter()
+            .map(|key| {
+                let mut hasher = DefaultHasher::new();
+                (target.table, key).hash(&mut hasher);
+                hasher.finish() as usize % LOCK_STRIPES
+            })
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|i| self.locks[i].lock().unwrap())
+            .collect()
+    }
+
+    fn write(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to serialize audit record: {}", e),
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.writer.lock().unwrap().write_all(&line) {
+            warn!("Failed to write audit record: {}", e);
+        }
+    }
+}
+
+/// 写命令修改的 table 和 key
+pub(super) struct AuditTarget<'a> {
+    pub(super) table: &'a str,
+    pub(super) keys: Vec<&'a str>,
+    detail: Option<String>,
+}
+
+impl<'a> AuditTarget<'a> {
+    /// 只读命令和 Publish 之类不修改存储的命令返回 None
+    pub(super) fn of(cmd: &'a CommandRequest) -> Option<Self> {
+        let (table, keys): (&str, Vec<&str>) = match cmd.request_data.as_ref()? {
+            RequestData::Hset(p) => (&p.table, p.pair.iter().map(|p| p.key.as_str()).collect()),
+            RequestData::Hmset(p) => (&p.table, p.pairs.iter().map(|p| p.key.as_str()).collect()),
+            RequestData::Hdel(p) => (&p.table, vec![&p.key]),
+            RequestData::Hmdel(p) => (&p.table, p.keys.iter().map(|k| k.as_str()).collect()),
+            RequestData::Lpush(p) => (&p.table, vec![&p.key]),
+            RequestData::Rpush(p) => (&p.table, vec![&p.key]),
+            RequestData::Lpop(p) => (&p.table, vec![&p.key]),
+            RequestData::Rpop(p) => (&p.table, vec![&p.key]),
+            RequestData::Sadd(p) => (&p.table, vec![&p.key]),
+            RequestData::Srem(p) => (&p.table, vec![&p.key]),
+            RequestData::Zadd(p) => (&p.table, vec![&p.key]),
+            // 脚本和恢复备份修改哪些 key 事先不知道，只记录脚本和备份
+            RequestData::Eval(p) => {
+                let hash = match p.script.is_empty() {
+                    true => p.hash.clone(),
+                    false => script_hash(&p.script),
+                };
+                return Some(Self::detail(hash));
+            }
+            RequestData::Restore(p) => return Some(Self::detail(p.name.clone())),
+            _ => return None,
+        };
+        Some(Self {
+            table,
+            keys,
+            detail: None,
+        })
+    }
+
+    fn detail(detail: String) -> Self {
+        Self {
+            table: "",
+            keys: vec![],
+            detail: Some(detail),
+        }
+    }
+
+    fn hashes(&self, store: &Arc<dyn Storage>) -> Vec<Option<String>> {
+        self.keys
+            .iter()
+            .map(|key| match store.get(self.table, key) {
+                Ok(Some(v)) => Some(value_hash(&v)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn stripes() -> Vec<Mutex<()>> {
+    (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect()
+}
+
+/// value 编码之后的 sha256，十六进制
+fn value_hash(value: &Value) -> String {
+    Sha256::digest(value.encode_to_vec())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::Value as Json;
+
+    use super::*;
+
+    /// 测试用的 writer，clone 之后共享同一块内存
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn records(&self) -> Vec<Json> {
+            let buf = self.0.lock().unwrap();
+            buf.split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_slice(line).unwrap())
+                .collect()
+        }
+    }
+
+    async fn execute(service: &Service, ctx: &ConnContext, cmd: CommandRequest) -> CommandResponse {
+        let res = service.execute_with(ctx, cmd).next().await.unwrap();
+        (*res).clone()
+    }
+
+    #[tokio::test]
+    async fn mutating_commands_should_be_audited() {
+        let buf = Buffer::default();
+        let service = Service::new(MemTable::new()).with_audit(AuditLog::new(buf.clone()));
+        let ctx = ConnContext::new(
+            Some("127.0.0.1:5000".parse().unwrap()),
+            Some("awesome-device-id".into()),
+        );
+
+        execute(
+            &service,
+            &ctx,
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+        )
+        .await;
+        execute(
+            &service,
+            &ctx,
+            CommandRequest::new_hset("t1", "k1", "v2".into()),
+        )
+        .await;
+        // 只读命令不记录
+        execute(&service, &ctx, CommandRequest::new_hget("t1", "k1")).await;
+        execute(&service, &ctx, CommandRequest::new_hdel("t1", "k1")).await;
+
+        let records = buf.records();
+        assert_eq!(records.len(), 3);
+
+        let r = &records[0];
+        assert_eq!(r["cmd"], "hset");
+        assert_eq!(r["conn"], ctx.id);
+        assert_eq!(r["identity"], "awesome-device-id");
+        assert_eq!(r["peer"], "127.0.0.1:5000");
+        assert_eq!(r["table"], "t1");
+        assert_eq!(r["keys"], serde_json::json!(["k1"]));
+        assert_eq!(r["old"], serde_json::json!([null]));
+        assert_eq!(r["new"][0], value_hash(&"v1".into()));
+        assert_eq!(r["status"], 200);
+        assert!(OffsetDateTime::parse(r["ts"].as_str().unwrap(), &Rfc3339).is_ok());
+        assert!(r.get("detail").is_none());
+
+        // 旧值是上一次写入的新值
+        assert_eq!(records[1]["old"], r["new"]);
+        assert_eq!(records[1]["new"][0], value_hash(&"v2".into()));
+
+        assert_eq!(records[2]["cmd"], "hdel");
+        assert_eq!(records[2]["old"], records[1]["new"]);
+        assert_eq!(records[2]["new"], serde_json::json!([null]));
+    }
+
+    #[tokio::test]
+    async fn collection_commands_should_record_every_key() {
+        let buf = Buffer::default();
+        let service = Service::new(MemTable::new()).with_audit(AuditLog::new(buf.clone()));
+        let ctx = ConnContext::default();
+
+        let pairs = vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())];
+        execute(&service, &ctx, CommandRequest::new_hmset("t1", pairs)).await;
+        execute(
+            &service,
+            &ctx,
+            CommandRequest::new_rpush("t1", "l1", vec![1.into()]),
+        )
+        .await;
+        // 类型错误的命令也会记录，值没有变化
+        let res = execute(
+            &service,
+            &ctx,
+            CommandRequest::new_sadd("t1", "l1", vec!["a"]),
+        )
+        .await;
+        assert_ne!(res.status, 200);
+
+        let records = buf.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["keys"], serde_json::json!(["k1", "k2"]));
+        assert_eq!(records[0]["new"][1], value_hash(&2.into()));
+        assert!(records[0]["identity"].is_null());
+        assert!(records[0]["peer"].is_null());
+
+        let list = Value::from(vec![Value::from(1)]);
+        assert_eq!(records[1]["new"][0], value_hash(&list));
+        assert_eq!(records[2]["status"], res.status);
+        assert_eq!(records[2]["old"], records[2]["new"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes_should_chain_old_and_new_hashes() {
+        let buf = Buffer::default();
+        let service = Service::new(MemTable::new()).with_audit(AuditLog::new(buf.clone()));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let ctx = ConnContext::default();
+                    for j in 0..50 {
+                        let value = format!("v{}-{}", i, j);
+                        let cmd = CommandRequest::new_hset("t1", "k1", value.into());
+                        execute(&service, &ctx, cmd).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // 每条记录的旧值都是上一条记录的新值
+        let records = buf.records();
+        assert_eq!(records.len(), 400);
+        assert_eq!(records[0]["old"], serde_json::json!([null]));
+        for pair in records.windows(2) {
+            assert_eq!(pair[1]["old"], pair[0]["new"]);
+        }
+    }
+
+    #[test]
+    fn audit_log_should_write_to_log_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = LogConfig {
+            path: dir.path().to_string_lossy().into(),
+            rotation: RotationConfig::Never,
+            level: None,
+            audit: true,
+        };
+        let store: Arc<dyn Storage> = Arc::new(MemTable::new());
+        let cmd = CommandRequest::new_restore("daily", "json", false);
+
+        let audit = AuditLog::from_config(&log);
+        audit.execute(&ConnContext::default(), &cmd, &store, CommandResponse::ok);
+        // drop 时把缓冲的记录写完
+        drop(audit);
+
+        let content = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        let record: Json = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(record["cmd"], "restore");
+        assert_eq!(record["detail"], "daily");
+        assert_eq!(record["keys"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+use crate::*;
+
+impl Service {
+    /// 把所有数据备份到备份目录下的文件，备份期间写命令会等待
+    ///
+    /// 调用者独占 write_gate，并且在阻塞线程池里执行。
+    pub(crate) fn backup(&self, param: Backup) -> CommandResponse {
+        let result = self.backup_path(&param.name).and_then(|path| {
+            let format = DumpFormat::detect(&param.format, &path)?;
+
+            // 先写到临时文件，成功之后再改名，这样不会留下不完整的备份
+            let tmp = path.with_extension("tmp");
+            let count = export(&*self.store, BufWriter::new(File::create(&tmp)?), format)?;
+            fs::rename(&tmp, &path)?;
+
+            info!("Backup {} pairs to {:?} ({})", count, path, format.name());
+            Ok(count)
+        });
+
+        match result {
+            Ok(count) => Value::from(count as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// 从备份目录下的文件恢复数据，恢复期间写命令会等待
+    ///
+    /// 先读出整个文件，文件损坏时不会清空已有的数据。
+    pub(crate) fn restore(&self, param: Restore) -> CommandResponse {
+        let result = self.backup_path(&param.name).and_then(|path| {
+            let format = DumpFormat::detect(&param.format, &path)?;
+            let file = File::open(&path)
+                .map_err(|_| KvError::NotFound("backup".into(), param.name.clone()))?;
+            let records = read_dump(BufReader::new(file), format)?;
+
+            if param.clear {
+                clear(&*self.store)?;
+            }
+            let count = apply_dump(&*self.store, records)?;
+
+            info!(
+                "Restore {} pairs from {:?} ({})",
+                count,
+                path,
+                format.name()
+            );
+            Ok(count)
+        });
+
+        match result {
+            Ok(count) => Value::from(count as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// 备份文件只能是备份目录下的文件名，不能包含路径
+    fn backup_path(&self, name: &str) -> Result<PathBuf, KvError> {
+        let dir = self.backup_dir.as_ref().ok_or_else(|| {
+            KvError::PermissionDenied("backup is disabled, set backup.dir to enable it".into())
+        })?;
+
+        if name.is_empty() || Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(KvError::InvalidCommand(format!(
+                "invalid backup name `{}`",
+                name
+            )));
+        }
+
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(dir.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::service::{assert_res_error, assert_res_ok};
+
+    async fn execute(service: &Service, cmd: CommandRequest) -> CommandResponse {
+        let res = service.execute(cmd).next().await.unwrap();
+        (*res).clone()
+    }
+
+    #[tokio::test]
+    async fn backup_should_restore_into_another_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let sled = Service::new(get_sled_store()).with_backup_dir(dir.path());
+        let memory = Service::new(MemTable::new()).with_backup_dir(dir.path());
+
+        execute(&sled, CommandRequest::new_hset("t1", "k1", 1.into())).await;
+        execute(&sled, CommandRequest::new_hset("t2", "k2", "v2".into())).await;
+        execute(&memory, CommandRequest::new_hset("t1", "old", "v".into())).await;
+
+        for name in ["backup.jsonl", "backup.pb"] {
+            let res = execute(&sled, CommandRequest::new_backup(name, "")).await;
+            assert_res_ok(&res, &[2.into()], &[]);
+
+            let res = execute(&memory, CommandRequest::new_restore(name, "", true)).await;
+            assert_res_ok(&res, &[2.into()], &[]);
+
+            let res = execute(&memory, CommandRequest::new_hgetall("t1")).await;
+            assert_res_ok(&res, &[], &[Kvpair::new("k1", 1.into())]);
+        }
+    }
+
+    #[tokio::test]
+    async fn backup_should_be_confined_to_backup_dir() {
+        let service = Service::new(MemTable::new());
+        let res = execute(&service, CommandRequest::new_backup("backup.pb", "")).await;
+        assert_res_error(&res, 403, "backup is disabled");
+
+        let dir = tempfile::tempdir().unwrap();
+        let service = service.with_backup_dir(dir.path());
+        let res = execute(&service, CommandRequest::new_backup("../backup.pb", "")).await;
+        assert_res_error(&res, 400, "invalid backup name `../backup.pb`");
+
+        let res = execute(
+            &service,
+            CommandRequest::new_restore("missing.pb", "", false),
+        )
+        .await;
+        assert_res_error(&res, 404, "missing.pb");
+
+        let res = execute(&service, CommandRequest::new_backup("backup", "xml")).await;
+        assert_res_error(&res, 400, "unknown dump format `xml`");
+    }
+
+    #[tokio::test]
+    async fn restore_should_keep_data_when_backup_is_corrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = Service::new(MemTable::new()).with_backup_dir(dir.path());
+        execute(&service, CommandRequest::new_hset("t1", "k1", 1.into())).await;
+
+        fs::write(
+            dir.path().join("bad.jsonl"),
+            "{\"table\":\"t2\",\"key\":\"k2\",\"value\":{}}\nnot json\n",
+        )
+        .unwrap();
+        let res = execute(&service, CommandRequest::new_restore("bad.jsonl", "", true)).await;
+        assert_res_error(&res, 500, "record 2");
+
+        let res = execute(&service, CommandRequest::new_hgetall("t1")).await;
+        assert_res_ok(&res, &[], &[Kvpair::new("k1", 1.into())]);
+        let res = execute(&service, CommandRequest::new_hgetall("t2")).await;
+        assert_res_ok(&res, &[], &[]);
+    }
+}
@@ -117,6 +117,143 @@ impl CommandService for Hmdel {
     }
 }
 
+impl CommandService for Stats {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        store.stats().into()
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.lpush(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpush {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.rpush(&self.table, &self.key, self.values) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpop {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.lpop(&self.table, &self.key, self.count.max(1) as usize) {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.rpop(&self.table, &self.key, self.count.max(1) as usize) {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.lrange(&self.table, &self.key, self.start, self.stop) {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sadd {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.sadd(&self.table, &self.key, &self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Srem {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.srem(&self.table, &self.key, &self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Smembers {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.smembers(&self.table, &self.key) {
+            Ok(members) => members_response(members),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sinter {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.sinter(&self.table, &self.keys) {
+            Ok(members) => members_response(members),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        let members: Vec<_> = self
+            .members
+            .into_iter()
+            .map(|m| (m.member, m.score))
+            .collect();
+        match store.zadd(&self.table, &self.key, &members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.zrange(&self.table, &self.key, self.start, self.stop) {
+            Ok(members) => scored_response(members),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    fn execute(self, store: &Arc<dyn Storage>) -> CommandResponse {
+        match store.zrange_by_score(&self.table, &self.key, self.min, self.max) {
+            Ok(members) => scored_response(members),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// set 的 member 放在 values 里
+fn members_response(members: Vec<String>) -> CommandResponse {
+    members
+        .into_iter()
+        .map(Value::from)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// sorted set 按顺序放在 pairs 里，key 是 member，value 是 score
+fn scored_response(members: Vec<(String, f64)>) -> CommandResponse {
+    members
+        .into_iter()
+        .map(|(member, score)| Kvpair::new(member, score.into()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,6 +326,46 @@ mod tests {
             let store: Arc<dyn Storage> = Arc::new(MemTable::new());
             test_hmexist(store);
         }
+
+        #[test]
+        fn memory_stats_should_report_evictions() {
+            let store: Arc<dyn Storage> =
+                Arc::new(MemTable::with_memory_limit(1024, EvictionPolicy::Lru));
+            for i in 0..20 {
+                dispatch(
+                    CommandRequest::new_hset("t1", i.to_string(), "v".into()),
+                    &store,
+                );
+            }
+
+            let res = dispatch(CommandRequest::new_stats(), &store);
+            assert_eq!(res.status, 200);
+            let stat = |name: &str| {
+                let pair = res.pairs.iter().find(|p| p.key == name).unwrap();
+                i64::try_from(pair.value.clone().unwrap()).unwrap()
+            };
+            assert_eq!(stat("max_bytes"), 1024);
+            assert!(stat("used_bytes") <= 1024);
+            assert_eq!(stat("keys") + stat("evictions"), 20);
+        }
+
+        #[test]
+        fn memory_collection_commands_should_work() {
+            let store: Arc<dyn Storage> = Arc::new(MemTable::new());
+            let cmd = CommandRequest::new_zadd("t1", "board", vec![("a", 2.0), ("b", 1.0)]);
+            let res = dispatch(cmd, &store);
+            assert_res_ok(&res, &[2.into()], &[]);
+
+            // pairs 按 score 排序
+            let res = dispatch(CommandRequest::new_zrange("t1", "board", 0, -1), &store);
+            assert_eq!(
+                res.pairs,
+                vec![Kvpair::new("b", 1.0.into()), Kvpair::new("a", 2.0.into())]
+            );
+
+            let res = dispatch(CommandRequest::new_lpop("t1", "board", 0), &store);
+            assert_res_error(&res, 400, "expected a list");
+        }
     }
 
     mod sled {
@@ -0,0 +1,435 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use http::StatusCode;
+use tracing::{info, warn};
+
+use crate::{CommandRequest, CommandResponse, Handshake, KvError};
+
+/// 下一个连接 id
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 每个连接的上下文，同一个连接上的所有 stream 共享
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnContext {
+    /// 连接 id，0 代表不是来自网络连接（比如直接调用 Service）
+    pub id: u64,
+    /// 对端地址
+    pub peer_addr: Option<SocketAddr>,
+    /// 对端身份，一般是 TLS 客户端证书里的 CN
+    pub identity: Option<String>,
+    /// 连接建立时握手协商出的参数
+    pub handshake: Option<Handshake>,
+}
+
+impl ConnContext {
+    /// 为一个新的连接创建上下文
+    pub fn new(peer_addr: Option<SocketAddr>, identity: Option<String>) -> Self {
+        Self {
+            id: NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            identity,
+            handshake: None,
+        }
+    }
+
+    /// 记录握手协商出的参数
+    pub fn with_handshake(mut self, handshake: Handshake) -> Self {
+        self.handshake = Some(handshake);
+        self
+    }
+}
+
+/// 处理 Command 前后的中间件
+///
+/// 请求按注册顺序经过中间件，响应按相反的顺序返回（洋葱模型）。
+/// 中间件可以持有状态，可以改写请求和响应，也可以在 `on_request`
+/// 里直接返回一个响应，让后面的中间件和 Command 都不再执行。
+pub trait Middleware: Send + Sync + 'static {
+    /// 收到请求时调用，返回 Some 则直接用这个响应短路
+    fn on_request(&self, _ctx: &ConnContext, _cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        None
+    }
+
+    /// Command 执行完成（或者被短路）后调用，可以改写响应
+    fn on_response(&self, _ctx: &ConnContext, _res: &mut CommandResponse) {}
+
+    /// 响应发送给客户端后调用
+    fn on_sent(&self, _ctx: &ConnContext) {}
+}
+
+/// 把之前的 fn 回调包装成中间件
+pub(crate) enum FnHook {
+    Received(fn(&CommandRequest)),
+    Executed(fn(&CommandResponse)),
+    BeforeSend(fn(&mut CommandResponse)),
+    AfterSend(fn()),
+}
+
+impl Middleware for FnHook {
+    fn on_request(&self, _ctx: &ConnContext, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        if let FnHook::Received(f) = self {
+            f(cmd);
+        }
+        None
+    }
+
+    fn on_response(&self, _ctx: &ConnContext, res: &mut CommandResponse) {
+        match self {
+            FnHook::Executed(f) => f(res),
+            FnHook::BeforeSend(f) => f(res),
+            _ => {}
+        }
+    }
+
+    fn on_sent(&self, _ctx: &ConnContext) {
+        if let FnHook::AfterSend(f) = self {
+            f();
+        }
+    }
+}
+
+/// 记录请求和响应的日志
+#[derive(Clone, Debug, Default)]
+pub struct LoggingMiddleware;
+
+impl Middleware for LoggingMiddleware {
+    fn on_request(&self, ctx: &ConnContext, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        info!(conn = ctx.id, peer = ?ctx.peer_addr, "Got request: {:?}", cmd);
+        None
+    }
+
+    fn on_response(&self, ctx: &ConnContext, res: &mut CommandResponse) {
+        if res.status >= StatusCode::BAD_REQUEST.as_u16() as u32 {
+            warn!(
+                conn = ctx.id,
+                "Request failed: {} {}", res.status, res.message
+            );
+        } else {
+            info!(conn = ctx.id, "Executed response: {}", res.status);
+        }
+    }
+}
+
+/// 统计请求数量的中间件，clone 之后共享同一份计数
+#[derive(Clone, Debug, Default)]
+pub struct MetricsMiddleware {
+    inner: Arc<Metrics>,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    commands: DashMap<&'static str, u64>,
+}
+
+/// 某个时刻的统计数据
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// 收到的请求总数
+    pub requests: u64,
+    /// 状态码是 4xx/5xx 的响应总数
+    pub errors: u64,
+    /// 每种命令收到的次数
+    pub commands: Vec<(&'static str, u64)>,
+}
+
+impl MetricsMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 获取当前的统计数据
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut commands: Vec<_> = self
+            .inner
+            .commands
+            .iter()
+            .map(|v| (*v.key(), *v.value()))
+            .collect();
+        commands.sort();
+
+        MetricsSnapshot {
+            requests: self.inner.requests.load(Ordering::Relaxed),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+            commands,
+        }
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn on_request(&self, _ctx: &ConnContext, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        *self.inner.commands.entry(cmd.name()).or_default() += 1;
+        None
+    }
+
+    fn on_response(&self, _ctx: &ConnContext, res: &mut CommandResponse) {
+        if res.status >= StatusCode::BAD_REQUEST.as_u16() as u32 {
+            self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 只允许特定身份的客户端访问，clone 之后共享同一份名单
+#[derive(Clone, Debug)]
+pub struct AuthMiddleware {
+    /// None 代表允许所有客户端访问
+    identities: Arc<RwLock<Option<HashSet<String>>>>,
+}
+
+impl AuthMiddleware {
+    /// 创建一个只允许 identities 里的客户端访问的中间件
+    pub fn new(identities: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let auth = Self::allow_all();
+        auth.set_identities(identities);
+        auth
+    }
+
+    /// 创建一个允许所有客户端访问的中间件，之后可以通过 `set_identities` 打开访问控制
+    pub fn allow_all() -> Self {
+        Self {
+            identities: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// 换成新的名单，对之后的请求生效
+    pub fn set_identities(&self, identities: impl IntoIterator<Item = impl Into<String>>) {
+        let identities = identities.into_iter().map(|v| v.into()).collect();
+        *self.identities.write().unwrap() = Some(identities);
+    }
+
+    /// 关闭访问控制，允许所有客户端访问
+    pub fn clear(&self) {
+        *self.identities.write().unwrap() = None;
+    }
+}
+
+impl Middleware for AuthMiddleware {
+    fn on_request(&self, ctx: &ConnContext, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        let identities = self.identities.read().unwrap();
+        let identities = identities.as_ref()?;
+        match &ctx.identity {
+            Some(id) if identities.contains(id) => None,
+            id => {
+                let id = id.as_deref().unwrap_or("anonymous");
+                Some(KvError::PermissionDenied(format!("{} cannot run {}", id, cmd.name())).into())
+            }
+        }
+    }
+}
+
+/// 超过这个数量的连接状态时，清理长时间没有请求的连接
+const MAX_IDLE_BUCKETS: usize = 1024;
+/// 多久没有请求的连接状态可以被清理
+const BUCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 用令牌桶限制每个连接的请求速率，clone 之后共享同一份配置和状态
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitMiddleware {
+    inner: Arc<RateLimit>,
+}
+
+#[derive(Debug, Default)]
+struct RateLimit {
+    /// (每秒补充的令牌, 桶的容量)，None 代表不限速
+    limit: RwLock<Option<(f64, f64)>>,
+    buckets: DashMap<u64, Bucket>,
+}
+
+/// 令牌桶
+#[derive(Debug)]
+pub(super) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// 装满令牌的桶
+    pub(super) fn full(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// 补充令牌之后尝试取出一个，取不到返回 false
+    pub(super) fn take(&mut self, now: Instant, rate: f64, burst: f64) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+}
+
+impl RateLimitMiddleware {
+    /// 每个连接每秒最多 requests_per_second 个请求，最多允许突发 burst 个
+    pub fn new(requests_per_second: u32, burst: u32) -> Self {
+        let limit = Self::default();
+        limit.set_limit(Some((requests_per_second, burst)));
+        limit
+    }
+
+    /// 修改限速，对之后的请求生效，None 代表不限速
+    pub fn set_limit(&self, limit: Option<(u32, u32)>) {
+        let limit = limit.map(|(rate, burst)| (rate as f64, burst.max(1) as f64));
+        *self.inner.limit.write().unwrap() = limit;
+    }
+}
+
+impl Middleware for RateLimitMiddleware {
+    fn on_request(&self, ctx: &ConnContext, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        let (rate, burst) = (*self.inner.limit.read().unwrap())?;
+        let buckets = &self.inner.buckets;
+        let now = Instant::now();
+
+        if buckets.len() >= MAX_IDLE_BUCKETS && !buckets.contains_key(&ctx.id) {
+            buckets.retain(|_, b| now.duration_since(b.updated) < BUCKET_IDLE_TIMEOUT);
+        }
+
+        let mut bucket = buckets
+            .entry(ctx.id)
+            .or_insert_with(|| Bucket::full(burst, now));
+        if bucket.take(now, rate, burst) {
+            return None;
+        }
+
+        let msg = format!("{} exceeds {} requests per second", cmd.name(), rate);
+        Some(KvError::RateLimited(msg).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{assert_res_error, assert_res_ok, MemTable, Service, Value};
+
+    /// 把所有请求的表名改成 prefix + 表名
+    struct Prefix(&'static str);
+
+    impl Middleware for Prefix {
+        fn on_request(
+            &self,
+            _ctx: &ConnContext,
+            cmd: &mut CommandRequest,
+        ) -> Option<CommandResponse> {
+            if let Some(crate::RequestData::Hset(param)) = cmd.request_data.as_mut() {
+                param.table = format!("{}{}", self.0, param.table);
+            }
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_should_rewrite_request() {
+        let service = Service::new(MemTable::new()).with_middleware(Prefix("ns:"));
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        service.execute(cmd).next().await.unwrap();
+
+        let mut res = service.execute(CommandRequest::new_hget("ns:t1", "k1"));
+        assert_res_ok(&res.next().await.unwrap(), &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn streaming_responses_should_pass_through_middlewares() {
+        let service = Service::new(MemTable::new()).fn_before_send(|res| res.status = 201);
+
+        let mut stream = service.execute(CommandRequest::new_subscribe("lobby"));
+        assert_eq!(stream.next().await.unwrap().status, 201);
+
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        service.execute(cmd).next().await.unwrap();
+        let data = stream.next().await.unwrap();
+        assert_eq!(data.status, 201);
+        assert_eq!(data.values, vec!["hello".into()]);
+    }
+
+    #[tokio::test]
+    async fn auth_middleware_should_reject_unknown_identity() {
+        let metrics = MetricsMiddleware::new();
+        let service = Service::new(MemTable::new())
+            .with_middleware(metrics.clone())
+            .with_middleware(AuthMiddleware::new(["awesome-device-id"]));
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let mut res = service.execute(cmd.clone());
+        assert_res_error(&res.next().await.unwrap(), 403, "anonymous cannot run hget");
+
+        let ctx = ConnContext::new(None, Some("awesome-device-id".into()));
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let mut res = service.execute_with(&ctx, cmd);
+        assert_res_ok(&res.next().await.unwrap(), &[Value::default()], &[]);
+
+        // 被拒绝的请求也会被外层的 metrics 统计到
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.requests, 2);
+        assert_eq!(snapshot.errors, 1);
+        assert_eq!(snapshot.commands, vec![("hget", 1), ("hset", 1)]);
+    }
+
+    #[tokio::test]
+    async fn auth_middleware_should_be_updatable() {
+        let auth = AuthMiddleware::allow_all();
+        let service = Service::new(MemTable::new()).with_middleware(auth.clone());
+        let ctx = ConnContext::new(None, Some("awesome-device-id".into()));
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let mut res = service.execute_with(&ctx, cmd.clone());
+        assert_res_error(&res.next().await.unwrap(), 404, "Not found");
+
+        auth.set_identities(["another-device-id"]);
+        let mut res = service.execute_with(&ctx, cmd.clone());
+        assert_res_error(&res.next().await.unwrap(), 403, "cannot run hget");
+
+        auth.clear();
+        let mut res = service.execute_with(&ctx, cmd);
+        assert_res_error(&res.next().await.unwrap(), 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_middleware_should_limit_each_connection() {
+        let limit = RateLimitMiddleware::new(1, 2);
+        let service = Service::new(MemTable::new()).with_middleware(limit.clone());
+        let ctx1 = ConnContext::new(None, None);
+        let ctx2 = ConnContext::new(None, None);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+
+        // 允许突发 2 个请求
+        for _ in 0..2 {
+            let mut res = service.execute_with(&ctx1, cmd.clone());
+            assert_eq!(res.next().await.unwrap().status, 200);
+        }
+        let mut res = service.execute_with(&ctx1, cmd.clone());
+        assert_res_error(
+            &res.next().await.unwrap(),
+            429,
+            "hset exceeds 1 requests per second",
+        );
+
+        // 其他连接不受影响
+        let mut res = service.execute_with(&ctx2, cmd.clone());
+        assert_eq!(res.next().await.unwrap().status, 200);
+
+        // 关闭限速
+        limit.set_limit(None);
+        let mut res = service.execute_with(&ctx1, cmd);
+        assert_eq!(res.next().await.unwrap().status, 200);
+    }
+}
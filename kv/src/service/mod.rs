@@ -1,13 +1,23 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use futures::stream;
+use futures::{stream, StreamExt};
+use tokio::{sync::RwLock, task::spawn_blocking};
 use tracing::debug;
 
 use crate::*;
 
+mod audit;
+mod backup_service;
 mod command_service;
+mod middleware;
+mod namespace;
+mod script_service;
 mod topic_service;
+
 use self::topic_service::{StreamingResponse, TopicService as _};
+pub use audit::*;
+pub use middleware::*;
+use namespace::{Namespaces, UsageTracker};
 
 /// 对 Command 的处理的抽象
 pub trait CommandService {
@@ -19,10 +29,17 @@ pub trait CommandService {
 pub struct Service {
     store: Arc<dyn Storage>,
     broadcaster: Arc<Broadcaster>,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_before_send: Vec<fn(&mut CommandResponse)>,
-    on_after_send: Vec<fn()>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    /// 备份文件所在的目录，None 代表不允许备份和恢复
+    backup_dir: Option<Arc<PathBuf>>,
+    /// 写命令共享这把锁，备份、恢复和脚本独占它，这样备份时看到的是一致的数据
+    write_gate: Arc<RwLock<()>>,
+    /// Eval 命令使用的脚本引擎，None 代表不允许执行脚本
+    scripts: Option<Arc<ScriptEngine>>,
+    /// 审计日志，None 代表不记录
+    audit: Option<Arc<AuditLog>>,
+    /// 多租户的命名空间，None 代表所有连接共享同一组 table
+    namespaces: Option<Arc<Namespaces>>,
 }
 
 impl Clone for Service {
@@ -30,10 +47,12 @@ impl Clone for Service {
         Self {
             store: Arc::clone(&self.store),
             broadcaster: Arc::clone(&self.broadcaster),
-            on_received: self.on_received.clone(),
-            on_executed: self.on_executed.clone(),
-            on_before_send: self.on_before_send.clone(),
-            on_after_send: self.on_after_send.clone(),
+            middlewares: self.middlewares.clone(),
+            backup_dir: self.backup_dir.clone(),
+            write_gate: Arc::clone(&self.write_gate),
+            scripts: self.scripts.clone(),
+            audit: self.audit.clone(),
+            namespaces: self.namespaces.clone(),
         }
     }
 }
@@ -43,74 +62,186 @@ impl Service {
         Self {
             store: Arc::new(store),
             broadcaster: Arc::new(Broadcaster::default()),
-            on_received: vec![],
-            on_executed: vec![],
-            on_before_send: vec![],
-            on_after_send: vec![],
+            middlewares: vec![],
+            backup_dir: None,
+            write_gate: Arc::new(RwLock::new(())),
+            scripts: None,
+            audit: None,
+            namespaces: None,
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    /// 允许 Backup / Restore 命令读写 dir 下的文件
+    pub fn with_backup_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = Some(Arc::new(dir.into()));
+        self
+    }
+
+    /// 允许 Eval 命令在 limits 的限制下执行 WASM 脚本
+    pub fn with_scripting(mut self, limits: ScriptLimits) -> Self {
+        self.scripts = Some(Arc::new(ScriptEngine::new(limits)));
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    /// 把执行的写命令记录到审计日志
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(Arc::new(audit));
         self
     }
 
-    pub fn fn_before_send(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_before_send.push(f);
+    /// 按 configs 划分命名空间，并从存储里统计每个命名空间已有的用量
+    ///
+    /// 之后所有的写入都经过 UsageTracker 更新用量
+    pub fn with_namespaces(mut self, configs: &[NamespaceConfig]) -> Self {
+        let namespaces = Arc::new(Namespaces::new(configs));
+        namespaces.load_usage(&self.store);
+        self.store = Arc::new(UsageTracker::new(self.store, namespaces.clone()));
+        self.namespaces = Some(namespaces);
         self
     }
 
-    pub fn fn_after_send(mut self, f: fn()) -> Self {
-        self.on_after_send.push(f);
+    /// 注册一个中间件，先注册的中间件在外层
+    pub fn with_middleware(mut self, m: impl Middleware) -> Self {
+        self.middlewares.push(Arc::new(m));
         self
     }
 
+    pub fn fn_received(self, f: fn(&CommandRequest)) -> Self {
+        self.with_middleware(FnHook::Received(f))
+    }
+
+    pub fn fn_executed(self, f: fn(&CommandResponse)) -> Self {
+        self.with_middleware(FnHook::Executed(f))
+    }
+
+    pub fn fn_before_send(self, f: fn(&mut CommandResponse)) -> Self {
+        self.with_middleware(FnHook::BeforeSend(f))
+    }
+
+    pub fn fn_after_send(self, f: fn()) -> Self {
+        self.with_middleware(FnHook::AfterSend(f))
+    }
+
     pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
+        self.execute_with(&ConnContext::default(), cmd)
+    }
+
+    /// 在某个连接的上下文中处理 Command
+    ///
+    /// 中间件的 on_request 立即执行，命令本身在返回的 stream 第一次被 poll 时执行。
+    pub fn execute_with(&self, ctx: &ConnContext, mut cmd: CommandRequest) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
-        self.on_received.notify(&cmd);
-        let mut res = dispatch(cmd.clone(), &self.store);
-        if res == CommandResponse::default() {
-            return dispatch_stream(cmd, Arc::clone(&self.broadcaster));
-        } else {
-            debug!("Executed response: {:?}", res);
 
-            self.on_executed.notify(&res);
-            self.on_before_send.notify(&mut res);
+        // 请求依次经过中间件，如果某个中间件短路，只有它外层的中间件能看到响应
+        for (i, m) in self.middlewares.iter().enumerate() {
+            if let Some(res) = m.on_request(ctx, &mut cmd) {
+                return self.respond(ctx, &self.middlewares[..i], res);
+            }
+        }
 
-            if !self.on_before_send.is_empty() {
-                debug!("Modified response: {:?}", res);
+        let service = self.clone();
+        let ctx = ctx.clone();
+        Box::pin(stream::once(async move { service.execute_gated(ctx, cmd).await }).flatten())
+    }
+
+    /// 按命令的类型获取 write_gate，然后执行命令
+    async fn execute_gated(self, ctx: ConnContext, mut cmd: CommandRequest) -> StreamingResponse {
+        let res = match &cmd.request_data {
+            // 备份和恢复要读写文件，脚本可能执行很久，它们在阻塞线程池里执行，等待的写命令不会占住 runtime；
+            // 脚本执行期间写命令会等待，这样脚本里的读写是一个原子操作
+            Some(RequestData::Backup(_) | RequestData::Restore(_) | RequestData::Eval(_)) => {
+                let guard = Arc::clone(&self.write_gate).write_owned().await;
+                let (service, task_ctx) = (self.clone(), ctx.clone());
+                let task = spawn_blocking(move || {
+                    let _guard = guard;
+                    service.execute_inner(&task_ctx, &mut cmd)
+                });
+                let res = task
+                    .await
+                    .unwrap_or_else(|e| KvError::Internal(e.to_string()).into());
+                return self.respond(&ctx, &self.middlewares, res);
+            }
+            _ if cmd.is_idempotent() => self.execute_inner(&ctx, &mut cmd),
+            _ => {
+                let _guard = self.write_gate.read().await;
+                self.execute_inner(&ctx, &mut cmd)
             }
+        };
+
+        if res == CommandResponse::default() {
+            let stream = dispatch_stream(cmd, Arc::clone(&self.broadcaster));
+            return self.stream_through_middlewares(&ctx, stream);
+        }
+        self.respond(&ctx, &self.middlewares, res)
+    }
+
+    /// 响应按相反的顺序经过 middlewares
+    fn respond(
+        &self,
+        ctx: &ConnContext,
+        middlewares: &[Arc<dyn Middleware>],
+        mut res: CommandResponse,
+    ) -> StreamingResponse {
+        debug!("Executed response: {:?}", res);
+        for m in middlewares.iter().rev() {
+            m.on_response(ctx, &mut res);
         }
 
         Box::pin(stream::once(async { Arc::new(res) }))
     }
-}
 
-pub trait Notify<Arg> {
-    fn notify(&self, arg: &Arg);
-}
+    /// 经过命名空间和审计日志，执行命令；流式命令返回默认的 CommandResponse
+    fn execute_inner(&self, ctx: &ConnContext, cmd: &mut CommandRequest) -> CommandResponse {
+        match &self.namespaces {
+            Some(namespaces) => namespaces.execute(ctx, cmd, &self.store, |cmd| {
+                self.audit_and_dispatch(ctx, cmd)
+            }),
+            None => self.audit_and_dispatch(ctx, cmd),
+        }
+    }
 
-pub trait NotifyMut<Arg> {
-    fn notify(&self, arg: &mut Arg);
-}
+    /// 流式响应里的每个数据也按相反的顺序经过所有中间件
+    fn stream_through_middlewares(
+        &self,
+        ctx: &ConnContext,
+        stream: StreamingResponse,
+    ) -> StreamingResponse {
+        if self.middlewares.is_empty() {
+            return stream;
+        }
+
+        let middlewares = self.middlewares.clone();
+        let ctx = ctx.clone();
+        Box::pin(stream.map(move |data| {
+            let mut res = Arc::unwrap_or_clone(data);
+            for m in middlewares.iter().rev() {
+                m.on_response(&ctx, &mut res);
+            }
+            Arc::new(res)
+        }))
+    }
 
-impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
-    fn notify(&self, arg: &Arg) {
-        for f in self {
-            f(arg);
+    fn audit_and_dispatch(&self, ctx: &ConnContext, cmd: &CommandRequest) -> CommandResponse {
+        match &self.audit {
+            Some(audit) => audit.execute(ctx, cmd, &self.store, || self.dispatch(cmd.clone())),
+            None => self.dispatch(cmd.clone()),
+        }
+    }
+
+    fn dispatch(&self, cmd: CommandRequest) -> CommandResponse {
+        match cmd.request_data {
+            Some(RequestData::Backup(param)) => self.backup(param),
+            Some(RequestData::Restore(param)) => self.restore(param),
+            Some(RequestData::Eval(param)) => self.eval(param),
+            Some(RequestData::ScriptLoad(param)) => self.script_load(param),
+            _ => dispatch(cmd, &self.store),
         }
     }
-}
 
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
-    fn notify(&self, arg: &mut Arg) {
-        for f in self {
-            f(arg)
+    /// 响应发送给客户端后，通知所有中间件
+    pub fn on_sent(&self, ctx: &ConnContext) {
+        for m in self.middlewares.iter().rev() {
+            m.on_sent(ctx);
         }
     }
 }
@@ -127,6 +258,19 @@ pub fn dispatch(cmd: CommandRequest, store: &Arc<dyn Storage>) -> CommandRespons
         Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hmdel(param)) => param.execute(store),
         Some(RequestData::Hmexist(param)) => param.execute(store),
+        Some(RequestData::Stats(param)) => param.execute(store),
+        Some(RequestData::Lpush(param)) => param.execute(store),
+        Some(RequestData::Rpush(param)) => param.execute(store),
+        Some(RequestData::Lpop(param)) => param.execute(store),
+        Some(RequestData::Rpop(param)) => param.execute(store),
+        Some(RequestData::Lrange(param)) => param.execute(store),
+        Some(RequestData::Sadd(param)) => param.execute(store),
+        Some(RequestData::Srem(param)) => param.execute(store),
+        Some(RequestData::Smembers(param)) => param.execute(store),
+        Some(RequestData::Sinter(param)) => param.execute(store),
+        Some(RequestData::Zadd(param)) => param.execute(store),
+        Some(RequestData::Zrange(param)) => param.execute(store),
+        Some(RequestData::Zrangebyscore(param)) => param.execute(store),
         None => KvError::InvalidCommand("Request has no data".into()).into(),
         _ => CommandResponse::default(),
     }
@@ -141,9 +285,55 @@ pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic) -> StreamingRespo
     }
 }
 
+#[cfg(test)]
+use crate::Value;
+
+// 测试成功返回的结果
+#[cfg(test)]
+pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    let mut sorted_pairs = res.pairs.clone();
+    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(sorted_pairs, pairs);
+}
+
+// 测试失败返回的结果
+#[cfg(test)]
+pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
+}
+
+#[cfg(test)]
+pub fn get_sled_store() -> SledTable {
+    let config = sled::Config::new().temporary(true);
+    let db = config.open().unwrap();
+
+    SledTable::new(db)
+}
+
+/// property-based 测试中随机生成的 Value，float 不包括 NaN，以便比较
+#[cfg(test)]
+pub fn arb_value() -> impl proptest::strategy::Strategy<Value = Value> {
+    use proptest::{collection::vec, prelude::*};
+
+    let scalar = prop_oneof![
+        any::<i64>().prop_map(Value::from),
+        (-1e12f64..1e12).prop_map(Value::from),
+        any::<bool>().prop_map(Value::from),
+        "\\PC{0,16}".prop_map(Value::from),
+        vec(any::<u8>(), 0..32).prop_map(Value::from),
+    ];
+    scalar.prop_recursive(2, 16, 4, |inner| vec(inner, 0..4).prop_map(Value::from))
+}
+
 #[cfg(test)]
 mod tests {
-    use futures::StreamExt;
     use http::StatusCode;
     use tracing::info;
 
@@ -198,40 +388,8 @@ mod tests {
 
         let data = res.next().await.unwrap();
 
-        assert_eq!(data.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(data.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(data.message, "");
         assert_eq!(data.values, vec![Value::default()]);
     }
 }
-
-#[cfg(test)]
-use crate::Value;
-
-// 测试成功返回的结果
-#[cfg(test)]
-pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    let mut sorted_pairs = res.pairs.clone();
-    sorted_pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(sorted_pairs, pairs);
-}
-
-// 测试失败返回的结果
-#[cfg(test)]
-pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
-}
-
-#[cfg(test)]
-pub fn get_sled_store() -> SledTable {
-    let config = sled::Config::new().temporary(true);
-    let db = config.open().unwrap();
-
-    SledTable::new(db)
-}
@@ -0,0 +1,889 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use tracing::warn;
+
+use super::{audit::AuditTarget, middleware::Bucket};
+use crate::*;
+
+/// 命名空间和 table 名字之间的分隔符，命名空间的名字里不会有它
+const SEPARATOR: char = '/';
+
+/// 多租户的命名空间
+///
+/// 绑定了命名空间的连接访问的 table 和 topic 都会加上 `{namespace}/` 前缀，
+/// MemTable 里是不同的 table，SledTable 里是不同的 sled tree。
+/// 没有绑定命名空间的连接不能访问这些 table 和 topic，也不能执行会访问所有 table 的
+/// 备份、恢复和脚本。
+///
+/// 用量在启动时统计一次，之后由 UsageTracker 根据每次写入前后的值更新。
+pub(crate) struct Namespaces {
+    namespaces: HashMap<String, Namespace>,
+    /// 客户端身份 -> 绑定的命名空间
+    identities: HashMap<String, String>,
+}
+
+struct Namespace {
+    name: String,
+    /// 没有绑定身份，任何客户端都可以在握手时选择
+    open: bool,
+    max_keys: Option<u64>,
+    max_bytes: Option<u64>,
+    /// (每秒补充的令牌, 桶的容量)，None 代表不限速
+    limit: Option<(f64, f64)>,
+    bucket: Mutex<Bucket>,
+    usage: Usage,
+    /// 有配额时同一个命名空间的写命令串行执行，检查配额之后用量不会被它们改变
+    quota: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    keys: AtomicI64,
+    bytes: AtomicI64,
+}
+
+/// 包装真正的存储，根据每次写入前后的值更新 key 所属命名空间的用量
+///
+/// 存储淘汰的 key 也会从用量里扣除，这样只需要在启动时扫描一次存储。
+pub(crate) struct UsageTracker {
+    inner: Arc<dyn Storage>,
+    namespaces: Arc<Namespaces>,
+}
+
+impl Namespaces {
+    pub(crate) fn new(configs: &[NamespaceConfig]) -> Self {
+        let now = Instant::now();
+        let mut namespaces = HashMap::new();
+        let mut identities = HashMap::new();
+
+        for config in configs {
+            for id in &config.identities {
+                identities.insert(id.clone(), config.name.clone());
+            }
+            let limit = config.rate_limit.as_ref().map(|limit| {
+                let burst = limit.burst.unwrap_or(limit.requests_per_second).max(1);
+                (limit.requests_per_second as f64, burst as f64)
+            });
+            let ns = Namespace {
+                name: config.name.clone(),
+                open: config.identities.is_empty(),
+                max_keys: config.max_keys,
+                max_bytes: config.max_bytes,
+                limit,
+                bucket: Mutex::new(Bucket::full(limit.map(|l| l.1).unwrap_or(0.0), now)),
+                usage: Usage::default(),
+                quota: Mutex::new(()),
+            };
+            namespaces.insert(config.name.clone(), ns);
+        }
+
+        Self {
+            namespaces,
+            identities,
+        }
+    }
+
+    /// 从存储里重新统计每个命名空间的用量
+    pub(crate) fn load_usage(&self, store: &Arc<dyn Storage>) {
+        let tables = match store.tables() {
+            Ok(tables) => tables,
+            Err(e) => return warn!("Failed to list tables: {}", e),
+        };
+
+        for ns in self.namespaces.values() {
+            let (mut keys, mut bytes) = (0, 0);
+            for table in tables.iter().filter(|t| ns.owns(t)) {
+                let iter = match store.get_iter(table) {
+                    Ok(iter) => iter,
+                    Err(e) => {
+                        warn!("Failed to read table {}: {}", table, e);
+                        continue;
+                    }
+                };
+                for pair in iter {
+                    let value = pair.value.unwrap_or_default();
+                    keys += 1;
+                    bytes += entry_size(&pair.key, &value);
+                }
+            }
+            ns.usage.keys.store(keys, Ordering::Relaxed);
+            ns.usage.bytes.store(bytes as i64, Ordering::Relaxed);
+        }
+    }
+
+    /// table 里的一个 key 的大小从 old 变成 new，None 代表 key 不存在
+    fn record(&self, table: &str, old: Option<u64>, new: Option<u64>) {
+        let ns = table
+            .split_once(SEPARATOR)
+            .and_then(|(ns, _)| self.namespaces.get(ns));
+        if let Some(ns) = ns {
+            ns.usage.record(old, new);
+        }
+    }
+
+    /// 在连接绑定的命名空间里执行命令，cmd 里的 table 和 topic 会被加上前缀
+    pub(crate) fn execute(
+        &self,
+        ctx: &ConnContext,
+        cmd: &mut CommandRequest,
+        store: &Arc<dyn Storage>,
+        f: impl FnOnce(&CommandRequest) -> CommandResponse,
+    ) -> CommandResponse {
+        match self.resolve(ctx) {
+            Ok(Some(ns)) => ns.execute(cmd, store, f),
+            Ok(None) => self.execute_unbound(cmd, f),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// 连接绑定的命名空间：TLS 身份绑定的优先，否则使用握手时要求的
+    fn resolve(&self, ctx: &ConnContext) -> Result<Option<&Namespace>, KvError> {
+        let id = ctx.identity.as_deref().unwrap_or("anonymous");
+        let requested = ctx.handshake.as_ref().and_then(|h| h.namespace.as_deref());
+        let bound = ctx.identity.as_ref().and_then(|id| self.identities.get(id));
+
+        match (bound, requested) {
+            (Some(bound), Some(requested)) if bound != requested => {
+                Err(KvError::PermissionDenied(format!(
+                    "{} is bound to namespace {}, cannot use {}",
+                    id, bound, requested
+                )))
+            }
+            (Some(bound), _) => Ok(self.namespaces.get(bound)),
+            (None, Some(requested)) => match self.namespaces.get(requested) {
+                Some(ns) if ns.open => Ok(Some(ns)),
+                Some(_) => Err(KvError::PermissionDenied(format!(
+                    "{} cannot use namespace {}",
+                    id, requested
+                ))),
+                None => Err(KvError::PermissionDenied(format!(
+                    "namespace {} does not exist",
+                    requested
+                ))),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// 没有绑定命名空间的连接不能访问命名空间里的 table 和 topic
+    fn execute_unbound(
+        &self,
+        cmd: &mut CommandRequest,
+        f: impl FnOnce(&CommandRequest) -> CommandResponse,
+    ) -> CommandResponse {
+        if let Some(scope) = scope_mut(cmd) {
+            if let Some(ns) = self.namespaces.values().find(|ns| ns.owns(scope)) {
+                let msg = format!("{} belongs to namespace {}", scope, ns.name);
+                return KvError::PermissionDenied(msg).into();
+            }
+        }
+        // 备份、恢复和脚本会读写所有的 table，包括命名空间里的
+        if matches!(
+            cmd.request_data,
+            Some(
+                RequestData::Backup(_)
+                    | RequestData::Restore(_)
+                    | RequestData::Eval(_)
+                    | RequestData::ScriptLoad(_)
+            )
+        ) {
+            let msg = format!("{} is not allowed when namespaces are enabled", cmd.name());
+            return KvError::PermissionDenied(msg).into();
+        }
+
+        f(cmd)
+    }
+}
+
+impl Namespace {
+    /// table 或者 topic 是否属于这个命名空间
+    fn owns(&self, scope: &str) -> bool {
+        matches!(scope.split_once(SEPARATOR), Some((ns, _)) if ns == self.name)
+    }
+
+    fn execute(
+        &self,
+        cmd: &mut CommandRequest,
+        store: &Arc<dyn Storage>,
+        f: impl FnOnce(&CommandRequest) -> CommandResponse,
+    ) -> CommandResponse {
+        if let Err(e) = self.check_rate(cmd) {
+            return e.into();
+        }
+
+        match scope_mut(cmd) {
+            Some(scope) => *scope = format!("{}{}{}", self.name, SEPARATOR, scope),
+            None => match &cmd.request_data {
+                Some(RequestData::Stats(_)) => return self.stats(),
+                // 没有数据的请求交给 dispatch 报错
+                None => return f(cmd),
+                // 备份、恢复和脚本会访问所有的 table
+                Some(_) => {
+                    let msg = format!("{} is not allowed in namespace {}", cmd.name(), self.name);
+                    return KvError::PermissionDenied(msg).into();
+                }
+            },
+        }
+
+        let cmd = &*cmd;
+        if self.max_keys.is_none() && self.max_bytes.is_none() {
+            return f(cmd);
+        }
+        let target = match AuditTarget::of(cmd) {
+            Some(target) => target,
+            None => return f(cmd),
+        };
+        let mut keys = target.keys.clone();
+        keys.sort_unstable();
+        keys.dedup();
+
+        // 持有锁直到命令执行完，用量由 UsageTracker 在写入时更新
+        let _quota = self.quota.lock().unwrap();
+        let old = sizes(store, target.table, &keys);
+        if let Err(e) = self.check_quota(cmd, &keys, &old) {
+            return e.into();
+        }
+        f(cmd)
+    }
+
+    fn check_rate(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        let (rate, burst) = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        match self
+            .bucket
+            .lock()
+            .unwrap()
+            .take(Instant::now(), rate, burst)
+        {
+            true => Ok(()),
+            false => Err(KvError::RateLimited(format!(
+                "{} exceeds {} requests per second of namespace {}",
+                cmd.name(),
+                rate,
+                self.name
+            ))),
+        }
+    }
+
+    /// 估算命令执行后的用量，超出配额时拒绝；删除之类不增加用量的命令总是可以执行
+    fn check_quota(
+        &self,
+        cmd: &CommandRequest,
+        keys: &[&str],
+        old: &[Option<u64>],
+    ) -> Result<(), KvError> {
+        let old: HashMap<_, _> = keys.iter().copied().zip(old.iter().copied()).collect();
+        let (new_keys, new_bytes) = growth(cmd, &old);
+        let (keys, bytes) = self.usage.get();
+
+        if let Some(max) = self.max_keys {
+            if new_keys > 0 && keys + new_keys > max {
+                return Err(KvError::QuotaExceeded(format!(
+                    "namespace {} is limited to {} keys",
+                    self.name, max
+                )));
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            if new_bytes > 0 && bytes + new_bytes > max {
+                return Err(KvError::QuotaExceeded(format!(
+                    "namespace {} is limited to {} bytes",
+                    self.name, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// 命名空间的用量和配额，0 代表不限制
+    fn stats(&self) -> CommandResponse {
+        let (keys, bytes) = self.usage.get();
+        vec![
+            Kvpair::new("namespace", self.name.as_str().into()),
+            Kvpair::new("keys", (keys as i64).into()),
+            Kvpair::new("used_bytes", (bytes as i64).into()),
+            Kvpair::new("max_keys", (self.max_keys.unwrap_or(0) as i64).into()),
+            Kvpair::new("max_bytes", (self.max_bytes.unwrap_or(0) as i64).into()),
+        ]
+        .into()
+    }
+}
+
+impl Usage {
+    /// 返回 (key 数量, 字节数)
+    fn get(&self) -> (u64, u64) {
+        let keys = self.keys.load(Ordering::Relaxed).max(0);
+        let bytes = self.bytes.load(Ordering::Relaxed).max(0);
+        (keys as u64, bytes as u64)
+    }
+
+    fn record(&self, old: Option<u64>, new: Option<u64>) {
+        let keys = new.is_some() as i64 - old.is_some() as i64;
+        let bytes = new.unwrap_or(0) as i64 - old.unwrap_or(0) as i64;
+        self.keys.fetch_add(keys, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl UsageTracker {
+    /// 包装 inner，并让 inner 淘汰 key 时扣除用量
+    pub(crate) fn new(inner: Arc<dyn Storage>, namespaces: Arc<Namespaces>) -> Self {
+        let evicted = namespaces.clone();
+        inner.set_eviction_listener(Arc::new(move |table, key, value| {
+            evicted.record(table, Some(entry_size(key, value)), None)
+        }));
+        Self { inner, namespaces }
+    }
+}
+
+impl Storage for UsageTracker {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inner.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let len = key.len() as u64;
+        let new = entry_size(&key, &value);
+        let old = self.inner.set(table, key, value)?;
+        let old_size = old.as_ref().map(|v| len + v.approx_size() as u64);
+        self.namespaces.record(table, old_size, Some(new));
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.inner.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.inner.del(table, key)?;
+        if let Some(v) = &old {
+            self.namespaces
+                .record(table, Some(entry_size(key, v)), None);
+        }
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.inner.get_iter(table)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        self.inner.tables()
+    }
+
+    fn stats(&self) -> Vec<Kvpair> {
+        self.inner.stats()
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<(), KvError> {
+        // f 可能被调用多次，只有最后一次的结果被写入
+        let mut change = (None, None);
+        self.inner.update(table, key, &mut |old| {
+            let old_size = old.as_ref().map(|v| entry_size(key, v));
+            let new = f(old)?;
+            change = (old_size, new.as_ref().map(|v| entry_size(key, v)));
+            Ok(new)
+        })?;
+        self.namespaces.record(table, change.0, change.1);
+        Ok(())
+    }
+}
+
+/// 命令访问的 table 或者 topic，Backup / Stats 之类的全局命令返回 None
+fn scope_mut(cmd: &mut CommandRequest) -> Option<&mut String> {
+    let scope = match cmd.request_data.as_mut()? {
+        RequestData::Hget(p) => &mut p.table,
+        RequestData::Hgetall(p) => &mut p.table,
+        RequestData::Hmget(p) => &mut p.table,
+        RequestData::Hset(p) => &mut p.table,
+        RequestData::Hmset(p) => &mut p.table,
+        RequestData::Hdel(p) => &mut p.table,
+        RequestData::Hmdel(p) => &mut p.table,
+        RequestData::Hexist(p) => &mut p.table,
+        RequestData::Hmexist(p) => &mut p.table,
+        RequestData::Lpush(p) => &mut p.table,
+        RequestData::Rpush(p) => &mut p.table,
+        RequestData::Lpop(p) => &mut p.table,
+        RequestData::Rpop(p) => &mut p.table,
+        RequestData::Lrange(p) => &mut p.table,
+        RequestData::Sadd(p) => &mut p.table,
+        RequestData::Srem(p) => &mut p.table,
+        RequestData::Smembers(p) => &mut p.table,
+        RequestData::Sinter(p) => &mut p.table,
+        RequestData::Zadd(p) => &mut p.table,
+        RequestData::Zrange(p) => &mut p.table,
+        RequestData::Zrangebyscore(p) => &mut p.table,
+        RequestData::Subscribe(p) => &mut p.topic,
+        RequestData::Unsubscribe(p) => &mut p.topic,
+        RequestData::Publish(p) => &mut p.topic,
+        RequestData::Backup(_)
+        | RequestData::Restore(_)
+        | RequestData::Stats(_)
+        | RequestData::Eval(_)
+        | RequestData::ScriptLoad(_) => return None,
+    };
+    Some(scope)
+}
+
+/// 一个 key 计入配额的字节数
+fn entry_size(key: &str, value: &Value) -> u64 {
+    (key.len() + value.approx_size()) as u64
+}
+
+/// 每个 key 当前计入配额的字节数，key 不存在时为 None
+fn sizes(store: &Arc<dyn Storage>, table: &str, keys: &[&str]) -> Vec<Option<u64>> {
+    keys.iter()
+        .map(|key| match store.get(table, key) {
+            Ok(Some(v)) => Some(entry_size(key, &v)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 估算写命令新增的 key 数量和字节数
+fn growth(cmd: &CommandRequest, old: &HashMap<&str, Option<u64>>) -> (u64, u64) {
+    let old_size = |key: &str| old.get(key).copied().flatten();
+    // 集合类型的命令在已有的值上追加
+    let append = |key: &str, added: usize| match old_size(key) {
+        Some(_) => (0, added as u64),
+        None => (1, (key.len() + mem::size_of::<Value>() + added) as u64),
+    };
+
+    match &cmd.request_data {
+        Some(RequestData::Hset(p)) => p.pair.iter().fold((0, 0), |acc, pair| {
+            add(acc, replace(pair, old_size(&pair.key)))
+        }),
+        Some(RequestData::Hmset(p)) => p.pairs.iter().fold((0, 0), |acc, pair| {
+            add(acc, replace(pair, old_size(&pair.key)))
+        }),
+        Some(RequestData::Lpush(p)) => {
+            append(&p.key, p.values.iter().map(|v| v.approx_size()).sum())
+        }
+        Some(RequestData::Rpush(p)) => {
+            append(&p.key, p.values.iter().map(|v| v.approx_size()).sum())
+        }
+        Some(RequestData::Sadd(p)) => append(
+            &p.key,
+            p.members
+                .iter()
+                .map(|m| Value::from(m.as_str()).approx_size())
+                .sum(),
+        ),
+        Some(RequestData::Zadd(p)) => append(
+            &p.key,
+            p.members
+                .iter()
+                .map(|m| Value::from(m.member.as_str()).approx_size() + mem::size_of::<Value>())
+                .sum(),
+        ),
+        _ => (0, 0),
+    }
+}
+
+/// 用 pair 覆盖旧值时新增的 key 数量和字节数
+fn replace(pair: &Kvpair, old: Option<u64>) -> (u64, u64) {
+    let size = entry_size(&pair.key, pair.value.as_ref().unwrap_or(&Value::default()));
+    match old {
+        Some(old) => (0, size.saturating_sub(old)),
+        None => (1, size),
+    }
+}
+
+fn add(a: (u64, u64), b: (u64, u64)) -> (u64, u64) {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn namespace(name: &str, identities: &[&str]) -> NamespaceConfig {
+        NamespaceConfig {
+            name: name.into(),
+            identities: identities.iter().map(|id| id.to_string()).collect(),
+            max_keys: None,
+            max_bytes: None,
+            rate_limit: None,
+        }
+    }
+
+    /// identity 是 TLS 身份，namespace 是握手时要求的命名空间
+    fn ctx(identity: Option<&str>, namespace: Option<&str>) -> ConnContext {
+        ConnContext::new(None, identity.map(|id| id.into())).with_handshake(Handshake {
+            version: PROTOCOL_VERSION,
+            codec: Codec::None,
+            peer_codecs: vec![],
+            max_frame_size: DEFAULT_MAX_FRAME,
+            features: vec![],
+            namespace: namespace.map(|ns| ns.into()),
+        })
+    }
+
+    async fn execute(service: &Service, ctx: &ConnContext, cmd: CommandRequest) -> CommandResponse {
+        let res = service.execute_with(ctx, cmd).next().await.unwrap();
+        (*res).clone()
+    }
+
+    async fn stat(service: &Service, ctx: &ConnContext, name: &str) -> i64 {
+        let res = execute(service, ctx, CommandRequest::new_stats()).await;
+        let pair = res.pairs.iter().find(|p| p.key == name).unwrap();
+        pair.value.clone().unwrap().try_into().unwrap()
+    }
+
+    async fn namespaces_should_isolate_tables(store: impl Storage) {
+        let configs = [namespace("team-a", &[]), namespace("team-b", &[])];
+        let service = Service::new(store).with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+        let b = ctx(None, Some("team-b"));
+        let admin = ctx(None, None);
+
+        execute(
+            &service,
+            &a,
+            CommandRequest::new_hset("t1", "k1", "a".into()),
+        )
+        .await;
+        execute(
+            &service,
+            &b,
+            CommandRequest::new_hset("t1", "k1", "b".into()),
+        )
+        .await;
+        execute(
+            &service,
+            &admin,
+            CommandRequest::new_hset("t1", "k1", "admin".into()),
+        )
+        .await;
+
+        let res = execute(&service, &a, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["a".into()], &[]);
+        let res = execute(&service, &b, CommandRequest::new_hgetall("t1")).await;
+        assert_res_ok(&res, &[], &[Kvpair::new("k1", "b".into())]);
+        let res = execute(&service, &admin, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&res, &["admin".into()], &[]);
+
+        // 没有绑定命名空间的连接不能直接访问加了前缀的 table
+        let res = execute(
+            &service,
+            &admin,
+            CommandRequest::new_hget("team-a/t1", "k1"),
+        )
+        .await;
+        assert_res_error(&res, 403, "team-a/t1 belongs to namespace team-a");
+        // 命名空间里的 table 名字不会逃出前缀
+        let res = execute(&service, &b, CommandRequest::new_hget("team-a/t1", "k1")).await;
+        assert_eq!(res.status, 404);
+
+        let tables = service.store.tables().unwrap();
+        assert!(tables.contains(&"team-a/t1".to_string()));
+        assert!(tables.contains(&"team-b/t1".to_string()));
+        assert!(tables.contains(&"t1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn memtable_namespaces_should_isolate_tables() {
+        namespaces_should_isolate_tables(MemTable::new()).await;
+    }
+
+    #[tokio::test]
+    async fn sledtable_namespaces_should_isolate_tables() {
+        namespaces_should_isolate_tables(get_sled_store()).await;
+    }
+
+    #[tokio::test]
+    async fn namespace_should_be_bound_by_identity_or_handshake() {
+        let configs = [namespace("team-a", &["device-a"]), namespace("team-b", &[])];
+        let service = Service::new(MemTable::new()).with_namespaces(&configs);
+        let cmd = || CommandRequest::new_hset("t1", "k1", "v1".into());
+
+        // TLS 身份决定命名空间，握手时不需要再指定
+        let res = execute(&service, &ctx(Some("device-a"), None), cmd()).await;
+        assert_eq!(res.status, 200);
+        let res = execute(&service, &ctx(Some("device-a"), Some("team-a")), cmd()).await;
+        assert_eq!(res.status, 200);
+        assert!(service.store.contains("team-a/t1", "k1").unwrap());
+
+        let res = execute(&service, &ctx(Some("device-a"), Some("team-b")), cmd()).await;
+        assert_res_error(
+            &res,
+            403,
+            "device-a is bound to namespace team-a, cannot use team-b",
+        );
+        // 绑定了身份的命名空间不能在握手时选择
+        let res = execute(&service, &ctx(Some("device-b"), Some("team-a")), cmd()).await;
+        assert_res_error(&res, 403, "device-b cannot use namespace team-a");
+        let res = execute(&service, &ctx(None, Some("team-c")), cmd()).await;
+        assert_res_error(&res, 403, "namespace team-c does not exist");
+
+        let res = execute(&service, &ctx(Some("device-b"), Some("team-b")), cmd()).await;
+        assert_eq!(res.status, 200);
+        assert!(service.store.contains("team-b/t1", "k1").unwrap());
+    }
+
+    #[tokio::test]
+    async fn key_quota_should_reject_new_keys() {
+        let configs = [NamespaceConfig {
+            max_keys: Some(2),
+            ..namespace("team-a", &[])
+        }];
+        let service = Service::new(MemTable::new()).with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+
+        let pairs = vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())];
+        let res = execute(&service, &a, CommandRequest::new_hmset("t1", pairs)).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(stat(&service, &a, "keys").await, 2);
+
+        let res = execute(&service, &a, CommandRequest::new_hset("t2", "k3", 3.into())).await;
+        assert_res_error(&res, 507, "namespace team-a is limited to 2 keys");
+        let res = execute(
+            &service,
+            &a,
+            CommandRequest::new_rpush("t1", "l1", vec![1.into()]),
+        )
+        .await;
+        assert_eq!(res.status, 507);
+
+        // 修改已有的 key 不受影响，删除之后又可以写入
+        let res = execute(
+            &service,
+            &a,
+            CommandRequest::new_hset("t1", "k1", 10.into()),
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        execute(&service, &a, CommandRequest::new_hdel("t1", "k2")).await;
+        assert_eq!(stat(&service, &a, "keys").await, 1);
+        let res = execute(&service, &a, CommandRequest::new_hset("t2", "k3", 3.into())).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(stat(&service, &a, "max_keys").await, 2);
+    }
+
+    #[tokio::test]
+    async fn byte_quota_should_reject_large_writes() {
+        let configs = [NamespaceConfig {
+            max_bytes: Some(1024),
+            ..namespace("team-a", &[])
+        }];
+        let service = Service::new(MemTable::new()).with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+
+        let res = execute(
+            &service,
+            &a,
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        let used = stat(&service, &a, "used_bytes").await;
+        assert_eq!(used as u64, entry_size("k1", &"v1".into()));
+
+        let big = Value::from("x".repeat(1024));
+        let res = execute(&service, &a, CommandRequest::new_hset("t1", "k2", big)).await;
+        assert_res_error(&res, 507, "namespace team-a is limited to 1024 bytes");
+        let values = vec![Value::from("x".repeat(512)); 2];
+        let res = execute(&service, &a, CommandRequest::new_lpush("t1", "l1", values)).await;
+        assert_eq!(res.status, 507);
+        assert_eq!(stat(&service, &a, "used_bytes").await, used);
+
+        // 删除总是可以执行
+        let res = execute(&service, &a, CommandRequest::new_hdel("t1", "k1")).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(stat(&service, &a, "used_bytes").await, 0);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_should_be_shared_by_namespace() {
+        let configs = [NamespaceConfig {
+            rate_limit: Some(RateLimitConfig {
+                requests_per_second: 1,
+                burst: Some(2),
+            }),
+            ..namespace("team-a", &[])
+        }];
+        let service = Service::new(MemTable::new()).with_namespaces(&configs);
+        let conn1 = ctx(None, Some("team-a"));
+        let conn2 = ctx(None, Some("team-a"));
+
+        let cmd = || CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_eq!(execute(&service, &conn1, cmd()).await.status, 200);
+        assert_eq!(execute(&service, &conn2, cmd()).await.status, 200);
+        let res = execute(&service, &conn1, cmd()).await;
+        assert_res_error(&res, 429, "of namespace team-a");
+
+        // 没有绑定命名空间的连接不受影响
+        assert_eq!(execute(&service, &ctx(None, None), cmd()).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn usage_should_be_loaded_from_store() {
+        let store = get_sled_store();
+        store.set("team-a/t1", "k1".into(), "v1".into()).unwrap();
+        store.set("team-a/t2", "k2".into(), "v2".into()).unwrap();
+        store.set("team-b/t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        let configs = [namespace("team-a", &[]), namespace("team-b", &[])];
+        let service = Service::new(store).with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+
+        assert_eq!(stat(&service, &a, "keys").await, 2);
+        let bytes = entry_size("k1", &"v1".into()) + entry_size("k2", &"v2".into());
+        assert_eq!(stat(&service, &a, "used_bytes").await as u64, bytes);
+        assert_eq!(stat(&service, &ctx(None, Some("team-b")), "keys").await, 1);
+    }
+
+    /// 直接从存储里统计命名空间的用量
+    fn scan_usage(service: &Service, ns: &str) -> (i64, i64) {
+        let (mut keys, mut bytes) = (0, 0);
+        for table in service.store.tables().unwrap() {
+            if !table.starts_with(&format!("{}/", ns)) {
+                continue;
+            }
+            for pair in service.store.get_iter(&table).unwrap() {
+                keys += 1;
+                bytes += entry_size(&pair.key, &pair.value.unwrap_or_default()) as i64;
+            }
+        }
+        (keys, bytes)
+    }
+
+    #[tokio::test]
+    async fn evicted_keys_should_be_subtracted_from_usage() {
+        let store = MemTable::with_memory_limit(4096, EvictionPolicy::Lru);
+        let configs = [namespace("team-a", &[])];
+        let service = Service::new(store).with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+
+        for i in 0..100 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), "v".into());
+            assert_eq!(execute(&service, &a, cmd).await.status, 200);
+        }
+
+        let (keys, bytes) = scan_usage(&service, "team-a");
+        assert!(keys < 100);
+        assert_eq!(stat(&service, &a, "keys").await, keys);
+        assert_eq!(stat(&service, &a, "used_bytes").await, bytes);
+    }
+
+    /// 往 team-a/t 里写入 k = "x"
+    const WRITE_TEAM_A: &str = r#"
+        (module
+          (import "kv" "set" (func $set (param i32 i32 i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "team-a/tk\0a\01x")
+          (func (export "run")
+            (call $set (i32.const 0) (i32.const 8) (i32.const 8) (i32.const 1)
+                       (i32.const 9) (i32.const 3))))
+    "#;
+
+    #[tokio::test]
+    async fn unbound_eval_should_not_reach_namespace() {
+        let script = wat::parse_str(WRITE_TEAM_A).unwrap();
+        let cmd = || CommandRequest::new_eval(script.clone(), vec![]);
+
+        // 没有命名空间时脚本可以写入这个 table
+        let service = Service::new(MemTable::new()).with_scripting(ScriptLimits::default());
+        assert_eq!(execute(&service, &ctx(None, None), cmd()).await.status, 200);
+        assert!(service.store.contains("team-a/t", "k").unwrap());
+
+        let configs = [namespace("team-a", &[])];
+        let service = Service::new(MemTable::new())
+            .with_scripting(ScriptLimits::default())
+            .with_namespaces(&configs);
+        let res = execute(&service, &ctx(None, None), cmd()).await;
+        assert_res_error(&res, 403, "eval is not allowed when namespaces are enabled");
+        assert!(!service.store.contains("team-a/t", "k").unwrap());
+
+        let res = execute(
+            &service,
+            &ctx(None, None),
+            CommandRequest::new_script_load(script.clone()),
+        )
+        .await;
+        assert_eq!(res.status, 403);
+    }
+
+    #[tokio::test]
+    async fn unbound_backup_and_restore_should_be_denied() {
+        let dir = tempfile::tempdir().unwrap();
+        let record = r#"{"key":"k","table":"team-a/t","value":{"string":"x"}}"#;
+        std::fs::write(dir.path().join("evil.jsonl"), format!("{}\n", record)).unwrap();
+
+        let configs = [namespace("team-a", &[])];
+        let service = Service::new(MemTable::new())
+            .with_backup_dir(dir.path())
+            .with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+        let admin = ctx(None, None);
+        execute(&service, &a, CommandRequest::new_hset("t", "k", "a".into())).await;
+
+        let restore = CommandRequest::new_restore("evil.jsonl", "", true);
+        let res = execute(&service, &admin, restore).await;
+        assert_res_error(
+            &res,
+            403,
+            "restore is not allowed when namespaces are enabled",
+        );
+        let backup = CommandRequest::new_backup("dump.jsonl", "");
+        let res = execute(&service, &admin, backup).await;
+        assert_res_error(
+            &res,
+            403,
+            "backup is not allowed when namespaces are enabled",
+        );
+
+        let res = execute(&service, &a, CommandRequest::new_hget("t", "k")).await;
+        assert_res_ok(&res, &["a".into()], &[]);
+        assert!(!dir.path().join("dump.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn global_commands_should_be_denied_in_namespace() {
+        let configs = [namespace("team-a", &[])];
+        let service = Service::new(MemTable::new())
+            .with_backup_dir(std::env::temp_dir())
+            .with_namespaces(&configs);
+        let a = ctx(None, Some("team-a"));
+
+        let res = execute(&service, &a, CommandRequest::new_backup("daily", "json")).await;
+        assert_res_error(&res, 403, "backup is not allowed in namespace team-a");
+        let res = execute(&service, &a, CommandRequest::new_eval_hash("abc", vec![])).await;
+        assert_res_error(&res, 403, "eval is not allowed in namespace team-a");
+
+        // topic 也有命名空间
+        let res = execute(
+            &service,
+            &ctx(None, None),
+            CommandRequest::new_publish("team-a/news", vec![]),
+        )
+        .await;
+        assert_res_error(&res, 403, "team-a/news belongs to namespace team-a");
+        let mut cmd = CommandRequest::new_subscribe("news");
+        assert_eq!(scope_mut(&mut cmd).unwrap(), "news");
+        let res = execute(&service, &a, CommandRequest::new_publish("news", vec![])).await;
+        assert_eq!(res.status, 200);
+    }
+}
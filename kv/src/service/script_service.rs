@@ -0,0 +1,115 @@
+use crate::*;
+
+impl Service {
+    /// 执行脚本，调用者独占 write_gate，并且在阻塞线程池里执行
+    pub(crate) fn eval(&self, param: Eval) -> CommandResponse {
+        let result = self.script_engine().and_then(|engine| {
+            let hash = match param.script.is_empty() {
+                true => param.hash,
+                false => engine.load(&param.script)?,
+            };
+            engine.eval(&self.store, &hash, param.args)
+        });
+
+        match result {
+            Ok(values) => values.into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// 编译并缓存脚本，返回脚本的 hash
+    pub(crate) fn script_load(&self, param: ScriptLoad) -> CommandResponse {
+        match self
+            .script_engine()
+            .and_then(|engine| engine.load(&param.script))
+        {
+            Ok(hash) => Value::from(hash).into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn script_engine(&self) -> Result<&ScriptEngine, KvError> {
+        self.scripts.as_deref().ok_or_else(|| {
+            KvError::PermissionDenied(
+                "scripting is disabled, add a [script] section to the config to enable it".into(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::service::{assert_res_error, assert_res_ok};
+
+    /// 把 table t1 里 counter 的值设置为第一个参数，返回之前的值
+    const SWAP: &str = r#"
+        (module
+          (import "kv" "get" (func $get (param i32 i32 i32 i32) (result i32)))
+          (import "kv" "set" (func $set (param i32 i32 i32 i32 i32 i32)))
+          (import "kv" "args" (func $args (result i32)))
+          (import "kv" "read" (func $read (param i32)))
+          (import "kv" "output" (func $output (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "t1counter")
+          (func (export "run")
+            (local $old i32)
+            (local.set $old (call $get (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 7)))
+            (if (i32.ge_s (local.get $old) (i32.const 0))
+              (then (call $read (i32.const 2050))))
+            ;; 参数是 ValueList，第一个 Value 从第 2 个字节开始（假设长度小于 128）
+            (drop (call $args))
+            (call $read (i32.const 1024))
+            (call $set (i32.const 0) (i32.const 2) (i32.const 2) (i32.const 7)
+                       (i32.const 1026) (i32.load8_u (i32.const 1025)))
+            ;; 之前的值作为 ValueList 的第一个元素返回
+            (if (i32.ge_s (local.get $old) (i32.const 0))
+              (then
+                (i32.store8 (i32.const 2048) (i32.const 0x0a))
+                (i32.store8 (i32.const 2049) (local.get $old))
+                (call $output (i32.const 2048) (i32.add (local.get $old) (i32.const 2)))))))
+    "#;
+
+    async fn execute(service: &Service, cmd: CommandRequest) -> CommandResponse {
+        let res = service.execute(cmd).next().await.unwrap();
+        (*res).clone()
+    }
+
+    #[tokio::test]
+    async fn eval_should_run_cached_script_by_hash() {
+        let service = Service::new(MemTable::new()).with_scripting(ScriptLimits::default());
+        let script = wat::parse_str(SWAP).unwrap();
+
+        let res = execute(&service, CommandRequest::new_script_load(script.clone())).await;
+        let hash = script_hash(&script);
+        assert_res_ok(&res, &[hash.clone().into()], &[]);
+
+        let res = execute(
+            &service,
+            CommandRequest::new_eval_hash(&hash, vec![1.into()]),
+        )
+        .await;
+        assert_res_ok(&res, &[], &[]);
+
+        let res = execute(&service, CommandRequest::new_eval(script, vec![2.into()])).await;
+        assert_res_ok(&res, &[1.into()], &[]);
+        let res = execute(&service, CommandRequest::new_hget("t1", "counter")).await;
+        assert_res_ok(&res, &[2.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn eval_should_report_errors() {
+        let service = Service::new(MemTable::new());
+        let res = execute(&service, CommandRequest::new_eval_hash("abc", vec![])).await;
+        assert_res_error(&res, 403, "scripting is disabled");
+
+        let service = service.with_scripting(ScriptLimits::default());
+        let res = execute(&service, CommandRequest::new_eval_hash("abc", vec![])).await;
+        assert_res_error(&res, 404, "abc");
+
+        let res = execute(&service, CommandRequest::new_eval(&b"oops"[..], vec![])).await;
+        assert_res_error(&res, 400, "invalid script");
+    }
+}
@@ -61,7 +61,8 @@ mod tests {
         assert!(id > 0);
     }
 
-    #[tokio::test]
+    // 时钟暂停时，sleep 要等 publish 的 task 执行完才会返回，不依赖真实的时间
+    #[tokio::test(start_paused = true)]
     async fn dispatch_subscribe_abnormal_quit_should_be_removed_on_next_publish() {
         let topic = Arc::new(Broadcaster::default());
         let id = {
@@ -0,0 +1,319 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{value, KvError, Kvpair, Storage, Value, ValueList, ValueMap};
+
+// list、set 和 sorted set 在 table 里的表示见 abi.proto，所有的修改都通过 Storage::update 完成
+//
+// 整个集合保存在一个 value 里，每次修改都要读出、修改、再写回整个集合，开销和集合的大小成正比，
+// 所以它们适合比较小的集合，不适合用作很长的队列。
+
+const LIST: &str = "list";
+/// 也是 set 在 ValueMap 里的 kind
+const SET: &str = "set";
+/// 也是 sorted set 在 ValueMap 里的 kind
+const SORTED_SET: &str = "sorted set";
+
+pub(crate) fn push(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    values: Vec<Value>,
+    front: bool,
+) -> Result<u64, KvError> {
+    let mut len = 0;
+    store.update(table, key, &mut |v| {
+        let mut list = to_list(table, key, v)?;
+        if front {
+            // 依次插入到头部，所以最后一个值在最前面
+            list.splice(0..0, values.iter().rev().cloned());
+        } else {
+            list.extend(values.iter().cloned());
+        }
+        len = list.len() as u64;
+        Ok(from_list(list))
+    })?;
+    Ok(len)
+}
+
+pub(crate) fn pop(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    count: usize,
+    front: bool,
+) -> Result<Vec<Value>, KvError> {
+    let mut popped = vec![];
+    store.update(table, key, &mut |v| {
+        let mut list = to_list(table, key, v)?;
+        let count = count.min(list.len());
+        popped = if front {
+            list.drain(..count).collect()
+        } else {
+            list.drain(list.len() - count..).rev().collect()
+        };
+        Ok(from_list(list))
+    })?;
+    Ok(popped)
+}
+
+pub(crate) fn lrange(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    start: i64,
+    stop: i64,
+) -> Result<Vec<Value>, KvError> {
+    let list = to_list(table, key, store.get(table, key)?)?;
+    Ok(slice(&list, start, stop).to_vec())
+}
+
+pub(crate) fn sadd(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    members: &[String],
+) -> Result<u64, KvError> {
+    let mut added = 0;
+    store.update(table, key, &mut |v| {
+        let mut set = to_set(table, key, v)?;
+        let len = set.len();
+        set.extend(members.iter().cloned());
+        set.sort_unstable();
+        set.dedup();
+        added = (set.len() - len) as u64;
+        Ok(from_set(set))
+    })?;
+    Ok(added)
+}
+
+pub(crate) fn srem(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    members: &[String],
+) -> Result<u64, KvError> {
+    let mut removed = 0;
+    store.update(table, key, &mut |v| {
+        let mut set = to_set(table, key, v)?;
+        let members: HashSet<_> = members.iter().collect();
+        let len = set.len();
+        set.retain(|m| !members.contains(m));
+        removed = (len - set.len()) as u64;
+        Ok(from_set(set))
+    })?;
+    Ok(removed)
+}
+
+pub(crate) fn smembers(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+) -> Result<Vec<String>, KvError> {
+    to_set(table, key, store.get(table, key)?)
+}
+
+pub(crate) fn sinter(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    keys: &[String],
+) -> Result<Vec<String>, KvError> {
+    let mut keys = keys.iter();
+    let mut result = match keys.next() {
+        Some(key) => smembers(store, table, key)?,
+        None => return Ok(vec![]),
+    };
+    for key in keys {
+        let set = smembers(store, table, key)?;
+        result.retain(|m| set.binary_search(m).is_ok());
+    }
+    Ok(result)
+}
+
+pub(crate) fn zadd(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    members: &[(String, f64)],
+) -> Result<u64, KvError> {
+    if let Some((m, _)) = members.iter().find(|(_, score)| score.is_nan()) {
+        return Err(KvError::InvalidCommand(format!(
+            "score of {} is not a number",
+            m
+        )));
+    }
+
+    let mut added = 0;
+    store.update(table, key, &mut |v| {
+        // 用 member 索引合并，再整体按 score 排序
+        let mut index: HashMap<_, _> = to_sorted_set(table, key, v)?.into_iter().collect();
+        let len = index.len();
+        for (member, score) in members {
+            index.insert(member.clone(), *score);
+        }
+        added = (index.len() - len) as u64;
+
+        let mut zset: Vec<_> = index.into_iter().collect();
+        zset.sort_unstable_by(|a, b| by_score(a, (&b.0, b.1)));
+        Ok(from_sorted_set(zset))
+    })?;
+    Ok(added)
+}
+
+pub(crate) fn zrange(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    start: i64,
+    stop: i64,
+) -> Result<Vec<(String, f64)>, KvError> {
+    let zset = to_sorted_set(table, key, store.get(table, key)?)?;
+    Ok(slice(&zset, start, stop).to_vec())
+}
+
+pub(crate) fn zrange_by_score(
+    store: &(impl Storage + ?Sized),
+    table: &str,
+    key: &str,
+    min: f64,
+    max: f64,
+) -> Result<Vec<(String, f64)>, KvError> {
+    let zset = to_sorted_set(table, key, store.get(table, key)?)?;
+    Ok(zset
+        .into_iter()
+        .filter(|(_, score)| *score >= min && *score <= max)
+        .collect())
+}
+
+/// 按 score 排序，score 相同时按 member 排序
+fn by_score(a: &(String, f64), b: (&String, f64)) -> Ordering {
+    a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0))
+}
+
+/// 下标在 [start, stop] 之间的元素，负数从尾部开始计算，超出范围的部分被忽略
+fn slice<T>(items: &[T], start: i64, stop: i64) -> &[T] {
+    let len = items.len() as i64;
+    let index = |i: i64| if i < 0 { len + i } else { i };
+    let start = index(start).max(0);
+    let stop = index(stop).min(len - 1);
+    if start > stop {
+        return &[];
+    }
+    &items[start as usize..=stop as usize]
+}
+
+fn wrong_type(table: &str, key: &str, expected: &'static str) -> KvError {
+    KvError::WrongType(table.into(), key.into(), expected)
+}
+
+fn to_list(table: &str, key: &str, v: Option<Value>) -> Result<Vec<Value>, KvError> {
+    match v.and_then(|v| v.value) {
+        None => Ok(vec![]),
+        Some(value::Value::List(l)) => Ok(l.values),
+        Some(_) => Err(wrong_type(table, key, LIST)),
+    }
+}
+
+fn from_list(values: Vec<Value>) -> Option<Value> {
+    (!values.is_empty()).then(|| Value {
+        value: Some(value::Value::List(ValueList { values })),
+    })
+}
+
+/// 读出 kind 为 expected 的 map，普通的 map 和其它类型的集合都会报错
+fn to_map(
+    table: &str,
+    key: &str,
+    v: Option<Value>,
+    expected: &'static str,
+) -> Result<Vec<Kvpair>, KvError> {
+    match v.and_then(|v| v.value) {
+        None => Ok(vec![]),
+        Some(value::Value::Map(m)) if m.kind == expected => Ok(m.pairs),
+        Some(_) => Err(wrong_type(table, key, expected)),
+    }
+}
+
+fn to_set(table: &str, key: &str, v: Option<Value>) -> Result<Vec<String>, KvError> {
+    to_map(table, key, v, SET)?
+        .into_iter()
+        .map(|p| match p.value.and_then(|v| v.value) {
+            Some(value::Value::Null(_)) => Ok(p.key),
+            _ => Err(wrong_type(table, key, SET)),
+        })
+        .collect()
+}
+
+fn from_set(members: Vec<String>) -> Option<Value> {
+    let pairs = members
+        .into_iter()
+        .map(|m| Kvpair::new(m, Value::null()))
+        .collect();
+    from_map(pairs, SET)
+}
+
+fn to_sorted_set(table: &str, key: &str, v: Option<Value>) -> Result<Vec<(String, f64)>, KvError> {
+    to_map(table, key, v, SORTED_SET)?
+        .into_iter()
+        .map(|p| match p.value.and_then(|v| v.value) {
+            Some(value::Value::Float(score)) => Ok((p.key, score)),
+            _ => Err(wrong_type(table, key, SORTED_SET)),
+        })
+        .collect()
+}
+
+fn from_sorted_set(members: Vec<(String, f64)>) -> Option<Value> {
+    let pairs = members
+        .into_iter()
+        .map(|(m, score)| Kvpair::new(m, score.into()))
+        .collect();
+    from_map(pairs, SORTED_SET)
+}
+
+fn from_map(pairs: Vec<Kvpair>, kind: &str) -> Option<Value> {
+    (!pairs.is_empty()).then(|| Value {
+        value: Some(value::Value::Map(ValueMap {
+            pairs,
+            kind: kind.into(),
+        })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_should_handle_negative_and_out_of_range_index() {
+        let items = [0, 1, 2, 3, 4];
+        assert_eq!(slice(&items, 0, -1), &[0, 1, 2, 3, 4]);
+        assert_eq!(slice(&items, 1, 2), &[1, 2]);
+        assert_eq!(slice(&items, -2, -1), &[3, 4]);
+        assert_eq!(slice(&items, -100, 100), &[0, 1, 2, 3, 4]);
+        assert_eq!(slice(&items, 3, 1), &[] as &[i32]);
+        assert_eq!(slice(&items, 5, 10), &[] as &[i32]);
+        assert_eq!(slice(&[] as &[i32], 0, -1), &[] as &[i32]);
+    }
+
+    #[test]
+    fn set_and_sorted_set_should_not_be_mixed() {
+        let set = from_set(vec!["a".into()]);
+        let zset = from_sorted_set(vec![("a".into(), 1.0)]);
+
+        assert_eq!(to_set("t", "k", set.clone()), Ok(vec!["a".to_string()]));
+        assert_eq!(
+            to_sorted_set("t", "k", set),
+            Err(wrong_type("t", "k", SORTED_SET))
+        );
+        assert_eq!(to_set("t", "k", zset), Err(wrong_type("t", "k", SET)));
+        // 形状相同的普通 map 也不是集合
+        let map: Value = vec![Kvpair::new("a", Value::null())].into();
+        assert_eq!(to_set("t", "k", Some(map)), Err(wrong_type("t", "k", SET)));
+        assert_eq!(
+            to_list("t", "k", Some("hello".into())),
+            Err(wrong_type("t", "k", LIST))
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use prost::Message;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    value, DumpRecord, KvError, Kvpair, Null, Storage, Value, ValueList, ValueMap,
+    DEFAULT_MAX_FRAME,
+};
+
+/// 备份文件的格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// 每行一个 JSON 对象，方便查看，也方便用其他工具处理
+    Jsonl,
+    /// 一组 length-delimited 的 DumpRecord，更紧凑
+    Protobuf,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsonl" | "json" => Some(Self::Jsonl),
+            "protobuf" | "pb" => Some(Self::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Protobuf => "protobuf",
+        }
+    }
+
+    /// format 为空时根据文件扩展名决定：`.jsonl` 和 `.json` 是 JSONL，其他的是 protobuf
+    pub fn detect(format: &str, path: impl AsRef<Path>) -> Result<Self, KvError> {
+        if !format.is_empty() {
+            return Self::from_name(format).ok_or_else(|| {
+                KvError::InvalidCommand(format!("unknown dump format `{}`", format))
+            });
+        }
+
+        let ext = path.as_ref().extension().and_then(|ext| ext.to_str());
+        Ok(ext.and_then(Self::from_name).unwrap_or(Self::Protobuf))
+    }
+}
+
+/// 把 store 里所有的 table 写到 writer，返回写入的 kvpair 数量
+pub fn export(
+    store: &dyn Storage,
+    mut writer: impl Write,
+    format: DumpFormat,
+) -> Result<u64, KvError> {
+    let mut tables = store.tables()?;
+    tables.sort();
+
+    let mut count = 0;
+    for table in tables {
+        for pair in store.get_iter(&table)? {
+            let record = DumpRecord {
+                table: table.clone(),
+                pair: Some(pair),
+            };
+            match format {
+                DumpFormat::Jsonl => {
+                    writer.write_all(record_to_json(&record).to_string().as_bytes())?;
+                    writer.write_all(b"\n")?;
+                }
+                DumpFormat::Protobuf => {
+                    writer.write_all(&record.encode_length_delimited_to_vec())?
+                }
+            }
+            count += 1;
+        }
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// 把 reader 里的数据写入 store，已有的 key 会被覆盖，返回写入的 kvpair 数量
+///
+/// 先读出所有的记录，有不合法的记录时不会写入任何数据。
+pub fn import(
+    store: &dyn Storage,
+    reader: impl BufRead,
+    format: DumpFormat,
+) -> Result<u64, KvError> {
+    apply_dump(store, read_dump(reader, format)?)
+}
+
+/// 读出 reader 里所有的记录
+pub fn read_dump(mut reader: impl BufRead, format: DumpFormat) -> Result<Vec<DumpRecord>, KvError> {
+    let mut records = vec![];
+    loop {
+        let record = match format {
+            DumpFormat::Jsonl => {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    break;
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                record_from_json(&line).map_err(|e| {
+                    KvError::DumpError(format!("record {}: {}", records.len() + 1, e))
+                })?
+            }
+            DumpFormat::Protobuf => match read_record(&mut reader)? {
+                Some(record) => record,
+                None => break,
+            },
+        };
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// 把读出来的记录写入 store，返回写入的 kvpair 数量
+pub fn apply_dump(store: &dyn Storage, records: Vec<DumpRecord>) -> Result<u64, KvError> {
+    let count = records.len() as u64;
+    for record in records {
+        let pair = record.pair.unwrap_or_default();
+        store.set(&record.table, pair.key, pair.value.unwrap_or_default())?;
+    }
+
+    Ok(count)
+}
+
+/// 删除所有 table 里的所有 key
+pub fn clear(store: &dyn Storage) -> Result<(), KvError> {
+    for table in store.tables()? {
+        for pair in store.get_iter(&table)? {
+            store.del(&table, &pair.key)?;
+        }
+    }
+    Ok(())
+}
+
+/// 读取一个 length-delimited 的 DumpRecord，读到结尾时返回 None
+///
+/// 记录的长度不能超过 DEFAULT_MAX_FRAME，避免损坏的文件导致分配巨大的内存。
+fn read_record(reader: &mut impl BufRead) -> Result<Option<DumpRecord>, KvError> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    // varint 编码的长度，最多 10 个字节
+    let mut len = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            if len > DEFAULT_MAX_FRAME as u64 {
+                return Err(KvError::FrameError);
+            }
+            let mut buf = vec![0; len as usize];
+            reader.read_exact(&mut buf)?;
+            return Ok(Some(DumpRecord::decode(&buf[..])?));
+        }
+    }
+
+    Err(KvError::DumpError("invalid record length".into()))
+}
+
+fn record_to_json(record: &DumpRecord) -> Json {
+    let pair = record.pair.clone().unwrap_or_default();
+    json!({
+        "table": record.table,
+        "key": pair.key,
+        "value": value_to_json(&pair.value.unwrap_or_default()),
+    })
+}
+
+fn record_from_json(line: &str) -> Result<DumpRecord, String> {
+    let json: Json = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let field = |name: &str| {
+        json.get(name)
+            .ok_or_else(|| format!("missing field `{}`", name))
+    };
+    let string = |name: &str| {
+        field(name)?
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| format!("`{}` must be a string", name))
+    };
+
+    Ok(DumpRecord {
+        table: string("table")?,
+        pair: Some(Kvpair::new(
+            string("key")?,
+            value_from_json(field("value")?)?,
+        )),
+    })
+}
+
+/// 带类型的 JSON，比如 `{"integer": 1}`，这样导入时能还原出同样的类型
+fn value_to_json(v: &Value) -> Json {
+    match &v.value {
+        Some(value::Value::String(s)) => json!({ "string": s }),
+        Some(value::Value::Binary(b)) => json!({ "binary": STANDARD.encode(b) }),
+        Some(value::Value::Integer(i)) => json!({ "integer": i }),
+        // JSON 不能表示 NaN 和无穷大，用字符串保存
+        Some(value::Value::Float(f)) if !f.is_finite() => json!({ "float": f.to_string() }),
+        Some(value::Value::Float(f)) => json!({ "float": f }),
+        Some(value::Value::Bool(b)) => json!({ "bool": b }),
+        Some(value::Value::List(l)) => {
+            let values: Vec<_> = l.values.iter().map(value_to_json).collect();
+            json!({ "list": values })
+        }
+        // 用 [key, value] 的数组保持 map 的顺序，集合用 kind 作为类型
+        Some(value::Value::Map(m)) => {
+            let pairs: Vec<_> = m
+                .pairs
+                .iter()
+                .map(|p| json!([p.key, value_to_json(&p.value.clone().unwrap_or_default())]))
+                .collect();
+            let ty = if m.kind.is_empty() { "map" } else { &m.kind };
+            json!({ ty: pairs })
+        }
+        Some(value::Value::Null(_)) => json!({ "null": null }),
+        None => json!({}),
+    }
+}
+
+fn value_from_json(json: &Json) -> Result<Value, String> {
+    let obj = json.as_object().ok_or("value must be an object")?;
+    let (ty, v) = match obj.len() {
+        0 => return Ok(Value::default()),
+        1 => obj.iter().next().unwrap(),
+        _ => return Err("value must have exactly one type".into()),
+    };
+    let invalid = || format!("invalid {} value: {}", ty, v);
+
+    let value = match ty.as_str() {
+        "string" => value::Value::String(v.as_str().ok_or_else(invalid)?.into()),
+        "binary" => {
+            let data = STANDARD
+                .decode(v.as_str().ok_or_else(invalid)?)
+                .map_err(|_| invalid())?;
+            value::Value::Binary(data.into())
+        }
+        "integer" => value::Value::Integer(v.as_i64().ok_or_else(invalid)?),
+        "float" => match v {
+            Json::String(s) => value::Value::Float(s.parse().map_err(|_| invalid())?),
+            v => value::Value::Float(v.as_f64().ok_or_else(invalid)?),
+        },
+        "bool" => value::Value::Bool(v.as_bool().ok_or_else(invalid)?),
+        "list" => {
+            let values = v.as_array().ok_or_else(invalid)?;
+            let values = values
+                .iter()
+                .map(value_from_json)
+                .collect::<Result<_, _>>()?;
+            value::Value::List(ValueList { values })
+        }
+        "map" | "set" | "sorted set" => {
+            let mut pairs = vec![];
+            for pair in v.as_array().ok_or_else(invalid)? {
+                match pair.as_array().map(|p| p.as_slice()) {
+                    Some([Json::String(k), v]) => pairs.push(Kvpair::new(k, value_from_json(v)?)),
+                    _ => return Err(invalid()),
+                }
+            }
+            let kind = if ty == "map" { "" } else { ty };
+            value::Value::Map(ValueMap {
+                pairs,
+                kind: kind.into(),
+            })
+        }
+        "null" => value::Value::Null(Null {}),
+        _ => return Err(format!("unknown value type `{}`", ty)),
+    };
+
+    Ok(Value { value: Some(value) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_sled_store, MemTable};
+
+    fn values() -> Vec<Value> {
+        vec![
+            "hello".into(),
+            vec![0u8, 255].into(),
+            42.into(),
+            1.5.into(),
+            f64::INFINITY.into(),
+            true.into(),
+            Value::null(),
+            Value::default(),
+            vec![Value::from("a"), 1.into()].into(),
+            vec![
+                Kvpair::new("z", "last".into()),
+                Kvpair::new("a", vec![Value::null()].into()),
+            ]
+            .into(),
+        ]
+    }
+
+    fn fill(store: &dyn Storage) {
+        for (i, v) in values().into_iter().enumerate() {
+            store.set("t1", format!("k{}", i), v).unwrap();
+        }
+        store.set("t2", "hello".into(), "world".into()).unwrap();
+        // 集合的类型也要保留下来
+        store.sadd("t2", "set", &["a".into()]).unwrap();
+        store.zadd("t2", "zset", &[("a".into(), 1.0)]).unwrap();
+    }
+
+    fn assert_same(from: &dyn Storage, to: &dyn Storage) {
+        for table in ["t1", "t2"] {
+            let mut expected = from.get_all(table).unwrap();
+            let mut actual = to.get_all(table).unwrap();
+            expected.sort_by(|a, b| a.key.cmp(&b.key));
+            actual.sort_by(|a, b| a.key.cmp(&b.key));
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn dump_should_roundtrip_across_backends() {
+        for format in [DumpFormat::Jsonl, DumpFormat::Protobuf] {
+            let sled = get_sled_store();
+            fill(&sled);
+
+            let mut buf = vec![];
+            let count = export(&sled, &mut buf, format).unwrap();
+            assert_eq!(count, values().len() as u64 + 3);
+
+            // sled 导出的数据可以导入到 MemTable
+            let memory = MemTable::new();
+            assert_eq!(import(&memory, &buf[..], format).unwrap(), count);
+            assert_same(&sled, &memory);
+            assert_eq!(memory.smembers("t2", "set").unwrap(), vec!["a"]);
+            assert_eq!(memory.zadd("t2", "zset", &[("b".into(), 2.0)]).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn jsonl_should_be_readable() {
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), 42.into()).unwrap();
+
+        let mut buf = vec![];
+        export(&store, &mut buf, DumpFormat::Jsonl).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "{\"key\":\"k1\",\"table\":\"t1\",\"value\":{\"integer\":42}}\n"
+        );
+    }
+
+    #[test]
+    fn import_should_report_bad_record() {
+        let store = MemTable::new();
+        let data =
+            "{\"table\":\"t1\",\"key\":\"k1\",\"value\":{\"integer\":1}}\n{\"table\":\"t1\"}\n";
+        let result = import(&store, data.as_bytes(), DumpFormat::Jsonl);
+
+        assert_eq!(
+            result,
+            Err(KvError::DumpError("record 2: missing field `key`".into()))
+        );
+        // 有不合法的记录时什么都不写入
+        assert!(store.get_all("t1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn import_should_reject_oversized_record() {
+        // varint 编码的 u32::MAX
+        let data = [0xff, 0xff, 0xff, 0xff, 0x0f];
+        let result = import(&MemTable::new(), &data[..], DumpFormat::Protobuf);
+        assert_eq!(result, Err(KvError::FrameError));
+    }
+
+    #[test]
+    fn clear_should_remove_all_keys() {
+        let store = MemTable::new();
+        fill(&store);
+        clear(&store).unwrap();
+
+        assert!(store.get_all("t1").unwrap().is_empty());
+        assert!(store.get_all("t2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn format_should_be_detected_by_extension() {
+        let detect = |format, path| DumpFormat::detect(format, path).unwrap();
+        assert_eq!(detect("", "backup.jsonl"), DumpFormat::Jsonl);
+        assert_eq!(detect("", "backup.pb"), DumpFormat::Protobuf);
+        assert_eq!(detect("", "backup"), DumpFormat::Protobuf);
+        assert_eq!(detect("jsonl", "backup.pb"), DumpFormat::Jsonl);
+        assert!(DumpFormat::detect("xml", "backup").is_err());
+    }
+}
@@ -1,10 +1,65 @@
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt,
+    hash::BuildHasher,
+    sync::{Mutex, RwLock},
+};
+
+use crate::{EvictionListener, KvError, Kvpair, Storage, StorageIter, Value};
+use dashmap::{
+    mapref::{entry::Entry as MapEntry, one::Ref},
+    DashMap,
+};
+use serde::{Deserialize, Serialize};
+
+/// 每个 key 除了 key 和 value 本身之外大约占用的内存：DashMap 的槽位和淘汰用的元数据
+const ENTRY_OVERHEAD: usize = 96;
 
 /// 使用 DashMap 构建的 MemTable，实现了 Storage trait
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
+    /// 内存上限，None 代表不限制
+    budget: Option<Mutex<Budget>>,
+    on_evict: OnEvict,
+}
+
+/// 被淘汰的 (table, key, value)
+type Evicted = Vec<(String, String, Value)>;
+
+/// 淘汰 key 时的回调，clone 出来的 MemTable 不会继承
+#[derive(Default)]
+struct OnEvict(RwLock<Option<EvictionListener>>);
+
+impl fmt::Debug for OnEvict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self.0.read().unwrap().is_some();
+        f.debug_tuple("OnEvict").field(&set).finish()
+    }
+}
+
+/// 超过内存上限时选择淘汰哪个 key
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// 淘汰最久没有被访问的 key
+    #[default]
+    Lru,
+    /// 淘汰访问次数最少的 key，次数相同时淘汰最久没有被访问的
+    Lfu,
+    /// 随机淘汰
+    Random,
+}
+
+/// MemTable 的内存使用情况
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemTableStats {
+    pub keys: u64,
+    /// 估算的内存占用
+    pub used_bytes: u64,
+    /// 0 代表不限制
+    pub max_bytes: u64,
+    /// 因为超过内存上限被淘汰的 key 数量
+    pub evictions: u64,
 }
 
 impl MemTable {
@@ -13,8 +68,51 @@ impl MemTable {
         Self::default()
     }
 
+    /// 创建一个最多使用 max_bytes 内存的 MemTable，超过时按 policy 淘汰所有 table 里的 key
+    pub fn with_memory_limit(max_bytes: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            tables: DashMap::new(),
+            budget: Some(Mutex::new(Budget::new(max_bytes, policy))),
+            on_evict: OnEvict::default(),
+        }
+    }
+
+    pub fn stats(&self) -> MemTableStats {
+        let keys = self.tables.iter().map(|t| t.len() as u64).sum();
+        match &self.budget {
+            Some(budget) => {
+                let budget = budget.lock().unwrap();
+                MemTableStats {
+                    keys,
+                    used_bytes: budget.used as u64,
+                    max_bytes: budget.max_bytes as u64,
+                    evictions: budget.evictions,
+                }
+            }
+            // 不限制内存时没有实时统计，现场计算一次
+            None => {
+                let used_bytes = self
+                    .tables
+                    .iter()
+                    .flat_map(|t| {
+                        let name = t.key().clone();
+                        t.value()
+                            .iter()
+                            .map(|v| entry_size(&name, v.key(), v.value()) as u64)
+                            .collect::<Vec<_>>()
+                    })
+                    .sum();
+                MemTableStats {
+                    keys,
+                    used_bytes,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
     /// 如果名为 name 的 hash table 不存在，则创建，否则返回
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    fn get_or_create_table(&self, name: &str) -> Ref<'_, String, DashMap<String, Value>> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -23,17 +121,99 @@ impl MemTable {
             }
         }
     }
+
+    /// 有内存上限时写入或者删除一个 key，返回旧的 value 和被淘汰的 key
+    ///
+    /// 调用者需要持有 budget 的锁，写操作都在这把锁里进行，这样元数据和数据保持一致。
+    /// 被淘汰的 key 要在释放锁之后交给 notify_evicted。
+    fn write_with_budget(
+        &self,
+        budget: &mut Budget,
+        table: &str,
+        key: String,
+        value: Option<Value>,
+    ) -> Result<(Option<Value>, Evicted), KvError> {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                budget.remove(table, &key);
+                let old = self
+                    .get_or_create_table(table)
+                    .remove(&key)
+                    .map(|(_k, v)| v);
+                return Ok((old, vec![]));
+            }
+        };
+
+        let size = entry_size(table, &key, &value);
+        if size > budget.max_bytes {
+            return Err(KvError::StorageError(
+                "set",
+                table.into(),
+                key,
+                format!(
+                    "value needs {} bytes, more than the memory limit {}",
+                    size, budget.max_bytes
+                ),
+            ));
+        }
+
+        budget.insert(table, &key, size);
+        let evicted = budget.evict(table, &key);
+        let old = self.get_or_create_table(table).insert(key, value);
+
+        let evicted = evicted
+            .into_iter()
+            .filter_map(|(table, key)| {
+                let (key, value) = self.tables.get(&table)?.remove(&key)?;
+                Some((table, key, value))
+            })
+            .collect();
+
+        Ok((old, evicted))
+    }
+
+    /// 在 budget 的锁之外调用淘汰的回调，回调里可以再访问 MemTable
+    fn notify_evicted(&self, evicted: Evicted) {
+        if evicted.is_empty() {
+            return;
+        }
+        let on_evict = self.on_evict.0.read().unwrap().clone();
+        if let Some(f) = on_evict {
+            for (table, key, value) in evicted {
+                f(&table, &key, &value);
+            }
+        }
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let value = self
+            .get_or_create_table(table)
+            .get(key)
+            .map(|v| v.value().clone());
+
+        // 读取不等待 budget 的锁，这样读操作不会被串行化。锁被占用时这次读取不计入
+        // 淘汰的顺序，所以 LRU / LFU 是近似的
+        if let (Some(budget), Some(_)) = (&self.budget, &value) {
+            if let Ok(mut budget) = budget.try_lock() {
+                budget.touch(table, key);
+            }
+        }
+        Ok(value)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+        match &self.budget {
+            Some(budget) => {
+                let (old, evicted) =
+                    self.write_with_budget(&mut budget.lock().unwrap(), table, key, Some(value))?;
+                self.notify_evicted(evicted);
+                Ok(old)
+            }
+            None => Ok(self.get_or_create_table(table).insert(key, value)),
+        }
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
@@ -42,8 +222,18 @@ impl Storage for MemTable {
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        match &self.budget {
+            Some(budget) => {
+                let mut budget = budget.lock().unwrap();
+                Ok(self
+                    .write_with_budget(&mut budget, table, key.into(), None)?
+                    .0)
+            }
+            None => {
+                let table = self.get_or_create_table(table);
+                Ok(table.remove(key).map(|(_k, v)| v))
+            }
+        }
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
@@ -61,4 +251,390 @@ impl Storage for MemTable {
 
         Ok(Box::new(iter))
     }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.tables.iter().map(|t| t.key().clone()).collect())
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<(), KvError> {
+        if let Some(budget) = &self.budget {
+            let evicted = {
+                let mut budget = budget.lock().unwrap();
+                let old = self
+                    .get_or_create_table(table)
+                    .get(key)
+                    .map(|v| v.value().clone());
+                let new = f(old)?;
+                self.write_with_budget(&mut budget, table, key.into(), new)?
+                    .1
+            };
+            self.notify_evicted(evicted);
+            return Ok(());
+        }
+
+        // 不限制内存时用 DashMap 的 entry 锁住这个 key
+        let t = self.get_or_create_table(table);
+        match t.entry(key.into()) {
+            MapEntry::Occupied(mut entry) => match f(Some(entry.get().clone()))? {
+                Some(v) => {
+                    entry.insert(v);
+                }
+                None => {
+                    entry.remove();
+                }
+            },
+            MapEntry::Vacant(entry) => {
+                if let Some(v) = f(None)? {
+                    entry.insert(v);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> Vec<Kvpair> {
+        let stats = MemTable::stats(self);
+        vec![
+            Kvpair::new("keys", (stats.keys as i64).into()),
+            Kvpair::new("used_bytes", (stats.used_bytes as i64).into()),
+            Kvpair::new("max_bytes", (stats.max_bytes as i64).into()),
+            Kvpair::new("evictions", (stats.evictions as i64).into()),
+        ]
+    }
+
+    fn set_eviction_listener(&self, listener: EvictionListener) {
+        *self.on_evict.0.write().unwrap() = Some(listener);
+    }
+}
+
+/// 一个 key 大约占用的内存，元数据和淘汰顺序里各保存了一份 table 和 key
+fn entry_size(table: &str, key: &str, value: &Value) -> usize {
+    ENTRY_OVERHEAD + key.len() + 2 * (table.len() + key.len()) + value.approx_size()
+}
+
+impl Clone for MemTable {
+    fn clone(&self) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            budget: self
+                .budget
+                .as_ref()
+                .map(|budget| Mutex::new(budget.lock().unwrap().clone())),
+            on_evict: OnEvict::default(),
+        }
+    }
+}
+
+/// 内存上限和淘汰用的元数据
+#[derive(Clone, Debug)]
+struct Budget {
+    max_bytes: usize,
+    policy: EvictionPolicy,
+    used: usize,
+    evictions: u64,
+    /// 每次访问加一，用来比较访问的先后
+    clock: u64,
+    entries: HashMap<String, HashMap<String, Entry>>,
+    /// 按 rank 排序，第一个最先被淘汰
+    order: BTreeMap<(u64, u64), (String, String)>,
+    random: RandomState,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    size: usize,
+    hits: u64,
+    rank: (u64, u64),
+}
+
+impl Budget {
+    fn new(max_bytes: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            max_bytes,
+            policy,
+            used: 0,
+            evictions: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            random: RandomState::new(),
+        }
+    }
+
+    /// LRU 按最后访问的时间，LFU 按访问次数，Random 在插入时随机生成
+    fn next_rank(&mut self, hits: u64) -> (u64, u64) {
+        self.clock += 1;
+        match self.policy {
+            EvictionPolicy::Lru => (self.clock, 0),
+            EvictionPolicy::Lfu => (hits, self.clock),
+            EvictionPolicy::Random => (self.random.hash_one(self.clock), self.clock),
+        }
+    }
+
+    /// 记录一次读取
+    fn touch(&mut self, table: &str, key: &str) {
+        if self.policy == EvictionPolicy::Random {
+            return;
+        }
+        let hits = match self.entries.get(table).and_then(|t| t.get(key)) {
+            Some(entry) => entry.hits + 1,
+            // 刚刚被淘汰或者删除了
+            None => return,
+        };
+        let rank = self.next_rank(hits);
+        let entry = self.entries.get_mut(table).unwrap().get_mut(key).unwrap();
+        let old = std::mem::replace(&mut entry.rank, rank);
+        entry.hits = hits;
+        let name = self.order.remove(&old).unwrap();
+        self.order.insert(rank, name);
+    }
+
+    /// 记录一次写入，覆盖已有的 key 也算一次访问
+    fn insert(&mut self, table: &str, key: &str, size: usize) {
+        let hits = self.remove(table, key).map(|e| e.hits).unwrap_or(0) + 1;
+        let rank = self.next_rank(hits);
+        self.entries
+            .entry(table.into())
+            .or_default()
+            .insert(key.into(), Entry { size, hits, rank });
+        self.order.insert(rank, (table.into(), key.into()));
+        self.used += size;
+    }
+
+    fn remove(&mut self, table: &str, key: &str) -> Option<Entry> {
+        let entry = self.entries.get_mut(table)?.remove(key)?;
+        self.order.remove(&entry.rank);
+        self.used -= entry.size;
+        Some(entry)
+    }
+
+    /// 超过上限时按顺序淘汰，返回被淘汰的 key
+    ///
+    /// 刚写入的 key 不会被淘汰，否则 LFU 下新 key 的访问次数最少，写入之后马上就会被淘汰。
+    /// 写入前已经保证了单个 key 不超过上限，所以淘汰掉其它的 key 总能腾出空间。
+    fn evict(&mut self, table: &str, key: &str) -> Vec<(String, String)> {
+        let mut evicted = vec![];
+        let mut kept = None;
+        while self.used > self.max_bytes {
+            let Some((rank, (t, k))) = self.order.pop_first() else {
+                break;
+            };
+            if t == table && k == key {
+                kept = Some((rank, (t, k)));
+                continue;
+            }
+            let (table, key) = (t, k);
+            if let Some(entry) = self.entries.get_mut(&table).and_then(|t| t.remove(&key)) {
+                self.used -= entry.size;
+            }
+            self.evictions += 1;
+            evicted.push((table, key));
+        }
+        if let Some((rank, name)) = kept {
+            self.order.insert(rank, name);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// 能放下 n 个测试用的 key 的上限
+    fn limit(n: usize) -> usize {
+        n * entry_size("t1", "k0", &"v".into())
+    }
+
+    fn keys(store: &MemTable, table: &str) -> Vec<String> {
+        let mut keys: Vec<_> = store
+            .get_all(table)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn lru_should_evict_least_recently_used() {
+        let store = MemTable::with_memory_limit(limit(3), EvictionPolicy::Lru);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), "v".into()).unwrap();
+        }
+        store.get("t1", "k0").unwrap();
+        store.set("t1", "k3".into(), "v".into()).unwrap();
+
+        assert_eq!(keys(&store, "t1"), vec!["k0", "k2", "k3"]);
+        assert_eq!(store.stats().evictions, 1);
+    }
+
+    #[test]
+    fn lfu_should_evict_least_frequently_used() {
+        let store = MemTable::with_memory_limit(limit(3), EvictionPolicy::Lfu);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), "v".into()).unwrap();
+        }
+        store.get("t1", "k0").unwrap();
+        store.get("t1", "k0").unwrap();
+        store.get("t1", "k1").unwrap();
+        store.set("t1", "k3".into(), "v".into()).unwrap();
+
+        assert_eq!(keys(&store, "t1"), vec!["k0", "k1", "k3"]);
+    }
+
+    #[test]
+    fn lfu_should_keep_newly_inserted_key() {
+        let store = MemTable::with_memory_limit(limit(3), EvictionPolicy::Lfu);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), "v".into()).unwrap();
+        }
+        // 所有的 key 都被读过两次，新写入的 key 访问次数最少，但是不能被淘汰
+        for _ in 0..2 {
+            for i in 0..3 {
+                store.get("t1", &format!("k{}", i)).unwrap();
+            }
+        }
+        store.set("t1", "k3".into(), "v".into()).unwrap();
+
+        assert_eq!(keys(&store, "t1"), vec!["k1", "k2", "k3"]);
+        assert_eq!(store.get("t1", "k3").unwrap(), Some("v".into()));
+    }
+
+    #[test]
+    fn clone_should_copy_data_and_budget() {
+        let store = MemTable::with_memory_limit(limit(3), EvictionPolicy::Lru);
+        store.set("t1", "k0".into(), "v".into()).unwrap();
+
+        let cloned = store.clone();
+        assert_eq!(cloned.stats(), store.stats());
+        cloned.set("t1", "k1".into(), "v".into()).unwrap();
+        assert_eq!(keys(&store, "t1"), vec!["k0"]);
+        assert_eq!(keys(&cloned, "t1"), vec!["k0", "k1"]);
+    }
+
+    #[test]
+    fn eviction_should_work_across_tables() {
+        let store = MemTable::with_memory_limit(limit(4), EvictionPolicy::Random);
+        for i in 0..100 {
+            store
+                .set(&format!("t{}", i % 3), format!("k{}", i), "v".into())
+                .unwrap();
+        }
+
+        let stats = store.stats();
+        assert!(stats.used_bytes <= stats.max_bytes);
+        assert_eq!(stats.keys + stats.evictions, 100);
+        let keys: usize = ["t0", "t1", "t2"]
+            .iter()
+            .map(|t| keys(&store, t).len())
+            .sum();
+        assert_eq!(keys as u64, stats.keys);
+    }
+
+    #[test]
+    fn eviction_listener_should_receive_evicted_keys() {
+        let store = MemTable::with_memory_limit(limit(2), EvictionPolicy::Lru);
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let sink = evicted.clone();
+        store.set_eviction_listener(Arc::new(move |table, key, value| {
+            let entry = (table.to_string(), key.to_string(), value.clone());
+            sink.lock().unwrap().push(entry);
+        }));
+
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), "v".into()).unwrap();
+        }
+        // 主动删除不算淘汰
+        store.del("t1", "k2").unwrap();
+
+        let expected = vec![("t1".to_string(), "k0".to_string(), Value::from("v"))];
+        assert_eq!(*evicted.lock().unwrap(), expected);
+        // clone 出来的 MemTable 不会继承回调
+        let cloned = store.clone();
+        cloned.set("t1", "k3".into(), "v".into()).unwrap();
+        cloned.set("t1", "k4".into(), "v".into()).unwrap();
+        assert_eq!(cloned.stats().evictions, 2);
+        assert_eq!(evicted.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn eviction_listener_should_run_outside_budget_lock() {
+        let store = Arc::new(MemTable::with_memory_limit(limit(2), EvictionPolicy::Lru));
+        let used = Arc::new(Mutex::new(vec![]));
+        let (weak, sink) = (Arc::downgrade(&store), used.clone());
+        // 回调里读取 MemTable 的统计信息需要 budget 的锁
+        store.set_eviction_listener(Arc::new(move |_, _, _| {
+            if let Some(store) = weak.upgrade() {
+                sink.lock().unwrap().push(store.stats().used_bytes);
+            }
+        }));
+
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), "v".into()).unwrap();
+        }
+        assert_eq!(*used.lock().unwrap(), vec![limit(2) as u64]);
+    }
+
+    #[test]
+    fn get_should_not_wait_for_budget_lock() {
+        let store = MemTable::with_memory_limit(limit(2), EvictionPolicy::Lru);
+        store.set("t1", "k1".into(), "v".into()).unwrap();
+
+        let _budget = store.budget.as_ref().unwrap().lock().unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v".into()));
+    }
+
+    #[test]
+    fn budget_should_track_overwrite_and_delete() {
+        let store = MemTable::with_memory_limit(1 << 20, EvictionPolicy::Lru);
+        store.set("t1", "k1".into(), "v".into()).unwrap();
+        let small = store.stats().used_bytes;
+        store
+            .set("t1", "k1".into(), "v".repeat(100).into())
+            .unwrap();
+        assert_eq!(store.stats().used_bytes, small + 99);
+
+        store.del("t1", "k1").unwrap();
+        assert_eq!(store.stats().used_bytes, 0);
+        assert_eq!(store.stats().keys, 0);
+    }
+
+    #[test]
+    fn set_should_reject_value_larger_than_limit() {
+        let store = MemTable::with_memory_limit(limit(1), EvictionPolicy::Lru);
+        let result = store.set("t1", "k1".into(), "v".repeat(1000).into());
+
+        assert!(matches!(result, Err(KvError::StorageError("set", ..))));
+        assert_eq!(
+            store.stats(),
+            MemTableStats {
+                max_bytes: limit(1) as u64,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unbounded_stats_should_estimate_usage() {
+        let store = MemTable::new();
+        store.set("t1", "k0".into(), "v".into()).unwrap();
+
+        assert_eq!(
+            store.stats(),
+            MemTableStats {
+                keys: 1,
+                used_bytes: limit(1) as u64,
+                ..Default::default()
+            }
+        );
+    }
 }
@@ -1,11 +1,19 @@
+mod collection;
+mod dump;
 pub mod memory;
 mod sleddb;
 
-pub use memory::MemTable;
+pub use dump::*;
+pub use memory::{EvictionPolicy, MemTable, MemTableStats};
 pub use sleddb::SledTable;
 
+use std::sync::Arc;
+
 use crate::{KvError, Kvpair, Value};
 
+/// 存储主动淘汰 key 时的回调，参数是 table、key 和被淘汰的 value
+pub type EvictionListener = Arc<dyn Fn(&str, &str, &Value) + Send + Sync>;
+
 /// 对存储的抽象，我们不关心数据存在哪儿，但需要定义外界如何和存储打交道
 pub trait Storage: Send + Sync + 'static {
     /// 从一个 HashTable 里获取一个 key 的 value
@@ -20,6 +28,84 @@ pub trait Storage: Send + Sync + 'static {
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     /// 遍历 HashTable，返回 kv pair 的 Iterator
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    /// 返回所有 HashTable 的名字
+    fn tables(&self) -> Result<Vec<String>, KvError>;
+    /// 存储的统计信息，比如内存占用和淘汰的 key 数量
+    fn stats(&self) -> Vec<Kvpair> {
+        vec![]
+    }
+    /// 设置淘汰 key 时的回调，会替换之前设置的；不会主动淘汰 key 的存储忽略它
+    fn set_eviction_listener(&self, _listener: EvictionListener) {}
+    /// 原子地读取并修改一个 key 的值，f 返回 None 时删除 key
+    ///
+    /// 并发修改时 f 可能被调用多次，所以 f 里不要有副作用
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<(), KvError>;
+
+    /// 在 list 的头部依次插入一组值，返回 list 的长度
+    fn lpush(&self, table: &str, key: &str, values: Vec<Value>) -> Result<u64, KvError> {
+        collection::push(self, table, key, values, true)
+    }
+    /// 在 list 的尾部依次添加一组值，返回 list 的长度
+    fn rpush(&self, table: &str, key: &str, values: Vec<Value>) -> Result<u64, KvError> {
+        collection::push(self, table, key, values, false)
+    }
+    /// 从 list 的头部取出最多 count 个值
+    fn lpop(&self, table: &str, key: &str, count: usize) -> Result<Vec<Value>, KvError> {
+        collection::pop(self, table, key, count, true)
+    }
+    /// 从 list 的尾部取出最多 count 个值
+    fn rpop(&self, table: &str, key: &str, count: usize) -> Result<Vec<Value>, KvError> {
+        collection::pop(self, table, key, count, false)
+    }
+    /// 返回 list 中下标在 [start, stop] 之间的值，负数从尾部开始计算
+    fn lrange(&self, table: &str, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, KvError> {
+        collection::lrange(self, table, key, start, stop)
+    }
+    /// 往 set 中添加一组 member，返回新添加的数量
+    fn sadd(&self, table: &str, key: &str, members: &[String]) -> Result<u64, KvError> {
+        collection::sadd(self, table, key, members)
+    }
+    /// 从 set 中删除一组 member，返回删除的数量
+    fn srem(&self, table: &str, key: &str, members: &[String]) -> Result<u64, KvError> {
+        collection::srem(self, table, key, members)
+    }
+    /// 返回 set 中所有的 member，按字典序排列
+    fn smembers(&self, table: &str, key: &str) -> Result<Vec<String>, KvError> {
+        collection::smembers(self, table, key)
+    }
+    /// 返回一组 set 的交集
+    fn sinter(&self, table: &str, keys: &[String]) -> Result<Vec<String>, KvError> {
+        collection::sinter(self, table, keys)
+    }
+    /// 往 sorted set 中添加一组 member，已有的 member 更新 score，返回新添加的数量
+    fn zadd(&self, table: &str, key: &str, members: &[(String, f64)]) -> Result<u64, KvError> {
+        collection::zadd(self, table, key, members)
+    }
+    /// 按排名返回 sorted set 中 [start, stop] 之间的 member 和 score
+    fn zrange(
+        &self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(String, f64)>, KvError> {
+        collection::zrange(self, table, key, start, stop)
+    }
+    /// 返回 sorted set 中 score 在 [min, max] 之间的 member 和 score
+    fn zrange_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(String, f64)>, KvError> {
+        collection::zrange_by_score(self, table, key, min, max)
+    }
 }
 
 pub struct StorageIter<T> {
@@ -52,6 +138,12 @@ impl From<(String, Value)> for Kvpair {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::arb_value;
+
     use super::*;
 
     fn test_basi_interface(store: impl Storage) {
@@ -122,9 +214,191 @@ mod tests {
         )
     }
 
+    fn test_structured_values(store: impl Storage) {
+        let v: Value = vec![
+            Kvpair::new("name", "tyr".into()),
+            Kvpair::new("tags", vec![Value::from("admin"), 1.into()].into()),
+            Kvpair::new("extra", Value::null()),
+        ]
+        .into();
+        store.set("t3", "u1".into(), v.clone()).unwrap();
+
+        assert_eq!(store.get("t3", "u1"), Ok(Some(v)));
+    }
+
+    fn test_tables(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+
+        let mut tables = store.tables().unwrap();
+        tables.sort();
+
+        assert_eq!(tables, vec!["t1", "t2"]);
+    }
+
+    fn test_list(store: impl Storage) {
+        assert_eq!(store.rpush("t1", "q", vec!["a".into(), "b".into()]), Ok(2));
+        assert_eq!(store.lpush("t1", "q", vec!["c".into(), "d".into()]), Ok(4));
+        assert_eq!(
+            store.lrange("t1", "q", 0, -1),
+            Ok(vec!["d".into(), "c".into(), "a".into(), "b".into()])
+        );
+        assert_eq!(
+            store.lrange("t1", "q", -2, 10),
+            Ok(vec!["a".into(), "b".into()])
+        );
+
+        assert_eq!(store.lpop("t1", "q", 1), Ok(vec!["d".into()]));
+        assert_eq!(store.rpop("t1", "q", 2), Ok(vec!["b".into(), "a".into()]));
+        assert_eq!(store.rpop("t1", "q", 5), Ok(vec!["c".into()]));
+
+        // 空的 list 会被删除
+        assert_eq!(store.contains("t1", "q"), Ok(false));
+        assert_eq!(store.lpop("t1", "q", 1), Ok(vec![]));
+        assert_eq!(store.lrange("t1", "q", 0, -1), Ok(vec![]));
+    }
+
+    fn test_set(store: impl Storage) {
+        let members = |m: &[&str]| m.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            store.sadd("t1", "s1", &members(&["b", "a", "c", "a"])),
+            Ok(3)
+        );
+        assert_eq!(store.sadd("t1", "s1", &members(&["a", "d"])), Ok(1));
+        assert_eq!(store.srem("t1", "s1", &members(&["d", "x"])), Ok(1));
+        assert_eq!(store.smembers("t1", "s1"), Ok(members(&["a", "b", "c"])));
+
+        store.sadd("t1", "s2", &members(&["c", "b", "z"])).unwrap();
+        assert_eq!(
+            store.sinter("t1", &members(&["s1", "s2"])),
+            Ok(members(&["b", "c"]))
+        );
+        assert_eq!(store.sinter("t1", &members(&["s1", "none"])), Ok(vec![]));
+
+        assert_eq!(store.srem("t1", "s2", &members(&["b", "c", "z"])), Ok(3));
+        assert_eq!(store.contains("t1", "s2"), Ok(false));
+    }
+
+    fn test_sorted_set(store: impl Storage) {
+        let board = [
+            ("alice".to_string(), 30.0),
+            ("bob".to_string(), 10.0),
+            ("carol".to_string(), 20.0),
+        ];
+        assert_eq!(store.zadd("t1", "board", &board), Ok(3));
+        // 已有的 member 只更新 score
+        assert_eq!(store.zadd("t1", "board", &[("bob".into(), 40.0)]), Ok(0));
+
+        let ranked = |names: &[(&str, f64)]| {
+            names
+                .iter()
+                .map(|(m, s)| (m.to_string(), *s))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            store.zrange("t1", "board", 0, -1),
+            Ok(ranked(&[("carol", 20.0), ("alice", 30.0), ("bob", 40.0)]))
+        );
+        assert_eq!(
+            store.zrange("t1", "board", -1, -1),
+            Ok(ranked(&[("bob", 40.0)]))
+        );
+        assert_eq!(
+            store.zrange_by_score("t1", "board", 25.0, f64::INFINITY),
+            Ok(ranked(&[("alice", 30.0), ("bob", 40.0)]))
+        );
+        assert!(store
+            .zadd("t1", "board", &[("eve".into(), f64::NAN)])
+            .is_err());
+    }
+
+    fn test_wrong_type(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.rpush("t1", "q", vec!["a".into()]).unwrap();
+
+        let err = KvError::WrongType("t1".into(), "k1".into(), "list");
+        assert_eq!(store.rpush("t1", "k1", vec!["a".into()]), Err(err));
+        let err = KvError::WrongType("t1".into(), "q".into(), "set");
+        assert_eq!(store.sadd("t1", "q", &["a".into()]), Err(err));
+        // 失败的操作不会修改原来的值
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Set(String, String, Value),
+        Get(String, String),
+        Contains(String, String),
+        Del(String, String),
+        GetAll(String),
+    }
+
+    fn arb_op() -> impl Strategy<Value = Op> {
+        // table 和 key 的范围很小，这样操作会经常落在同一个 key 上
+        let (table, key) = ("t[0-2]", "k[0-4]");
+        prop_oneof![
+            (table, key, arb_value()).prop_map(|(t, k, v)| Op::Set(t, k, v)),
+            (table, key).prop_map(|(t, k)| Op::Get(t, k)),
+            (table, key).prop_map(|(t, k)| Op::Contains(t, k)),
+            (table, key).prop_map(|(t, k)| Op::Del(t, k)),
+            table.prop_map(Op::GetAll),
+        ]
+    }
+
+    /// 对 store 执行 ops，结果应该和 BTreeMap 一致
+    fn test_against_model(store: impl Storage, ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let mut model: BTreeMap<(String, String), Value> = BTreeMap::new();
+
+        for op in ops {
+            match op {
+                Op::Set(t, k, v) => {
+                    let old = model.insert((t.clone(), k.clone()), v.clone());
+                    prop_assert_eq!(store.set(&t, k, v), Ok(old));
+                }
+                Op::Get(t, k) => {
+                    let v = model.get(&(t.clone(), k.clone())).cloned();
+                    prop_assert_eq!(store.get(&t, &k), Ok(v));
+                }
+                Op::Contains(t, k) => {
+                    let exists = model.contains_key(&(t.clone(), k.clone()));
+                    prop_assert_eq!(store.contains(&t, &k), Ok(exists));
+                }
+                Op::Del(t, k) => {
+                    let old = model.remove(&(t.clone(), k.clone()));
+                    prop_assert_eq!(store.del(&t, &k), Ok(old));
+                }
+                Op::GetAll(t) => {
+                    let expected: Vec<_> = model
+                        .iter()
+                        .filter(|((table, _), _)| *table == t)
+                        .map(|((_, k), v)| Kvpair::new(k, v.clone()))
+                        .collect();
+                    let mut pairs = store.get_all(&t).unwrap();
+                    pairs.sort_by(|a, b| a.key.cmp(&b.key));
+                    prop_assert_eq!(pairs, expected);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     mod memory_table {
         use super::*;
 
+        proptest! {
+            #[test]
+            fn memtable_should_behave_like_a_map(ops in vec(arb_op(), 0..64)) {
+                test_against_model(MemTable::new(), ops)?;
+            }
+        }
+
+        #[test]
+        fn memtable_structured_values_should_work() {
+            test_structured_values(MemTable::new());
+        }
+
         #[test]
         fn memtable_basic_interface_should_work() {
             let store = MemTable::new();
@@ -142,6 +416,28 @@ mod tests {
             let store = MemTable::new();
             test_get_iter(store);
         }
+
+        #[test]
+        fn memtable_tables_should_work() {
+            test_tables(MemTable::new());
+        }
+
+        #[test]
+        fn memtable_collections_should_work() {
+            test_list(MemTable::new());
+            test_set(MemTable::new());
+            test_sorted_set(MemTable::new());
+            test_wrong_type(MemTable::new());
+        }
+
+        #[test]
+        fn memtable_with_limit_collections_should_work() {
+            let store = || MemTable::with_memory_limit(1 << 20, EvictionPolicy::Lru);
+            test_list(store());
+            test_set(store());
+            test_sorted_set(store());
+            test_wrong_type(store());
+        }
     }
 
     mod sled_table {
@@ -150,6 +446,16 @@ mod tests {
 
         use super::*;
 
+        proptest! {
+            // 每个 case 都要创建一个 sled 数据库，少跑一些
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            #[test]
+            fn sled_should_behave_like_a_map(ops in vec(arb_op(), 0..64)) {
+                test_against_model(get_sled_store(), ops)?;
+            }
+        }
+
         #[test]
         fn sled_basic_interface_should_work() {
             test_basi_interface(get_sled_store());
@@ -164,5 +470,33 @@ mod tests {
         fn sled_get_iter_should_work() {
             test_get_iter(get_sled_store());
         }
+
+        #[test]
+        fn sled_tables_should_work() {
+            test_tables(get_sled_store());
+        }
+
+        #[test]
+        fn sled_collections_should_work() {
+            test_list(get_sled_store());
+            test_set(get_sled_store());
+            test_sorted_set(get_sled_store());
+            test_wrong_type(get_sled_store());
+        }
+
+        #[test]
+        fn sled_structured_values_should_work() {
+            test_structured_values(get_sled_store());
+        }
+
+        #[test]
+        fn sled_should_read_values_stored_before_structured_types() {
+            let store = get_sled_store();
+            // 之前的版本写入的 Value::String("world")
+            let old: &[u8] = b"\x0a\x05world";
+            store.open_tree("t1").unwrap().insert("k1", old).unwrap();
+
+            assert_eq!(store.get("t1", "k1"), Ok(Some("world".into())));
+        }
     }
 }
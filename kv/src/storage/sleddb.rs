@@ -86,4 +86,36 @@ impl Storage for SledTable {
 
         Ok(Box::new(table.iter().map(|r| r.into())))
     }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut dyn FnMut(Option<Value>) -> Result<Option<Value>, KvError>,
+    ) -> Result<(), KvError> {
+        let table = self.open_tree(table)?;
+
+        // 其他人在这期间修改了 key 时 compare_and_swap 失败，重新读取再试
+        loop {
+            let old = table.get(key)?;
+            let new = f(sled2kv_res(Ok(old.clone()))?)?;
+            if table
+                .compare_and_swap(key, old, new.map(|v| v.encode_to_vec()))?
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KvError> {
+        // sled 自带的 default tree 不是我们创建的 table
+        let default = self.name();
+        Ok(self
+            .tree_names()
+            .into_iter()
+            .filter(|name| *name != default)
+            .map(|name| String::from_utf8_lossy(&name).into_owned())
+            .collect())
+    }
 }
@@ -0,0 +1,217 @@
+use std::{future::Future, pin::Pin, sync::Arc, sync::Mutex};
+
+use futures::{Stream, StreamExt};
+use tokio::sync::oneshot;
+
+use crate::{CommandRequest, CommandResponse, KvClient, KvError, Service, Subscription, Value};
+
+const NOT_FOUND: u32 = 404;
+
+/// 嵌入在进程里的 Service 或者远端的 KvClient，各语言的绑定通过它执行命令
+#[derive(Clone)]
+pub enum Store {
+    Local(Service),
+    Remote(KvClient),
+}
+
+/// 订阅的数据来源，远端的订阅在重连之后会自动恢复
+pub struct Feed(FeedInner);
+
+enum FeedInner {
+    Local {
+        service: Service,
+        topic: String,
+        id: u32,
+        stream: StreamingResponse,
+    },
+    Remote(Subscription),
+}
+
+/// 在后台转发订阅数据的任务，drop 时同样会取消订阅
+pub struct FeedHandle {
+    cancel: Mutex<Option<oneshot::Sender<Cancel>>>,
+}
+
+type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
+
+/// 取消订阅的请求，附带一个用来返回结果的 channel
+type Cancel = oneshot::Sender<Result<(), KvError>>;
+
+impl Store {
+    /// 执行命令，返回第一个响应
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        match self {
+            Store::Local(service) => match service.execute(cmd).next().await {
+                Some(res) => Ok((*res).clone()),
+                None => Err(KvError::Internal("no response".into())),
+            },
+            Store::Remote(client) => client.execute(&cmd).await,
+        }
+    }
+
+    /// 执行命令，把非 2xx 的响应转换成错误
+    pub async fn run(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        self.execute(cmd).await?.into_result()
+    }
+
+    /// 存在时返回 200，不存在时返回 404
+    pub async fn exists(&self, cmd: CommandRequest) -> Result<bool, KvError> {
+        let res = self.execute(cmd).await?;
+        if res.status == NOT_FOUND {
+            return Ok(false);
+        }
+        res.into_result()?;
+        Ok(true)
+    }
+
+    /// 订阅 topic
+    pub async fn subscribe(&self, topic: impl Into<String>) -> Result<Feed, KvError> {
+        let topic = topic.into();
+        let inner = match self {
+            Store::Local(service) => {
+                let mut stream = service.execute(CommandRequest::new_subscribe(&topic));
+                let id = match stream.next().await {
+                    Some(res) => i64::try_from(&(*res).clone().into_result()?)? as u32,
+                    None => return Err(KvError::Internal("subscribe failed".into())),
+                };
+                FeedInner::Local {
+                    service: service.clone(),
+                    topic,
+                    id,
+                    stream,
+                }
+            }
+            Store::Remote(client) => FeedInner::Remote(client.subscribe(topic).await?),
+        };
+        Ok(Feed(inner))
+    }
+}
+
+impl Feed {
+    /// 读取下一批数据，数据流结束时返回 None
+    pub async fn next(&mut self) -> Option<Vec<Value>> {
+        match &mut self.0 {
+            FeedInner::Local { stream, .. } => stream.next().await.map(|res| res.values.clone()),
+            FeedInner::Remote(sub) => sub.next().await.map(|v| vec![v]),
+        }
+    }
+
+    /// 取消订阅
+    pub async fn unsubscribe(self) -> Result<(), KvError> {
+        let res = match self.0 {
+            FeedInner::Local {
+                service, topic, id, ..
+            } => {
+                let mut res = service.execute(CommandRequest::new_unsubscribe(topic, id));
+                match res.next().await {
+                    Some(res) => (*res).clone(),
+                    None => return Ok(()),
+                }
+            }
+            FeedInner::Remote(sub) => sub.unsubscribe().await?,
+        };
+        res.into_result().map(|_| ())
+    }
+
+    /// 在 tokio 运行时上把收到的数据依次交给 sink，直到取消订阅或者数据流结束
+    ///
+    /// sink 返回的 future 完成之前不会读取下一个值，等待 sink 时也可以取消订阅。
+    pub fn forward<F, Fut>(self, sink: F) -> FeedHandle
+    where
+        F: FnMut(Value) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        tokio::spawn(forward(self, sink, cancel_rx));
+        FeedHandle {
+            cancel: Mutex::new(Some(cancel_tx)),
+        }
+    }
+}
+
+impl FeedHandle {
+    /// 取消订阅，返回之后 sink 不会再被调用；重复调用什么都不做
+    pub async fn unsubscribe(&self) -> Result<(), KvError> {
+        let cancel = self.cancel.lock().unwrap().take();
+        let Some(cancel) = cancel else {
+            return Ok(());
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if cancel.send(tx).is_err() {
+            // 数据流已经结束了
+            return Ok(());
+        }
+        rx.await.unwrap_or(Ok(()))
+    }
+}
+
+/// FeedHandle 被 drop 时 cancel 的发送端也被 drop，同样会取消订阅
+async fn forward<F, Fut>(mut feed: Feed, mut sink: F, mut cancel: oneshot::Receiver<Cancel>)
+where
+    F: FnMut(Value) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let reply = 'forward: loop {
+        let values = tokio::select! {
+            reply = &mut cancel => break 'forward reply,
+            values = feed.next() => match values {
+                Some(values) => values,
+                None => return,
+            },
+        };
+        for v in values {
+            tokio::select! {
+                reply = &mut cancel => break 'forward reply,
+                _ = sink(v) => {}
+            }
+        }
+    };
+
+    let res = feed.unsubscribe().await;
+    if let Ok(reply) = reply {
+        let _ = reply.send(res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn store_should_report_existence() {
+        let store = Store::Local(Service::new(MemTable::new()));
+        let cmd = CommandRequest::new_hexist("t1", "k1");
+        assert!(!store.exists(cmd.clone()).await.unwrap());
+
+        store
+            .run(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        assert!(store.exists(cmd).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn forward_should_stop_after_unsubscribe() {
+        let store = Store::Local(Service::new(MemTable::new()));
+        let feed = store.subscribe("lobby").await.unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let handle = feed.forward(move |v| {
+            let _ = tx.send(v);
+            async {}
+        });
+
+        let publish = |v: &str| CommandRequest::new_publish("lobby", vec![v.into()]);
+        store.run(publish("hello")).await.unwrap();
+        assert_eq!(rx.recv().await, Some("hello".into()));
+
+        handle.unsubscribe().await.unwrap();
+        store.run(publish("world")).await.unwrap();
+        // 转发任务结束后 sink 被 drop，channel 随之关闭
+        assert_eq!(rx.recv().await, None);
+        handle.unsubscribe().await.unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    runtime::Tokio,
+    sdk::{
+        export::trace::stdout, propagation::TraceContextPropagator, trace::Config, trace::Tracer,
+        Resource,
+    },
+    trace::TraceContextExt,
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::{KvError, TraceContext, TraceExporter, TracingConfig};
+
+/// 客户端和服务器处理请求的 span 使用的 target
+pub const REQUEST_TARGET: &str = "kv::request";
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// 根据配置创建导出 span 的 tracer，不导出时返回 None
+///
+/// 批量导出在 tokio runtime 的后台任务里进行，退出前调用
+/// `opentelemetry::global::shutdown_tracer_provider()` 把剩下的 span 导出。
+pub fn init_tracer(config: &TracingConfig, service_name: &str) -> Result<Option<Tracer>, KvError> {
+    let name = config.service_name.as_deref().unwrap_or(service_name);
+    let trace_config = Config::default().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        name.to_owned(),
+    )]));
+    let endpoint = config.endpoint.as_deref();
+
+    let tracer = match config.exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Stdout => stdout::new_pipeline()
+            .with_trace_config(trace_config)
+            .install_simple(),
+        TraceExporter::Jaeger => {
+            let mut pipeline = opentelemetry_jaeger::new_pipeline()
+                .with_service_name(name)
+                .with_trace_config(trace_config);
+            if let Some(endpoint) = endpoint {
+                pipeline = pipeline.with_agent_endpoint(endpoint);
+            }
+            match config.batch {
+                true => pipeline.install_batch(Tokio),
+                false => pipeline.install_simple(),
+            }
+            .map_err(|e| KvError::Internal(format!("Failed to install Jaeger exporter: {}", e)))?
+        }
+        TraceExporter::Otlp => {
+            let mut exporter = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(endpoint) = endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            let pipeline = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(trace_config);
+            match config.batch {
+                true => pipeline.install_batch(Tokio),
+                false => pipeline.install_simple(),
+            }
+            .map_err(|e| KvError::Internal(format!("Failed to install OTLP exporter: {}", e)))?
+        }
+    };
+
+    Ok(Some(tracer))
+}
+
+/// 根据配置的日志级别生成过滤规则，不配置时使用 `RUST_LOG`
+///
+/// trace_requests 为 true 时不管日志级别是什么都记录请求的 span。
+pub fn log_filter(level: Option<&str>, trace_requests: bool) -> Result<EnvFilter, KvError> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)
+            .map_err(|e| KvError::InvalidConfig(format!("invalid log level `{}`: {}", level, e)))?,
+        None => EnvFilter::from_default_env(),
+    };
+    Ok(match trace_requests {
+        true => enable_request_spans(filter),
+        false => filter,
+    })
+}
+
+/// 在日志的过滤规则里打开请求的 span，这样日志级别较高时也能导出它们
+///
+/// fmt layer 默认不打印 span，所以不会多出日志。
+pub fn enable_request_spans(filter: EnvFilter) -> EnvFilter {
+    filter.add_directive(format!("{}=info", REQUEST_TARGET).parse().unwrap())
+}
+
+impl TraceContext {
+    /// span 的 W3C trace context，span 没有被 OpenTelemetry 记录时返回 None
+    pub fn from_span(span: &Span) -> Option<Self> {
+        let cx = span.context();
+        if !cx.span().span_context().is_valid() {
+            return None;
+        }
+
+        let mut trace = TraceContext::default();
+        TraceContextPropagator::new().inject_context(&cx, &mut trace);
+        Some(trace)
+    }
+
+    /// 转换成 OpenTelemetry 的 Context，用作服务器 span 的父节点
+    pub fn extract(&self) -> Context {
+        TraceContextPropagator::new().extract(self)
+    }
+}
+
+impl Injector for TraceContext {
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            TRACEPARENT => self.traceparent = value,
+            TRACESTATE => self.tracestate = value,
+            _ => {}
+        }
+    }
+}
+
+impl Extractor for TraceContext {
+    fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            TRACEPARENT => Some(&self.traceparent),
+            TRACESTATE => Some(&self.tracestate),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![TRACEPARENT, TRACESTATE]
+    }
+}
+
+/// 使用 OpenTelemetry 记录 span 的 subscriber，不导出 span
+#[cfg(test)]
+pub fn otel_subscriber() -> impl tracing::Subscriber + Send + Sync {
+    use std::sync::OnceLock;
+
+    use opentelemetry::{sdk::trace::TracerProvider, trace::TracerProvider as _};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    // tracer 只持有 provider 的弱引用，provider 要一直存在
+    static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+    let provider = PROVIDER.get_or_init(|| TracerProvider::builder().build());
+    let tracer = provider.tracer("test", None);
+    Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// span 所在的 trace
+#[cfg(test)]
+pub fn trace_id(span: &Span) -> opentelemetry::trace::TraceId {
+    span.context().span().span_context().trace_id()
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TraceId;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn trace_context_should_round_trip() {
+        tracing::subscriber::with_default(otel_subscriber(), || {
+            let client = info_span!("client");
+            let trace = TraceContext::from_span(&client).unwrap();
+            assert!(trace.traceparent.starts_with("00-"));
+            assert_ne!(trace_id(&client), TraceId::invalid());
+
+            let server = info_span!("server");
+            server.set_parent(trace.extract());
+            assert_eq!(trace_id(&server), trace_id(&client));
+        });
+    }
+
+    #[test]
+    fn span_without_opentelemetry_should_have_no_trace_context() {
+        let span = info_span!("client");
+        assert_eq!(TraceContext::from_span(&span), None);
+
+        // 空的 trace context 不会产生有效的父节点
+        let cx = TraceContext::default().extract();
+        assert!(!cx.span().span_context().is_valid());
+    }
+
+    #[test]
+    fn request_spans_should_be_enabled_with_any_log_level() {
+        let filter = enable_request_spans(EnvFilter::new("error"));
+        let subscriber = tracing_subscriber::registry().with(filter);
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(info_span!("other").is_disabled());
+            assert!(!info_span!(target: REQUEST_TARGET, "client_request").is_disabled());
+        });
+    }
+
+    #[test]
+    fn no_exporter_should_not_install_tracer() {
+        let tracer = init_tracer(&TracingConfig::default(), "kv-server").unwrap();
+        assert!(tracer.is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use kv::{
-    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest,
-    ProstClientStream, ServerConfig, StorageConfig,
+    start_client_with_config, start_server_with_config, ClientConfig, CommandRequest, ServerConfig,
+    StorageConfig, TransportConfig,
 };
 use std::time::Duration;
 use tokio::time;
@@ -24,15 +24,95 @@ async fn yamux_server_client_full_tests() -> Result<()> {
     config.general.addr = addr.into();
 
     let mut ctrl = start_client_with_config(&config).await.unwrap();
-    let mut stream: ProstClientStream<_> = ctrl.open_stream().await?.into();
+    let mut stream = ctrl.open_stream().await?;
 
     // 生成一个 HSET 命令
     let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
-    stream.execute_unary(&cmd).await?;
+    stream.execute_unary(cmd).await?;
 
     // 生成一个 HGET 命令
     let cmd = CommandRequest::new_hget("table1", "hello");
-    let data = stream.execute_unary(&cmd).await?;
+    let data = stream.execute_unary(cmd).await?;
+
+    assert_eq!(data.status, 200);
+    assert_eq!(data.values, &["world".into()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn quic_server_client_full_tests() -> Result<()> {
+    let addr = "127.0.0.1:10087";
+
+    let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
+    config.general.addr = addr.into();
+    config.general.transport = TransportConfig::Quic;
+    config.storage = StorageConfig::MemTable;
+
+    // 启动服务器
+    tokio::spawn(async move {
+        start_server_with_config(&config).await.unwrap();
+    });
+
+    time::sleep(Duration::from_millis(10)).await;
+    let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
+    config.general.addr = addr.into();
+    config.general.transport = TransportConfig::Quic;
+
+    let mut ctrl = start_client_with_config(&config).await.unwrap();
+    let mut stream1 = ctrl.open_stream().await?;
+    let mut stream2 = ctrl.open_stream().await?;
+
+    // 生成一个 HSET 命令
+    let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
+    stream1.execute_unary(cmd).await?;
+
+    // 在另一个 stream 上生成一个 HGET 命令
+    let cmd = CommandRequest::new_hget("table1", "hello");
+    let data = stream2.execute_unary(cmd).await?;
+
+    assert_eq!(data.status, 200);
+    assert_eq!(data.values, &["world".into()]);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_server_client_without_tls_full_tests() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let addr = dir.path().join("kv.sock").to_string_lossy().to_string();
+
+    let mut config: ServerConfig = toml::from_str(include_str!("../fixtures/server.conf"))?;
+    config.general.addr = addr.clone();
+    config.general.transport = TransportConfig::Unix;
+    config.storage = StorageConfig::MemTable;
+    config.tls = None;
+
+    // 启动服务器
+    tokio::spawn(async move {
+        start_server_with_config(&config).await.unwrap();
+    });
+
+    time::sleep(Duration::from_millis(10)).await;
+    let mut config: ClientConfig = toml::from_str(include_str!("../fixtures/client.conf"))?;
+    config.general.addr = addr;
+    config.general.transport = TransportConfig::Unix;
+    config.general.max_frame_size = 64 * 1024;
+    config.tls = None;
+
+    // 握手的结果保存在连接上，打开的 stream 会使用协商出来的参数
+    let mut ctrl = start_client_with_config(&config).await.unwrap();
+    assert_eq!(ctrl.handshake().unwrap().max_frame_size, 64 * 1024);
+    let mut stream = ctrl.open_stream().await?;
+
+    // 生成一个 HSET 命令
+    let cmd = CommandRequest::new_hset("table1", "hello", "world".to_string().into());
+    stream.execute_unary(cmd).await?;
+
+    // 生成一个 HGET 命令
+    let cmd = CommandRequest::new_hget("table1", "hello");
+    let data = stream.execute_unary(cmd).await?;
 
     assert_eq!(data.status, 200);
     assert_eq!(data.values, &["world".into()]);
@@ -0,0 +1,261 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
+};
+
+use bytes::{Buf, BytesMut};
+use kv::{MemTable, ProstClientStream, ProstServerStream, Service, LEN_LEN, LEN_MASK};
+use tokio::{
+    io::{duplex, AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+    time::{self, Sleep},
+};
+
+/// 每个连接的缓冲区大小
+const BUF_SIZE: usize = 64 * 1024;
+
+/// 连接上数据流动的方向
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// 一个方向上的故障
+#[derive(Default)]
+struct Fault {
+    /// 接下来要丢弃的 frame 数量
+    drop_frames: usize,
+    /// 已经丢弃的 frame 数量
+    dropped: usize,
+    /// 关闭之后读端读到 EOF，写端得到 BrokenPipe
+    closed: bool,
+    /// 读端每次最多读取的字节数，以及每次读取之前等待的时间
+    slow: Option<(usize, Duration)>,
+    /// 等待中的读端和写端，关闭时唤醒
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+/// 控制一个连接上的故障，clone 之后共享同一组故障
+#[derive(Clone, Default)]
+pub struct Faults {
+    inner: Arc<Mutex<[Fault; 2]>>,
+}
+
+impl Faults {
+    /// 丢弃 dir 方向上接下来的 n 个完整的 frame
+    pub fn drop_frames(&self, dir: Direction, n: usize) {
+        self.with(dir, |f| f.drop_frames += n);
+    }
+
+    /// dir 方向上已经丢弃的 frame 数量
+    pub fn dropped(&self, dir: Direction) -> usize {
+        self.with(dir, |f| f.dropped)
+    }
+
+    /// 关闭 dir 方向，另一个方向不受影响
+    pub fn half_close(&self, dir: Direction) {
+        let wakers = self.with(dir, |f| {
+            f.closed = true;
+            [f.reader.take(), f.writer.take()]
+        });
+        wakers.into_iter().flatten().for_each(Waker::wake);
+    }
+
+    /// dir 方向的读端每次等待 delay 之后最多读取 bytes 个字节
+    pub fn slow_reader(&self, dir: Direction, bytes: usize, delay: Duration) {
+        self.with(dir, |f| f.slow = Some((bytes, delay)));
+    }
+
+    fn with<T>(&self, dir: Direction, f: impl FnOnce(&mut Fault) -> T) -> T {
+        let mut faults = self.inner.lock().unwrap();
+        f(&mut faults[dir as usize])
+    }
+}
+
+/// 按照 Faults 注入故障的 stream
+///
+/// 写入的数据按 frame 切分之后才转发给对端，这样丢弃的总是完整的 frame。
+pub struct FaultyStream {
+    inner: DuplexStream,
+    faults: Faults,
+    /// 读取的数据的方向，写入的方向与之相反
+    read_dir: Direction,
+    /// 还不是完整 frame 的数据
+    pending: BytesMut,
+    /// 等待转发给对端的数据
+    out: BytesMut,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl FaultyStream {
+    fn new(inner: DuplexStream, faults: Faults, read_dir: Direction) -> Self {
+        Self {
+            inner,
+            faults,
+            read_dir,
+            pending: BytesMut::new(),
+            out: BytesMut::new(),
+            delay: None,
+        }
+    }
+
+    fn write_dir(&self) -> Direction {
+        match self.read_dir {
+            Direction::ToServer => Direction::ToClient,
+            Direction::ToClient => Direction::ToServer,
+        }
+    }
+
+    /// 把 pending 里完整的 frame 移到 out，需要丢弃的 frame 直接扔掉
+    fn split_frames(&mut self) {
+        while self.pending.len() >= LEN_LEN {
+            let header = u32::from_be_bytes(self.pending[..LEN_LEN].try_into().unwrap());
+            let len = LEN_LEN + (header & LEN_MASK) as usize;
+            if self.pending.len() < len {
+                break;
+            }
+
+            let frame = self.pending.split_to(len);
+            let dropped = self.faults.with(self.write_dir(), |f| {
+                if f.drop_frames == 0 {
+                    return false;
+                }
+                f.drop_frames -= 1;
+                f.dropped += 1;
+                true
+            });
+            if !dropped {
+                self.out.extend_from_slice(&frame);
+            }
+        }
+    }
+
+    /// 把 out 里的数据写给对端
+    fn poll_forward(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.out.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out))?;
+            self.out.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// 读取的方向关闭时返回 true，否则登记 waker 以便关闭时被唤醒
+    fn read_closed(&self, cx: &mut Context<'_>) -> bool {
+        self.faults.with(self.read_dir, |f| {
+            f.reader = Some(cx.waker().clone());
+            f.closed
+        })
+    }
+
+    /// 写入的方向关闭时返回 true，否则登记 waker 以便关闭时被唤醒
+    fn write_closed(&self, cx: &mut Context<'_>) -> bool {
+        self.faults.with(self.write_dir(), |f| {
+            f.writer = Some(cx.waker().clone());
+            f.closed
+        })
+    }
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read_closed(cx) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let Some((bytes, delay)) = this.faults.with(this.read_dir, |f| f.slow) else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+
+        let sleep = this
+            .delay
+            .get_or_insert_with(|| Box::pin(time::sleep(delay)));
+        ready!(sleep.as_mut().poll(cx));
+
+        let mut data = vec![0; bytes.min(buf.remaining())];
+        let mut limited = ReadBuf::new(&mut data);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        buf.put_slice(limited.filled());
+        this.delay = None;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for FaultyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write_closed(cx) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        ready!(this.poll_forward(cx))?;
+
+        this.pending.extend_from_slice(buf);
+        this.split_frames();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_forward(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_forward(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// 在内存里运行服务器和任意多个客户端
+///
+/// 配合 `#[tokio::test(start_paused = true)]` 使用：时钟只有在所有 task 都在等待时
+/// 才会前进，所以超时和慢速读取的结果是确定的。
+pub struct Sim {
+    service: Service,
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self {
+            service: Service::new(MemTable::new()),
+        }
+    }
+}
+
+impl Sim {
+    /// 建立一个新的连接，返回客户端和控制这个连接上故障的句柄
+    pub fn connect(&self) -> (ProstClientStream<FaultyStream>, Faults) {
+        let faults = Faults::default();
+        let (client, server) = duplex(BUF_SIZE);
+
+        let server = FaultyStream::new(server, faults.clone(), Direction::ToServer);
+        let server = ProstServerStream::new(server, self.service.clone());
+        tokio::spawn(async move {
+            // 注入的故障会让连接出错，这里不关心
+            let _ = server.process().await;
+        });
+
+        let client = FaultyStream::new(client, faults.clone(), Direction::ToClient);
+        (ProstClientStream::new(client), faults)
+    }
+
+    /// 等待其它 task 都执行完，时钟暂停时不会消耗真实的时间
+    pub async fn settle() {
+        time::sleep(Duration::from_millis(1)).await;
+    }
+}
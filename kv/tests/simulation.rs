@@ -0,0 +1,254 @@
+mod sim;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Result;
+use futures::StreamExt;
+use kv::{CommandRequest, CommandResponse, Value};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sim::{Direction, Sim};
+use tokio::time::{self, Instant};
+
+fn published(res: CommandResponse) -> i64 {
+    res.values[0].clone().try_into().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn many_clients_should_see_the_same_data() -> Result<()> {
+    let sim = Sim::default();
+    let mut rng = StdRng::seed_from_u64(9527);
+
+    // 50 个客户端并发写入随机的 key，每个 key 留下来的应该是某个客户端对它的最后一次写入
+    let mut candidates: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    let mut tasks = vec![];
+    for client in 0..50 {
+        let (mut conn, _) = sim.connect();
+        let ops: Vec<_> = (0..20)
+            .map(|i| {
+                let key = format!("k{}", rng.gen_range(0..10));
+                let v = rng.gen::<i64>();
+                let value: Value =
+                    vec![Value::from(client as i64), Value::from(i as i64), v.into()].into();
+                (key, value, v)
+            })
+            .collect();
+
+        let mut last = BTreeMap::new();
+        for (key, value, _) in &ops {
+            last.insert(key.clone(), value.clone());
+        }
+        for (key, value) in last {
+            candidates.entry(key).or_default().push(value);
+        }
+
+        tasks.push(tokio::spawn(async move {
+            for (key, value, v) in ops {
+                let cmd = CommandRequest::new_hset("t1", key, value);
+                assert_eq!(conn.execute_unary(cmd).await.unwrap().status, 200);
+                // 随机的延迟让各个客户端交错执行
+                time::sleep(Duration::from_millis((v as u64) % 7)).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await?;
+    }
+
+    let (mut conn, _) = sim.connect();
+    let res = conn
+        .execute_unary(CommandRequest::new_hgetall("t1"))
+        .await?;
+    let expected: BTreeMap<_, _> = res.pairs.into_iter().map(|p| (p.key, p.value)).collect();
+    assert_eq!(
+        expected.keys().collect::<Vec<_>>(),
+        candidates.keys().collect::<Vec<_>>()
+    );
+    for (key, value) in &expected {
+        let value = value.as_ref().unwrap();
+        assert!(candidates[key].contains(value), "{key}: {value:?}");
+    }
+
+    // 每个客户端再读一遍，看到的都一样
+    for _ in 0..10 {
+        let (mut conn, _) = sim.connect();
+        for (key, value) in &expected {
+            let res = conn
+                .execute_unary(CommandRequest::new_hget("t1", key))
+                .await?;
+            assert_eq!(res.values.first(), value.as_ref());
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn all_subscribers_should_receive_messages_in_order() -> Result<()> {
+    let sim = Sim::default();
+
+    let mut subs = vec![];
+    for _ in 0..20 {
+        let (conn, _) = sim.connect();
+        subs.push(
+            conn.execute_streaming(CommandRequest::new_subscribe("lobby"))
+                .await?,
+        );
+    }
+
+    let (mut publisher, _) = sim.connect();
+    for i in 0..10 {
+        let cmd = CommandRequest::new_publish("lobby", vec![i.into()]);
+        publisher.execute_unary(cmd).await?;
+    }
+
+    for mut sub in subs {
+        let mut received = vec![];
+        for _ in 0..10 {
+            received.push(published(sub.next().await.unwrap()?));
+        }
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn dropped_request_should_time_out_and_connection_should_recover() -> Result<()> {
+    let sim = Sim::default();
+    let (mut conn, faults) = sim.connect();
+
+    faults.drop_frames(Direction::ToServer, 1);
+    let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+    let start = Instant::now();
+    let res = time::timeout(Duration::from_secs(1), conn.execute_unary(cmd)).await;
+    assert!(res.is_err());
+    assert_eq!(faults.dropped(Direction::ToServer), 1);
+    // 时钟是虚拟的，超时正好发生在 1 秒之后
+    assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+    // 请求被丢弃了，服务器上没有数据，连接还可以继续使用
+    let res = conn
+        .execute_unary(CommandRequest::new_hget("t1", "k1"))
+        .await?;
+    assert_eq!(res.status, 404);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn dropped_messages_should_not_break_the_subscription() -> Result<()> {
+    let sim = Sim::default();
+    let (conn, faults) = sim.connect();
+    let mut sub = conn
+        .execute_streaming(CommandRequest::new_subscribe("lobby"))
+        .await?;
+
+    let (mut publisher, _) = sim.connect();
+    let publish = |i: i64| CommandRequest::new_publish("lobby", vec![i.into()]);
+    publisher.execute_unary(publish(1)).await?;
+    Sim::settle().await;
+
+    // 丢掉第 2、3 条消息，之后的消息还能正常收到
+    faults.drop_frames(Direction::ToClient, 2);
+    for i in 2..=5 {
+        publisher.execute_unary(publish(i)).await?;
+    }
+
+    let mut received = vec![];
+    for _ in 0..3 {
+        received.push(published(sub.next().await.unwrap()?));
+    }
+    assert_eq!(received, vec![1, 4, 5]);
+    assert_eq!(faults.dropped(Direction::ToClient), 2);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn half_closed_subscriber_should_be_removed_on_next_publish() -> Result<()> {
+    let sim = Sim::default();
+    let (conn, faults) = sim.connect();
+    let mut sub = conn
+        .execute_streaming(CommandRequest::new_subscribe("lobby"))
+        .await?;
+    let id = sub.id;
+
+    // 服务器到客户端的方向断开，客户端读到 EOF，不会再收到数据
+    faults.half_close(Direction::ToClient);
+    assert!(!matches!(sub.next().await, Some(Ok(_))));
+
+    // 第一次 publish 时服务器发送失败，关闭连接；第二次 publish 时清理订阅
+    let (mut publisher, _) = sim.connect();
+    for i in 0..2 {
+        let cmd = CommandRequest::new_publish("lobby", vec![i.into()]);
+        publisher.execute_unary(cmd).await?;
+        Sim::settle().await;
+    }
+
+    let res = publisher
+        .execute_unary(CommandRequest::new_unsubscribe("lobby", id))
+        .await?;
+    assert_eq!(res.status, 404);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn half_closed_request_stream_should_not_stop_the_subscription() -> Result<()> {
+    let sim = Sim::default();
+    let (conn, faults) = sim.connect();
+    let mut sub = conn
+        .execute_streaming(CommandRequest::new_subscribe("lobby"))
+        .await?;
+
+    // 客户端不再发送请求，但订阅的数据还会继续推送
+    faults.half_close(Direction::ToServer);
+
+    let (mut publisher, _) = sim.connect();
+    let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+    publisher.execute_unary(cmd).await?;
+
+    let res = sub.next().await.unwrap()?;
+    assert_eq!(res.values, vec!["hello".into()]);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_reader_should_not_delay_other_subscribers() -> Result<()> {
+    let sim = Sim::default();
+
+    let (conn, faults) = sim.connect();
+    // 每 10ms 只能读 16 个字节
+    faults.slow_reader(Direction::ToClient, 16, Duration::from_millis(10));
+    let mut slow = conn
+        .execute_streaming(CommandRequest::new_subscribe("lobby"))
+        .await?;
+    let (conn, _) = sim.connect();
+    let mut fast = conn
+        .execute_streaming(CommandRequest::new_subscribe("lobby"))
+        .await?;
+
+    let start = Instant::now();
+    let (mut publisher, _) = sim.connect();
+    for i in 0..50 {
+        let cmd = CommandRequest::new_publish("lobby", vec![i.into()]);
+        publisher.execute_unary(cmd).await?;
+    }
+
+    for i in 0..50 {
+        assert_eq!(published(fast.next().await.unwrap()?), i);
+    }
+    let fast_elapsed = start.elapsed();
+
+    for i in 0..50 {
+        let res = time::timeout(Duration::from_secs(1), slow.next()).await?;
+        assert_eq!(published(res.unwrap()?), i);
+    }
+    let slow_elapsed = start.elapsed();
+
+    assert!(fast_elapsed < Duration::from_millis(10));
+    assert!(slow_elapsed >= Duration::from_millis(500));
+
+    Ok(())
+}